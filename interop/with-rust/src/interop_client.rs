@@ -22,6 +22,7 @@ use futures::future::Future;
 use httpbis::Client;
 use httpbis::ClientConf;
 use httpbis::ClientTlsOption;
+use httpbis::alpn;
 
 use httpbis_interop::PORT;
 
@@ -30,12 +31,12 @@ use clap::Arg;
 
 fn not_found(client: Client) {
     let r = client.start_get("/404", "localhost").collect().wait().expect("get");
-    assert_eq!(404, r.headers.status());
+    assert_eq!(Some(404), r.headers.status());
 }
 
 fn found(client: Client) {
     let r = client.start_get("/200", "localhost").collect().wait().expect("get");
-    assert_eq!(200, r.headers.status());
+    assert_eq!(Some(200), r.headers.status());
     assert_eq!(Bytes::from("200 200 200"), r.body);
 }
 
@@ -59,6 +60,9 @@ fn test_tls_connector() -> TlsConnector {
 
     let mut builder = TlsConnector::builder().unwrap();
     builder.add_root_certificate(root_ca).expect("add_root_certificate");
+    if alpn::SUPPORTED {
+        alpn::connector_set_protocols(&mut builder, &[alpn::H2]).expect("ALPN");
+    }
     builder.build().unwrap()
 }
 
@@ -18,6 +18,7 @@ use httpbis::Server;
 use httpbis::ServerTlsOption;
 use httpbis::ServerConf;
 use httpbis::HttpPartStream;
+use httpbis::alpn;
 use httpbis_interop::PORT;
 
 struct ServiceImpl {
@@ -27,7 +28,7 @@ impl httpbis::Service for ServiceImpl {
     fn start_request(&self, headers: Headers, _req: HttpPartStream) -> Response {
         info!("request: {:?}", headers);
 
-        if headers.path() == "/200" {
+        if headers.path() == Some("/200") {
             Response::message(SimpleHttpMessage::found_200_plain_text("200 200 200"))
         } else {
             Response::message(SimpleHttpMessage::not_found_404("not found"))
@@ -38,7 +39,10 @@ impl httpbis::Service for ServiceImpl {
 fn test_tls_acceptor() -> TlsAcceptor {
     let buf = include_bytes!("../../identity.p12");
     let pkcs12 = Pkcs12::from_der(buf, "mypass").unwrap();
-    let builder = TlsAcceptor::builder(pkcs12).unwrap();
+    let mut builder = TlsAcceptor::builder(pkcs12).unwrap();
+    if alpn::SUPPORTED {
+        alpn::acceptor_set_protocols(&mut builder, &[alpn::H2]).expect("ALPN");
+    }
     builder.build().unwrap()
 }
 
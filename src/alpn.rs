@@ -0,0 +1,134 @@
+//! ALPN negotiation for TLS connections.
+//!
+//! HTTP/2 over TLS must negotiate `h2` protocol identifier (RFC 7540, section 3.3).
+//! Negotiation is only implemented for OpenSSL backend of native-tls
+//! and requires `openssl-v102` or `openssl-v110` feature matching installed OpenSSL,
+//! otherwise protocols cannot be configured and negotiation is not checked.
+
+use native_tls::TlsAcceptorBuilder;
+use native_tls::TlsConnectorBuilder;
+use native_tls::TlsStream;
+
+use error;
+use result;
+
+/// Protocol identifier of HTTP/2 over TLS
+pub const H2: &'static [u8] = b"h2";
+
+/// Protocol identifier of HTTP/1.1
+pub const HTTP_1_1: &'static [u8] = b"http/1.1";
+
+#[cfg(all(
+    any(feature = "openssl-v102", feature = "openssl-v110"),
+    not(any(target_os = "windows", target_os = "macos", target_os = "ios"))))]
+mod imp {
+    use std::io;
+
+    use native_tls::TlsAcceptorBuilder;
+    use native_tls::TlsConnectorBuilder;
+    use native_tls::TlsStream;
+    use native_tls::backend::openssl::TlsAcceptorBuilderExt;
+    use native_tls::backend::openssl::TlsConnectorBuilderExt;
+    use native_tls::backend::openssl::TlsStreamExt;
+
+    use openssl::error::ErrorStack;
+
+    use error;
+    use result;
+
+    fn from_openssl(e: ErrorStack) -> error::Error {
+        error::Error::IoError(io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    pub const SUPPORTED: bool = true;
+
+    pub fn acceptor_set_protocols(builder: &mut TlsAcceptorBuilder, protocols: &[&[u8]])
+        -> result::Result<()>
+    {
+        builder.builder_mut().set_alpn_protocols(protocols).map_err(from_openssl)
+    }
+
+    pub fn connector_set_protocols(builder: &mut TlsConnectorBuilder, protocols: &[&[u8]])
+        -> result::Result<()>
+    {
+        builder.builder_mut().set_alpn_protocols(protocols).map_err(from_openssl)
+    }
+
+    pub fn negotiated_protocol<S>(stream: &TlsStream<S>) -> Option<Vec<u8>> {
+        stream.raw_stream().ssl().selected_alpn_protocol().map(|p| p.to_vec())
+    }
+}
+
+#[cfg(not(all(
+    any(feature = "openssl-v102", feature = "openssl-v110"),
+    not(any(target_os = "windows", target_os = "macos", target_os = "ios")))))]
+mod imp {
+    use native_tls::TlsAcceptorBuilder;
+    use native_tls::TlsConnectorBuilder;
+    use native_tls::TlsStream;
+
+    use error;
+    use result;
+
+    pub const SUPPORTED: bool = false;
+
+    pub fn acceptor_set_protocols(_builder: &mut TlsAcceptorBuilder, _protocols: &[&[u8]])
+        -> result::Result<()>
+    {
+        Err(error::Error::Other("ALPN is not supported"))
+    }
+
+    pub fn connector_set_protocols(_builder: &mut TlsConnectorBuilder, _protocols: &[&[u8]])
+        -> result::Result<()>
+    {
+        Err(error::Error::Other("ALPN is not supported"))
+    }
+
+    pub fn negotiated_protocol<S>(_stream: &TlsStream<S>) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// ALPN protocols can be configured and negotiated protocol is known
+pub const SUPPORTED: bool = imp::SUPPORTED;
+
+/// Configure protocols server is willing to negotiate, in order of preference.
+///
+/// Acceptors used with `ServerTlsOption::Tls` should be configured with `H2`.
+pub fn acceptor_set_protocols(builder: &mut TlsAcceptorBuilder, protocols: &[&[u8]])
+    -> result::Result<()>
+{
+    imp::acceptor_set_protocols(builder, protocols)
+}
+
+/// Configure protocols offered by client, in order of preference
+pub fn connector_set_protocols(builder: &mut TlsConnectorBuilder, protocols: &[&[u8]])
+    -> result::Result<()>
+{
+    imp::connector_set_protocols(builder, protocols)
+}
+
+/// Protocol negotiated during TLS handshake, `None` if nothing was negotiated
+pub fn negotiated_protocol<S>(stream: &TlsStream<S>) -> Option<Vec<u8>> {
+    imp::negotiated_protocol(stream)
+}
+
+/// Fail with `Error::AlpnNotH2` if protocol other than `h2` was negotiated,
+/// or if nothing was negotiated and `require` is set
+pub fn check_negotiated_h2(protocol: Option<&[u8]>, require: bool) -> result::Result<()> {
+    match protocol {
+        Some(p) if p == H2 => Ok(()),
+        None if !require => Ok(()),
+        p => Err(error::Error::AlpnNotH2(p.map(|p| p.to_vec()))),
+    }
+}
+
+/// Check negotiated protocol with `check_negotiated_h2`,
+/// nothing is checked if ALPN is not supported
+pub fn check_h2<S>(stream: &TlsStream<S>, require: bool) -> result::Result<()> {
+    if !imp::SUPPORTED {
+        return Ok(());
+    }
+
+    check_negotiated_h2(negotiated_protocol(stream).as_ref().map(|p| &p[..]), require)
+}
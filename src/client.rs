@@ -1,9 +1,14 @@
 use std::sync::mpsc;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::thread;
 use std::io;
+use std::mem;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
+use std::time::Duration;
 
 use bytes::Bytes;
 
@@ -23,6 +28,7 @@ use futures_misc::*;
 
 use error;
 use error::Error;
+use error::GoAwayInfo;
 use result::Result;
 
 use solicit::header::*;
@@ -31,11 +37,21 @@ use solicit::StreamId;
 
 use solicit_async::*;
 
+use client_conn;
 use client_conn::*;
 use client_conf::*;
 use common::*;
+
+use metrics::Metrics;
+use metrics::MetricsSet;
 use stream_part::*;
 use service::Service;
+use resp::RequestHandle;
+use alpn;
+use client_auth;
+
+#[cfg(feature = "gzip")]
+use content_coding;
 
 pub use client_tls::ClientTlsOption;
 
@@ -48,14 +64,87 @@ struct LoopToClient {
     controller_tx: UnboundedSender<ControllerCommand>,
 }
 
+/// Sending half of a `CONNECT` tunnel.
+///
+/// Bytes are sent to peer in DATA frames, dropping writer
+/// sends END_STREAM, i. e. half-closes the tunnel.
+pub type TunnelWriter = RequestWriter;
+
+/// Sending half of a request with streamed body.
+///
+/// Dropping the writer sends END_STREAM, `finish_request` does it explicitly
+/// while writer is still alive.
+pub struct RequestWriter {
+    tx: UnboundedSender<HttpStreamPart>,
+    finished: AtomicBool,
+}
+
+impl RequestWriter {
+    fn new() -> (RequestWriter, HttpPartStream) {
+        let (tx, rx) = unbounded();
+        let body = HttpPartStream::new(rx.map_err(|()| error::Error::Other("request writer")));
+
+        let writer = RequestWriter {
+            tx: tx,
+            finished: AtomicBool::new(false),
+        };
+
+        (writer, body)
+    }
+
+    /// Send bytes to peer in DATA frames
+    pub fn write(&self, data: Bytes) -> Result<()> {
+        if self.finished.load(Ordering::SeqCst) {
+            return Err(error::Error::Other("request is finished"));
+        }
+        self.tx.send(HttpStreamPart::intermediate_data(data))
+            .map_err(|_| error::Error::Other("request stream is closed"))
+    }
+
+    /// Send empty DATA frame with END_STREAM, half-closing the stream
+    pub fn finish_request(&self) -> Result<()> {
+        if self.finished.swap(true, Ordering::SeqCst) {
+            return Err(error::Error::Other("request is finished"));
+        }
+        self.tx.send(HttpStreamPart::last_data(Bytes::new()))
+            .map_err(|_| error::Error::Other("request stream is closed"))
+    }
+
+    /// Half-close the stream, same as drop
+    pub fn close(self) {
+    }
+}
+
 pub struct Client {
     loop_to_client: LoopToClient,
     thread_join_handle: Option<thread::JoinHandle<()>>,
     http_scheme: HttpScheme,
+    // number of times idempotent requests are replayed on connection failure
+    max_retries: u32,
+    // taken by `pushed_responses`
+    pushed_rx: Mutex<Option<UnboundedReceiver<(Headers, Response)>>>,
+    // origin set of current connection, updated by event loop
+    origins: Arc<Mutex<Vec<String>>>,
+    // alternative services advertised by server
+    alt_svc: Arc<Mutex<Vec<AltSvc>>>,
+    // counters of all connections, updated by event loop
+    metrics: Arc<Mutex<MetricsSet>>,
+    // decode `content-encoding` of response bodies
+    #[cfg(feature = "gzip")]
+    decompress: bool,
+    // `ClientConf::max_response_body_size`, also applied to decoded body
+    #[cfg(feature = "gzip")]
+    max_response_body_size: Option<u64>,
+    // encode request bodies with `gzip`
+    #[cfg(feature = "gzip")]
+    compress_requests: bool,
 }
 
 impl Client {
 
+    /// Connect to `host:port` over TLS if `tls` is set, cleartext otherwise.
+    ///
+    /// See `new_plain` for how cleartext connections are started.
     pub fn new(host: &str, port: u16, tls: bool, conf: ClientConf) -> Result<Client> {
         // TODO: sync
         // TODO: try connect to all addrs
@@ -63,8 +152,13 @@ impl Client {
 
         let tls_enabled = match tls {
             true => {
-                let tls_connector = TlsConnector::builder().expect("TlsConnector::Builder")
-                    .build().expect("TlsConnectorBuilder::build");
+                let mut builder = TlsConnector::builder().expect("TlsConnector::Builder");
+                // ignore error, ALPN is not supported on some platforms
+                drop(alpn::connector_set_protocols(&mut builder, &[alpn::H2]));
+                if let Some(ref identity) = conf.tls_identity {
+                    client_auth::connector_set_identity(&mut builder, identity)?;
+                }
+                let tls_connector = builder.build().expect("TlsConnectorBuilder::build");
                 let connector = Arc::new(tls_connector);
                 ClientTlsOption::Tls(host.to_owned(), connector)
             },
@@ -74,19 +168,72 @@ impl Client {
         Client::new_expl(&socket_addr, tls_enabled, conf)
     }
 
+    /// Connect to `host:port` over cleartext TCP (h2c).
+    ///
+    /// By default connection is started with prior knowledge:
+    /// HTTP/2 preface is sent immediately, so server must support HTTP/2,
+    /// otherwise requests fail with connection error.
+    /// If `conf.h2c_upgrade` is set, HTTP/1.1 `Upgrade: h2c` is attempted first.
+    pub fn new_plain(host: &str, port: u16, conf: ClientConf) -> Result<Client> {
+        Client::new(host, port, false, conf)
+    }
+
     pub fn new_expl(addr: &SocketAddr, tls: ClientTlsOption, conf: ClientConf) -> Result<Client> {
+        Client::new_addrs(vec![addr.clone()], tls, conf)
+    }
+
+    /// Create a client which distributes requests round-robin
+    /// among several backends.
+    ///
+    /// Each backend has its own connection which is reconnected
+    /// independently of others. Backends whose connection failed are skipped
+    /// until they reconnect, and requests not yet sent when connection failed
+    /// are started on another backend. With `ClientConf::retry_idempotent`
+    /// a request failed with connection error is retried on the next backend.
+    ///
+    /// Connection-level operations (like `ping` or `peer_settings`)
+    /// are performed with the first backend.
+    pub fn new_pool(addrs: Vec<SocketAddr>, tls: ClientTlsOption, conf: ClientConf) -> Result<Client> {
+        if addrs.is_empty() {
+            return Err(Error::Other("empty list of pool addresses"));
+        }
+        Client::new_addrs(addrs, tls, conf)
+    }
+
+    fn new_addrs(addrs: Vec<SocketAddr>, tls: ClientTlsOption, conf: ClientConf) -> Result<Client> {
+        // fail early on invalid configuration instead of failing each connection
+        client_conn::settings(&conf)?;
+
         // We need some data back from event loop.
         // This channel is used to exchange that data
         let (get_from_loop_tx, get_from_loop_rx) = mpsc::channel();
 
-        let addr = addr.clone();
+        let (pushed_tx, pushed_rx) = unbounded();
+        let origins = Arc::new(Mutex::new(Vec::new()));
+        let origins_for_loop = origins.clone();
+        let alt_svc = Arc::new(Mutex::new(Vec::new()));
+        let alt_svc_for_loop = alt_svc.clone();
+        let metrics = Arc::new(Mutex::new(MetricsSet::default()));
+        let metrics_for_loop = metrics.clone();
+
         let http_scheme = tls.http_scheme();
+        let max_retries = match conf.retry_idempotent.unwrap_or(false) {
+            true => conf.max_retries.unwrap_or(1),
+            false => 0,
+        };
+        #[cfg(feature = "gzip")]
+        let decompress = conf.decompress.unwrap_or(false);
+        #[cfg(feature = "gzip")]
+        let max_response_body_size = conf.max_response_body_size;
+        #[cfg(feature = "gzip")]
+        let compress_requests = conf.compress_requests.unwrap_or(false);
 
         // Start event loop.
         let join_handle = thread::Builder::new()
             .name(conf.thread_name.clone().unwrap_or_else(|| "http2-client-loop".to_owned()).to_string())
             .spawn(move || {
-                run_client_event_loop(addr, tls, conf, get_from_loop_tx);
+                run_client_event_loop(
+                    addrs, tls, conf, pushed_tx, origins_for_loop, alt_svc_for_loop, metrics_for_loop, get_from_loop_tx);
             })
             .expect("spawn");
 
@@ -98,18 +245,57 @@ impl Client {
             loop_to_client: loop_to_client,
             thread_join_handle: Some(join_handle),
             http_scheme: http_scheme,
+            max_retries: max_retries,
+            pushed_rx: Mutex::new(Some(pushed_rx)),
+            origins: origins,
+            alt_svc: alt_svc,
+            metrics: metrics,
+            #[cfg(feature = "gzip")]
+            decompress: decompress,
+            #[cfg(feature = "gzip")]
+            max_response_body_size: max_response_body_size,
+            #[cfg(feature = "gzip")]
+            compress_requests: compress_requests,
         })
     }
 
+    /// Encode request body if enabled with `ClientConf::compress_requests`
+    #[cfg(feature = "gzip")]
+    fn encode_request(&self, headers: &mut Headers, body: HttpPartStream) -> HttpPartStream {
+        if self.compress_requests {
+            content_coding::compress_gzip(headers, body)
+        } else {
+            body
+        }
+    }
+
+    /// Request body is sent as is without `gzip` feature
+    #[cfg(not(feature = "gzip"))]
+    fn encode_request(&self, _headers: &mut Headers, body: HttpPartStream) -> HttpPartStream {
+        body
+    }
+
+    /// Decode response body if enabled with `ClientConf::decompress`
+    fn decode_response(&self, resp: Response) -> Response {
+        #[cfg(feature = "gzip")]
+        {
+            if self.decompress {
+                return resp.decompress_limited(self.max_response_body_size);
+            }
+        }
+        resp
+    }
+
     pub fn start_request_simple(
         &self,
         headers: Headers,
         body: Bytes)
             -> Response
     {
-        self.start_request(
-            headers,
-            HttpPartStream::once_bytes(body))
+        self.start_request_impl(StartRequest {
+            encode_body: !body.is_empty(),
+            ..StartRequest::new(headers, HttpPartStream::once_bytes(body))
+        })
     }
 
     pub fn start_get(
@@ -124,7 +310,9 @@ impl Client {
             Header::new(":authority", authority.to_owned()),
             Header::new(":scheme", self.http_scheme.as_bytes()),
         ]);
-        self.start_request_simple(headers, Bytes::new())
+        self.decode_response(
+            start_request_retry(
+                self.loop_to_client.controller_tx.clone(), headers, self.max_retries, RequestHandle::default()))
     }
 
     pub fn start_post(
@@ -143,6 +331,214 @@ impl Client {
         self.start_request_simple(headers, body)
     }
 
+    /// Start POST request with body streamed from `body`.
+    ///
+    /// Chunks are pulled from the stream only while send flow control window is available,
+    /// and split into DATA frames according to peer's max frame size,
+    /// so body does not need to fit in memory.
+    /// END_STREAM is sent when the stream ends.
+    pub fn start_post_stream(
+        &self,
+        path: &str,
+        authority: &str,
+        body: HttpFutureStreamSend<Bytes>)
+            -> Response
+    {
+        let headers = match Headers::request(Method::Post, path, self.http_scheme, authority) {
+            Ok(headers) => headers,
+            Err(e) => return Response::err(error::Error::InvalidHeaders(e)),
+        };
+        self.start_request(headers, HttpPartStream::bytes(body))
+    }
+
+    /// Start POST request with `Expect: 100-continue`.
+    ///
+    /// Body is sent only after server responds with `100 Continue`,
+    /// or after `ClientConf::expect_continue_timeout` if server does not respond.
+    /// If server sends final response instead, body is not sent
+    /// and the request stream is ended without data.
+    pub fn start_post_expect_continue(
+        &self,
+        path: &str,
+        authority: &str,
+        body: Bytes)
+            -> Response
+    {
+        let mut headers = match Headers::request(Method::Post, path, self.http_scheme, authority) {
+            Ok(headers) => headers,
+            Err(e) => return Response::err(error::Error::InvalidHeaders(e)),
+        };
+        headers.add("expect", "100-continue");
+
+        let encode_body = !body.is_empty();
+
+        let (continue_tx, continue_rx) = oneshot::channel();
+        let body = continue_rx
+            .then(move |r| match r {
+                Ok(()) => Ok::<_, error::Error>(HttpPartStream::once_bytes(body)),
+                Err(oneshot::Canceled) => Ok(HttpPartStream::empty()),
+            })
+            .flatten_stream();
+
+        self.start_request_impl(StartRequest {
+            continue_tx: Some(continue_tx),
+            encode_body: encode_body,
+            ..StartRequest::new(headers, HttpPartStream::new(body))
+        })
+    }
+
+    /// Start POST request with body written using returned `RequestWriter`
+    pub fn start_post_writer(&self, path: &str, authority: &str) -> (RequestWriter, Response) {
+        match Headers::request(Method::Post, path, self.http_scheme, authority) {
+            Ok(headers) => self.start_request_writer(headers),
+            Err(e) => {
+                // writes fail because body is dropped
+                let (writer, _body) = RequestWriter::new();
+                (writer, Response::err(error::Error::InvalidHeaders(e)))
+            }
+        }
+    }
+
+    /// Start request with body written using returned `RequestWriter`
+    pub fn start_request_writer(&self, headers: Headers) -> (RequestWriter, Response) {
+        let (writer, body) = RequestWriter::new();
+        (writer, self.start_request(headers, body))
+    }
+
+    /// Start request, interim (1xx) responses like `103 Early Hints`
+    /// are delivered in returned stream before the final response.
+    ///
+    /// The stream ends when the final response headers are received.
+    pub fn start_request_with_interim(
+        &self,
+        headers: Headers,
+        body: HttpPartStream)
+            -> (HttpFutureStreamSend<Headers>, Response)
+    {
+        let (interim_tx, interim_rx) = unbounded();
+        let resp = self.start_request_impl(StartRequest {
+            interim_tx: Some(interim_tx),
+            ..StartRequest::new(headers, body)
+        });
+        (Box::new(interim_rx.map_err(|()| error::Error::Other("interim responses"))), resp)
+    }
+
+    /// Start GET request with given stream priority
+    pub fn start_get_with_priority(
+        &self,
+        path: &str,
+        authority: &str,
+        priority: StreamPriority)
+            -> Response
+    {
+        let headers = match Headers::request(Method::Get, path, self.http_scheme, authority) {
+            Ok(headers) => headers,
+            Err(e) => return Response::err(error::Error::InvalidHeaders(e)),
+        };
+        self.start_request_impl(StartRequest {
+            priority: Some(priority),
+            encode_body: false,
+            ..StartRequest::new(headers, HttpPartStream::once_bytes(Bytes::new()))
+        })
+    }
+
+    /// Start POST request with given stream priority
+    pub fn start_post_with_priority(
+        &self,
+        path: &str,
+        authority: &str,
+        body: Bytes,
+        priority: StreamPriority)
+            -> Response
+    {
+        let headers = match Headers::request(Method::Post, path, self.http_scheme, authority) {
+            Ok(headers) => headers,
+            Err(e) => return Response::err(error::Error::InvalidHeaders(e)),
+        };
+        self.start_request_impl(StartRequest {
+            priority: Some(priority),
+            encode_body: !body.is_empty(),
+            ..StartRequest::new(headers, HttpPartStream::once_bytes(body))
+        })
+    }
+
+    /// Start GET request which fails with `Error::RequestTimeout`
+    /// unless completed within given duration
+    pub fn start_get_with_timeout(
+        &self,
+        path: &str,
+        authority: &str,
+        timeout: Duration)
+            -> Response
+    {
+        let headers = match Headers::request(Method::Get, path, self.http_scheme, authority) {
+            Ok(headers) => headers,
+            Err(e) => return Response::err(error::Error::InvalidHeaders(e)),
+        };
+        self.start_request_impl(StartRequest {
+            timeout: Some(timeout),
+            encode_body: false,
+            ..StartRequest::new(headers, HttpPartStream::once_bytes(Bytes::new()))
+        })
+    }
+
+    /// Open a tunnel to given authority with `CONNECT` method.
+    ///
+    /// Response headers are received first, then response body
+    /// contains bytes sent by peer; END_STREAM from peer ends the body.
+    pub fn connect_tunnel(&self, authority: &str) -> (TunnelWriter, Response) {
+        let headers = Headers(vec![
+            Header::new(":method", "CONNECT"),
+            Header::new(":authority", authority.to_owned()),
+        ]);
+
+        // tunneled bytes are never encoded
+        let (writer, body) = RequestWriter::new();
+        (writer, self.start_request_impl(StartRequest {
+            encode_body: false,
+            ..StartRequest::new(headers, body)
+        }))
+    }
+
+    /// Start request, priority is sent in HEADERS frame if specified
+    pub fn start_request_with_priority(
+        &self,
+        headers: Headers,
+        body: HttpPartStream,
+        priority: Option<StreamPriority>)
+            -> Response
+    {
+        self.start_request_impl(StartRequest {
+            priority: priority,
+            ..StartRequest::new(headers, body)
+        })
+    }
+
+    /// Start request, stream is reset with `CANCEL` and response fails
+    /// with `Error::RequestTimeout` if not completed within given duration
+    pub fn start_request_with_timeout(
+        &self,
+        headers: Headers,
+        body: HttpPartStream,
+        timeout: Duration)
+            -> Response
+    {
+        self.start_request_impl(StartRequest {
+            timeout: Some(timeout),
+            ..StartRequest::new(headers, body)
+        })
+    }
+
+    /// Encode request body, start request on current connection and decode response
+    fn start_request_impl(&self, mut request: StartRequest) -> Response {
+        if request.encode_body {
+            let body = mem::replace(&mut request.body, HttpPartStream::empty());
+            request.body = self.encode_request(&mut request.headers, body);
+        }
+        self.decode_response(
+            start_request_on(&self.loop_to_client.controller_tx, request, RequestHandle::default()))
+    }
+
     pub fn dump_state(&self) -> HttpFutureSend<ConnectionStateSnapshot> {
         let (tx, rx) = oneshot::channel();
         // ignore error
@@ -150,46 +546,227 @@ impl Client {
         Box::new(rx.map_err(|_| error::Error::Other("conn died")))
     }
 
+    /// For tests: skip stream ids of current connection so that `next_id` is allocated next
+    pub(crate) fn fast_forward_stream_ids(&self, next_id: StreamId) -> HttpFutureSend<()> {
+        let (tx, rx) = oneshot::channel();
+        // error is reported by canceled oneshot
+        drop(self.loop_to_client.controller_tx.send(ControllerCommand::FastForwardStreamIds(next_id, tx)));
+        Box::new(rx.then(|r| match r {
+            Ok(r) => r,
+            Err(oneshot::Canceled) => Err(error::Error::Other("conn died")),
+        }))
+    }
+
+    /// Send PING frame and wait for ACK, resolves to round-trip time
+    pub fn ping(&self) -> HttpFutureSend<Duration> {
+        let (tx, rx) = oneshot::channel();
+        // ignore error
+        drop(self.loop_to_client.controller_tx.send(ControllerCommand::Ping(tx)));
+        Box::new(rx.map_err(|_| error::Error::Other("conn died")))
+    }
+
+    /// Last GOAWAY frame received from server, if any
+    pub fn last_goaway(&self) -> HttpFutureSend<Option<GoAwayInfo>> {
+        let (tx, rx) = oneshot::channel();
+        // ignore error
+        drop(self.loop_to_client.controller_tx.send(ControllerCommand::LastGoaway(tx)));
+        Box::new(rx.map_err(|_| error::Error::Other("conn died")))
+    }
+
+    /// Settings advertised by server on current connection,
+    /// updated as server sends new SETTINGS frames
+    pub fn peer_settings(&self) -> HttpFutureSend<PeerSettings> {
+        let (tx, rx) = oneshot::channel();
+        // ignore error
+        drop(self.loop_to_client.controller_tx.send(ControllerCommand::PeerSettings(tx)));
+        Box::new(rx.map_err(|_| error::Error::Other("conn died")))
+    }
+
+    /// Responses pushed by server with promised request headers.
+    ///
+    /// Push must be enabled with `ClientConf::enable_push`.
+    /// Can be called only once, subsequent calls return failing stream.
+    pub fn pushed_responses(&self) -> HttpFutureStreamSend<(Headers, Response)> {
+        match self.pushed_rx.lock().unwrap().take() {
+            Some(rx) => Box::new(rx.map_err(|()| error::Error::Other("pushed responses"))),
+            None => Box::new(futures::stream::once(Err(error::Error::Other("pushed responses already taken")))),
+        }
+    }
+
+    /// Origins received from server in ORIGIN frames (RFC 8336).
+    ///
+    /// Empty if server did not send ORIGIN frame, origin set is reset on reconnect.
+    pub fn origins(&self) -> Vec<String> {
+        self.origins.lock().unwrap().clone()
+    }
+
+    /// Alternative services received from server in ALTSVC frames (RFC 7838),
+    /// in order of arrival
+    pub fn alt_svc(&self) -> Vec<AltSvc> {
+        self.alt_svc.lock().unwrap().clone()
+    }
+
+    /// Frame, byte and stream counters summed over all connections of this client
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.lock().unwrap().total()
+    }
+
     pub fn wait_for_connect(&self) -> HttpFutureSend<()> {
         let (tx, rx) = oneshot::channel();
         // ignore error
         drop(self.loop_to_client.controller_tx.send(ControllerCommand::WaitForConnect(tx)));
         Box::new(rx.map_err(|_| error::Error::Other("conn died")).and_then(|r| r))
     }
+
+    /// Resolves with `Error::ConnectionError` or `Error::ConnectionDied`
+    /// when current connection is closed,
+    /// see `ClientConnection::wait_for_disconnect`.
+    ///
+    /// Client reconnects on the next request, so the future is resolved
+    /// once per connection.
+    pub fn wait_for_disconnect(&self) -> HttpFutureSend<Error> {
+        let (tx, rx) = oneshot::channel();
+        // ignore error
+        drop(self.loop_to_client.controller_tx.send(ControllerCommand::WaitForDisconnect(tx)));
+        Box::new(rx.map_err(|_| error::Error::Other("conn died")))
+    }
 }
 
 impl Service for Client {
-    // TODO: copy-paste with HttpClientConnectionAsync
     fn start_request(
         &self,
         headers: Headers,
         body: HttpPartStream)
             -> Response
     {
-        let (resp_tx, resp_rx) = unbounded();
+        self.start_request_impl(StartRequest::new(headers, body))
+    }
+}
+
+/// Request passed to `start_request_on`, see `StartRequestMessage` for field descriptions
+struct StartRequest {
+    headers: Headers,
+    body: HttpPartStream,
+    priority: Option<StreamPriority>,
+    timeout: Option<Duration>,
+    continue_tx: Option<oneshot::Sender<()>>,
+    interim_tx: Option<UnboundedSender<Headers>>,
+    /// Body is encoded if enabled with `ClientConf::compress_requests`,
+    /// unset for requests known to have no body
+    encode_body: bool,
+}
 
-        let start = StartRequestMessage {
+impl StartRequest {
+    fn new(headers: Headers, body: HttpPartStream) -> StartRequest {
+        StartRequest {
             headers: headers,
             body: body,
-            resp_tx: resp_tx,
-        };
-
-        if let Err(_) = self.loop_to_client.controller_tx.send(ControllerCommand::StartRequest(start)) {
-            return Response::err(error::Error::Other("client controller died"));
+            priority: None,
+            timeout: None,
+            continue_tx: None,
+            interim_tx: None,
+            encode_body: true,
         }
+    }
+}
+
+fn start_request_on(
+    controller_tx: &UnboundedSender<ControllerCommand>,
+    request: StartRequest,
+    handle: RequestHandle)
+        -> Response
+{
+    if let Err(e) = request.headers.check_connection_specific() {
+        return Response::err(error::Error::InvalidHeaders(e));
+    }
+
+    let (resp_tx, resp_rx) = unbounded();
+    let (cancel_guard, cancel_rx) = handle.cancel_channel();
 
-        let req_rx = resp_rx.map_err(|()| Error::from(io::Error::new(io::ErrorKind::Other, "req")));
+    let start = StartRequestMessage {
+        headers: request.headers,
+        body: request.body,
+        priority: request.priority,
+        timeout: request.timeout,
+        resp_tx: resp_tx,
+        cancel_rx: cancel_rx,
+        continue_tx: request.continue_tx,
+        interim_tx: request.interim_tx,
+        stream_id: handle.stream_id_slot(),
+    };
+
+    if let Err(_) = controller_tx.send(ControllerCommand::StartRequest(start)) {
+        return Response::err(error::Error::Other("client controller died"));
+    }
+
+    let req_rx = resp_rx.map_err(|()| Error::from(io::Error::new(io::ErrorKind::Other, "req")));
+
+    let req_rx = stream_with_eof_and_error(req_rx, || error::Error::Other("client is likely died"));
 
-        let req_rx = stream_with_eof_and_error(req_rx, || error::Error::Other("client is likely died"));
+    let req_rx = stream_with_guard(req_rx, cancel_guard);
 
-        Response::from_stream(req_rx)
-    }}
+    Response::from_stream(req_rx).with_request_handle(handle)
+}
+
+/// Start request without body, replaying it on a new connection
+/// if connection fails before response headers are received.
+/// Must be used only for idempotent requests.
+fn start_request_retry(
+    controller_tx: UnboundedSender<ControllerCommand>,
+    headers: Headers,
+    retries: u32,
+    handle: RequestHandle)
+        -> Response
+{
+    let resp = start_request_on(
+        &controller_tx,
+        StartRequest::new(headers.clone(), HttpPartStream::once_bytes(Bytes::new())),
+        handle.clone());
+
+    if retries == 0 {
+        return resp;
+    }
+
+    // Response future resolves when headers are received,
+    // so errors after that are not retried.
+    // Replayed request reports id of its new stream and is canceled by the same handle.
+    let handle_for_retry = handle.clone();
+    Response::new(resp.0.or_else(move |e| {
+        match e {
+            Error::ConnectionError(..) | Error::ConnectionDied(..) => {
+                debug!("retrying request after connection error: {:?}", e);
+                start_request_retry(controller_tx, headers, retries - 1, handle_for_retry).0
+            }
+            e => Response::err(e).0,
+        }
+    })).with_request_handle(handle)
+}
 
 enum ControllerCommand {
-    GoAway,
+    GoAway(GoAwayInfo),
     StartRequest(StartRequestMessage),
     WaitForConnect(oneshot::Sender<Result<()>>),
+    WaitForDisconnect(oneshot::Sender<Error>),
     DumpState(oneshot::Sender<ConnectionStateSnapshot>),
+    Ping(oneshot::Sender<Duration>),
+    LastGoaway(oneshot::Sender<Option<GoAwayInfo>>),
+    PeerSettings(oneshot::Sender<PeerSettings>),
+    StreamIdsExhausted,
+    FastForwardStreamIds(StreamId, oneshot::Sender<Result<()>>),
+    /// Connection failed before request was sent
+    RequestNotSent(StartRequestMessage, Error),
+    /// Sent to pool: connection of member with given index failed before request was sent
+    RetryNotSent(usize, StartRequestMessage, Error),
+    /// Sent to pool: connection of member established or failed
+    MemberConnected(usize, bool),
+    /// Sent to pool member: open a new connection
+    Reconnect,
+}
+
+/// Pool controller of a backend controller and index of backend in the pool
+struct PoolLink {
+    index: usize,
+    tx: UnboundedSender<ControllerCommand>,
 }
 
 struct ControllerState {
@@ -200,6 +777,13 @@ struct ControllerState {
     // current connection
     conn: Arc<ClientConnection>,
     tx: UnboundedSender<ControllerCommand>,
+    pushed_tx: UnboundedSender<(Headers, Response)>,
+    origins: Arc<Mutex<Vec<String>>>,
+    alt_svc: Arc<Mutex<Vec<AltSvc>>>,
+    metrics: Arc<Mutex<MetricsSet>>,
+    last_goaway: Option<GoAwayInfo>,
+    // set if backend is a member of pool
+    pool: Option<PoolLink>,
 }
 
 impl ControllerState {
@@ -211,16 +795,53 @@ impl ControllerState {
             self.conf.clone(),
             CallbacksImpl {
                 tx: self.tx.clone(),
+                pushed_tx: self.pushed_tx.clone(),
+                origins: self.origins.clone(),
+                alt_svc: self.alt_svc.clone(),
             });
 
+        // origin set is per connection
+        self.origins.lock().unwrap().clear();
+
+        self.metrics.lock().unwrap().add_conn(conn.shared_metrics());
+
         self.handle.spawn(future.map_err(|e| { warn!("client error: {:?}", e); () }));
 
         self.conn = Arc::new(conn);
+        self.watch_conn();
+    }
+
+    /// Report to pool when current connection is established or fails
+    fn watch_conn(&self) {
+        let pool = match self.pool {
+            Some(ref pool) => pool,
+            None => return,
+        };
+
+        let index = pool.index;
+        let pool_tx = pool.tx.clone();
+
+        let (tx, rx) = oneshot::channel();
+        if let Err(_) = self.conn.wait_for_connect_with_resp_sender(tx) {
+            // ignore error, pool controller runs until event loop is stopped
+            drop(pool_tx.send(ControllerCommand::MemberConnected(index, false)));
+            return;
+        }
+
+        self.handle.spawn(rx.then(move |r| {
+            let connected = match r {
+                Ok(Ok(())) => true,
+                _ => false,
+            };
+            drop(pool_tx.send(ControllerCommand::MemberConnected(index, connected)));
+            Ok(())
+        }));
     }
 
     fn iter(mut self, cmd: ControllerCommand) -> ControllerState {
         match cmd {
-            ControllerCommand::GoAway => {
+            ControllerCommand::GoAway(info) => {
+                self.last_goaway = Some(info);
                 self.init_conn();
             },
             ControllerCommand::StartRequest(start) => {
@@ -243,9 +864,47 @@ impl ControllerState {
                     }
                 }
             }
+            ControllerCommand::WaitForDisconnect(tx) => {
+                self.conn.wait_for_disconnect_with_resp_sender(tx);
+            }
+            ControllerCommand::StreamIdsExhausted => {
+                // 5.1.1: stream ids cannot be reused, new connection starts from 1
+                self.init_conn();
+            }
+            ControllerCommand::FastForwardStreamIds(next_id, tx) => {
+                self.conn.fast_forward_stream_ids_with_resp_sender(next_id, tx);
+            }
             ControllerCommand::DumpState(tx) => {
                 self.conn.dump_state_with_resp_sender(tx);
             }
+            ControllerCommand::Ping(tx) => {
+                self.conn.ping_with_resp_sender(tx);
+            }
+            ControllerCommand::LastGoaway(tx) => {
+                // ignore error
+                drop(tx.send(self.last_goaway.clone()));
+            }
+            ControllerCommand::PeerSettings(tx) => {
+                self.conn.peer_settings_with_resp_sender(tx);
+            }
+            ControllerCommand::RequestNotSent(start, error) => {
+                match self.pool {
+                    Some(ref pool) => {
+                        // pool starts request with another backend
+                        drop(pool.tx.send(ControllerCommand::RetryNotSent(pool.index, start, error)));
+                    }
+                    None => {
+                        // ignore error
+                        drop(start.resp_tx.send(ResultOrEof::Error(error)));
+                    }
+                }
+            }
+            ControllerCommand::Reconnect => {
+                self.init_conn();
+            }
+            ControllerCommand::RetryNotSent(..) | ControllerCommand::MemberConnected(..) => {
+                unreachable!("pool command sent to backend controller");
+            }
         }
         self
     }
@@ -264,19 +923,157 @@ impl ControllerState {
 
 struct CallbacksImpl {
     tx: UnboundedSender<ControllerCommand>,
+    pushed_tx: UnboundedSender<(Headers, Response)>,
+    origins: Arc<Mutex<Vec<String>>>,
+    alt_svc: Arc<Mutex<Vec<AltSvc>>>,
 }
 
 impl ClientConnectionCallbacks for CallbacksImpl {
-    fn goaway(&self, _stream_id: StreamId, _error_code: u32) {
-        drop(self.tx.send(ControllerCommand::GoAway));
+    fn goaway(&self, info: GoAwayInfo) {
+        drop(self.tx.send(ControllerCommand::GoAway(info)));
+    }
+
+    fn push(&self, promised_request: Headers, response: Response) {
+        // ignore error: pushed responses receiver is dropped with client
+        drop(self.pushed_tx.send((promised_request, response)));
+    }
+
+    fn origin(&self, origins: Vec<String>) {
+        let mut set = self.origins.lock().unwrap();
+        for origin in origins {
+            if !set.contains(&origin) {
+                set.push(origin);
+            }
+        }
+    }
+
+    fn alt_svc(&self, alt_svc: AltSvc) {
+        self.alt_svc.lock().unwrap().push(alt_svc);
+    }
+
+    fn stream_ids_exhausted(&self) {
+        drop(self.tx.send(ControllerCommand::StreamIdsExhausted));
+    }
+
+    fn retry_request(&self, start: StartRequestMessage) {
+        // sent after `StreamIdsExhausted`, so request is started on new connection
+        drop(self.tx.send(ControllerCommand::StartRequest(start)));
+    }
+
+    fn request_not_sent(&self, start: StartRequestMessage, error: Error) {
+        drop(self.tx.send(ControllerCommand::RequestNotSent(start, error)));
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum PoolMemberState {
+    /// Initial connection is not yet established
+    Connecting,
+    Connected,
+    /// Connection failed, requests are not sent to member until it reconnects
+    Failed,
+    /// Connection failed, and member is connecting again
+    Reconnecting,
+}
+
+struct PoolMember {
+    tx: UnboundedSender<ControllerCommand>,
+    state: PoolMemberState,
+}
+
+impl PoolMember {
+    fn send(&self, cmd: ControllerCommand) {
+        // ignore error, member controllers run until event loop is stopped
+        drop(self.tx.send(cmd));
+    }
+}
+
+/// Distributes requests of client created with `Client::new_pool`
+/// among controllers of backends, each of which maintains its own connection
+struct PoolControllerState {
+    members: Vec<PoolMember>,
+    next: usize,
+}
+
+impl PoolControllerState {
+    /// Next member in round-robin order skipping members whose connection failed,
+    /// failed members are asked to reconnect
+    fn next_healthy_member(&mut self) -> Option<usize> {
+        for _ in 0..self.members.len() {
+            let member = self.next;
+            self.next = (self.next + 1) % self.members.len();
+            match self.members[member].state {
+                PoolMemberState::Connecting | PoolMemberState::Connected => return Some(member),
+                PoolMemberState::Failed => {
+                    self.members[member].state = PoolMemberState::Reconnecting;
+                    self.members[member].send(ControllerCommand::Reconnect);
+                }
+                PoolMemberState::Reconnecting => {}
+            }
+        }
+        None
+    }
+
+    fn iter(mut self, cmd: ControllerCommand) -> PoolControllerState {
+        match cmd {
+            ControllerCommand::StartRequest(start) => {
+                let member = match self.next_healthy_member() {
+                    Some(member) => member,
+                    None => {
+                        // all backends are down, request waits for reconnect
+                        let member = self.next;
+                        self.next = (self.next + 1) % self.members.len();
+                        member
+                    }
+                };
+                self.members[member].send(ControllerCommand::StartRequest(start));
+            }
+            ControllerCommand::RetryNotSent(failed, start, error) => {
+                self.members[failed].state = PoolMemberState::Failed;
+                match self.next_healthy_member() {
+                    Some(member) => {
+                        debug!("request not sent to pool member {}, retrying with member {}", failed, member);
+                        self.members[member].send(ControllerCommand::StartRequest(start));
+                    }
+                    None => {
+                        // ignore error
+                        drop(start.resp_tx.send(ResultOrEof::Error(error)));
+                    }
+                }
+            }
+            ControllerCommand::MemberConnected(member, connected) => {
+                self.members[member].state = match connected {
+                    true => PoolMemberState::Connected,
+                    false => PoolMemberState::Failed,
+                };
+            }
+            // connection-level commands are served by the first backend
+            cmd => self.members[0].send(cmd),
+        }
+        self
+    }
+
+    fn run(self, rx: UnboundedReceiver<ControllerCommand>)
+        -> HttpFuture<()>
+    {
+        let rx = rx.map_err(|_| error::Error::Other("channel died"));
+        let r = rx.fold(self, |state, cmd| {
+            Ok::<_, error::Error>(state.iter(cmd))
+        });
+        let r = r.map(|_| ());
+        Box::new(r)
     }
 }
 
 // Event loop entry point
 fn run_client_event_loop(
-    socket_addr: SocketAddr,
+    socket_addrs: Vec<SocketAddr>,
     tls: ClientTlsOption,
     conf: ClientConf,
+    pushed_tx: UnboundedSender<(Headers, Response)>,
+    origins: Arc<Mutex<Vec<String>>>,
+    alt_svc: Arc<Mutex<Vec<AltSvc>>>,
+    metrics: Arc<Mutex<MetricsSet>>,
     send_to_back: mpsc::Sender<LoopToClient>)
 {
     // Create an event loop.
@@ -285,25 +1082,68 @@ fn run_client_event_loop(
     // Create a channel to receive shutdown signal.
     let (shutdown_signal, shutdown_future) = shutdown_signal();
 
-    let (controller_tx, controller_rx) = unbounded();
+    // pool controller is created if there are several backends
+    let (pool_tx, pool_rx) = unbounded();
+    let is_pool = socket_addrs.len() > 1;
+
+    // controller per backend address
+    let mut members = Vec::new();
+    for (index, socket_addr) in socket_addrs.into_iter().enumerate() {
+        let (controller_tx, controller_rx) = unbounded();
+
+        let (http_conn, conn_future) =
+            ClientConnection::new(lp.handle(), &socket_addr, tls.clone(), conf.clone(), CallbacksImpl {
+                tx: controller_tx.clone(),
+                pushed_tx: pushed_tx.clone(),
+                origins: origins.clone(),
+                alt_svc: alt_svc.clone(),
+            });
 
-    let (http_conn, conn_future) =
-        ClientConnection::new(lp.handle(), &socket_addr, tls.clone(), conf.clone(), CallbacksImpl {
+        lp.handle().spawn(conn_future.map_err(|e| { warn!("client error: {:?}", e); () }));
+
+        metrics.lock().unwrap().add_conn(http_conn.shared_metrics());
+
+        let init = ControllerState {
+            handle: lp.handle(),
+            socket_addr: socket_addr.clone(),
+            tls: tls.clone(),
+            conf: conf.clone(),
+            conn: Arc::new(http_conn),
             tx: controller_tx.clone(),
-        });
+            pushed_tx: pushed_tx.clone(),
+            origins: origins.clone(),
+            alt_svc: alt_svc.clone(),
+            metrics: metrics.clone(),
+            last_goaway: None,
+            pool: match is_pool {
+                true => Some(PoolLink { index: index, tx: pool_tx.clone() }),
+                false => None,
+            },
+        };
 
-    lp.handle().spawn(conn_future.map_err(|e| { warn!("client error: {:?}", e); () }));
+        init.watch_conn();
 
-    let init = ControllerState {
-        handle: lp.handle(),
-        socket_addr: socket_addr.clone(),
-        tls: tls,
-        conf: conf,
-        conn: Arc::new(http_conn),
-        tx: controller_tx.clone(),
-    };
+        members.push((controller_tx, init.run(controller_rx)));
+    }
+
+    let (controller_tx, controller_future) = if members.len() == 1 {
+        members.pop().unwrap()
+    } else {
+        let mut pool_members = Vec::new();
+        for (member_tx, member_future) in members {
+            lp.handle().spawn(member_future.map_err(|e| { warn!("client controller error: {:?}", e); () }));
+            pool_members.push(PoolMember {
+                tx: member_tx,
+                state: PoolMemberState::Connecting,
+            });
+        }
 
-    let controller_future = init.run(controller_rx);
+        let pool = PoolControllerState {
+            members: pool_members,
+            next: 0,
+        };
+        (pool_tx, pool.run(pool_rx))
+    };
 
     // Send channels back to Http2Client
     send_to_back
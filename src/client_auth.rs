@@ -0,0 +1,156 @@
+//! TLS client certificate authentication (mutual TLS).
+//!
+//! Verification of client certificates is only implemented for OpenSSL backend of native-tls
+//! and requires `openssl-v102` or `openssl-v110` feature, otherwise acceptors
+//! cannot be configured to request client certificates.
+
+use std::fmt;
+
+use native_tls::Pkcs12;
+use native_tls::TlsAcceptorBuilder;
+use native_tls::TlsConnectorBuilder;
+use native_tls::TlsStream;
+
+use error;
+use result;
+
+/// Client certificate and private key in PKCS #12 archive
+#[derive(Clone)]
+pub struct TlsIdentity {
+    /// DER-encoded PKCS #12 archive
+    pub pkcs12: Vec<u8>,
+    /// Password of the archive
+    pub password: String,
+}
+
+impl TlsIdentity {
+    pub fn new(pkcs12: Vec<u8>, password: &str) -> TlsIdentity {
+        TlsIdentity {
+            pkcs12: pkcs12,
+            password: password.to_owned(),
+        }
+    }
+}
+
+impl fmt::Debug for TlsIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsIdentity")
+            .field("pkcs12", &format!("<{} bytes>", self.pkcs12.len()))
+            .finish()
+    }
+}
+
+/// Names of verified certificate sent by peer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerCertificate {
+    /// Subject common name, `None` if certificate subject has no CN
+    pub common_name: Option<String>,
+    /// DNS names of subject alternative name extension
+    pub dns_names: Vec<String>,
+}
+
+#[cfg(all(any(feature = "openssl-v102", feature = "openssl-v110"),
+    not(any(target_os = "windows", target_os = "macos", target_os = "ios"))))]
+mod imp {
+    use std::io;
+
+    use native_tls::TlsAcceptorBuilder;
+    use native_tls::TlsStream;
+    use native_tls::backend::openssl::TlsAcceptorBuilderExt;
+    use native_tls::backend::openssl::TlsStreamExt;
+
+    use openssl::error::ErrorStack;
+    use openssl::nid;
+    use openssl::ssl::SSL_VERIFY_FAIL_IF_NO_PEER_CERT;
+    use openssl::ssl::SSL_VERIFY_PEER;
+    use openssl::x509::X509;
+
+    use error;
+    use result;
+
+    use super::PeerCertificate;
+
+    fn from_openssl(e: ErrorStack) -> error::Error {
+        error::Error::IoError(io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    pub fn acceptor_require_client_cert(builder: &mut TlsAcceptorBuilder, root_ca_der: &[u8])
+        -> result::Result<()>
+    {
+        let root_ca = X509::from_der(root_ca_der).map_err(from_openssl)?;
+        let builder = builder.builder_mut();
+        builder.cert_store_mut().add_cert(root_ca).map_err(from_openssl)?;
+        builder.set_verify(SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT);
+        Ok(())
+    }
+
+    pub fn verified_peer_certificate<S>(stream: &TlsStream<S>) -> Option<PeerCertificate> {
+        let ssl = stream.raw_stream().ssl();
+        if ssl.verify_result().is_some() {
+            return None;
+        }
+        let cert = ssl.peer_certificate()?;
+        let common_name = cert.subject_name().entries_by_nid(nid::COMMONNAME).next()
+            .and_then(|cn| cn.data().as_utf8().ok().map(|cn| cn.to_string()));
+        let dns_names = match cert.subject_alt_names() {
+            Some(names) => names.iter().filter_map(|n| n.dnsname().map(|n| n.to_owned())).collect(),
+            None => Vec::new(),
+        };
+        Some(PeerCertificate {
+            common_name: common_name,
+            dns_names: dns_names,
+        })
+    }
+}
+
+#[cfg(not(all(any(feature = "openssl-v102", feature = "openssl-v110"),
+    not(any(target_os = "windows", target_os = "macos", target_os = "ios")))))]
+mod imp {
+    use native_tls::TlsAcceptorBuilder;
+    use native_tls::TlsStream;
+
+    use error;
+    use result;
+
+    use super::PeerCertificate;
+
+    pub fn acceptor_require_client_cert(_builder: &mut TlsAcceptorBuilder, _root_ca_der: &[u8])
+        -> result::Result<()>
+    {
+        Err(error::Error::Other("client certificate verification requires openssl-v102 or openssl-v110 feature"))
+    }
+
+    pub fn verified_peer_certificate<S>(_stream: &TlsStream<S>) -> Option<PeerCertificate> {
+        None
+    }
+}
+
+/// Request certificate from clients and fail handshake unless
+/// client sent certificate signed by given DER-encoded CA certificate.
+///
+/// Use together with `ServerConf::require_client_cert`.
+pub fn acceptor_require_client_cert(builder: &mut TlsAcceptorBuilder, root_ca_der: &[u8])
+    -> result::Result<()>
+{
+    imp::acceptor_require_client_cert(builder, root_ca_der)
+}
+
+/// Present given certificate when server requests it
+pub fn connector_set_identity(builder: &mut TlsConnectorBuilder, identity: &TlsIdentity)
+    -> result::Result<()>
+{
+    let pkcs12 = Pkcs12::from_der(&identity.pkcs12, &identity.password)?;
+    builder.identity(pkcs12)?;
+    Ok(())
+}
+
+/// Names of certificate sent by peer,
+/// `None` if peer sent no certificate or it was not verified
+pub fn verified_peer_certificate<S>(stream: &TlsStream<S>) -> Option<PeerCertificate> {
+    imp::verified_peer_certificate(stream)
+}
+
+/// Fail unless peer sent verified certificate
+pub fn check_client_cert<S>(stream: &TlsStream<S>) -> result::Result<PeerCertificate> {
+    verified_peer_certificate(stream).ok_or(error::Error::Other("no verified TLS client certificate"))
+}
@@ -1,13 +1,61 @@
 use std::time::Duration;
 
 use common::CommonConf;
+use client_auth::TlsIdentity;
+
+/// Default of `ClientConf::expect_continue_timeout`, in seconds
+pub const DEFAULT_EXPECT_CONTINUE_TIMEOUT_SECS: u64 = 1;
 
 #[derive(Default, Debug, Clone)]
 pub struct ClientConf {
-    /// TCP_NODELAY
+    /// TCP_NODELAY, default is true
     pub no_delay: Option<bool>,
     pub thread_name: Option<String>,
     pub connection_timeout: Option<Duration>,
+    /// Send PING when no frames received from server for this time
+    pub keepalive_interval: Option<Duration>,
+    /// Close connection if PING ACK is not received within this time,
+    /// defaults to `keepalive_interval`
+    pub keepalive_timeout: Option<Duration>,
+    /// SETTINGS_INITIAL_WINDOW_SIZE advertised to server
+    pub initial_window_size: Option<u32>,
+    /// Advertise SETTINGS_ENABLE_PUSH, pushed responses are
+    /// available from `Client::pushed_responses`. Default is false
+    pub enable_push: Option<bool>,
+    /// Transparently replay GET requests started with `Client::start_get`
+    /// when connection fails before response headers are received.
+    /// Requests are replayed lazily, when response future is polled. Default is false
+    pub retry_idempotent: Option<bool>,
+    /// Maximum number of replays when `retry_idempotent` is set, defaults to 1
+    pub max_retries: Option<u32>,
+    /// Start cleartext connection with HTTP/1.1 request with `Upgrade: h2c`
+    /// instead of prior knowledge. If server does not upgrade,
+    /// client reconnects and falls back to prior knowledge. Default is false
+    pub h2c_upgrade: Option<bool>,
+    /// Transparently decode `gzip` and `deflate` response bodies, see `Response::decompress`.
+    /// Raw body is returned when not set. Default is false
+    #[cfg(feature = "gzip")]
+    pub decompress: Option<bool>,
+    /// Compress body of requests with `gzip` and set `content-encoding`,
+    /// unless request headers already have `content-encoding`.
+    /// Applies to all requests except requests known to have empty body
+    /// (e. g. `start_get` or `start_post` with empty body) and `connect_tunnel`.
+    /// Default is false
+    #[cfg(feature = "gzip")]
+    pub compress_requests: Option<bool>,
+    /// Reset stream with `CANCEL` and fail response with `Error::BodyTooLarge`
+    /// when server sends more than this many bytes of response body.
+    /// With `decompress` the limit also applies to decoded body.
+    /// Default is no limit
+    pub max_response_body_size: Option<u64>,
+    /// Client certificate presented to servers which request it.
+    /// Used by `Client::new`, connectors passed with `ClientTlsOption::Tls`
+    /// must be configured with `client_auth::connector_set_identity`
+    pub tls_identity: Option<TlsIdentity>,
+    /// Send body of request started with `Client::start_post_expect_continue`
+    /// if server sends neither `100 Continue` nor final response within this time
+    /// (RFC 7231, section 5.1.1). Default is `DEFAULT_EXPECT_CONTINUE_TIMEOUT_SECS`
+    pub expect_continue_timeout: Option<Duration>,
 
     pub common: CommonConf,
 }
@@ -3,33 +3,58 @@
 use std::result::Result as std_Result;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::io;
+use std::time::Duration;
+use std::collections::VecDeque;
+
+use bytes::Bytes;
 
 use error;
 use error::Error;
+use error::ErrorCode;
+use error::GoAwayInfo;
 use result;
 
 use solicit::StreamId;
+use solicit::MAX_WINDOW_SIZE;
+use solicit::session::StreamState;
 use solicit::header::*;
 use solicit::connection::EndStream;
+use solicit::frame::settings::HttpSetting;
+use solicit::frame::settings::MIN_MAX_FRAME_SIZE;
+use solicit::frame::settings::MAX_MAX_FRAME_SIZE;
+use solicit::frame::rst_stream::RstStreamFrame;
+use solicit::frame::RawFrame;
+use solicit::frame::AltSvcFrame;
 
 use service::Service;
+use resp::RequestHandle;
+use resp::StreamIdSlot;
+use resp::CancelOnDrop;
 
+use futures::Async;
 use futures::future;
 use futures::future::Future;
+use futures::future::Either;
 use futures::stream::Stream;
 use futures::sync::oneshot;
 use futures::sync::mpsc::unbounded;
 use futures::sync::mpsc::UnboundedSender;
+use futures::sync::mpsc::UnboundedReceiver;
 
 use native_tls::TlsConnector;
 
+#[cfg(feature = "tls-rustls")]
+use rustls::ClientConfig;
+
 use tokio_core::net::TcpStream;
 use tokio_core::reactor;
 use tokio_timer::Timer;
 use tokio_io::AsyncWrite;
 use tokio_io::AsyncRead;
 use tokio_tls::TlsConnectorExt;
+use tokio_tls::TlsStream;
 
 use futures_misc::*;
 
@@ -39,6 +64,13 @@ use common::*;
 use stream_part::*;
 use client_conf::*;
 use client_tls::*;
+use frame_observer::ObservedIo;
+use metrics::Metrics;
+use metrics::MeteredIo;
+use metrics::SharedMetrics;
+use h2c::client_upgrade;
+#[cfg(feature = "tls-rustls")]
+use rustls_tls;
 
 use rc_mut::*;
 
@@ -59,6 +91,16 @@ impl Types for ClientTypes {
 
 
 pub struct ClientStreamData {
+    // subsequent HEADERS frame is trailers
+    headers_received: bool,
+    // notified on `100 Continue`, dropped when final response is received
+    continue_tx: Option<oneshot::Sender<()>>,
+    // receives interim (1xx) responses, dropped when final response is received
+    interim_tx: Option<UnboundedSender<Headers>>,
+    // response to `HEAD` request has no body whatever `content-length` is
+    head_request: bool,
+    // dropped when stream is removed, cancelling timers of the stream
+    timers_cancel: Vec<oneshot::Sender<()>>,
 }
 
 impl HttpStreamDataSpecific for ClientStreamData {
@@ -72,6 +114,13 @@ impl HttpStream for ClientStream {
 
 pub struct ClientConnData {
     callbacks: Box<ClientConnectionCallbacks>,
+    /// Requests waiting for server SETTINGS_MAX_CONCURRENT_STREAMS slot
+    pending_requests: VecDeque<StartRequestMessage>,
+    /// `ClientConf::max_response_body_size`
+    max_response_body_size: Option<u64>,
+    /// `ClientConf::expect_continue_timeout`
+    expect_continue_timeout: Duration,
+    disconnect: Arc<Mutex<DisconnectState>>,
 }
 
 impl ConnDataSpecific for ClientConnData {
@@ -80,11 +129,234 @@ impl ConnDataSpecific for ClientConnData {
 type ClientInner = ConnData<ClientTypes>;
 
 impl ClientInner {
+    /// Caller must check stream ids are not exhausted
     fn insert_stream(&mut self, stream: ClientStream) -> StreamId {
-        let id = self.next_local_stream_id();
+        let id = self.local_stream_ids.allocate().expect("stream ids exhausted");
         self.streams.insert(id, stream);
         id
     }
+
+    /// Stream 1 is used by HTTP/1.1 request sent with `Upgrade: h2c`,
+    /// response to it is ignored
+    fn h2c_upgraded(&mut self) {
+        let (latch_ctr, _latch) = latch();
+        let (resp_tx, _resp_rx) = unbounded();
+
+        let mut stream = HttpStreamCommon::new(
+            self.conn.our_settings.initial_window_size,
+            self.conn.peer_settings.initial_window_size,
+            resp_tx,
+            latch_ctr,
+            ClientStreamData {
+                headers_received: false,
+                continue_tx: None,
+                interim_tx: None,
+                head_request: false,
+                timers_cancel: Vec::new(),
+            });
+        stream.state = StreamState::HalfClosedLocal;
+
+        let stream_id = self.insert_stream(stream);
+        assert_eq!(1, stream_id);
+    }
+
+    /// Server SETTINGS_MAX_CONCURRENT_STREAMS allows opening another stream
+    fn can_start_request(&self) -> bool {
+        self.streams.local_count() < self.conn.peer_settings.max_concurrent_streams as usize
+    }
+
+    fn start_request(&mut self, self_rc: RcMut<Self>, start: StartRequestMessage) -> StreamId {
+        let StartRequestMessage {
+            headers, body, priority, timeout, resp_tx, cancel_rx, continue_tx, interim_tx, stream_id: stream_id_slot,
+        } = start;
+
+        let (latch_ctr, latch) = latch();
+
+        // TODO
+        latch_ctr.open();
+
+        let head_request = headers.method() == Some("HEAD");
+        let expect_continue = continue_tx.is_some();
+
+        let mut stream = HttpStreamCommon::new(
+            self.conn.our_settings.initial_window_size,
+            self.conn.peer_settings.initial_window_size,
+            resp_tx,
+            latch_ctr,
+            ClientStreamData {
+                headers_received: false,
+                continue_tx: continue_tx,
+                interim_tx: interim_tx,
+                head_request: head_request,
+                timers_cancel: Vec::new(),
+            });
+
+        stream.priority = priority;
+        stream.max_body_size = self.specific.max_response_body_size;
+        stream.outgoing.push_back(HttpStreamPartContent::Headers(headers));
+
+        let stream_id = self.insert_stream(stream);
+        stream_id_slot.set(stream_id);
+
+        if let Some(timeout) = timeout {
+            self.set_request_timeout(self_rc.clone(), stream_id, timeout);
+        }
+
+        if expect_continue {
+            self.set_expect_continue_timeout(self_rc.clone(), stream_id);
+        }
+
+        self.cancel_on_response_drop(self_rc.clone(), stream_id, cancel_rx);
+
+        self.pump_stream_to_write_loop(self_rc, stream_id, body, latch, CancelToken::never());
+
+        if self.local_stream_ids.is_exhausted() {
+            self.stream_ids_exhausted();
+        }
+
+        stream_id
+    }
+
+    /// Last stream id is allocated: tell server no more streams will be opened
+    /// and let client retry queued requests on new connection
+    fn stream_ids_exhausted(&mut self) {
+        info!("stream ids exhausted, last stream id {}", self.local_stream_ids.last());
+
+        // connection is closed when remaining streams complete
+        let (tx, _rx) = oneshot::channel();
+        self.send_goaway(ErrorCode::NoError, Bytes::new(), tx).ok();
+
+        self.specific.callbacks.stream_ids_exhausted();
+
+        for start in self.specific.pending_requests.drain(..) {
+            self.specific.callbacks.retry_request(start);
+        }
+    }
+
+    /// Start queued requests while server limit allows, return true if any started
+    fn start_pending_requests(&mut self, self_rc: RcMut<Self>) -> bool {
+        let mut started = false;
+        while self.can_start_request() {
+            match self.specific.pending_requests.pop_front() {
+                Some(start) => {
+                    let stream_id = self.start_request(self_rc.clone(), start);
+                    debug!("started queued request in stream {}", stream_id);
+                    started = true;
+                }
+                None => break,
+            }
+        }
+        started
+    }
+
+    /// Fail request with timeout error and reset the stream with `CANCEL`
+    fn request_timed_out(&mut self, stream_id: StreamId) {
+        debug!("request timed out, resetting stream {}", stream_id);
+        self.reset_request(stream_id, Error::RequestTimeout, ErrorCode::Cancel);
+    }
+
+    /// Server did not respond to `Expect: 100-continue` in time, send the body anyway
+    fn expect_continue_timed_out(&mut self, stream_id: StreamId) {
+        if let Some(mut stream) = self.streams.get_mut(stream_id) {
+            if let Some(continue_tx) = stream.stream().specific.continue_tx.take() {
+                debug!("no 100 Continue in stream {}, sending request body", stream_id);
+                continue_tx.send(()).ok();
+            }
+        }
+    }
+
+    fn request_canceled(&mut self, stream_id: StreamId, error_code: ErrorCode) {
+        debug!("request canceled, resetting stream {} with {:?}", stream_id, error_code);
+        self.reset_request(stream_id, Error::Canceled(error_code), error_code);
+    }
+
+    /// Fail the response with given error and reset the stream
+    fn reset_request(&mut self, stream_id: StreamId, error: Error, error_code: ErrorCode) {
+        match self.streams.get_mut(stream_id) {
+            Some(mut stream) => {
+                if let Some(response_handler) = stream.stream().peer_tx.take() {
+                    // it is OK to ignore error: handler may be already dead
+                    drop(response_handler.send(ResultOrEof::Error(error)));
+                }
+                stream.rst_remove(error_code);
+            }
+            None => return,
+        }
+
+        if let Err(e) = self.send_frame(RstStreamFrame::new(stream_id, error_code)) {
+            warn!("failed to send RST_STREAM: {:?}", e);
+        }
+
+        self.streams_updated();
+    }
+
+    /// Reset the stream with `CANCEL` because caller is no longer interested in response
+    fn request_dropped(&mut self, stream_id: StreamId) {
+        match self.streams.get_mut(stream_id) {
+            Some(stream) => stream.rst_remove(ErrorCode::Cancel),
+            None => return,
+        }
+
+        debug!("response dropped, resetting stream {}", stream_id);
+
+        if let Err(e) = self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::Cancel)) {
+            warn!("failed to send RST_STREAM: {:?}", e);
+        }
+
+        self.streams_updated();
+    }
+
+    fn cancel_on_response_drop(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, cancel_rx: oneshot::Receiver<ErrorCode>) {
+        // receiver completes with error code on `RequestHandle::cancel`
+        // or with error when response is dropped
+        self.loop_handle.spawn(cancel_rx
+            .then(move |r| {
+                match r {
+                    Ok(error_code) => self_rc.with(|inner| inner.request_canceled(stream_id, error_code)),
+                    Err(oneshot::Canceled) => self_rc.with(|inner| inner.request_dropped(stream_id)),
+                }
+                Ok(())
+            }));
+    }
+
+    /// Call `timed_out` after `timeout` unless stream is removed before
+    fn set_stream_timer<F>(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, timeout: Duration, timed_out: F)
+        where F : FnOnce(&mut Self) + 'static
+    {
+        let timeout = match reactor::Timeout::new(timeout, &self.loop_handle) {
+            Ok(timeout) => timeout,
+            Err(e) => {
+                warn!("failed to create timeout: {:?}", e);
+                return;
+            }
+        };
+
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        match self.streams.get_mut(stream_id) {
+            Some(mut stream) => stream.stream().specific.timers_cancel.push(cancel_tx),
+            None => return,
+        }
+
+        self.loop_handle.spawn(timeout.select2(cancel_rx)
+            .then(move |r| {
+                match r {
+                    Ok(Either::A(((), _))) => self_rc.with(timed_out),
+                    Err(Either::A((e, _))) => warn!("timeout error: {:?}", e),
+                    // stream is removed
+                    Ok(Either::B(..)) | Err(Either::B(..)) => {}
+                }
+                Ok(())
+            }));
+    }
+
+    fn set_request_timeout(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, timeout: Duration) {
+        self.set_stream_timer(self_rc, stream_id, timeout, move |inner| inner.request_timed_out(stream_id));
+    }
+
+    fn set_expect_continue_timeout(&mut self, self_rc: RcMut<Self>, stream_id: StreamId) {
+        let timeout = self.specific.expect_continue_timeout;
+        self.set_stream_timer(self_rc, stream_id, timeout, move |inner| inner.expect_continue_timed_out(stream_id));
+    }
 }
 
 impl ConnInner for ClientInner {
@@ -93,10 +365,104 @@ impl ConnInner for ClientInner {
     fn process_headers(&mut self, _self_rc: RcMut<Self>, stream_id: StreamId, end_stream: EndStream, headers: Headers)
         -> result::Result<Option<HttpStreamRef<ClientTypes>>>
     {
+        let mut informational = false;
+        let malformed = match self.streams.get_mut(stream_id) {
+            Some(mut stream) => {
+                let stream = stream.stream();
+                if !stream.specific.headers_received {
+                    let status = headers.status_code().ok();
+                    informational = status.map(|s| s.is_informational()).unwrap_or(false);
+                    let mut invalid_content_length = false;
+                    if !informational {
+                        stream.specific.headers_received = true;
+                        // final response without `100 Continue`, request body is not sent
+                        stream.specific.continue_tx.take();
+                        stream.specific.interim_tx.take();
+                        // 8.1.2.6: responses to `HEAD`, `204` and `304` have no body
+                        let no_body = stream.specific.head_request
+                            || status.map(|s| s.as_u16() == 204 || s.as_u16() == 304).unwrap_or(false);
+                        if !no_body {
+                            match headers.content_length() {
+                                Ok(content_length) => stream.content_length = content_length,
+                                Err(_) => invalid_content_length = true,
+                            }
+                        }
+                    }
+                    // 8.1.2.1: pseudo-headers must be known, unique and precede regular headers
+                    if let Err(e) = headers.validate(RequestOrResponse::Response) {
+                        warn!("malformed response headers in stream {}: {:?}", stream_id, e);
+                        true
+                    } else if invalid_content_length {
+                        // 8.1.2.6
+                        warn!("invalid content-length in response in stream {}", stream_id);
+                        true
+                    } else if informational {
+                        // 8.1: interim response cannot end the stream,
+                        // 8.1.1: 101 is not supported in HTTP/2
+                        let malformed = end_stream == EndStream::Yes
+                            || status.map(|s| s.as_u16()) == Some(101);
+                        if malformed {
+                            warn!("malformed informational response in stream {}", stream_id);
+                        } else {
+                            if status.map(|s| s.as_u16()) == Some(100) {
+                                if let Some(continue_tx) = stream.specific.continue_tx.take() {
+                                    continue_tx.send(()).ok();
+                                }
+                            }
+                            if let Some(ref interim_tx) = stream.specific.interim_tx {
+                                // caller may be not interested in interim responses
+                                interim_tx.send(headers.clone()).ok();
+                            }
+                        }
+                        malformed
+                    } else if stream.content_length_mismatch(end_stream == EndStream::Yes) {
+                        warn!("response without body in stream {} declares content-length {:?}",
+                            stream_id, stream.content_length);
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    // 8.1: trailers must end the stream and must not contain pseudo-headers
+                    let malformed = end_stream != EndStream::Yes || headers.contains_pseudo_headers();
+                    if malformed {
+                        warn!("malformed trailers in stream {}", stream_id);
+                        true
+                    } else if stream.content_length_mismatch(true) {
+                        warn!("body of stream {} does not match content-length {:?}, received {}",
+                            stream_id, stream.content_length, stream.data_received);
+                        true
+                    } else {
+                        false
+                    }
+                }
+            }
+            None => false,
+        };
+
+        if malformed {
+            self.streams.get_mut(stream_id).unwrap().rst_remove(ErrorCode::ProtocolError);
+            self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::ProtocolError))?;
+            return Ok(None);
+        }
+
+        if informational {
+            debug!("informational response in stream {}", stream_id);
+            return Ok(None);
+        }
+
+        if self.streams.get_mut(stream_id).is_none() && self.streams.is_recently_closed(stream_id) {
+            // e. g. response HEADERS sent before server received RST_STREAM
+            debug!("ignoring HEADERS for recently closed stream {}", stream_id);
+            return Ok(None);
+        }
+
         let mut stream: HttpStreamRef<ClientTypes> = match self.streams.get_mut(stream_id) {
             None => {
-                // TODO: send stream closed
-                return Err(error::Error::Other("??"));
+                // 5.1.1: server cannot initiate streams with HEADERS,
+                // pushed streams are created by PUSH_PROMISE
+                warn!("HEADERS for stream {} which was not opened", stream_id);
+                return Err(error::Error::CodeError(ErrorCode::ProtocolError));
             }
             Some(stream) => stream,
         };
@@ -115,14 +481,164 @@ impl ConnInner for ClientInner {
         Ok(Some(stream))
     }
 
-    fn goaway_received(&mut self, stream_id: StreamId, raw_error_code: u32) {
-        self.specific.callbacks.goaway(stream_id, raw_error_code);
+    fn process_push_promise(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, promised_stream_id: StreamId, headers: Headers)
+        -> result::Result<()>
+    {
+        // 8.2: push must be enabled, and promised stream must be a new server-initiated stream
+        if !self.conn.our_settings.enable_push {
+            return Err(error::Error::CodeError(ErrorCode::ProtocolError));
+        }
+        if promised_stream_id % 2 != 0 || promised_stream_id <= self.last_peer_stream_id {
+            return Err(error::Error::CodeError(ErrorCode::ProtocolError));
+        }
+        self.last_peer_stream_id = promised_stream_id;
+
+        if self.streams.get_mut(stream_id).is_none() {
+            debug!("PUSH_PROMISE on closed stream {}, refusing {}", stream_id, promised_stream_id);
+            self.send_frame(RstStreamFrame::new(promised_stream_id, ErrorCode::Cancel))?;
+            return Ok(());
+        }
+
+        if let Err(e) = headers.validate(RequestOrResponse::Request) {
+            warn!("malformed promised request headers in stream {}: {:?}", promised_stream_id, e);
+            self.send_frame(RstStreamFrame::new(promised_stream_id, ErrorCode::ProtocolError))?;
+            return Ok(());
+        }
+
+        let (resp_tx, resp_rx) = unbounded();
+        let handle = RequestHandle::default();
+        let (cancel_guard, cancel_rx) = handle.cancel_channel();
+
+        let (latch_ctr, _latch) = latch();
+
+        let head_request = headers.method() == Some("HEAD");
+
+        let mut stream = HttpStreamCommon::new(
+            self.conn.our_settings.initial_window_size,
+            self.conn.peer_settings.initial_window_size,
+            resp_tx,
+            latch_ctr,
+            ClientStreamData {
+                headers_received: false,
+                continue_tx: None,
+                interim_tx: None,
+                head_request: head_request,
+                timers_cancel: Vec::new(),
+            });
+
+        // reserved (remote), nothing is ever sent by client on promised stream
+        stream.state = StreamState::HalfClosedLocal;
+        stream.max_body_size = self.specific.max_response_body_size;
+
+        self.streams.insert(promised_stream_id, stream);
+
+        self.cancel_on_response_drop(self_rc, promised_stream_id, cancel_rx);
+
+        debug!("stream {} promised in stream {}", promised_stream_id, stream_id);
+
+        handle.stream_id_slot().set(promised_stream_id);
+        let response = response_from_channel(resp_rx, cancel_guard).with_request_handle(handle);
+
+        self.specific.callbacks.push(headers, response);
+
+        Ok(())
+    }
+
+    fn goaway_received(&mut self, info: GoAwayInfo) {
+        self.specific.disconnect.lock().unwrap().goaway = Some(info.clone());
+        self.specific.callbacks.goaway(info);
+    }
+
+    fn origin_received(&mut self, origins: Vec<String>) {
+        self.specific.callbacks.origin(origins);
+    }
+
+    fn alt_svc_received(&mut self, frame: AltSvcFrame) {
+        self.specific.callbacks.alt_svc(AltSvc {
+            stream_id: frame.stream_id,
+            origin: String::from_utf8_lossy(&frame.origin).into_owned(),
+            field_value: String::from_utf8_lossy(&frame.field_value).into_owned(),
+        });
+    }
+
+    fn streams_updated(&mut self) {
+        if !self.specific.pending_requests.is_empty() && self.can_start_request() {
+            // ignore error, write loop may be already dead
+            drop(self.to_write_tx.send(ClientToWriteMessage::StartPending));
+        }
+    }
+
+    fn process_headers_too_large(&mut self, _self_rc: RcMut<Self>, stream_id: StreamId, _end_stream: EndStream)
+        -> result::Result<Option<HttpStreamRef<ClientTypes>>>
+    {
+        // server cannot open streams with HEADERS
+        self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::Cancel))?;
+        Ok(None)
     }
+
+    fn stream_reset_by_peer(&mut self, _stream_id: StreamId) -> result::Result<()> {
+        Ok(())
+    }
+}
+
+/// Alternative service advertised by server with ALTSVC frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct AltSvc {
+    /// Stream frame was received on, `0` if frame is not associated with a stream
+    pub stream_id: StreamId,
+    /// Origin alternative service applies to,
+    /// empty if frame is associated with a stream, then origin of the request is implied
+    pub origin: String,
+    /// Same format as `Alt-Svc` header field value
+    pub field_value: String,
 }
 
 pub struct ClientConnection {
     write_tx: UnboundedSender<ClientToWriteMessage>,
     command_tx: UnboundedSender<ClientCommandMessage>,
+    metrics: SharedMetrics,
+    disconnect: Arc<Mutex<DisconnectState>>,
+}
+
+/// Why connection closed, filled when connection task completes
+#[derive(Default)]
+struct DisconnectState {
+    /// Last GOAWAY received from server
+    goaway: Option<GoAwayInfo>,
+    /// `Error::ConnectionError` or `Error::ConnectionDied` passed to waiters
+    closed: Option<Error>,
+    waiters: Vec<oneshot::Sender<Error>>,
+}
+
+impl DisconnectState {
+    fn add_waiter(&mut self, tx: oneshot::Sender<Error>) {
+        match self.closed {
+            // ignore error
+            Some(ref error) => drop(tx.send(error.clone_conn_error())),
+            None => self.waiters.push(tx),
+        }
+    }
+
+    /// Record result of connection task, connection error is returned
+    /// as `Error::ConnectionError` or `Error::ConnectionDied`
+    fn closed(&mut self, result: result::Result<()>) -> result::Result<()> {
+        let (error, result) = match (&self.goaway, result) {
+            (&Some(ref goaway), result) =>
+                (Error::ConnectionError(goaway.error_code, goaway.debug_data.clone()), result),
+            (&None, Ok(())) => (Error::ConnectionError(ErrorCode::NoError, Bytes::new()), Ok(())),
+            (&None, Err(e)) => {
+                let e = e.into_conn_error();
+                (e.clone_conn_error(), Err(e))
+            }
+        };
+
+        for tx in self.waiters.drain(..) {
+            // ignore error
+            drop(tx.send(error.clone_conn_error()));
+        }
+        self.closed = Some(error);
+        result
+    }
 }
 
 unsafe impl Sync for ClientConnection {}
@@ -130,11 +646,26 @@ unsafe impl Sync for ClientConnection {}
 pub struct StartRequestMessage {
     pub headers: Headers,
     pub body: HttpPartStream,
+    pub priority: Option<StreamPriority>,
+    /// Stream is reset and request fails with `RequestTimeout` if not completed in time
+    pub timeout: Option<Duration>,
     pub resp_tx: UnboundedSender<ResultOrEof<HttpStreamPart, Error>>,
+    /// Resolves with error when response is dropped by caller,
+    /// stream is reset with `CANCEL` then
+    pub cancel_rx: oneshot::Receiver<ErrorCode>,
+    /// Notified when `100 Continue` is received,
+    /// dropped if final response is received first
+    pub continue_tx: Option<oneshot::Sender<()>>,
+    /// Receives interim (1xx) responses preceding final response
+    pub interim_tx: Option<UnboundedSender<Headers>>,
+    /// Set to id of request stream when it is opened
+    pub stream_id: StreamIdSlot,
 }
 
 enum ClientToWriteMessage {
     Start(StartRequestMessage),
+    /// Start requests queued because of server SETTINGS_MAX_CONCURRENT_STREAMS
+    StartPending,
     Common(CommonToWriteMessage),
 }
 
@@ -147,71 +678,195 @@ impl From<CommonToWriteMessage> for ClientToWriteMessage {
 enum ClientCommandMessage {
     DumpState(oneshot::Sender<ConnectionStateSnapshot>),
     WaitForHandshake(oneshot::Sender<result::Result<()>>),
+    Ping(oneshot::Sender<Duration>),
+    SendRawFrame(RawFrame, oneshot::Sender<()>),
+    FastForwardStreamIds(StreamId, oneshot::Sender<result::Result<()>>),
+    PeerSettings(oneshot::Sender<PeerSettings>),
 }
 
 
 impl<I : AsyncWrite + Send + 'static> ClientWriteLoop<I> {
     fn process_start(self, start: StartRequestMessage) -> HttpFuture<Self> {
-        let StartRequestMessage { headers, body, resp_tx } = start;
-
         let inner_rc = self.inner.clone();
 
         let stream_id = self.inner.with(move |inner: &mut ClientInner| {
+            // 5.1.1: request was sent to connection before it ran out of stream ids
+            if inner.local_stream_ids.is_exhausted() {
+                inner.specific.callbacks.retry_request(start);
+                return None;
+            }
 
-            let (latch_ctr, latch) = latch();
-
-            // TODO
-            latch_ctr.open();
-
-            let mut stream = HttpStreamCommon::new(
-                inner.conn.peer_settings.initial_window_size,
-                resp_tx,
-                latch_ctr,
-                ClientStreamData { });
-
-            stream.outgoing.push_back(HttpStreamPartContent::Headers(headers));
+            // 5.1.2: queue request until server allows another stream, preserving order
+            if !inner.specific.pending_requests.is_empty() || !inner.can_start_request() {
+                debug!("too many concurrent streams, queueing request");
+                inner.specific.pending_requests.push_back(start);
+                return None;
+            }
 
-            let stream_id = inner.insert_stream(stream);
+            Some(inner.start_request(inner_rc, start))
+        });
 
-            inner.pump_stream_to_write_loop(inner_rc, stream_id, body, latch);
+        match stream_id {
+            Some(stream_id) => self.send_outg_stream(stream_id),
+            None => Box::new(future::finished(self)),
+        }
+    }
 
-            stream_id
-        });
+    fn process_start_pending(self) -> HttpFuture<Self> {
+        let inner_rc = self.inner.clone();
 
-        self.send_outg_stream(stream_id)
+        if self.inner.with(move |inner: &mut ClientInner| inner.start_pending_requests(inner_rc)) {
+            self.send_outg_conn()
+        } else {
+            Box::new(future::finished(self))
+        }
     }
 
     fn process_message(self, message: ClientToWriteMessage) -> HttpFuture<Self> {
         match message {
             ClientToWriteMessage::Start(start) => self.process_start(start),
+            ClientToWriteMessage::StartPending => self.process_start_pending(),
             ClientToWriteMessage::Common(common) => self.process_common(common),
         }
     }
 
     pub fn run(self, requests: HttpFutureStreamSend<ClientToWriteMessage>) -> HttpFuture<()> {
         let requests = requests.map_err(Error::from);
-        Box::new(requests
-            .fold(self, move |wl, message: ClientToWriteMessage| {
-                wl.process_message(message)
-            })
-            .map(|_| ()))
+        self.run_messages(requests, Self::process_message)
     }
 }
 
 type ClientReadLoop<I> = ReadLoopData<I, ClientTypes>;
 type ClientWriteLoop<I> = WriteLoopData<I, ClientTypes>;
 type ClientCommandLoop = CommandLoopData<ClientTypes>;
+type ClientKeepaliveLoop = KeepaliveLoopData<ClientTypes>;
 
 
 pub trait ClientConnectionCallbacks : 'static {
     // called at most once
-    fn goaway(&self, stream_id: StreamId, raw_error_code: u32);
+    fn goaway(&self, info: GoAwayInfo);
+
+    /// Called on PUSH_PROMISE with promised request headers and pushed response
+    fn push(&self, promised_request: Headers, response: Response);
+
+    /// Called on ORIGIN frame with origins to be added to the origin set of the connection
+    fn origin(&self, origins: Vec<String>);
+
+    /// Called on ALTSVC frame
+    fn alt_svc(&self, alt_svc: AltSvc);
+
+    /// Called at most once when connection allocated the last stream id,
+    /// new requests must be started on another connection
+    fn stream_ids_exhausted(&self);
+
+    /// Called with request connection cannot start because stream ids are exhausted
+    fn retry_request(&self, start: StartRequestMessage);
+
+    /// Called with request queued before connection failed to establish,
+    /// request was not sent to server, so it can be started on another connection
+    fn request_not_sent(&self, start: StartRequestMessage, error: Error);
+}
+
+/// Pass requests queued while connecting to `request_not_sent`,
+/// returns connection error
+fn fail_unsent_requests<C : ClientConnectionCallbacks>(
+    mut to_write_rx: UnboundedReceiver<ClientToWriteMessage>,
+    callbacks: &C,
+    error: Error)
+        -> Error
+{
+    let error = error.into_conn_error();
+    // closed receiver returns remaining messages and then EOF
+    to_write_rx.close();
+    while let Ok(Async::Ready(Some(message))) = to_write_rx.poll() {
+        if let ClientToWriteMessage::Start(start) = message {
+            callbacks.request_not_sent(start, error.clone_conn_error());
+        }
+    }
+    error
+}
+
+/// Response reading parts sent to `resp_tx` by connection,
+/// `cancel_guard` is dropped with response to reset the stream
+fn response_from_channel(
+    resp_rx: UnboundedReceiver<ResultOrEof<HttpStreamPart, Error>>,
+    cancel_guard: CancelOnDrop)
+        -> Response
+{
+    let resp_rx = resp_rx.map_err(|()| Error::from(io::Error::new(io::ErrorKind::Other, "req")));
+
+    let resp_rx = stream_with_eof_and_error(resp_rx, || error::Error::Other("client is likely died"));
+
+    let resp_rx = stream_with_guard(resp_rx, cancel_guard);
+
+    Response::from_stream(resp_rx)
+}
+
+
+/// Settings sent to server in addition to default
+pub(crate) fn settings(conf: &ClientConf) -> result::Result<Vec<HttpSetting>> {
+    let mut settings = Vec::new();
+    settings.push(HttpSetting::EnablePush(conf.enable_push.unwrap_or(false)));
+    if let Some(initial_window_size) = conf.initial_window_size {
+        // 6.5.2: values above the maximum flow-control window size are not allowed
+        if initial_window_size > MAX_WINDOW_SIZE {
+            return Err(Error::InvalidSetting(HttpSetting::InitialWindowSize(initial_window_size)));
+        }
+        settings.push(HttpSetting::InitialWindowSize(initial_window_size));
+    }
+    if let Some(header_table_size) = conf.common.header_table_size {
+        settings.push(HttpSetting::HeaderTableSize(header_table_size));
+    }
+    if let Some(max_concurrent_streams) = conf.common.max_concurrent_streams {
+        settings.push(HttpSetting::MaxConcurrentStreams(max_concurrent_streams));
+    }
+    if let Some(max_header_list_size) = conf.common.max_header_list_size {
+        settings.push(HttpSetting::MaxHeaderListSize(max_header_list_size));
+    }
+    if let Some(max_frame_size) = conf.common.max_frame_size {
+        // 6.5.2: values outside of this range are not allowed
+        if max_frame_size < MIN_MAX_FRAME_SIZE || max_frame_size > MAX_MAX_FRAME_SIZE {
+            return Err(Error::InvalidSetting(HttpSetting::MaxFrameSize(max_frame_size)));
+        }
+        settings.push(HttpSetting::MaxFrameSize(max_frame_size));
+    }
+    conf.common.add_extra_settings(&mut settings)?;
+    Ok(settings)
 }
 
+/// Connect TCP socket with given TCP_NODELAY and perform TLS handshake
+fn connect_tls(
+    lh: &reactor::Handle,
+    addr: &SocketAddr,
+    domain: &str,
+    connector: Arc<TlsConnector>,
+    no_delay: bool)
+        -> HttpFuture<TlsStream<TcpStream>>
+{
+    let domain = domain.to_owned();
+    let addr = addr.clone();
+
+    let connect = TcpStream::connect(&addr, lh)
+        .map(move |c| {
+            info!("connected to {}", addr);
+            c.set_nodelay(no_delay).expect("failed to set TCP_NODELAY");
+            c
+        })
+        .map_err(|e| e.into());
+
+    let tls_conn = connect.and_then(move |conn| {
+        connector.connect_async(&domain, conn).map_err(|e| {
+            Error::IoError(io::Error::new(io::ErrorKind::Other, e))
+        })
+    });
+
+    Box::new(tls_conn.map_err(Error::from))
+}
 
 impl ClientConnection {
+    /// `connect` resolves to connection and flag whether it was upgraded with `Upgrade: h2c`
     fn connected<I, C>(
-        lh: reactor::Handle, connect: HttpFutureSend<I>,
+        lh: reactor::Handle, connect: HttpFuture<(I, bool)>,
         conf: ClientConf,
         callbacks: C)
             -> (Self, HttpFuture<()>)
@@ -222,17 +877,40 @@ impl ClientConnection {
         let (to_write_tx, to_write_rx) = unbounded();
         let (command_tx, command_rx) = unbounded();
 
-        let to_write_rx = Box::new(to_write_rx.map_err(|()| Error::IoError(io::Error::new(io::ErrorKind::Other, "to_write"))));
         let command_rx = Box::new(command_rx.map_err(|()| Error::IoError(io::Error::new(io::ErrorKind::Other, "to_write"))));
 
+        let metrics = SharedMetrics::default();
+        let disconnect = Arc::new(Mutex::new(DisconnectState::default()));
+
         let c = ClientConnection {
             write_tx: to_write_tx.clone(),
             command_tx: command_tx,
+            metrics: metrics.clone(),
+            disconnect: disconnect.clone(),
+        };
+
+        let handshake = {
+            let frame_observer = conf.common.frame_observer.clone();
+            let metrics = metrics.clone();
+            future::result(settings(&conf))
+                .and_then(move |settings| {
+                    connect.map(move |(conn, upgraded)| {
+                        (ObservedIo::client(MeteredIo::client(conn, metrics), frame_observer), upgraded, settings)
+                    })
+                })
+                .and_then(move |(conn, upgraded, settings)| {
+                    client_handshake(conn, settings.clone()).map(move |conn| (conn, upgraded, settings))
+                })
         };
 
-        let handshake = connect.and_then(client_handshake);
+        let disconnect_for_conn = disconnect.clone();
+
+        let future = handshake.then(move |r| -> HttpFuture<()> {
+            let (conn, upgraded, settings) = match r {
+                Ok(r) => r,
+                Err(e) => return Box::new(future::err(fail_unsent_requests(to_write_rx, &callbacks, e))),
+            };
 
-        let future = handshake.and_then(move |conn| {
             debug!("handshake done");
             let (read, write) = conn.split();
 
@@ -240,17 +918,42 @@ impl ClientConnection {
                 lh,
                 ClientConnData {
                     callbacks: Box::new(callbacks),
+                    pending_requests: VecDeque::new(),
+                    max_response_body_size: conf.max_response_body_size,
+                    expect_continue_timeout: conf.expect_continue_timeout
+                        .unwrap_or(Duration::from_secs(DEFAULT_EXPECT_CONTINUE_TIMEOUT_SECS)),
+                    disconnect: disconnect_for_conn,
                 },
                 conf.common,
+                &settings,
+                metrics,
                 to_write_tx.clone()));
 
-            let run_write = ClientWriteLoop { write: write, inner: inner.clone() }.run(to_write_rx);
-            let run_read = ClientReadLoop { read: read, inner: inner.clone() }.run();
+            if upgraded {
+                inner.with(|inner| inner.h2c_upgraded());
+            }
+
+            let inner_rc = inner.clone();
+            inner.with(move |inner| inner.start_settings_ack_timer(inner_rc));
+
+            let to_write_rx = Box::new(to_write_rx.map_err(|()| Error::IoError(io::Error::new(io::ErrorKind::Other, "to_write"))));
+            let run_write = ClientWriteLoop::new(write, inner.clone()).run(to_write_rx);
+            let run_read = ClientReadLoop { read: PooledRead::new(read), inner: inner.clone() }.run();
             let run_command = ClientCommandLoop { inner: inner.clone() }.run(command_rx);
 
-            run_write.join(run_read).join(run_command).map(|_| ())
+            let run_keepalive: HttpFuture<()> = match conf.keepalive_interval {
+                Some(interval) => {
+                    let timeout = conf.keepalive_timeout.unwrap_or(interval);
+                    ClientKeepaliveLoop { inner: inner.clone() }.run(interval, timeout)
+                }
+                None => Box::new(future::empty()),
+            };
+
+            Box::new(run_write.join(run_read).join(run_command).join(run_keepalive).map(|_| ()))
         });
 
+        let future = future.then(move |r| disconnect.lock().unwrap().closed(r));
+
         (c, Box::new(future))
     }
 
@@ -268,6 +971,9 @@ impl ClientConnection {
                 ClientConnection::new_plain(lh, addr, conf, callbacks),
             ClientTlsOption::Tls(domain, connector) =>
                 ClientConnection::new_tls(lh, &domain, connector, addr, conf, callbacks),
+            #[cfg(feature = "tls-rustls")]
+            ClientTlsOption::Rustls(domain, config) =>
+                ClientConnection::new_rustls(lh, &domain, config, addr, conf, callbacks),
         }
     }
 
@@ -298,6 +1004,28 @@ impl ClientConnection {
             connect.map(map_callback).boxed()
         };
 
+        let connect: HttpFuture<(TcpStream, bool)> = if conf.h2c_upgrade.unwrap_or(false) {
+            let settings = settings(&conf);
+            let lh = lh.clone();
+            Box::new(future::result(settings)
+                .and_then(move |settings| connect.map(move |socket| (socket, settings)))
+                .and_then(move |(socket, settings)| client_upgrade(socket, &addr.to_string(), &settings))
+                .and_then(move |socket| -> HttpFuture<(TcpStream, bool)> {
+                    match socket {
+                        Some(socket) => Box::new(future::ok((socket, true))),
+                        None => {
+                            info!("server refused h2c upgrade, reconnecting to {} with prior knowledge", addr);
+                            Box::new(TcpStream::connect(&addr, &lh)
+                                .map_err(Into::into)
+                                .map(map_callback)
+                                .map(|socket| (socket, false)))
+                        }
+                    }
+                }))
+        } else {
+            Box::new(connect.map(|socket| (socket, false)))
+        };
+
         ClientConnection::connected(lh, connect, conf, callbacks)
     }
 
@@ -310,21 +1038,42 @@ impl ClientConnection {
         callbacks: C)
             -> (Self, HttpFuture<()>)
         where C : ClientConnectionCallbacks
+    {
+        let no_delay = conf.no_delay.unwrap_or(true);
+        let tls_conn = connect_tls(&lh, addr, domain, connector, no_delay);
+
+        let tls_conn = tls_conn.map(|conn| (conn, false));
+
+        ClientConnection::connected(lh, Box::new(tls_conn), conf, callbacks)
+    }
+
+    /// Connect with rustls instead of native-tls
+    #[cfg(feature = "tls-rustls")]
+    pub fn new_rustls<C>(
+        lh: reactor::Handle,
+        domain: &str,
+        config: Arc<ClientConfig>,
+        addr: &SocketAddr,
+        conf: ClientConf,
+        callbacks: C)
+            -> (Self, HttpFuture<()>)
+        where C : ClientConnectionCallbacks
     {
         let domain = domain.to_owned();
         let addr = addr.clone();
 
+        let no_delay = conf.no_delay.unwrap_or(true);
         let connect = TcpStream::connect(&addr, &lh)
-            .map(move |c| { info!("connected to {}", addr); c })
-            .map_err(|e| e.into());
-
-        let tls_conn = connect.and_then(move |conn| {
-            connector.connect_async(&domain, conn).map_err(|e| {
-                Error::IoError(io::Error::new(io::ErrorKind::Other, e))
+            .map(move |c| {
+                info!("connected to {}", addr);
+                c.set_nodelay(no_delay).expect("failed to set TCP_NODELAY");
+                c
             })
-        });
+            .map_err(Error::from);
+
+        let tls_conn = connect.and_then(move |conn| rustls_tls::connect_h2(&config, &domain, conn));
 
-        let tls_conn = tls_conn.map_err(Error::from);
+        let tls_conn = tls_conn.map(|conn| (conn, false));
 
         ClientConnection::connected(lh, Box::new(tls_conn), conf, callbacks)
     }
@@ -359,6 +1108,90 @@ impl ClientConnection {
         Box::new(rx)
     }
 
+    /// Frame, byte and stream counters of this connection
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    #[doc(hidden)]
+    pub fn shared_metrics(&self) -> SharedMetrics {
+        self.metrics.clone()
+    }
+
+    pub fn ping_with_resp_sender(&self, tx: oneshot::Sender<Duration>) {
+        // ignore error
+        drop(self.command_tx.send(ClientCommandMessage::Ping(tx)));
+    }
+
+    /// Send PING and wait for ACK, resolves to round-trip time
+    pub fn ping(&self) -> HttpFutureSend<Duration> {
+        let (tx, rx) = oneshot::channel();
+
+        self.ping_with_resp_sender(tx);
+
+        let rx = rx.map_err(|_| Error::from(io::Error::new(io::ErrorKind::Other, "oneshot canceled")));
+
+        Box::new(rx)
+    }
+
+    pub fn peer_settings_with_resp_sender(&self, tx: oneshot::Sender<PeerSettings>) {
+        // ignore error
+        drop(self.command_tx.send(ClientCommandMessage::PeerSettings(tx)));
+    }
+
+    /// Settings server advertised so far
+    pub fn peer_settings(&self) -> HttpFutureSend<PeerSettings> {
+        let (tx, rx) = oneshot::channel();
+
+        self.peer_settings_with_resp_sender(tx);
+
+        let rx = rx.map_err(|_| Error::from(io::Error::new(io::ErrorKind::Other, "oneshot canceled")));
+
+        Box::new(rx)
+    }
+
+    /// Write arbitrary frame to this connection, for tests and experiments.
+    ///
+    /// Frame is written as is, after frames already queued.
+    /// Returned future is resolved when frame is written.
+    pub fn send_raw_frame(&self, frame: RawFrame) -> HttpFutureSend<()> {
+        let (tx, rx) = oneshot::channel();
+
+        // error is reported by canceled oneshot
+        drop(self.command_tx.send(ClientCommandMessage::SendRawFrame(frame, tx)));
+
+        let rx = rx.map_err(|_| Error::Other("connection closed"));
+
+        Box::new(rx)
+    }
+
+    /// For tests: skip stream ids so that `next_id` is allocated to the next request
+    pub(crate) fn fast_forward_stream_ids_with_resp_sender(
+        &self, next_id: StreamId, tx: oneshot::Sender<result::Result<()>>)
+    {
+        // error is reported by canceled oneshot
+        drop(self.command_tx.send(ClientCommandMessage::FastForwardStreamIds(next_id, tx)));
+    }
+
+    pub fn wait_for_disconnect_with_resp_sender(&self, tx: oneshot::Sender<Error>) {
+        self.disconnect.lock().unwrap().add_waiter(tx);
+    }
+
+    /// Resolves when connection is closed with `Error::ConnectionError`
+    /// with error code and debug data of GOAWAY if server sent one,
+    /// or with error code of GOAWAY sent by client because of protocol error.
+    /// Otherwise resolves with `Error::ConnectionDied` with error which terminated the connection,
+    /// e. g. I/O error.
+    pub fn wait_for_disconnect(&self) -> HttpFutureSend<Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.wait_for_disconnect_with_resp_sender(tx);
+
+        let rx = rx.map_err(|_| Error::from(io::Error::new(io::ErrorKind::Other, "oneshot canceled")));
+
+        Box::new(rx)
+    }
+
     pub fn wait_for_connect_with_resp_sender(&self, tx: oneshot::Sender<result::Result<()>>)
         -> std_Result<(), oneshot::Sender<result::Result<()>>>
     {
@@ -380,22 +1213,26 @@ impl Service for ClientConnection {
             -> Response
     {
         let (resp_tx, resp_rx) = unbounded();
+        let handle = RequestHandle::default();
+        let (cancel_guard, cancel_rx) = handle.cancel_channel();
 
         let start = StartRequestMessage {
             headers: headers,
             body: body,
+            priority: None,
+            timeout: None,
             resp_tx: resp_tx,
+            cancel_rx: cancel_rx,
+            continue_tx: None,
+            interim_tx: None,
+            stream_id: handle.stream_id_slot(),
         };
 
         if let Err(_) = self.start_request_with_resp_sender(start) {
             return Response::err(error::Error::Other("client died"));
         }
 
-        let req_rx = resp_rx.map_err(|()| Error::from(io::Error::new(io::ErrorKind::Other, "req")));
-
-        let req_rx = stream_with_eof_and_error(req_rx, || error::Error::Other("client is likely died"));
-
-        Response::from_stream(req_rx)
+        response_from_channel(resp_rx, cancel_guard).with_request_handle(handle)
     }
 }
 
@@ -406,9 +1243,31 @@ impl ClientCommandLoop {
         Box::new(future::finished(self))
     }
 
+    fn process_ping(self, tx: oneshot::Sender<Duration>) -> HttpFuture<Self> {
+        let r = self.inner.with(move |inner| inner.ping(tx));
+        Box::new(future::result(r.map(|()| self)))
+    }
+
+    fn process_send_raw_frame(self, frame: RawFrame, tx: oneshot::Sender<()>) -> HttpFuture<Self> {
+        let r = self.inner.with(move |inner| inner.send_raw_frame(frame, tx));
+        Box::new(future::result(r.map(|()| self)))
+    }
+
     fn process_message(self, message: ClientCommandMessage) -> HttpFuture<Self> {
         match message {
             ClientCommandMessage::DumpState(sender) => self.process_dump_state(sender),
+            ClientCommandMessage::Ping(tx) => self.process_ping(tx),
+            ClientCommandMessage::SendRawFrame(frame, tx) => self.process_send_raw_frame(frame, tx),
+            ClientCommandMessage::PeerSettings(tx) => {
+                // ignore error
+                tx.send(self.inner.with(|inner| inner.conn.peer_settings)).ok();
+                Box::new(future::ok(self))
+            },
+            ClientCommandMessage::FastForwardStreamIds(next_id, tx) => {
+                // ignore error
+                drop(tx.send(self.inner.with(|inner| inner.local_stream_ids.fast_forward(next_id))));
+                Box::new(future::ok(self))
+            },
             ClientCommandMessage::WaitForHandshake(tx) => {
                 // ignore error
                 drop(tx.send(Ok(())));
@@ -426,3 +1285,48 @@ impl ClientCommandLoop {
             .map(|_| ()))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net;
+    use std::sync::Arc;
+    use std::thread;
+
+    use native_tls::Certificate;
+    use native_tls::Pkcs12;
+    use native_tls::TlsAcceptor;
+    use native_tls::TlsConnector;
+
+    use tokio_core::reactor;
+
+    use super::connect_tls;
+
+    #[test]
+    fn tls_no_delay() {
+        let pkcs12 = Pkcs12::from_der(include_bytes!("../tests/sni-a.p12"), "mypass").unwrap();
+        let acceptor = TlsAcceptor::builder(pkcs12).unwrap().build().unwrap();
+
+        let mut builder = TlsConnector::builder().unwrap();
+        let root_ca = Certificate::from_der(include_bytes!("../tests/sni-root-ca.der")).unwrap();
+        builder.add_root_certificate(root_ca).unwrap();
+        let connector = Arc::new(builder.build().unwrap());
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (socket, _) = listener.accept().unwrap();
+                acceptor.accept(socket).expect("server handshake");
+            }
+        });
+
+        let mut lp = reactor::Core::new().unwrap();
+        for &no_delay in &[true, false] {
+            let tls = lp.run(connect_tls(&lp.handle(), &addr, "a.example.com", connector.clone(), no_delay))
+                .expect("connect");
+            assert_eq!(no_delay, tls.get_ref().get_ref().nodelay().unwrap());
+        }
+
+        server.join().unwrap();
+    }
+}
@@ -2,12 +2,18 @@ use std::sync::Arc;
 
 use native_tls::TlsConnector;
 
+#[cfg(feature = "tls-rustls")]
+use rustls::ClientConfig;
+
 use solicit::HttpScheme;
 
 #[derive(Clone)]
 pub enum ClientTlsOption {
     Plain,
     Tls(String, Arc<TlsConnector>), // domain
+    /// TLS with rustls, config must offer `h2`, see `rustls_tls::h2_protocols`
+    #[cfg(feature = "tls-rustls")]
+    Rustls(String, Arc<ClientConfig>), // domain
 }
 
 impl ClientTlsOption {
@@ -15,6 +21,8 @@ impl ClientTlsOption {
         match self {
             &ClientTlsOption::Plain => HttpScheme::Http,
             &ClientTlsOption::Tls(..) => HttpScheme::Https,
+            #[cfg(feature = "tls-rustls")]
+            &ClientTlsOption::Rustls(..) => HttpScheme::Https,
         }
     }
 }
@@ -1,9 +1,97 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use error;
+use result;
+
+use frame_observer::FrameObserver;
+use solicit::header::Headers;
+use solicit::frame::settings::HttpSetting;
+
+/// Default `CommonConf::write_buffer_size`
+pub const DEFAULT_WRITE_BUFFER_SIZE: u32 = 16384;
+
+/// Called with headers of each outgoing request (on client) or response (on server)
+/// right before they are encoded, e. g. to add tracing headers.
+///
+/// Not called for trailers. If headers are invalid after the hook
+/// (e. g. contain connection-specific fields), stream is reset with `INTERNAL_ERROR`.
+pub trait HeadersHook : Send + Sync + 'static {
+    fn headers(&self, headers: &mut Headers);
+}
+
+impl fmt::Debug for HeadersHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HeadersHook")
+    }
+}
+
+impl<F : Fn(&mut Headers) + Send + Sync + 'static> HeadersHook for F {
+    fn headers(&self, headers: &mut Headers) {
+        self(headers)
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct CommonConf {
+    /// Send connection-level WINDOW_UPDATE when this many bytes of DATA are received,
+    /// default is half of initial window size
+    pub conn_window_update_threshold: Option<u32>,
+    /// Called for each frame sent or received
+    pub frame_observer: Option<Arc<FrameObserver>>,
+    /// Max number of CONTINUATION frames in single header block,
+    /// default is `DEFAULT_MAX_CONTINUATION_FRAMES`
+    pub max_continuation_frames: Option<u32>,
+    /// SETTINGS_HEADER_TABLE_SIZE advertised to peer, limits HPACK decoder dynamic table
+    pub header_table_size: Option<u32>,
+    /// SETTINGS_MAX_HEADER_LIST_SIZE advertised to peer, streams with larger
+    /// header list are reset by client and answered with 431 by server
+    pub max_header_list_size: Option<u32>,
+    /// SETTINGS_MAX_CONCURRENT_STREAMS advertised to peer,
+    /// streams opened by peer above this limit are refused
+    pub max_concurrent_streams: Option<u32>,
+    /// SETTINGS_MAX_FRAME_SIZE advertised to peer, between 16384 and 16777215,
+    /// otherwise connection fails with `Error::InvalidSetting`.
+    /// Larger frames are accepted only after peer acknowledged the setting
+    pub max_frame_size: Option<u32>,
+    /// Frames queued for writing are coalesced into single socket write
+    /// up to this many bytes, default is `DEFAULT_WRITE_BUFFER_SIZE`.
+    /// Buffer is also flushed when no more frames are queued, `0` disables coalescing
+    pub write_buffer_size: Option<u32>,
+    /// Connection is closed with SETTINGS_TIMEOUT error if peer does not acknowledge
+    /// our SETTINGS in this time (6.5.3), default is to wait forever
+    pub settings_ack_timeout: Option<Duration>,
+    /// Inspect and modify outgoing request or response headers
+    pub headers_hook: Option<Arc<HeadersHook>>,
+    /// Pad each DATA frame sent with this many bytes to obscure message sizes.
+    /// Padding counts against flow control windows. Default is no padding
+    pub data_padding: Option<u8>,
+    /// Settings advertised to peer in initial SETTINGS frame in addition to
+    /// settings derived from other options, overriding them for the same setting.
+    /// `HttpSetting::Unknown` can be used for settings not known to this library.
+    /// Connection fails with `Error::InvalidSetting` if value is not allowed by 6.5.2.
+    pub extra_settings: Vec<HttpSetting>,
 }
 
 impl CommonConf {
     pub fn new() -> CommonConf {
         Default::default()
     }
+
+    /// Merge `extra_settings` into settings to be sent, last value of a setting wins.
+    ///
+    /// Fails if value of a known setting is not allowed by 6.5.2.
+    pub fn add_extra_settings(&self, settings: &mut Vec<HttpSetting>) -> result::Result<()> {
+        for setting in &self.extra_settings {
+            if HttpSetting::check_value(setting.get_id(), setting.get_val()).is_err() {
+                return Err(error::Error::InvalidSetting(*setting));
+            }
+            match settings.iter().position(|s| s.get_id() == setting.get_id()) {
+                Some(pos) => settings[pos] = *setting,
+                None => settings.push(*setting),
+            }
+        }
+        Ok(())
+    }
 }
@@ -1,11 +1,20 @@
 use std::collections::HashMap;
 use std::cmp;
+use std::time::Duration;
+use std::time::Instant;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use bytes::Bytes;
 
 use futures::Future;
 use futures::future;
 use futures::future::Loop;
 use futures::future::loop_fn;
+use futures::future::Either;
 use futures::stream::Stream;
+use futures::sync::oneshot;
 use futures;
 
 use tokio_core::reactor;
@@ -16,12 +25,15 @@ use tokio_io::AsyncRead;
 use tokio_io::AsyncWrite;
 use tokio_io::io as tokio_io;
 
+
 use error;
 use error::ErrorCode;
+use error::GoAwayInfo;
 use result;
 
 use solicit::session::StreamState;
 use solicit::frame::*;
+use solicit::frame::settings::HttpSettings;
 use solicit::header::*;
 use solicit::StreamId;
 use solicit::DEFAULT_SETTINGS;
@@ -38,6 +50,7 @@ use solicit_async::*;
 
 use super::stream::*;
 use super::stream_map::*;
+use super::stream_id::*;
 use super::types::*;
 use super::conf::*;
 
@@ -47,20 +60,95 @@ pub use resp::Response;
 
 use rc_mut::*;
 
+use metrics::SharedMetrics;
+
 
 pub enum CommonToWriteMessage {
     TryFlushStream(Option<StreamId>), // flush stream when window increased or new data added
     Frame(HttpFrame),
     StreamEnd(StreamId, ErrorCode), // send when user provided handler completed the stream
+    DataConsumed(StreamId, u32), // consumer took DATA from stream, window can be increased
+    RawFrame(RawFrame, oneshot::Sender<()>), // frame bytes written as is, sender notified when written
+    CloseConn(GoawayFrame), // GOAWAY reporting connection error, connection is closed after it
 }
 
 pub trait ConnDataSpecific : 'static {
 }
 
+/// Stream of DATA received from peer which notifies connection
+/// when consumer takes DATA, so flow control window can be released
+pub struct DataConsumedStream<S, M : From<CommonToWriteMessage>> {
+    stream: S,
+    stream_id: StreamId,
+    to_write_tx: futures::sync::mpsc::UnboundedSender<M>,
+    // DATA sent to the stream but not yet taken by consumer
+    unconsumed: Arc<AtomicUsize>,
+}
+
+impl<S, M : From<CommonToWriteMessage>> DataConsumedStream<S, M> {
+    pub fn new(
+        stream: S,
+        stream_id: StreamId,
+        to_write_tx: futures::sync::mpsc::UnboundedSender<M>,
+        unconsumed: Arc<AtomicUsize>)
+            -> DataConsumedStream<S, M>
+    {
+        DataConsumedStream {
+            stream: stream,
+            stream_id: stream_id,
+            to_write_tx: to_write_tx,
+            unconsumed: unconsumed,
+        }
+    }
+
+    fn data_consumed(&self, size: usize) {
+        if size == 0 {
+            return;
+        }
+        // ignore error: connection is likely closed
+        self.to_write_tx.send(CommonToWriteMessage::DataConsumed(self.stream_id, size as u32).into()).ok();
+    }
+}
+
+impl<S, M> Stream for DataConsumedStream<S, M>
+    where
+        S : Stream<Item=HttpStreamPart, Error=error::Error>,
+        M : From<CommonToWriteMessage>,
+{
+    type Item = HttpStreamPart;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> futures::Poll<Option<HttpStreamPart>, error::Error> {
+        let part = match self.stream.poll()? {
+            futures::Async::Ready(Some(part)) => part,
+            r => return Ok(r),
+        };
+
+        if let HttpStreamPartContent::Data(ref data) = part.content {
+            self.unconsumed.fetch_sub(data.len(), Ordering::SeqCst);
+            self.data_consumed(data.len());
+        }
+
+        Ok(futures::Async::Ready(Some(part)))
+    }
+}
+
+impl<S, M : From<CommonToWriteMessage>> Drop for DataConsumedStream<S, M> {
+    fn drop(&mut self) {
+        // DATA will never be taken, so window must be released now
+        let unconsumed = self.unconsumed.swap(0, Ordering::SeqCst);
+        self.data_consumed(unconsumed);
+    }
+}
+
 
 pub struct ConnData<T : Types> {
+    /// Unique within process, included in log messages along with stream id
+    /// to tell apart frames of multiplexed connections and streams
+    pub conn_id: u64,
     /// Client or server specific data
     pub specific: T::ConnDataSpecific,
+    pub conf: CommonConf,
     /// Messages to be sent to write loop
     pub to_write_tx: futures::sync::mpsc::UnboundedSender<T::ToWriteMessage>,
     /// Reactor we are using
@@ -69,22 +157,71 @@ pub struct ConnData<T : Types> {
     pub conn: HttpConnection,
     /// Known streams
     pub streams: StreamMap<T>,
-    pub last_local_stream_id: StreamId,
+    /// Ids of streams we initiate
+    pub local_stream_ids: StreamIdAllocator,
     pub last_peer_stream_id: StreamId,
+    /// DATA bytes received since last connection-level WINDOW_UPDATE
+    pub conn_in_consumed: u32,
     pub goaway_sent: Option<GoawayFrame>,
     pub goaway_received: Option<GoawayFrame>,
-    pub ping_sent: Option<u64>,
+    /// PINGs waiting for ACK by opaque data
+    pub pings_sent: HashMap<u64, PingSent>,
+    pub next_ping_opaque_data: u64,
+    /// When last frame was received from peer
+    pub last_frame_received: Instant,
+    /// When frames were last written to peer
+    pub last_frame_sent: Instant,
+    /// Notified when GOAWAY is sent and all streams are completed
+    pub drained_waiters: Vec<oneshot::Sender<()>>,
+    /// Peer acknowledged SETTINGS we sent in handshake
+    pub settings_acked: bool,
+    /// Largest frame accepted from peer: our SETTINGS_MAX_FRAME_SIZE once it is acknowledged,
+    /// read by read loop before each frame
+    pub recv_max_frame_size: u32,
+}
+
+/// PING sent by `ConnData::ping`
+pub struct PingSent {
+    pub sent: Instant,
+    pub tx: oneshot::Sender<Duration>,
 }
 
 
 #[derive(Debug)]
 pub struct ConnectionStateSnapshot {
-    pub streams: HashMap<StreamId, StreamState>,
+    pub streams: HashMap<StreamId, StreamStateSnapshot>,
+    /// Connection-level send flow control window
+    pub out_window_size: i32,
+    /// Connection-level receive flow control window
+    pub in_window_size: i32,
+    /// Settings last advertised by peer
+    pub peer_settings: HttpSettings,
+}
+
+/// Settings advertised by peer in SETTINGS frames,
+/// protocol defaults for settings peer did not send
+pub type PeerSettings = HttpSettings;
+
+/// Stream state and flow control counters
+#[derive(Debug, Clone)]
+pub struct StreamStateSnapshot {
+    pub state: StreamState,
+    /// Send flow control window
+    pub out_window_size: i32,
+    /// Receive flow control window
+    pub in_window_size: i32,
+    /// DATA bytes sent, padding excluded
+    pub data_sent: u64,
+    /// DATA bytes received, padding excluded
+    pub data_received: u64,
 }
 
 
 
 
+/// Source of `ConnData::conn_id`
+static NEXT_CONN_ID: AtomicUsize = AtomicUsize::new(1);
+
 impl<T : Types> ConnData<T>
     where
         Self : ConnInner<Types=T>,
@@ -94,37 +231,153 @@ impl<T : Types> ConnData<T>
     pub fn new(
         loop_handle: reactor::Handle,
         specific: T::ConnDataSpecific,
-        _conf: CommonConf,
+        conf: CommonConf,
+        our_settings: &[HttpSetting],
+        metrics: SharedMetrics,
         to_write_tx: futures::sync::mpsc::UnboundedSender<T::ToWriteMessage>)
             -> ConnData<T>
     {
+        let mut conn = HttpConnection::new();
+        for setting in our_settings {
+            conn.our_settings.apply(*setting);
+        }
+
+        let conn_id = NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed) as u64;
+
+        debug!("conn {}: created", conn_id);
+
         ConnData {
+            conn_id: conn_id,
             specific: specific,
+            conf: conf,
             to_write_tx: to_write_tx,
-            conn: HttpConnection::new(),
-            streams: StreamMap::new(),
-            last_local_stream_id: 0,
+            conn: conn,
+            streams: StreamMap::new(conn_id, metrics),
+            local_stream_ids: StreamIdAllocator::new(T::first_id()),
             last_peer_stream_id: 0,
+            conn_in_consumed: 0,
             loop_handle: loop_handle,
             goaway_sent: None,
             goaway_received: None,
-            ping_sent: None,
+            pings_sent: HashMap::new(),
+            next_ping_opaque_data: 1,
+            last_frame_received: Instant::now(),
+            last_frame_sent: Instant::now(),
+            drained_waiters: Vec::new(),
+            settings_acked: false,
+            recv_max_frame_size: DEFAULT_SETTINGS.max_frame_size,
         }
     }
 
-    /// Allocate stream id for locally initiated stream
-    pub fn next_local_stream_id(&mut self) -> StreamId {
-        let id = match self.last_local_stream_id {
-            0 => T::first_id(),
-            n => n + 2,
+    /// Close connection if SETTINGS sent in handshake are not acknowledged
+    /// in `CommonConf::settings_ack_timeout`
+    pub fn start_settings_ack_timer(&mut self, self_rc: RcMut<Self>)
+        where T::ToWriteMessage : From<CommonToWriteMessage>
+    {
+        let timeout = match self.conf.settings_ack_timeout {
+            Some(timeout) => timeout,
+            None => return,
         };
-        self.last_local_stream_id = id;
-        id
+
+        let timeout = match reactor::Timeout::new(timeout, &self.loop_handle) {
+            Ok(timeout) => timeout,
+            Err(e) => {
+                warn!("failed to create timeout: {:?}", e);
+                return;
+            }
+        };
+
+        self.loop_handle.spawn(timeout
+            .map(move |()| self_rc.with(|inner| inner.settings_ack_timed_out()))
+            .map_err(|e| warn!("timeout error: {:?}", e)));
+    }
+
+    fn settings_ack_timed_out(&mut self)
+        where T::ToWriteMessage : From<CommonToWriteMessage>
+    {
+        if self.settings_acked {
+            return;
+        }
+
+        // 6.5.3
+        // If the sender of a SETTINGS frame does not receive an acknowledgement
+        // within a reasonable amount of time, it MAY issue a connection error
+        // of type SETTINGS_TIMEOUT.
+        warn!("SETTINGS ACK not received in time, closing connection");
+        let error = error::Error::CodeError(ErrorCode::SettingsTimeout);
+        self.conn_died(&error);
+        // ignore error, connection may be already closed
+        self.send_goaway_and_close(ErrorCode::SettingsTimeout).ok();
+    }
+
+    /// Send PING frame, `tx` is notified with round-trip time when ACK is received
+    pub fn ping(&mut self, tx: oneshot::Sender<Duration>) -> result::Result<()> {
+        let opaque_data = self.next_ping_opaque_data;
+        self.next_ping_opaque_data = self.next_ping_opaque_data.wrapping_add(1);
+
+        self.pings_sent.insert(opaque_data, PingSent {
+            sent: Instant::now(),
+            tx: tx,
+        });
+
+        self.send_frame(PingFrame::with_data(opaque_data))
+    }
+
+    /// Write arbitrary frame, ordered with other frames of this connection.
+    ///
+    /// Frame is not validated and connection state is not updated.
+    pub fn send_raw_frame(&mut self, frame: RawFrame, tx: oneshot::Sender<()>) -> result::Result<()>
+        where T::ToWriteMessage : From<CommonToWriteMessage>
+    {
+        self.send_common(CommonToWriteMessage::RawFrame(frame, tx))
+    }
+
+    /// Report connection error to peer with GOAWAY, connection is closed after GOAWAY is written
+    fn send_goaway_and_close(&mut self, error_code: ErrorCode) -> result::Result<()>
+        where T::ToWriteMessage : From<CommonToWriteMessage>
+    {
+        let frame = GoawayFrame::new(self.last_peer_stream_id, error_code);
+        self.send_common(CommonToWriteMessage::CloseConn(frame))
+    }
+
+    /// Send GOAWAY frame, stop accepting new streams from peer.
+    ///
+    /// `tx` is notified when all remaining streams are completed.
+    pub fn send_goaway(&mut self, error_code: ErrorCode, debug_data: Bytes, tx: oneshot::Sender<()>)
+        -> result::Result<()>
+    {
+        // 6.8
+        // Endpoints MUST NOT increase the value they send in the last stream identifier,
+        // since the peers might already have retried unprocessed requests on another connection.
+        let last_stream_id = match self.goaway_sent {
+            Some(ref f) => cmp::min(f.last_stream_id, self.last_peer_stream_id),
+            None => self.last_peer_stream_id,
+        };
+
+        let frame = GoawayFrame::with_debug_data(last_stream_id, error_code, debug_data);
+        self.goaway_sent = Some(frame.clone());
+        self.send_frame(frame)?;
+
+        self.drained_waiters.push(tx);
+        self.notify_if_drained();
+
+        Ok(())
+    }
+
+    fn notify_if_drained(&mut self) {
+        if self.goaway_sent.is_some() && self.streams.is_empty() {
+            for tx in self.drained_waiters.drain(..) {
+                // ignore error
+                tx.send(()).ok();
+            }
+        }
     }
 
     pub fn pop_outg_all_for_stream(&mut self, stream_id: StreamId) -> Vec<HttpStreamCommand> {
+        let padding = self.conf.data_padding.unwrap_or(0);
+        let max_frame_size = self.conn.peer_settings.max_frame_size;
         if let Some(stream) = self.streams.get_mut(stream_id) {
-            stream.pop_outg_all_maybe_remove(&mut self.conn.out_window_size)
+            stream.pop_outg_all_maybe_remove(&mut self.conn.out_window_size, padding, max_frame_size)
         } else {
             Vec::new()
         }
@@ -141,7 +394,8 @@ impl<T : Types> ConnData<T>
         r
     }
 
-    fn write_part(&mut self, target: &mut VecSendFrame, stream_id: StreamId, part: HttpStreamCommand) {
+    /// Returns `false` if stream was reset instead, so remaining parts must not be written
+    fn write_part(&mut self, target: &mut VecSendFrame, stream_id: StreamId, part: HttpStreamCommand) -> bool {
         match part {
             HttpStreamCommand::Data(data, end_stream) => {
                 // if client requested end of stream,
@@ -151,15 +405,23 @@ impl<T : Types> ConnData<T>
                     let mut frame = DataFrame::with_data(stream_id, Vec::new());
                     frame.set_flag(DataFlag::EndStream);
 
-                    debug!("sending frame {:?}", frame);
+                    debug!("conn {} stream {}: sending frame {:?}", self.conn_id, stream_id, frame);
 
-                    return target.send_frame(frame).unwrap();
+                    target.send_frame(frame).unwrap();
+                    return true;
                 }
 
+                let padding = self.conf.data_padding.unwrap_or(0);
+
+                // 6.5.2
+                // SETTINGS_MAX_FRAME_SIZE: Indicates the size of the largest frame payload
+                // that the sender is willing to receive
+                let max_data_size = self.conn.peer_settings.max_frame_size as usize
+                    - data_padding_overhead(padding) as usize;
+
                 let mut pos = 0;
-                const MAX_CHUNK_SIZE: usize = 8 * 1024;
                 while pos < data.len() {
-                    let end = cmp::min(data.len(), pos + MAX_CHUNK_SIZE);
+                    let end = cmp::min(data.len(), pos + max_data_size);
 
                     let end_stream_in_frame =
                         if end == data.len() && end_stream == EndStream::Yes {
@@ -172,76 +434,166 @@ impl<T : Types> ConnData<T>
                     if end_stream_in_frame == EndStream::Yes {
                         frame.set_flag(DataFlag::EndStream);
                     }
+                    if padding != 0 {
+                        frame.set_padding(padding);
+                    }
 
-                    debug!("sending frame {:?}", frame);
+                    debug!("conn {} stream {}: sending frame {:?}", self.conn_id, stream_id, frame);
 
                     target.send_frame(frame).unwrap();
 
                     pos = end;
                 }
             }
-            HttpStreamCommand::Headers(headers, end_stream) => {
+            HttpStreamCommand::Headers(mut headers, end_stream, priority) => {
+                let checked = match self.conf.headers_hook {
+                    // trailers have no pseudo-headers
+                    Some(ref hook) if headers.contains_pseudo_headers() => {
+                        hook.headers(&mut headers);
+                        headers.check_connection_specific()
+                    }
+                    _ => Ok(()),
+                };
+
+                if let Err(e) = checked {
+                    warn!("conn {} stream {}: headers hook produced invalid headers: {:?}",
+                        self.conn_id, stream_id, e);
+
+                    if let Some(mut stream) = self.streams.get_mut(stream_id) {
+                        if let Some(peer_tx) = stream.stream().peer_tx.take() {
+                            // it is OK to ignore error: handler may be already dead
+                            drop(peer_tx.send(ResultOrEof::Error(error::Error::InvalidHeaders(e))));
+                        }
+                        stream.rst_remove(ErrorCode::InternalError);
+                    }
+
+                    let frame = RstStreamFrame::new(stream_id, ErrorCode::InternalError);
+
+                    debug!("conn {} stream {}: sending frame {:?}", self.conn_id, stream_id, frame);
+
+                    target.send_frame(frame).unwrap();
+                    return false;
+                }
+
                 let headers_fragment = self
                     .conn.encoder.encode(headers.0.iter().map(|h| (h.name(), h.value())));
 
                 // For now, sending header fragments larger than 16kB is not supported
                 // (i.e. the encoded representation cannot be split into CONTINUATION
                 // frames).
-                let mut frame = HeadersFrame::new(headers_fragment, stream_id);
+                let mut frame = match priority {
+                    Some(priority) => HeadersFrame::with_dependency(
+                        headers_fragment, stream_id, priority.to_dependency()),
+                    None => HeadersFrame::new(headers_fragment, stream_id),
+                };
                 frame.set_flag(HeadersFlag::EndHeaders);
 
                 if end_stream == EndStream::Yes {
                     frame.set_flag(HeadersFlag::EndStream);
                 }
 
-                debug!("sending frame {:?}", frame);
+                debug!("conn {} stream {}: sending frame {:?}", self.conn_id, stream_id, frame);
 
                 target.send_frame(frame).unwrap();
             }
             HttpStreamCommand::Rst(error_code) => {
                 let frame = RstStreamFrame::new(stream_id, error_code);
 
-                debug!("sending frame {:?}", frame);
+                debug!("conn {} stream {}: sending frame {:?}", self.conn_id, stream_id, frame);
 
                 target.send_frame(frame).unwrap();
             }
         }
+        true
     }
 
     pub fn pop_outg_all_for_stream_bytes(&mut self, stream_id: StreamId) -> Vec<u8> {
         let mut send = VecSendFrame(Vec::new());
         for part in self.pop_outg_all_for_stream(stream_id) {
-            self.write_part(&mut send, stream_id, part);
+            if !self.write_part(&mut send, stream_id, part) {
+                break;
+            }
         }
+        self.notify_if_drained();
+        self.streams_updated();
         send.0
     }
 
     pub fn pop_outg_all_for_conn_bytes(&mut self) -> Vec<u8> {
         // TODO: maintain own limits of out window
         let mut send = VecSendFrame(Vec::new());
+        let mut reset_streams = Vec::new();
         for (stream_id, part) in self.pop_outg_all_for_conn() {
-            self.write_part(&mut send, stream_id, part);
+            if reset_streams.contains(&stream_id) {
+                continue;
+            }
+            if !self.write_part(&mut send, stream_id, part) {
+                reset_streams.push(stream_id);
+            }
         }
+        self.notify_if_drained();
+        self.streams_updated();
         send.0
     }
 
     pub fn dump_state(&self) -> ConnectionStateSnapshot {
         ConnectionStateSnapshot {
             streams: self.streams.snapshot(),
+            out_window_size: self.conn.out_window_size.size(),
+            in_window_size: self.conn.in_window_size.size(),
+            peer_settings: self.conn.peer_settings,
         }
     }
 
+    /// 4.3: header block decoding error is a connection error of type `COMPRESSION_ERROR`
+    fn decode_header_block(&mut self, fragment: &[u8]) -> result::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let conn_id = self.conn_id;
+        self.conn.decoder.decode(fragment)
+            .map_err(|e| {
+                warn!("conn {}: failed to decode header block: {:?}", conn_id, e);
+                error::Error::CodeError(ErrorCode::CompressionError)
+            })
+    }
+
     fn process_headers_frame(&mut self, self_rc: RcMut<Self>, frame: HeadersFrame) -> result::Result<Option<HttpStreamRef<T>>> {
-        let headers = self.conn.decoder
-            .decode(&frame.header_fragment())
-            .map_err(error::Error::CompressionError).unwrap(); // TODO: do not panic
+        let headers = self.decode_header_block(&frame.header_fragment())?;
         let headers = Headers(headers.into_iter().map(|h| Header::new(h.0, h.1)).collect());
 
         let end_stream = if frame.is_end_of_stream() { EndStream::Yes } else { EndStream::No };
 
+        let max_header_list_size = self.conn.our_settings.max_header_list_size;
+        if headers.list_size() > max_header_list_size as usize {
+            warn!("conn {} stream {}: header list size {} exceeds {}",
+                self.conn_id, frame.stream_id, headers.list_size(), max_header_list_size);
+
+            if let Some(mut stream) = self.streams.get_mut(frame.stream_id) {
+                if let Some(peer_tx) = stream.stream().peer_tx.take() {
+                    // it is OK to ignore error: handler may be already dead
+                    drop(peer_tx.send(ResultOrEof::Error(error::Error::HeaderListTooLarge(max_header_list_size))));
+                }
+                stream.rst_remove(ErrorCode::Cancel);
+            } else {
+                return self.process_headers_too_large(self_rc, frame.stream_id, end_stream);
+            }
+            self.send_frame(RstStreamFrame::new(frame.stream_id, ErrorCode::Cancel))?;
+            return Ok(None);
+        }
+
         self.process_headers(self_rc, frame.stream_id, end_stream, headers)
     }
 
+    fn process_push_promise_frame(&mut self, self_rc: RcMut<Self>, frame: PushPromiseFrame)
+        -> result::Result<Option<HttpStreamRef<T>>>
+    {
+        // header block must be decoded anyway to keep HPACK decoder state in sync
+        let headers = self.decode_header_block(&frame.header_fragment)?;
+        let headers = Headers(headers.into_iter().map(|h| Header::new(h.0, h.1)).collect());
+
+        self.process_push_promise(self_rc, frame.stream_id, frame.promised_stream_id, headers)?;
+
+        Ok(None)
+    }
+
     fn process_priority_frame(&mut self, frame: PriorityFrame)
         -> result::Result<Option<HttpStreamRef<T>>>
     {
@@ -251,17 +603,61 @@ impl<T : Types> ConnData<T>
     fn process_settings_global(&mut self, frame: SettingsFrame) -> result::Result<()> {
         if frame.is_ack() {
             // TODO: remember which settings acked
+            if !self.settings_acked {
+                // 6.5.3: peer applied our SETTINGS only when it sends ACK
+                self.recv_max_frame_size = self.conn.our_settings.max_frame_size;
+                let header_table_size = self.conn.our_settings.header_table_size;
+                self.conn.decoder.set_max_table_size(header_table_size as usize);
+            }
+            self.settings_acked = true;
             return Ok(());
         }
 
+        let out_window_increased = self.update_peer_settings(frame.settings)?;
+
+        // 6.5.3
+        // Upon receiving a SETTINGS frame with the ACK flag set to 0, the recipient
+        // MUST immediately emit a SETTINGS frame with the ACK flag set.
+        // ACK is queued before any frame sent in response to this or subsequent frames,
+        // including DATA unblocked by increased window.
+        self.ack_settings()?;
+
+        if out_window_increased {
+            self.out_window_increased(None)?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply settings received in SETTINGS frame or in `HTTP2-Settings` header
+    pub fn apply_peer_settings(&mut self, settings: Vec<HttpSetting>) -> result::Result<()> {
+        if self.update_peer_settings(settings)? {
+            self.out_window_increased(None)?;
+        }
+
+        Ok(())
+    }
+
+    /// Update connection state with peer settings without sending anything,
+    /// return true if streams out windows increased
+    fn update_peer_settings(&mut self, settings: Vec<HttpSetting>) -> result::Result<bool> {
         let mut out_window_increased = false;
 
-        for setting in frame.settings {
+        for setting in settings {
             if let HttpSetting::InitialWindowSize(new_size) = setting {
                 let old_size = self.conn.peer_settings.initial_window_size;
                 let delta = (new_size as i32) - (old_size as i32);
 
                 if delta != 0 {
+                    // 6.9.2
+                    // An endpoint MUST treat a change to SETTINGS_INITIAL_WINDOW_SIZE that
+                    // causes any flow-control window to exceed the maximum size as a
+                    // connection error of type FLOW_CONTROL_ERROR.
+                    if self.streams.map.values().any(|s| s.out_window_size.0.checked_add(delta).is_none()) {
+                        warn!("SETTINGS_INITIAL_WINDOW_SIZE {} overflows stream window", new_size);
+                        return Err(error::Error::CodeError(ErrorCode::FlowControlError));
+                    }
+
                     for (_, s) in &mut self.streams.map {
                         // In addition to changing the flow-control window for streams
                         // that are not yet active, a SETTINGS frame can alter the initial
@@ -280,29 +676,40 @@ impl<T : Types> ConnData<T>
                 }
             }
 
-            self.conn.peer_settings.apply(setting);
-        }
-
-        self.ack_settings()?;
+            if let HttpSetting::HeaderTableSize(new_size) = setting {
+                // Encoder may use any table size not exceeding peer's limit,
+                // so update is needed only when peer limit is below current size
+                if new_size < self.conn.peer_settings.header_table_size {
+                    self.conn.encoder.set_max_table_size(new_size as usize);
+                }
+            }
 
-        if out_window_increased {
-            self.out_window_increased(None)?;
+            self.conn.peer_settings.apply(setting);
         }
 
-        Ok(())
+        Ok(out_window_increased)
     }
-    
+
     fn process_stream_window_update_frame(&mut self, frame: WindowUpdateFrame)
         -> result::Result<Option<HttpStreamRef<T>>>
     {
-        self.out_window_increased(Some(frame.get_stream_id()))?;
+        let stream_id = frame.get_stream_id();
 
-        match self.streams.get_mut(frame.get_stream_id()) {
+        let error_code = match self.streams.get_mut(stream_id) {
             Some(mut stream) => {
-                // TODO: stream error, not conn error
-                stream.stream().out_window_size.try_increase(frame.increment())
-                    .map_err(|()| error::Error::Other("failed to increment stream window"))?;
-                Ok(Some(stream))
+                if frame.increment() == 0 {
+                    // 6.9
+                    // A receiver MUST treat the receipt of a WINDOW_UPDATE frame with
+                    // an flow-control window increment of 0 as a stream error of type PROTOCOL_ERROR
+                    Some(ErrorCode::ProtocolError)
+                } else if stream.stream().out_window_size.try_increase(frame.increment()).is_err() {
+                    // 6.9.1
+                    // If a sender receives a WINDOW_UPDATE that causes a flow-control window
+                    // to exceed this maximum, it MUST terminate either the stream or the connection.
+                    Some(ErrorCode::FlowControlError)
+                } else {
+                    None
+                }
             }
             None => {
                 // 6.9
@@ -311,22 +718,57 @@ impl<T : Types> ConnData<T>
                 // WINDOW_UPDATE frame on a "half-closed (remote)" or "closed" stream.
                 // A receiver MUST NOT treat this as an error (see Section 5.1).
                 debug!("WINDOW_UPDATE of unknown stream: {}", frame.get_stream_id());
-                Ok(None)
+                return Ok(None);
             }
+        };
+
+        if let Some(error_code) = error_code {
+            warn!("invalid WINDOW_UPDATE increment {} for stream {}, resetting stream",
+                frame.increment(), stream_id);
+            self.streams.get_mut(stream_id).unwrap().rst_remove(error_code);
+            self.send_frame(RstStreamFrame::new(stream_id, error_code))?;
+            return Ok(None);
         }
+
+        self.out_window_increased(Some(stream_id))?;
+
+        Ok(self.streams.get_mut(stream_id))
     }
 
     fn process_conn_window_update(&mut self, frame: WindowUpdateFrame) -> result::Result<()> {
+        // 6.9: errors on the connection flow-control window are connection errors
+        if frame.increment() == 0 {
+            warn!("WINDOW_UPDATE with zero increment for connection");
+            return Err(error::Error::CodeError(ErrorCode::ProtocolError));
+        }
         self.conn.out_window_size.try_increase(frame.increment())
-            .map_err(|()| error::Error::Other("failed to increment conn window"))?;
+            .map_err(|()| {
+                warn!("WINDOW_UPDATE overflows connection window");
+                error::Error::CodeError(ErrorCode::FlowControlError)
+            })?;
         self.out_window_increased(None)
     }
 
     fn process_rst_stream_frame(&mut self, frame: RstStreamFrame)
         -> result::Result<Option<HttpStreamRef<T>>>
     {
-        if let Some(stream) = self.streams.get_mut(frame.get_stream_id()) {
-            stream.rst_remove(frame.error_code());
+        let reset = match self.streams.get_mut(frame.get_stream_id()) {
+            Some(stream) => {
+                stream.rst_remove(frame.error_code());
+                true
+            }
+            None => false,
+        };
+
+        if reset {
+            self.stream_reset_by_peer(frame.stream_id)?;
+        } else if self.is_idle_stream(frame.stream_id) {
+            // 6.4: RST_STREAM on idle stream is a connection error
+            warn!("RST_STREAM on idle stream: {}", frame.stream_id);
+            return Err(error::Error::CodeError(ErrorCode::ProtocolError));
+        } else if self.streams.is_recently_closed(frame.stream_id) {
+            // 5.1: peer could have sent RST_STREAM before it learned stream is closed
+            debug!("ignoring RST_STREAM on recently closed stream: {}", frame.stream_id);
         } else {
             warn!("RST_STREAM on non-existent stream: {}", frame.stream_id);
         }
@@ -334,6 +776,15 @@ impl<T : Types> ConnData<T>
         Ok(None)
     }
 
+    /// Stream is in "idle" state: it was never opened by us or by peer
+    fn is_idle_stream(&self, stream_id: StreamId) -> bool {
+        if T::is_init_locally(stream_id) {
+            stream_id > self.local_stream_ids.last()
+        } else {
+            stream_id > self.last_peer_stream_id
+        }
+    }
+
     fn get_stream_or_send_stream_closed(&mut self, stream_id: StreamId)
         -> result::Result<Option<HttpStreamRef<T>>>
     {
@@ -353,53 +804,88 @@ impl<T : Types> ConnData<T>
     {
         let stream_id = frame.get_stream_id();
 
-        self.conn.decrease_in_window(frame.payload_len())?;
-
-        let increment_conn =
-            // TODO: need something better
-            if self.conn.in_window_size.size() < (DEFAULT_SETTINGS.initial_window_size / 2) as i32 {
-                let increment = DEFAULT_SETTINGS.initial_window_size;
-                self.conn.in_window_size.try_increase(increment)
-                    .map_err(|()| error::Error::Other("failed to increase window size"))?;
+        // 6.9.1: peer sent more than connection window allows
+        self.conn.decrease_in_window(frame.payload_len())
+            .map_err(|_| error::Error::CodeError(ErrorCode::FlowControlError))?;
 
-                Some(increment)
-            } else {
-                None
-            };
+        let initial_window_size = self.conn.our_settings.initial_window_size;
 
-        let increment_stream = {
+        let (increment_stream, consumed) = {
             // If a DATA frame is received whose stream is not in "open" or
             // "half-closed (local)" state, the recipient MUST respond with
             // a stream error (Section 5.4.2) of type STREAM_CLOSED.
             let mut stream = match self.get_stream_or_send_stream_closed(frame.get_stream_id())? {
                 Some(stream) => stream,
                 None => {
+                    self.release_conn_in_window(frame.payload_len())?;
                     return Ok(None);
                 }
             };
 
             stream.stream().in_window_size.try_decrease_to_positive(frame.payload_len() as i32)
                 .map_err(|()| error::Error::CodeError(ErrorCode::FlowControlError))?;
+            stream.stream().data_received += frame.data.len() as u64;
+
+            if stream.stream().content_length_mismatch(frame.is_end_of_stream()) {
+                let received = stream.stream().data_received;
+                warn!("DATA in stream {} does not match content-length {:?}, received {}",
+                    stream_id, stream.stream().content_length, received);
+                stream.rst_remove(ErrorCode::ProtocolError);
+                self.release_conn_in_window(frame.payload_len())?;
+                self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::ProtocolError))?;
+                return Ok(None);
+            }
 
-            let increment_stream =
-                if stream.stream().in_window_size.size() < (DEFAULT_SETTINGS.initial_window_size / 2) as i32 {
-                    let increment = DEFAULT_SETTINGS.initial_window_size;
-                    stream.stream().in_window_size.try_increase(increment)
-                        .map_err(|()| error::Error::Other("failed to increase window size"))?;
+            if let Some(max_body_size) = stream.stream().max_body_size {
+                if stream.stream().data_received > max_body_size {
+                    warn!("body in stream {} exceeds {} bytes, resetting stream", stream_id, max_body_size);
+                    if let Some(peer_tx) = stream.stream().peer_tx.take() {
+                        // it is OK to ignore error: handler may be already dead
+                        drop(peer_tx.send(ResultOrEof::Error(error::Error::BodyTooLarge(max_body_size))));
+                    }
+                    stream.rst_remove(ErrorCode::Cancel);
+                    self.release_conn_in_window(frame.payload_len())?;
+                    self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::Cancel))?;
+                    return Ok(None);
+                }
+            }
 
-                    Some(increment)
-                } else {
-                    None
-                };
+            let unconsumed = stream.stream().in_window_by_consumer.clone();
+            if let Some(ref unconsumed) = unconsumed {
+                unconsumed.fetch_add(frame.data.len(), Ordering::SeqCst);
+            }
+
+            let delivered = stream.stream().new_data_chunk(frame.data.clone(), frame.is_end_of_stream());
 
-            stream.stream().new_data_chunk(&frame.data.as_ref(), frame.is_end_of_stream());
+            if let Some(ref unconsumed) = unconsumed {
+                if !delivered {
+                    unconsumed.fetch_sub(frame.data.len(), Ordering::SeqCst);
+                }
+            }
 
-            increment_stream
+            if unconsumed.is_some() && delivered {
+                // Window is released by `data_consumed` when consumer takes the data,
+                // padding is never passed to consumer, so release it now
+                let padding = frame.payload_len() - frame.data.len() as u32;
+                let increment_stream = stream.stream().consume_in_window(padding, initial_window_size)?;
+                (increment_stream, padding)
+            } else {
+                let increment_stream =
+                    if stream.stream().in_window_size.size() < (initial_window_size / 2) as i32 {
+                        let increment = initial_window_size;
+                        stream.stream().in_window_size.try_increase(increment)
+                            .map_err(|()| error::Error::Other("failed to increase window size"))?;
+
+                        Some(increment)
+                    } else {
+                        None
+                    };
+
+                (increment_stream, frame.payload_len())
+            }
         };
 
-        if let Some(increment_conn) = increment_conn {
-            self.send_frame(WindowUpdateFrame::for_connection(increment_conn))?;
-        }
+        self.release_conn_in_window(consumed)?;
 
         if let Some(increment_stream) = increment_stream {
             self.send_frame(WindowUpdateFrame::for_stream(stream_id, increment_stream))?;
@@ -408,17 +894,66 @@ impl<T : Types> ConnData<T>
         Ok(Some(self.streams.get_mut(stream_id).expect("stream must be found")))
     }
 
+    /// Consumer took DATA of given size from stream,
+    /// so the window can be given back to peer
+    pub fn data_consumed(&mut self, stream_id: StreamId, size: u32) -> result::Result<()> {
+        let initial_window_size = self.conn.our_settings.initial_window_size;
+
+        let increment_stream = match self.streams.get_mut(stream_id) {
+            Some(mut stream) => stream.stream().consume_in_window(size, initial_window_size)?,
+            None => None,
+        };
+
+        self.release_conn_in_window(size)?;
+
+        if let Some(increment_stream) = increment_stream {
+            self.send_frame(WindowUpdateFrame::for_stream(stream_id, increment_stream))?;
+        }
+
+        Ok(())
+    }
+
+    /// Account DATA in connection window and send WINDOW_UPDATE if needed
+    fn release_conn_in_window(&mut self, size: u32) -> result::Result<()> {
+        if let Some(increment_conn) = self.consume_conn_in_window(size)? {
+            self.send_frame(WindowUpdateFrame::for_connection(increment_conn))?;
+        }
+        Ok(())
+    }
+
+    /// Account received DATA in connection window,
+    /// return connection-level WINDOW_UPDATE increment when threshold is reached
+    fn consume_conn_in_window(&mut self, size: u32) -> result::Result<Option<u32>> {
+        // Connection window is never increased by SETTINGS,
+        // so threshold must not be greater than initial window size
+        let threshold = cmp::min(
+            self.conf.conn_window_update_threshold
+                .unwrap_or(DEFAULT_SETTINGS.initial_window_size / 2),
+            DEFAULT_SETTINGS.initial_window_size);
+
+        self.conn_in_consumed += size;
+
+        if self.conn_in_consumed == 0 || self.conn_in_consumed < threshold {
+            return Ok(None);
+        }
+
+        let increment = self.conn_in_consumed;
+        self.conn_in_consumed = 0;
+        self.conn.in_window_size.try_increase(increment)
+            .map_err(|()| error::Error::Other("failed to increase window size"))?;
+        Ok(Some(increment))
+    }
+
     fn process_ping(&mut self, frame: PingFrame) -> result::Result<()> {
         if frame.is_ack() {
-            if let Some(opaque_data) = self.ping_sent.take() {
-                if opaque_data == frame.opaque_data {
-                    Ok(())
-                } else {
-                    Err(error::Error::Other("PING ACK opaque data mismatch"))
+            if let Some(ping) = self.pings_sent.remove(&frame.opaque_data) {
+                if let Err(_) = ping.tx.send(ping.sent.elapsed()) {
+                    debug!("PING ACK received, but caller is no longer interested");
                 }
             } else {
-                Err(error::Error::Other("PING ACK without PING"))
+                warn!("PING ACK with unknown opaque data: {}", frame.opaque_data);
             }
+            Ok(())
         } else {
             self.send_frame(PingFrame::new_ack(frame.opaque_data()))
         }
@@ -430,25 +965,54 @@ impl<T : Types> ConnData<T>
         }
 
         let last_stream_id = frame.last_stream_id;
-        let raw_error_code = frame.raw_error_code;
+        let error_code = frame.error_code();
+        let debug_data = frame.debug_data.clone();
+
+        let info = GoAwayInfo {
+            last_stream_id: last_stream_id,
+            error_code: frame.error_code(),
+            debug_data: frame.debug_data.clone(),
+        };
 
         self.goaway_received = Some(frame);
 
+        // Notify before failing streams, so GOAWAY is observable
+        // by the time outstanding requests complete with error
+        self.goaway_received(info);
+
+        // Streams with id not greater than last stream id might be still processed by peer,
+        // so they are left to complete, and connection is closed after that
         for (stream_id, mut stream) in self.streams.remove_local_streams_with_id_gt(last_stream_id) {
-            debug!("removed stream {} because of GOAWAY", stream_id);
-            stream.goaway_recvd(raw_error_code);
+            debug!("conn {} stream {}: removed because of GOAWAY", self.conn_id, stream_id);
+            stream.goaway_recvd(error_code, debug_data.clone());
         }
 
-
         Ok(())
     }
 
+    /// Fail all remaining streams with connection-level error,
+    /// see `Error::into_conn_error`
+    fn conn_died(&mut self, error: &error::Error) {
+        for (stream_id, mut stream) in self.streams.remove_all() {
+            debug!("conn {} stream {}: failing because connection died", self.conn_id, stream_id);
+            stream.conn_error(error.clone_conn_error());
+        }
+    }
+
     fn process_conn_frame(&mut self, frame: HttpFrameConn) -> result::Result<()> {
         match frame {
             HttpFrameConn::Settings(f) => self.process_settings_global(f),
             HttpFrameConn::Ping(f) => self.process_ping(f),
             HttpFrameConn::Goaway(f) => self.process_goaway(f),
             HttpFrameConn::WindowUpdate(f) => self.process_conn_window_update(f),
+            HttpFrameConn::Origin(f) => {
+                self.origin_received(f.origins);
+                Ok(())
+            }
+            HttpFrameConn::AltSvc(f) => {
+                self.alt_svc_received(f);
+                Ok(())
+            }
         }
     }
 
@@ -456,15 +1020,32 @@ impl<T : Types> ConnData<T>
         let stream_id = frame.get_stream_id();
         let end_of_stream = frame.is_end_of_stream();
 
+        // 6.1, 6.2, 6.3, 6.4, 6.6, 6.10
+        // If a frame is received whose stream identifier field is 0x0,
+        // the recipient MUST respond with a connection error of type PROTOCOL_ERROR.
+        if stream_id == 0 {
+            warn!("received stream frame with stream id 0");
+            return Err(error::Error::CodeError(ErrorCode::ProtocolError));
+        }
+
         // 6.8
         // Once sent, the sender will ignore frames sent on streams initiated by the receiver
         // if the stream has an identifier higher than the included last stream identifier.
-        if let Some(ref f) = self.goaway_sent.as_ref() {
-            if !T::is_init_locally(stream_id) {
-                if stream_id > f.last_stream_id {
-                    return Ok(());
+        let refused = match self.goaway_sent {
+            Some(ref f) => !T::is_init_locally(stream_id) && stream_id > f.last_stream_id,
+            None => false,
+        };
+        if refused {
+            if let HttpFrameStream::Headers(headers) = frame {
+                // header block must be decoded anyway to keep HPACK decoder state in sync
+                drop(self.conn.decoder.decode(&headers.header_fragment()));
+
+                if self.streams.get_mut(stream_id).is_none() {
+                    debug!("refusing stream {} after GOAWAY", stream_id);
+                    self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::RefusedStream))?;
                 }
             }
+            return Ok(());
         }
 
         let stream = match frame {
@@ -472,7 +1053,7 @@ impl<T : Types> ConnData<T>
             HttpFrameStream::Headers(headers) => self.process_headers_frame(self_rc, headers)?,
             HttpFrameStream::Priority(priority) => self.process_priority_frame(priority)?,
             HttpFrameStream::RstStream(rst) => self.process_rst_stream_frame(rst)?,
-            HttpFrameStream::PushPromise(_f) => unimplemented!(),
+            HttpFrameStream::PushPromise(push_promise) => self.process_push_promise_frame(self_rc, push_promise)?,
             HttpFrameStream::WindowUpdate(window_update) => self.process_stream_window_update_frame(window_update)?,
             HttpFrameStream::Continuation(_continuation) => unreachable!("must be joined with HEADERS before that"),
         };
@@ -489,16 +1070,19 @@ impl<T : Types> ConnData<T>
 
     fn process_http_frame(&mut self, self_rc: RcMut<Self>, frame: HttpFrame) -> result::Result<()> {
         // TODO: decode headers
-        debug!("received frame: {:?}", frame);
+        debug!("conn {} stream {}: received frame: {:?}", self.conn_id, frame.get_stream_id(), frame);
+        self.last_frame_received = Instant::now();
         match HttpFrameClassified::from(frame) {
-            HttpFrameClassified::Conn(f) => self.process_conn_frame(f),
-            HttpFrameClassified::Stream(f) => self.process_stream_frame(self_rc, f),
+            HttpFrameClassified::Conn(f) => self.process_conn_frame(f)?,
+            HttpFrameClassified::Stream(f) => self.process_stream_frame(self_rc, f)?,
             HttpFrameClassified::Unknown(_f) => {
                 // 4.1
                 // Implementations MUST ignore and discard any frame that has a type that is unknown.
-                Ok(())
             },
         }
+        self.notify_if_drained();
+        self.streams_updated();
+        Ok(())
     }
 
     fn send_common(&mut self, message: CommonToWriteMessage)
@@ -511,7 +1095,7 @@ impl<T : Types> ConnData<T>
 
     /// Schedule a write for HTTP frame
     /// Must not be data frame
-    fn send_frame<F : Into<HttpFrame>>(&mut self, frame: F) -> result::Result<()> {
+    pub fn send_frame<F : Into<HttpFrame>>(&mut self, frame: F) -> result::Result<()> {
         let frame = frame.into();
         assert!(frame.frame_type() != HttpFrameType::Data);
         self.send_common(CommonToWriteMessage::Frame(frame))
@@ -538,7 +1122,8 @@ impl<T : Types> ConnData<T>
         self_rc: RcMut<Self>,
         stream_id: StreamId,
         stream: HttpPartStream,
-        ready_to_write: Latch)
+        ready_to_write: Latch,
+        cancel: CancelToken)
     {
         let to_write_tx_2 = self.to_write_tx.clone();
 
@@ -558,6 +1143,13 @@ impl<T : Types> ConnData<T>
 
                     stream.into_future().map_err(|(e, _)| e)
                         .and_then(move |(part_opt, stream)| {
+                            if let Some(HttpStreamPart { content: HttpStreamPartContent::Headers(ref headers), .. }) = part_opt {
+                                if let Err(e) = headers.check_connection_specific() {
+                                    warn!("refusing to send invalid headers in stream {}: {:?}", stream_id, e);
+                                    return future::err(error::Error::InvalidHeaders(e));
+                                }
+                            }
+
                             let (cont, to_write_tx) = self_rc.with(move |conn| {
                                 let cont = if let Some(mut stream) = conn.streams.get_mut(stream_id) {
                                     if !stream.stream().state.is_closed_local() {
@@ -593,11 +1185,16 @@ impl<T : Types> ConnData<T>
                 })
         });
 
-        let future = future.then(move |r| {
+        // user stream is dropped when stream is reset or connection failed
+        let future = future.select2(cancel).then(move |r| {
             let error_code =
                 match r {
-                    Ok(()) => ErrorCode::NoError,
-                    Err(e) => {
+                    Ok(Either::A(((), _))) => ErrorCode::NoError,
+                    Ok(Either::B(((), _))) => {
+                        debug!("stream {} canceled, dropping handler stream", stream_id);
+                        return Ok(());
+                    }
+                    Err(Either::A((e, _))) | Err(Either::B((e, _))) => {
                         warn!("handler stream error: {:?}", e);
                         ErrorCode::InternalError
                     }
@@ -619,7 +1216,29 @@ pub trait ConnInner : Sized + 'static {
     fn process_headers(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, end_stream: EndStream, headers: Headers)
         -> result::Result<Option<HttpStreamRef<Self::Types>>>;
 
-    fn goaway_received(&mut self, stream_id: StreamId, raw_error_code: u32);
+    /// HEADERS opening new stream with header list larger than
+    /// our SETTINGS_MAX_HEADER_LIST_SIZE
+    fn process_headers_too_large(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, end_stream: EndStream)
+        -> result::Result<Option<HttpStreamRef<Self::Types>>>;
+
+    /// PUSH_PROMISE with decoded promised request headers
+    fn process_push_promise(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, promised_stream_id: StreamId, headers: Headers)
+        -> result::Result<()>;
+
+    fn goaway_received(&mut self, info: GoAwayInfo);
+
+    /// ORIGIN frame received (RFC 8336)
+    fn origin_received(&mut self, origins: Vec<String>);
+
+    /// ALTSVC frame received (RFC 7838)
+    fn alt_svc_received(&mut self, frame: AltSvcFrame);
+
+    /// Called after streams may have been closed or peer settings changed
+    fn streams_updated(&mut self);
+
+    /// RST_STREAM received for a stream which was not yet closed,
+    /// error closes the connection
+    fn stream_reset_by_peer(&mut self, stream_id: StreamId) -> result::Result<()>;
 }
 
 
@@ -630,7 +1249,7 @@ pub struct ReadLoopData<I, T>
         ConnData<T> : ConnInner,
         HttpStreamCommon<T> : HttpStream,
 {
-    pub read: ReadHalf<I>,
+    pub read: PooledRead<ReadHalf<I>>,
     pub inner: RcMut<ConnData<T>>,
 }
 
@@ -643,6 +1262,28 @@ pub struct WriteLoopData<I, T>
 {
     pub write: WriteHalf<I>,
     pub inner: RcMut<ConnData<T>>,
+    // serialized frames not yet written to the socket
+    write_buf: Vec<u8>,
+    // notified when `write_buf` is flushed
+    flush_waiters: Vec<oneshot::Sender<()>>,
+}
+
+pub struct KeepaliveLoopData<T>
+    where
+        T : Types,
+        ConnData<T> : ConnInner,
+        HttpStreamCommon<T> : HttpStream,
+{
+    pub inner: RcMut<ConnData<T>>,
+}
+
+pub struct IdleTimeoutLoopData<T>
+    where
+        T : Types,
+        ConnData<T> : ConnInner,
+        HttpStreamCommon<T> : HttpStream,
+{
+    pub inner: RcMut<ConnData<T>>,
 }
 
 pub struct CommandLoopData<T>
@@ -666,9 +1307,14 @@ impl<I, T> ReadLoopData<I, T>
     fn recv_http_frame(self) -> HttpFuture<(Self, HttpFrame)> {
         let ReadLoopData { read, inner } = self;
 
-        let max_frame_size = inner.with(|inner| inner.conn.peer_settings.max_frame_size);
+        let (max_frame_size, max_continuation_frames) = inner.with(|inner| {
+            (
+                inner.recv_max_frame_size,
+                inner.conf.max_continuation_frames.unwrap_or(DEFAULT_MAX_CONTINUATION_FRAMES),
+            )
+        });
 
-        Box::new(recv_http_frame_join_cont(read, max_frame_size)
+        Box::new(recv_http_frame_join_cont_pooled(read, max_frame_size, max_continuation_frames)
             .map(|(read, frame)| (ReadLoopData { read: read, inner: inner }, frame)))
     }
 
@@ -686,8 +1332,32 @@ impl<I, T> ReadLoopData<I, T>
         Box::new(self.read_process_frame().map(Loop::Continue))
     }
 
-    pub fn run(self) -> HttpFuture<()> {
-        Box::new(loop_fn(self, Self::loop_iter))
+    pub fn run(self) -> HttpFuture<()>
+        where T::ToWriteMessage : From<CommonToWriteMessage>
+    {
+        let inner = self.inner.clone();
+        Box::new(loop_fn(self, Self::loop_iter)
+            .or_else(move |e| {
+                inner.with(|inner| {
+                    // 5.4.1
+                    // An endpoint that encounters a connection error SHOULD first send
+                    // a GOAWAY frame, write loop closes the connection after that
+                    match e {
+                        error::Error::CodeError(error_code) => {
+                            inner.conn_died(&e);
+                            match inner.send_goaway_and_close(error_code) {
+                                Ok(()) => Ok(()),
+                                Err(_) => Err(e),
+                            }
+                        }
+                        e => {
+                            let e = e.into_conn_error();
+                            inner.conn_died(&e);
+                            Err(e)
+                        }
+                    }
+                })
+            }))
     }
 
     fn process_http_frame(self, frame: HttpFrame) -> HttpFuture<Self> {
@@ -707,16 +1377,84 @@ impl<I, T> WriteLoopData<I, T>
         ConnData<T> : ConnInner<Types=T>,
         HttpStreamCommon<T> : HttpStream<Types=T>,
 {
-    fn write_all(self, buf: Vec<u8>) -> HttpFuture<Self> {
-        let WriteLoopData { write, inner } = self;
+    pub fn new(write: WriteHalf<I>, inner: RcMut<ConnData<T>>) -> Self {
+        WriteLoopData {
+            write: write,
+            inner: inner,
+            write_buf: Vec::new(),
+            flush_waiters: Vec::new(),
+        }
+    }
+
+    /// Append serialized frames to write buffer, flush if buffer is full
+    fn write_all(mut self, buf: Vec<u8>) -> HttpFuture<Self> {
+        let write_buffer_size = self.inner.with(|inner| {
+            inner.conf.write_buffer_size.unwrap_or(DEFAULT_WRITE_BUFFER_SIZE)
+        });
+
+        if self.write_buf.is_empty() {
+            self.write_buf = buf;
+        } else {
+            self.write_buf.extend_from_slice(&buf);
+        }
+
+        if self.write_buf.len() >= write_buffer_size as usize {
+            self.flush()
+        } else {
+            Box::new(future::finished(self))
+        }
+    }
+
+    /// Write buffered frames to the socket
+    pub fn flush(self) -> HttpFuture<Self> {
+        let WriteLoopData { write, inner, write_buf, flush_waiters } = self;
+
+        if write_buf.is_empty() {
+            for tx in flush_waiters {
+                // ignore error, caller may be not interested
+                drop(tx.send(()));
+            }
+            return Box::new(future::finished(WriteLoopData::new(write, inner)));
+        }
 
-        Box::new(tokio_io::write_all(write, buf)
-            .map(move |(write, _)| WriteLoopData { write: write, inner: inner })
+        Box::new(tokio_io::write_all(write, write_buf)
+            .map(move |(write, mut write_buf)| {
+                inner.with(|inner| inner.last_frame_sent = Instant::now());
+                for tx in flush_waiters {
+                    // ignore error, caller may be not interested
+                    drop(tx.send(()));
+                }
+                write_buf.clear();
+                WriteLoopData {
+                    write: write,
+                    inner: inner,
+                    write_buf: write_buf,
+                    flush_waiters: Vec::new(),
+                }
+            })
             .map_err(error::Error::from))
     }
 
-    fn write_frame(self, frame: HttpFrame) -> HttpFuture<Self> {
-        debug!("send {:?}", frame);
+    /// Process messages from the stream, flushing buffered frames
+    /// when no more messages are immediately available
+    pub fn run_messages<S, F>(self, messages: S, process_message: F) -> HttpFuture<()>
+        where
+            S : Stream<Error=error::Error> + 'static,
+            F : Fn(Self, S::Item) -> HttpFuture<Self> + 'static,
+    {
+        Box::new(stream_with_flush(messages)
+            .fold(self, move |wl, message| {
+                match message {
+                    StreamWithFlushMessage::Item(message) => process_message(wl, message),
+                    StreamWithFlushMessage::Flush => wl.flush(),
+                }
+            })
+            .map(|_| ()))
+    }
+
+    pub fn write_frame(self, frame: HttpFrame) -> HttpFuture<Self> {
+        let conn_id = self.with_inner(|inner| inner.conn_id);
+        debug!("conn {} stream {}: send {:?}", conn_id, frame.get_stream_id(), frame);
 
         let mut send_buf = VecSendFrame(Vec::new());
         send_buf.send_frame(frame).unwrap();
@@ -738,7 +1476,7 @@ impl<I, T> WriteLoopData<I, T>
         self.write_all(bytes)
     }
 
-    fn send_outg_conn(self) -> HttpFuture<Self> {
+    pub fn send_outg_conn(self) -> HttpFuture<Self> {
         let bytes = self.with_inner(|inner| {
             inner.pop_outg_all_for_conn_bytes()
         });
@@ -763,16 +1501,140 @@ impl<I, T> WriteLoopData<I, T>
         }
     }
 
+    fn process_raw_frame(mut self, frame: RawFrame, tx: oneshot::Sender<()>) -> HttpFuture<Self> {
+        let conn_id = self.with_inner(|inner| inner.conn_id);
+        debug!("conn {} stream {}: send raw frame {:?}", conn_id, frame.get_stream_id(), frame.frame_type());
+
+        self.flush_waiters.push(tx);
+        self.write_all(frame.serialize().to_vec())
+    }
+
+    fn process_close_conn(self, frame: GoawayFrame) -> HttpFuture<Self> {
+        let error_code = frame.error_code();
+        Box::new(self.write_frame(frame.into())
+            .and_then(|wl| wl.flush())
+            .and_then(move |_| future::err(error::Error::CodeError(error_code))))
+    }
+
+    fn process_data_consumed(self, stream_id: StreamId, size: u32) -> HttpFuture<Self> {
+        let r = self.with_inner(move |inner| inner.data_consumed(stream_id, size));
+        Box::new(future::result(r.map(|()| self)))
+    }
+
     pub fn process_common(self, common: CommonToWriteMessage) -> HttpFuture<Self> {
         match common {
             CommonToWriteMessage::TryFlushStream(None) => self.send_outg_conn(),
             CommonToWriteMessage::TryFlushStream(Some(stream_id)) => self.send_outg_stream(stream_id),
             CommonToWriteMessage::Frame(frame) => self.write_frame(frame),
             CommonToWriteMessage::StreamEnd(stream_id, error_code) => self.process_stream_end(stream_id, error_code),
+            CommonToWriteMessage::DataConsumed(stream_id, size) => self.process_data_consumed(stream_id, size),
+            CommonToWriteMessage::RawFrame(frame, tx) => self.process_raw_frame(frame, tx),
+            CommonToWriteMessage::CloseConn(frame) => self.process_close_conn(frame),
         }
     }
 }
 
+impl<T> KeepaliveLoopData<T>
+    where
+        T : Types,
+        ConnData<T> : ConnInner<Types=T>,
+        HttpStreamCommon<T> : HttpStream<Types=T>,
+{
+    fn loop_iter(self, interval: Duration, timeout: Duration) -> HttpFuture<Loop<(), Self>> {
+        let (idle, loop_handle) = self.inner.with(|inner| {
+            (inner.last_frame_received.elapsed(), inner.loop_handle.clone())
+        });
+
+        if idle < interval {
+            let sleep = match reactor::Timeout::new(interval - idle, &loop_handle) {
+                Ok(sleep) => sleep,
+                Err(e) => return Box::new(future::err(e.into())),
+            };
+            return Box::new(sleep
+                .map(move |()| Loop::Continue(self))
+                .map_err(error::Error::from));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        if let Err(e) = self.inner.with(move |inner| inner.ping(tx)) {
+            return Box::new(future::err(e));
+        }
+
+        let ack = rx.map_err(|_| error::Error::Other("connection died"));
+
+        let ack_timeout = match reactor::Timeout::new(timeout, &loop_handle) {
+            Ok(ack_timeout) => ack_timeout,
+            Err(e) => return Box::new(future::err(e.into())),
+        };
+        let ack_timeout = ack_timeout
+            .map_err(error::Error::from)
+            .and_then(|()| {
+                warn!("PING ACK not received in time, closing connection");
+                Err(error::Error::Other("keepalive PING ACK timeout"))
+            });
+
+        Box::new(ack.select(ack_timeout)
+            .map(move |_| Loop::Continue(self))
+            .map_err(|(e, _)| e))
+    }
+
+    /// Send PING after `interval` of inactivity and fail if ACK is not received within `timeout`
+    pub fn run(self, interval: Duration, timeout: Duration) -> HttpFuture<()> {
+        Box::new(loop_fn(self, move |lp| lp.loop_iter(interval, timeout)))
+    }
+}
+
+impl<T> IdleTimeoutLoopData<T>
+    where
+        T : Types,
+        ConnData<T> : ConnInner<Types=T>,
+        HttpStreamCommon<T> : HttpStream<Types=T>,
+{
+    /// Time of last frame sent or received, `None` if connection has open streams:
+    /// connection is not idle while peer is silently receiving a long response
+    fn idle_since(&self) -> Option<Instant> {
+        self.inner.with(|inner| {
+            match inner.streams.is_empty() {
+                true => Some(cmp::max(inner.last_frame_received, inner.last_frame_sent)),
+                false => None,
+            }
+        })
+    }
+
+    fn loop_iter(self, timeout: Duration) -> HttpFuture<Loop<(), Self>> {
+        let idle_since = self.idle_since();
+        let loop_handle = self.inner.with(|inner| inner.loop_handle.clone());
+
+        let sleep = match idle_since {
+            Some(since) => timeout.checked_sub(since.elapsed()).unwrap_or(Duration::from_secs(0)),
+            None => timeout,
+        };
+
+        let sleep = match reactor::Timeout::new(sleep, &loop_handle) {
+            Ok(sleep) => sleep,
+            Err(e) => return Box::new(future::err(e.into())),
+        };
+
+        // check there was no activity while sleeping instead of comparing durations
+        Box::new(sleep
+            .map_err(error::Error::from)
+            .and_then(move |()| {
+                if idle_since.is_some() && self.idle_since() == idle_since {
+                    warn!("connection is idle for {:?}, closing", timeout);
+                    Err(error::Error::ConnectionTimeout)
+                } else {
+                    Ok(Loop::Continue(self))
+                }
+            }))
+    }
+
+    /// Fail with `Error::ConnectionTimeout` when no frames are sent or received
+    /// within `timeout` while there are no open streams
+    pub fn run(self, timeout: Duration) -> HttpFuture<()> {
+        Box::new(loop_fn(self, move |lp| lp.loop_iter(timeout)))
+    }
+}
+
 impl<T> CommandLoopData<T>
     where
         T : Types,
@@ -3,6 +3,7 @@
 mod conn;
 mod stream;
 mod stream_map;
+mod stream_id;
 mod types;
 mod conf;
 
@@ -1,30 +1,60 @@
 use std::collections::VecDeque;
 use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
 
 use futures::sync::mpsc::UnboundedSender;
 
 use bytes::Bytes;
 
 use error;
+use result;
 
+use solicit::StreamId;
 use solicit::session::StreamState;
 use solicit::WindowSize;
-use solicit::DEFAULT_SETTINGS;
 use solicit::header::Headers;
 use solicit::connection::EndStream;
+use solicit::frame::headers::StreamDependency;
 
 use futures_misc::ResultOrEof;
 use futures_misc::LatchController;
+use futures_misc::CancelController;
 
 use stream_part::*;
 
 use error::ErrorCode;
 
 use super::types::Types;
+use super::conn::StreamStateSnapshot;
 
 
+/// Priority of outgoing stream, sent in HEADERS frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamPriority {
+    /// Stream this stream depends on, zero for root
+    pub dependency: StreamId,
+    /// Weight as sent on wire, i. e. actual weight minus one
+    pub weight: u8,
+    pub exclusive: bool,
+}
+
+impl StreamPriority {
+    pub fn new(dependency: StreamId, weight: u8, exclusive: bool) -> StreamPriority {
+        StreamPriority {
+            dependency: dependency,
+            weight: weight,
+            exclusive: exclusive,
+        }
+    }
+
+    pub fn to_dependency(&self) -> StreamDependency {
+        StreamDependency::new(self.dependency, self.weight, self.exclusive)
+    }
+}
+
 pub enum HttpStreamCommand {
-    Headers(Headers, EndStream),
+    Headers(Headers, EndStream, Option<StreamPriority>),
     Data(Bytes, EndStream),
     Rst(ErrorCode),
 }
@@ -40,7 +70,7 @@ impl HttpStreamCommand {
                 HttpStreamCommand::Data(data, end_stream)
             },
             HttpStreamPartContent::Headers(headers) => {
-                HttpStreamCommand::Headers(headers, end_stream)
+                HttpStreamCommand::Headers(headers, end_stream, None)
             },
         }
     }
@@ -58,6 +88,15 @@ pub struct StreamOutQueue {
     data_size: usize,
 }
 
+/// Flow-controlled bytes of padded DATA frame in addition to data: pad length field and padding
+pub fn data_padding_overhead(padding: u8) -> i32 {
+    if padding == 0 {
+        0
+    } else {
+        1 + padding as i32
+    }
+}
+
 fn data_size(content: &HttpStreamPartContent) -> usize {
     match *content {
         HttpStreamPartContent::Headers(_) => 0,
@@ -78,6 +117,11 @@ impl StreamOutQueue {
         self.queue.is_empty()
     }
 
+    /// Total size of DATA frames payload in the queue
+    pub fn data_size(&self) -> usize {
+        self.data_size
+    }
+
     pub fn push_back(&mut self, part: HttpStreamPartContent) {
         if let Some(_) = self.outgoing_end {
             return;
@@ -137,10 +181,27 @@ pub struct HttpStreamCommon<T : Types> {
     pub peer_tx: Option<UnboundedSender<ResultOrEof<HttpStreamPart, error::Error>>>,
     // task waiting for window increase
     pub ready_to_write: LatchController,
+    // sent with first HEADERS frame
+    pub priority: Option<StreamPriority>,
+    // when set, stream in window is released when consumer takes DATA from `peer_tx`
+    // rather than when DATA is received; counter is DATA bytes not yet taken by consumer
+    pub in_window_by_consumer: Option<Arc<AtomicUsize>>,
+    // DATA bytes consumed since last stream WINDOW_UPDATE
+    pub in_consumed: u32,
+    // DATA payload bytes sent and received, padding excluded
+    pub data_sent: u64,
+    pub data_received: u64,
+    // `content-length` of received headers, checked against DATA received
+    pub content_length: Option<u64>,
+    // stream is reset with `CANCEL` when more DATA is received
+    pub max_body_size: Option<u64>,
+    // canceled when stream is reset or connection failed
+    pub cancel: Option<CancelController>,
 }
 
 impl<T : Types> HttpStreamCommon<T> {
     pub fn new(
+        in_window_size: u32,
         out_window_size: u32,
         peer_tx: UnboundedSender<ResultOrEof<HttpStreamPart, error::Error>>,
         ready_to_write: LatchController,
@@ -150,11 +211,41 @@ impl<T : Types> HttpStreamCommon<T> {
         HttpStreamCommon {
             specific: specific,
             state: StreamState::Open,
-            in_window_size: WindowSize::new(DEFAULT_SETTINGS.initial_window_size as i32),
+            in_window_size: WindowSize::new(in_window_size as i32),
             out_window_size: WindowSize::new(out_window_size as i32),
             outgoing: StreamOutQueue::new(),
             peer_tx: Some(peer_tx),
             ready_to_write: ready_to_write,
+            priority: None,
+            in_window_by_consumer: None,
+            in_consumed: 0,
+            data_sent: 0,
+            data_received: 0,
+            content_length: None,
+            max_body_size: None,
+            cancel: None,
+        }
+    }
+
+    /// Received DATA does not match declared `content-length` (8.1.2.6):
+    /// more DATA than declared, or less when stream is ended
+    pub fn content_length_mismatch(&self, end_stream: bool) -> bool {
+        match self.content_length {
+            Some(content_length) => {
+                self.data_received > content_length
+                    || end_stream && self.data_received != content_length
+            }
+            None => false,
+        }
+    }
+
+    pub fn snapshot(&self) -> StreamStateSnapshot {
+        StreamStateSnapshot {
+            state: self.state,
+            out_window_size: self.out_window_size.size(),
+            in_window_size: self.in_window_size.size(),
+            data_sent: self.data_sent,
+            data_received: self.data_received,
         }
     }
 
@@ -179,7 +270,11 @@ impl<T : Types> HttpStreamCommon<T> {
         }
     }
 
-    pub fn pop_outg(&mut self, conn_out_window_size: &mut WindowSize) -> Option<HttpStreamCommand> {
+    /// Pop next frame to send. When `padding` is not zero, DATA is limited
+    /// to fit into single padded frame of `max_frame_size`.
+    pub fn pop_outg(&mut self, conn_out_window_size: &mut WindowSize, padding: u8, max_frame_size: u32)
+        -> Option<HttpStreamCommand>
+    {
         if self.outgoing.is_empty() {
             return
                 if let Some(error_code) = self.outgoing.end() {
@@ -209,13 +304,24 @@ impl<T : Types> HttpStreamCommon<T> {
             if last {
                 self.close_local();
             }
-            return Some(HttpStreamCommand::from(HttpStreamPart {
-                content: r,
-                last: last,
-            }))
+            let end_stream = if last { EndStream::Yes } else { EndStream::No };
+            return Some(match r {
+                HttpStreamPartContent::Headers(headers) =>
+                    HttpStreamCommand::Headers(headers, end_stream, self.priority.take()),
+                HttpStreamPartContent::Data(..) => unreachable!(),
+            })
+        }
+
+        // 6.9.1: pad length field and padding count against flow control windows
+        let overhead = data_padding_overhead(padding);
+
+        // Max of connection and stream window size
+        let mut max_window = cmp::min(self.out_window_size.size(), conn_out_window_size.size()) - overhead;
+        if padding != 0 {
+            max_window = cmp::min(max_window, max_frame_size as i32 - overhead);
         }
 
-        if self.out_window_size.size() <= 0 {
+        if max_window <= 0 {
             return None
         }
 
@@ -226,9 +332,6 @@ impl<T : Types> HttpStreamCommon<T> {
                 unreachable!()
             };
 
-        // Max of connection and stream window size
-        let max_window = cmp::min(self.out_window_size.size(), conn_out_window_size.size());
-
         if data.len() as usize > max_window as usize {
             trace!("truncating data of len {} to {}", data.len(), max_window);
             let size = max_window as usize;
@@ -236,8 +339,9 @@ impl<T : Types> HttpStreamCommon<T> {
             self.outgoing.push_front(HttpStreamPartContent::Data(rem));
         };
 
-        self.out_window_size.try_decrease(data.len() as i32).unwrap();
-        conn_out_window_size.try_decrease(data.len() as i32).unwrap();
+        self.out_window_size.try_decrease(data.len() as i32 + overhead).unwrap();
+        conn_out_window_size.try_decrease(data.len() as i32 + overhead).unwrap();
+        self.data_sent += data.len() as u64;
 
         let last = self.outgoing.end() == Some(ErrorCode::NoError);
         if last {
@@ -252,32 +356,70 @@ impl<T : Types> HttpStreamCommon<T> {
 
     pub fn _pop_outg_all(&mut self, conn_out_window_size: &mut WindowSize) -> Vec<HttpStreamCommand> {
         let mut r = Vec::new();
-        while let Some(p) = self.pop_outg(conn_out_window_size) {
+        while let Some(p) = self.pop_outg(conn_out_window_size, 0, 0) {
             r.push(p);
         }
         r
     }
 
-    pub fn new_data_chunk(&mut self, data: &[u8], last: bool) {
+    /// Pass DATA to consumer, return false if consumer is dead
+    /// `data` is passed to consumer as is, so it may reference read buffer
+    pub fn new_data_chunk(&mut self, data: Bytes, last: bool) -> bool {
         if let Some(ref mut response_handler) = self.peer_tx {
             // TODO: reset stream if rx is dead
-            drop(response_handler.send(ResultOrEof::Item(HttpStreamPart {
-                content: HttpStreamPartContent::Data(Bytes::from(data)),
+            response_handler.send(ResultOrEof::Item(HttpStreamPart {
+                content: HttpStreamPartContent::Data(data),
                 last: last,
-            })));
+            })).is_ok()
+        } else {
+            false
+        }
+    }
+
+    /// Account DATA consumed by consumer,
+    /// return stream-level WINDOW_UPDATE increment when half of initial window is consumed
+    pub fn consume_in_window(&mut self, size: u32, initial_window_size: u32)
+        -> result::Result<Option<u32>>
+    {
+        self.in_consumed += size;
+
+        // no point in increasing window if peer won't send anything
+        if self.state.is_closed_remote() {
+            return Ok(None);
+        }
+
+        if self.in_consumed == 0 || self.in_consumed < initial_window_size / 2 {
+            return Ok(None);
         }
+
+        let increment = self.in_consumed;
+        self.in_consumed = 0;
+        self.in_window_size.try_increase(increment)
+            .map_err(|()| error::Error::Other("failed to increase window size"))?;
+        Ok(Some(increment))
     }
 
-    pub fn rst(&mut self, error_code: ErrorCode) {
+    pub fn rst(&mut self, stream_id: StreamId, error_code: ErrorCode) {
+        if let Some(ref cancel) = self.cancel {
+            cancel.cancel();
+        }
         if let Some(ref mut response_handler) = self.peer_tx.take() {
-            drop(response_handler.send(ResultOrEof::Error(error::Error::CodeError(error_code))));
+            drop(response_handler.send(ResultOrEof::Error(error::Error::StreamReset(stream_id, error_code))));
         }
     }
 
-    pub fn goaway_recvd(&mut self, _raw_error_code: u32) {
+    pub fn goaway_recvd(&mut self, error_code: ErrorCode, debug_data: Bytes) {
+        self.conn_error(error::Error::ConnectionError(error_code, debug_data));
+    }
+
+    /// Fail the stream because whole connection failed
+    pub fn conn_error(&mut self, error: error::Error) {
+        if let Some(ref cancel) = self.cancel {
+            cancel.cancel();
+        }
         if let Some(response_handler) = self.peer_tx.take() {
             // it is OK to ignore error: handler may be already dead
-            drop(response_handler.send(ResultOrEof::Error(error::Error::Other("peer sent GOAWAY"))));
+            drop(response_handler.send(ResultOrEof::Error(error)));
         }
     }
 }
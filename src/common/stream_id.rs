@@ -0,0 +1,116 @@
+use solicit::StreamId;
+
+use error;
+use result;
+
+
+/// Largest stream identifier, stream id is 31-bit integer (5.1.1)
+pub const MAX_STREAM_ID: StreamId = 0x7fffffff;
+
+/// Allocator of ids of locally initiated streams of one connection.
+///
+/// Client initiated streams use odd ids and server initiated streams use even ids;
+/// ids are allocated in increasing order and never reused within connection (5.1.1).
+/// Allocator is created with connection, so after reconnect ids start from
+/// the first id again.
+pub struct StreamIdAllocator {
+    /// Id returned by next allocation, greater than `MAX_STREAM_ID` when exhausted
+    next: u64,
+}
+
+impl StreamIdAllocator {
+    /// Allocator yielding `first_id`, `first_id + 2`, ...
+    pub fn new(first_id: StreamId) -> StreamIdAllocator {
+        assert!(first_id == 1 || first_id == 2);
+        StreamIdAllocator {
+            next: first_id as u64,
+        }
+    }
+
+    /// Allocate stream id, `None` if id space is exhausted
+    pub fn allocate(&mut self) -> Option<StreamId> {
+        if self.is_exhausted() {
+            return None;
+        }
+        let id = self.next as StreamId;
+        self.next += 2;
+        Some(id)
+    }
+
+    /// Last allocated id, `0` if no ids allocated yet
+    pub fn last(&self) -> StreamId {
+        if self.next <= 2 {
+            0
+        } else {
+            (self.next - 2) as StreamId
+        }
+    }
+
+    /// No more streams can be opened on connection
+    pub fn is_exhausted(&self) -> bool {
+        self.next > MAX_STREAM_ID as u64
+    }
+
+    /// Skip ids so that `next_id` is allocated next, for tests of id space exhaustion
+    pub fn fast_forward(&mut self, next_id: StreamId) -> result::Result<()> {
+        if (next_id as u64) < self.next {
+            return Err(error::Error::Other("stream ids cannot be reused"));
+        }
+        if next_id > MAX_STREAM_ID || next_id % 2 != self.next as StreamId % 2 {
+            return Err(error::Error::Other("invalid stream id"));
+        }
+        self.next = next_id as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn client_odd() {
+        let mut ids = StreamIdAllocator::new(1);
+        assert_eq!(0, ids.last());
+        assert_eq!(Some(1), ids.allocate());
+        assert_eq!(Some(3), ids.allocate());
+        assert_eq!(Some(5), ids.allocate());
+        assert_eq!(5, ids.last());
+    }
+
+    #[test]
+    fn server_even() {
+        let mut ids = StreamIdAllocator::new(2);
+        assert_eq!(0, ids.last());
+        assert_eq!(Some(2), ids.allocate());
+        assert_eq!(Some(4), ids.allocate());
+        assert_eq!(4, ids.last());
+    }
+
+    #[test]
+    fn exhausted() {
+        let mut ids = StreamIdAllocator::new(1);
+        ids.fast_forward(MAX_STREAM_ID - 2).expect("fast_forward");
+        assert_eq!(Some(MAX_STREAM_ID - 2), ids.allocate());
+        assert!(!ids.is_exhausted());
+        assert_eq!(Some(MAX_STREAM_ID), ids.allocate());
+        assert!(ids.is_exhausted());
+        assert_eq!(None, ids.allocate());
+        assert_eq!(MAX_STREAM_ID, ids.last());
+
+        let mut ids = StreamIdAllocator::new(2);
+        ids.fast_forward(MAX_STREAM_ID - 1).expect("fast_forward");
+        assert_eq!(Some(MAX_STREAM_ID - 1), ids.allocate());
+        assert_eq!(None, ids.allocate());
+    }
+
+    #[test]
+    fn fast_forward_invalid() {
+        let mut ids = StreamIdAllocator::new(1);
+        assert_eq!(Some(1), ids.allocate());
+        assert!(ids.fast_forward(1).is_err());
+        assert!(ids.fast_forward(4).is_err());
+        assert!(ids.fast_forward(MAX_STREAM_ID + 2).is_err());
+        assert_eq!(Some(3), ids.allocate());
+    }
+}
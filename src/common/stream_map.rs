@@ -1,5 +1,6 @@
 use solicit::StreamId;
 use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::collections::hash_map::Entry;
 use std::collections::hash_map::OccupiedEntry;
 
@@ -10,30 +11,56 @@ use solicit::WindowSize;
 use super::stream::HttpStreamCommon;
 use super::stream::HttpStreamCommand;
 use super::types::Types;
+use super::conn::StreamStateSnapshot;
 
+use metrics::SharedMetrics;
+
+
+/// Number of recently closed stream ids remembered
+/// to tolerate frames peer sent before it learned about stream close
+const RECENTLY_CLOSED_STREAMS: usize = 100;
 
 pub struct StreamMap<T : Types> {
+    /// Id of connection, for logging
+    conn_id: u64,
     pub map: HashMap<StreamId, HttpStreamCommon<T>>,
+    /// Ids of recently removed streams, oldest first
+    recently_closed: VecDeque<StreamId>,
+    /// Streams opened and closed are counted here
+    metrics: SharedMetrics,
 }
 
 /// Reference to a stream within `StreamMap`
 pub struct HttpStreamRef<'m, T : Types + 'm> {
+    conn_id: u64,
     entry: OccupiedEntry<'m, StreamId, HttpStreamCommon<T>>,
+    recently_closed: &'m mut VecDeque<StreamId>,
+    metrics: &'m SharedMetrics,
 }
 
 impl<T : Types> StreamMap<T> {
-    pub fn new() -> StreamMap<T> {
+    pub fn new(conn_id: u64, metrics: SharedMetrics) -> StreamMap<T> {
         StreamMap {
+            conn_id: conn_id,
             map: HashMap::new(),
+            recently_closed: VecDeque::new(),
+            metrics: metrics,
         }
     }
 
+    /// Stream was removed from the map recently
+    pub fn is_recently_closed(&self, id: StreamId) -> bool {
+        self.recently_closed.contains(&id)
+    }
+
     /// Insert a stream into a map and return a reference to it
     pub fn insert(&mut self, id: StreamId, stream: HttpStreamCommon<T>) -> HttpStreamRef<T> {
         match self.map.entry(id) {
             Entry::Occupied(_) => panic!("stream to insert that already exists: {}", id),
             Entry::Vacant(v) => v.insert(stream),
         };
+        self.metrics.lock().unwrap().record_stream_opened();
+        debug!("conn {} stream {}: opened", self.conn_id, id);
 
         // unfortunately HashMap doesn't have an API to convert vacant entry into occupied
         self.get_mut(id).unwrap()
@@ -42,7 +69,10 @@ impl<T : Types> StreamMap<T> {
     pub fn get_mut(&mut self, id: StreamId) -> Option<HttpStreamRef<T>> {
         match self.map.entry(id) {
             Entry::Occupied(e) => Some(HttpStreamRef {
+                conn_id: self.conn_id,
                 entry: e,
+                recently_closed: &mut self.recently_closed,
+                metrics: &self.metrics,
             }),
             Entry::Vacant(_) => None,
         }
@@ -60,6 +90,14 @@ impl<T : Types> StreamMap<T> {
         for r_id in stream_ids {
             r.push((r_id, self.map.remove(&r_id).unwrap()))
         }
+        self.metrics.lock().unwrap().record_streams_closed(r.len());
+        r
+    }
+
+    /// Remove all streams, e. g. when connection died
+    pub fn remove_all(&mut self) -> Vec<(StreamId, HttpStreamCommon<T>)> {
+        let r: Vec<_> = self.map.drain().collect();
+        self.metrics.lock().unwrap().record_streams_closed(r.len());
         r
     }
 
@@ -67,12 +105,22 @@ impl<T : Types> StreamMap<T> {
         self.map.is_empty()
     }
 
+    /// Number of streams initiated by this side of connection
+    pub fn local_count(&self) -> usize {
+        self.map.keys().filter(|&&id| T::is_init_locally(id)).count()
+    }
+
+    /// Number of streams initiated by peer
+    pub fn peer_count(&self) -> usize {
+        self.map.keys().filter(|&&id| !T::is_init_locally(id)).count()
+    }
+
     pub fn stream_ids(&self) -> Vec<StreamId> {
         self.map.keys().cloned().collect()
     }
 
-    pub fn snapshot(&self) -> HashMap<StreamId, StreamState> {
-        self.map.iter().map(|(&k, s)| (k, s.state)).collect()
+    pub fn snapshot(&self) -> HashMap<StreamId, StreamStateSnapshot> {
+        self.map.iter().map(|(&k, s)| (k, s.snapshot())).collect()
     }
 }
 
@@ -90,30 +138,39 @@ impl <'m, T : Types + 'm> HttpStreamRef<'m, T> {
     }
 
     fn remove(self) {
-        self.entry.remove();
+        let (stream_id, _) = self.entry.remove_entry();
+        self.metrics.lock().unwrap().record_streams_closed(1);
+        debug!("conn {} stream {}: closed", self.conn_id, stream_id);
+        if self.recently_closed.len() == RECENTLY_CLOSED_STREAMS {
+            self.recently_closed.pop_front();
+        }
+        self.recently_closed.push_back(stream_id);
     }
 
     pub fn remove_if_closed(mut self) {
         if self.stream().state == StreamState::Closed {
-            debug!("removing stream {}, because it's closed", self.id());
             self.remove();
         }
     }
 
     pub fn check_ready_to_write(&mut self, conn_out_window_size: &mut WindowSize) {
-        if conn_out_window_size.size() > 0 && self.stream().out_window_size.size() > 0 {
+        // Do not pull more data from the user stream
+        // while already queued data exhausts the stream window
+        let queued = self.stream().outgoing.data_size() as i64;
+        if conn_out_window_size.size() > 0 && self.stream().out_window_size.size() as i64 > queued {
             self.stream().ready_to_write.open();
         } else {
             self.stream().ready_to_write.close();
         }
     }
 
-    pub fn pop_outg_all_maybe_remove(mut self, conn_out_window_size: &mut WindowSize)
-        -> Vec<HttpStreamCommand>
+    pub fn pop_outg_all_maybe_remove(
+        mut self, conn_out_window_size: &mut WindowSize, padding: u8, max_frame_size: u32)
+            -> Vec<HttpStreamCommand>
     {
         let mut r = Vec::new();
         loop {
-            if let Some(c) = self.stream().pop_outg(conn_out_window_size) {
+            if let Some(c) = self.stream().pop_outg(conn_out_window_size, padding, max_frame_size) {
                 r.push(c);
             } else {
                 self.check_ready_to_write(conn_out_window_size);
@@ -125,7 +182,8 @@ impl <'m, T : Types + 'm> HttpStreamRef<'m, T> {
 
     // Reset stream and remove it
     pub fn rst_remove(mut self, error_code: ErrorCode) {
-        self.stream().rst(error_code);
+        let stream_id = self.id();
+        self.stream().rst(stream_id, error_code);
         self.stream().state = StreamState::Closed;
         self.remove_if_closed();
     }
@@ -0,0 +1,489 @@
+//! Decoding of response body and encoding of request body according to `content-encoding`
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use std::mem;
+
+use bytes::Bytes;
+
+use futures::Async;
+use futures::Poll;
+use futures::stream::Stream;
+
+use flate2::Compression;
+use flate2::Decompress;
+use flate2::FlushDecompress;
+use flate2::Status;
+use flate2::write::GzEncoder;
+use flate2::write::MultiGzDecoder;
+
+use error;
+use result;
+use solicit::header::Headers;
+use stream_part::*;
+
+
+/// Decoder of zlib stream which, unlike `flate2::write::ZlibDecoder`,
+/// reports truncated stream
+struct ZlibDecoder {
+    decompress: Decompress,
+    output: Vec<u8>,
+    stream_end: bool,
+}
+
+impl ZlibDecoder {
+    fn new() -> ZlibDecoder {
+        ZlibDecoder {
+            decompress: Decompress::new(true),
+            output: Vec::new(),
+            stream_end: false,
+        }
+    }
+
+    fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !self.stream_end {
+            if self.output.capacity() - self.output.len() < 4096 {
+                let reserve = cmp::max(4096, data.len() * 2);
+                self.output.reserve(reserve);
+            }
+
+            let total_in = self.decompress.total_in();
+            let status = self.decompress.decompress_vec(data, &mut self.output, FlushDecompress::None)?;
+            data = &data[(self.decompress.total_in() - total_in) as usize..];
+
+            if status == Status::StreamEnd {
+                self.stream_end = true;
+            } else if data.is_empty() && self.output.len() < self.output.capacity() {
+                // all input consumed and all available output is taken
+                return Ok(());
+            }
+        }
+
+        if !data.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "data after end of zlib stream"));
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.stream_end {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated zlib stream"));
+        }
+        Ok(())
+    }
+}
+
+/// Decoder of supported content coding
+enum Decoder {
+    Gzip(MultiGzDecoder<Vec<u8>>),
+    /// `deflate` content coding is zlib format (RFC 7230, 4.2.2)
+    Deflate(ZlibDecoder),
+}
+
+impl Decoder {
+    /// Decoder for `content-encoding` of headers, `None` if body is not encoded
+    /// or encoding is not supported
+    fn for_headers(headers: &Headers) -> Option<Decoder> {
+        let encodings = headers.get_all("content-encoding");
+        if encodings.len() != 1 {
+            return None;
+        }
+        match encodings[0].trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Decoder::Gzip(MultiGzDecoder::new(Vec::new()))),
+            "deflate" => Some(Decoder::Deflate(ZlibDecoder::new())),
+            _ => None,
+        }
+    }
+
+    fn take_output(&mut self) -> Bytes {
+        let output = match *self {
+            Decoder::Gzip(ref mut d) => d.get_mut(),
+            Decoder::Deflate(ref mut d) => &mut d.output,
+        };
+        Bytes::from(mem::replace(output, Vec::new()))
+    }
+
+    /// Decode next chunk of body, encoded data may be split at any byte
+    fn decode(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        match *self {
+            Decoder::Gzip(ref mut d) => { d.write_all(data)?; d.flush()?; }
+            Decoder::Deflate(ref mut d) => d.write(data)?,
+        }
+        Ok(self.take_output())
+    }
+
+    /// Decode remaining data at the end of body, fail if body is truncated
+    fn finish(&mut self) -> io::Result<Bytes> {
+        match *self {
+            Decoder::Gzip(ref mut d) => d.try_finish()?,
+            Decoder::Deflate(ref mut d) => d.finish()?,
+        }
+        Ok(self.take_output())
+    }
+}
+
+/// Transformation of body DATA according to content coding
+trait Coder {
+    /// Code next chunk of body
+    fn code(&mut self, data: &[u8]) -> result::Result<Bytes>;
+    /// Code remaining data at the end of body, `fed` is false if body had no data
+    fn finish(&mut self, fed: bool) -> result::Result<Bytes>;
+}
+
+impl Coder for Decoder {
+    fn code(&mut self, data: &[u8]) -> result::Result<Bytes> {
+        self.decode(data).map_err(error::Error::DecompressionError)
+    }
+
+    fn finish(&mut self, fed: bool) -> result::Result<Bytes> {
+        // e. g. response to HEAD or 304 with `content-encoding`
+        if !fed {
+            return Ok(Bytes::new());
+        }
+        Decoder::finish(self).map_err(error::Error::DecompressionError)
+    }
+}
+
+/// `gzip` encoder of request body
+struct GzipEncoder(GzEncoder<Vec<u8>>);
+
+impl GzipEncoder {
+    fn take_output(&mut self) -> Bytes {
+        Bytes::from(mem::replace(self.0.get_mut(), Vec::new()))
+    }
+}
+
+impl Coder for GzipEncoder {
+    fn code(&mut self, data: &[u8]) -> result::Result<Bytes> {
+        // flush, so streamed body is not delayed until encoder buffer is full
+        self.0.write_all(data)?;
+        self.0.flush()?;
+        Ok(self.take_output())
+    }
+
+    fn finish(&mut self, _fed: bool) -> result::Result<Bytes> {
+        // empty body is still encoded as valid gzip stream
+        self.0.try_finish()?;
+        Ok(self.take_output())
+    }
+}
+
+/// Body stream with DATA transformed by `Coder`, trailers are passed as is
+struct CodingStream<C : Coder> {
+    stream: HttpPartStream,
+    // `None` after body is finished
+    coder: Option<C>,
+    // coded parts not yet taken by consumer
+    pending: VecDeque<HttpStreamPart>,
+    // any DATA was passed to coder
+    fed: bool,
+    // fail with `Error::BodyTooLarge` when coded body exceeds this many bytes
+    max_size: Option<u64>,
+    coded_size: u64,
+}
+
+impl<C : Coder> CodingStream<C> {
+    fn new(stream: HttpPartStream, coder: C, max_size: Option<u64>) -> CodingStream<C> {
+        CodingStream {
+            stream: stream,
+            coder: Some(coder),
+            pending: VecDeque::new(),
+            fed: false,
+            max_size: max_size,
+            coded_size: 0,
+        }
+    }
+
+    fn check_size(&mut self, coded: Bytes) -> result::Result<Bytes> {
+        self.coded_size += coded.len() as u64;
+        match self.max_size {
+            Some(max_size) if self.coded_size > max_size => Err(error::Error::BodyTooLarge(max_size)),
+            _ => Ok(coded),
+        }
+    }
+
+    fn finish(&mut self) -> result::Result<Bytes> {
+        let tail = match self.coder.take() {
+            Some(mut coder) => coder.finish(self.fed)?,
+            None => Bytes::new(),
+        };
+        self.check_size(tail)
+    }
+
+    fn code(&mut self, data: &[u8]) -> result::Result<Bytes> {
+        if data.is_empty() {
+            return Ok(Bytes::new());
+        }
+        self.fed = true;
+        let coded = match self.coder {
+            Some(ref mut coder) => coder.code(data)?,
+            None => return Err(error::Error::Other("DATA after end of coded body")),
+        };
+        self.check_size(coded)
+    }
+}
+
+impl<C : Coder> Stream for CodingStream<C> {
+    type Item = HttpStreamPart;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Option<HttpStreamPart>, error::Error> {
+        loop {
+            if let Some(part) = self.pending.pop_front() {
+                return Ok(Async::Ready(Some(part)));
+            }
+
+            match try_ready!(self.stream.poll()) {
+                Some(HttpStreamPart { content: HttpStreamPartContent::Data(data), last }) => {
+                    let mut coded = self.code(&data)?;
+                    if last {
+                        let tail = self.finish()?;
+                        if !tail.is_empty() {
+                            coded.extend_from_slice(&tail);
+                        }
+                        self.pending.push_back(HttpStreamPart::last_data(coded));
+                    } else if !coded.is_empty() {
+                        self.pending.push_back(HttpStreamPart::intermediate_data(coded));
+                    }
+                }
+                Some(part @ HttpStreamPart { content: HttpStreamPartContent::Headers(..), .. }) => {
+                    // trailers end the body
+                    let tail = self.finish()?;
+                    if !tail.is_empty() {
+                        self.pending.push_back(HttpStreamPart::intermediate_data(tail));
+                    }
+                    self.pending.push_back(part);
+                }
+                None => {
+                    let tail = self.finish()?;
+                    if tail.is_empty() {
+                        return Ok(Async::Ready(None));
+                    }
+                    self.pending.push_back(HttpStreamPart::last_data(tail));
+                }
+            }
+        }
+    }
+}
+
+/// Decode body if headers specify supported `content-encoding`.
+///
+/// `content-encoding` and `content-length` are removed from headers of decoded response.
+/// Decoded body fails with `Error::BodyTooLarge` if it exceeds `max_size`.
+pub fn decompress(mut headers: Headers, body: HttpPartStream, max_size: Option<u64>)
+    -> (Headers, HttpPartStream)
+{
+    match Decoder::for_headers(&headers) {
+        Some(decoder) => {
+            headers.0.retain(|h| h.name() != b"content-encoding" && h.name() != b"content-length");
+            (headers, HttpPartStream::new(CodingStream::new(body, decoder, max_size)))
+        }
+        None => (headers, body),
+    }
+}
+
+/// Encode body with `gzip` and set `content-encoding` header.
+///
+/// Body is not changed if headers already have `content-encoding`,
+/// `content-length` is removed from headers of encoded request.
+pub fn compress_gzip(headers: &mut Headers, body: HttpPartStream) -> HttpPartStream {
+    if headers.get_opt("content-encoding").is_some() {
+        return body;
+    }
+    headers.0.retain(|h| h.name() != b"content-length");
+    headers.add("content-encoding", "gzip");
+    let encoder = GzipEncoder(GzEncoder::new(Vec::new(), Compression::default()));
+    HttpPartStream::new(CodingStream::new(body, encoder, None))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use bytes::Bytes;
+
+    use futures::Future;
+    use futures::stream;
+    use futures::stream::Stream;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use flate2::write::ZlibEncoder;
+
+    use error;
+    use result;
+    use solicit::header::Headers;
+    use stream_part::*;
+    use message::SimpleHttpMessage;
+
+    use super::decompress;
+    use super::compress_gzip;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn headers_with_encoding(encoding: &str) -> Headers {
+        let mut headers = Headers::ok_200();
+        headers.add("content-encoding", encoding);
+        headers.add("content-length", "100");
+        headers
+    }
+
+    /// Decompress body split in chunks of given size
+    fn decompress_chunks(headers: Headers, body: &[u8], chunk_size: usize, trailers: Option<Headers>)
+        -> result::Result<SimpleHttpMessage>
+    {
+        decompress_chunks_limited(headers, body, chunk_size, trailers, None)
+    }
+
+    fn decompress_chunks_limited(
+        headers: Headers, body: &[u8], chunk_size: usize, trailers: Option<Headers>, max_size: Option<u64>)
+            -> result::Result<SimpleHttpMessage>
+    {
+        let mut parts: Vec<result::Result<HttpStreamPart>> = body.chunks(chunk_size)
+            .map(|c| Ok(HttpStreamPart::intermediate_data(Bytes::from(c))))
+            .collect();
+        if let Some(trailers) = trailers {
+            parts.push(Ok(HttpStreamPart::last_headers(trailers)));
+        }
+        let (headers, body) = decompress(headers, HttpPartStream::new(stream::iter_result(parts)), max_size);
+        let parts = body.collect().wait()?;
+        let mut message = SimpleHttpMessage::from_parts(parts);
+        message.headers = headers;
+        Ok(message)
+    }
+
+    #[test]
+    fn any_chunk_boundary() {
+        let body = b"hello hello hello hello, compressed world".repeat(10);
+        for &(encoding, ref encoded) in &[("gzip", gzip(&body)), ("deflate", deflate(&body))] {
+            for chunk_size in 1..encoded.len() + 1 {
+                let message = decompress_chunks(headers_with_encoding(encoding), &encoded, chunk_size, None)
+                    .expect("decompress");
+                assert_eq!(&body[..], &message.body[..], "{} chunk size {}", encoding, chunk_size);
+                assert_eq!(None, message.headers.get_opt("content-encoding"));
+                assert_eq!(None, message.headers.get_opt("content-length"));
+            }
+        }
+    }
+
+    #[test]
+    fn deflate_with_trailers() {
+        let body = b"deflated body".to_vec();
+        let mut trailers = Headers::new();
+        trailers.add("x-checksum", "1");
+        let message = decompress_chunks(
+            headers_with_encoding("deflate"), &deflate(&body), 3, Some(trailers))
+                .expect("decompress");
+        assert_eq!(&body[..], &message.body[..]);
+        assert_eq!("1", message.trailers.expect("trailers").get("x-checksum"));
+    }
+
+    #[test]
+    fn not_encoded() {
+        let message = decompress_chunks(Headers::ok_200(), b"plain", 2, None).expect("decompress");
+        assert_eq!(&b"plain"[..], &message.body[..]);
+
+        // unsupported encoding is passed as is
+        let message = decompress_chunks(headers_with_encoding("br"), b"brotli", 2, None)
+            .expect("decompress");
+        assert_eq!(&b"brotli"[..], &message.body[..]);
+        assert_eq!("br", message.headers.get("content-encoding"));
+    }
+
+    #[test]
+    fn corrupted() {
+        match decompress_chunks(headers_with_encoding("gzip"), b"not gzip at all", 4, None) {
+            Err(error::Error::DecompressionError(..)) => {}
+            r => panic!("wrong result: {:?}", r.map(|m| m.body)),
+        }
+
+        let encoded = gzip(b"truncated body");
+        match decompress_chunks(headers_with_encoding("gzip"), &encoded[..encoded.len() - 4], 4, None) {
+            Err(error::Error::DecompressionError(..)) => {}
+            r => panic!("wrong result: {:?}", r.map(|m| m.body)),
+        }
+
+        let encoded = deflate(b"truncated body");
+        match decompress_chunks(headers_with_encoding("deflate"), &encoded[..encoded.len() - 4], 4, None) {
+            Err(error::Error::DecompressionError(..)) => {}
+            r => panic!("wrong result: {:?}", r.map(|m| m.body)),
+        }
+    }
+
+    #[test]
+    fn empty_body() {
+        for encoding in &["gzip", "deflate"] {
+            // HEAD or 304 response
+            let message = decompress_chunks(headers_with_encoding(encoding), b"", 1, None)
+                .expect("decompress");
+            assert!(message.body.is_empty());
+
+            let mut trailers = Headers::new();
+            trailers.add("x-checksum", "1");
+            let message = decompress_chunks(headers_with_encoding(encoding), b"", 1, Some(trailers))
+                .expect("decompress");
+            assert!(message.body.is_empty());
+
+            // empty DATA with END_STREAM
+            let parts = vec![Ok(HttpStreamPart::last_data(Bytes::new()))];
+            let (_, body) = decompress(
+                headers_with_encoding(encoding), HttpPartStream::new(stream::iter_result(parts)), None);
+            let parts = body.collect().wait().expect("decompress");
+            assert!(SimpleHttpMessage::from_parts(parts).body.is_empty());
+        }
+    }
+
+    #[test]
+    fn decoded_size_limit() {
+        let body = vec![0; 100_000];
+        let encoded = gzip(&body);
+        assert!(encoded.len() < 1000);
+
+        let message = decompress_chunks_limited(
+            headers_with_encoding("gzip"), &encoded, 100, None, Some(100_000)).expect("decompress");
+        assert_eq!(body.len(), message.body.len());
+
+        match decompress_chunks_limited(headers_with_encoding("gzip"), &encoded, 100, None, Some(99_999)) {
+            Err(error::Error::BodyTooLarge(99_999)) => {}
+            r => panic!("wrong result: {:?}", r.map(|m| m.body.len())),
+        }
+    }
+
+    #[test]
+    fn compress_gzip_stream() {
+        let chunks: Vec<Bytes> = vec![Bytes::from("first "), Bytes::from("second "), Bytes::from("third")];
+        let mut headers = Headers::new_post("/");
+        headers.add("content-length", "18");
+        let body = compress_gzip(&mut headers, HttpPartStream::bytes(stream::iter_ok(chunks)));
+        assert_eq!("gzip", headers.get("content-encoding"));
+        assert_eq!(None, headers.get_opt("content-length"));
+
+        let parts = body.collect().wait().expect("compress");
+        let encoded = SimpleHttpMessage::from_parts(parts).body;
+        let message = decompress_chunks(headers_with_encoding("gzip"), &encoded, 5, None).expect("decompress");
+        assert_eq!(&b"first second third"[..], &message.body[..]);
+    }
+
+    #[test]
+    fn compress_gzip_already_encoded() {
+        let mut headers = Headers::new_post("/");
+        headers.add("content-encoding", "br");
+        let body = compress_gzip(&mut headers, HttpPartStream::once_bytes("raw"));
+        assert_eq!(vec!["br"], headers.get_all("content-encoding"));
+
+        let parts = body.collect().wait().expect("compress");
+        assert_eq!(&b"raw"[..], &SimpleHttpMessage::from_parts(parts).body[..]);
+    }
+}
@@ -1,9 +1,17 @@
 use std::fmt;
 use std::io;
+use std::sync::Arc;
 use std::error::Error as StdError;
 
+use bytes::Bytes;
+
 use assert_types::*;
 
+use solicit::StreamId;
+use solicit::frame::FrameHeader;
+use solicit::frame::settings::HttpSetting;
+use solicit::header::HeaderError;
+
 use hpack::decoder::DecoderError;
 
 use native_tls;
@@ -105,6 +113,51 @@ impl Into<u32> for ErrorCode {
     }
 }
 
+/// Content of GOAWAY frame received from peer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoAwayInfo {
+    /// Last stream id peer might have processed
+    pub last_stream_id: StreamId,
+    pub error_code: ErrorCode,
+    /// Opaque data peer sent for diagnostic purposes
+    pub debug_data: Bytes,
+}
+
+/// Details of `Error::InvalidFrame`: human-readable message and,
+/// when the error is caused by a particular frame, fields of its header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidFrameInfo {
+    pub message: String,
+    pub frame_type: Option<u8>,
+    pub stream_id: Option<StreamId>,
+    /// Payload length
+    pub length: Option<u32>,
+}
+
+impl InvalidFrameInfo {
+    pub fn new<S : Into<String>>(message: S) -> InvalidFrameInfo {
+        InvalidFrameInfo {
+            message: message.into(),
+            frame_type: None,
+            stream_id: None,
+            length: None,
+        }
+    }
+
+    pub fn with_header(mut self, header: FrameHeader) -> InvalidFrameInfo {
+        self.frame_type = Some(header.frame_type);
+        self.stream_id = Some(header.stream_id);
+        self.length = Some(header.length);
+        self
+    }
+}
+
+impl fmt::Display for InvalidFrameInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
 /// An enum representing errors that can arise when performing operations involving an HTTP/2
 /// connection.
 #[derive(Debug)]
@@ -112,13 +165,37 @@ pub enum Error {
     /// The underlying IO layer raised an error
     IoError(io::Error),
     TlsError(native_tls::Error),
+    /// TLS peer did not negotiate `h2` with ALPN, contains negotiated protocol if any
+    AlpnNotH2(Option<Vec<u8>>),
     CodeError(ErrorCode),
+    /// Stream was reset with RST_STREAM, other streams of the connection are not affected
+    StreamReset(StreamId, ErrorCode),
+    /// Whole connection failed: peer sent GOAWAY with error code and debug data,
+    /// or connection was terminated with GOAWAY because of protocol error
+    ConnectionError(ErrorCode, Bytes),
+    /// Whole connection failed because of this error without error code
+    /// sent or received, e. g. I/O error or EOF.
+    /// Shared by all streams of the connection
+    ConnectionDied(Arc<Error>),
     /// The HTTP/2 connection received an invalid HTTP/2 frame
-    InvalidFrame(String),
+    InvalidFrame(InvalidFrameInfo),
+    /// Connection preface starts with TLS handshake record,
+    /// i. e. client tries to connect with TLS to cleartext port
+    TlsConnectionToPlaintextPort,
+    /// Connection was closed in the middle of a frame: `got` bytes of `expected` payload
+    /// were received. If frame header itself is truncated, `expected` is the header size.
+    /// EOF at frame boundary is reported as `IoError` with `UnexpectedEof` kind.
+    IncompleteFrame { expected: u32, got: u32 },
     /// The HPACK decoder was unable to decode a header chunk and raised an error.
     /// Any decoder error is fatal to the HTTP/2 connection as it means that the decoder contexts
     /// will be out of sync.
     CompressionError(DecoderError),
+    /// Response body could not be decoded according to `content-encoding`
+    DecompressionError(io::Error),
+    /// Outgoing headers are not valid HTTP/2 headers, e. g. contain connection-specific fields
+    InvalidHeaders(HeaderError),
+    /// Configured setting value is not allowed by 6.5.2
+    InvalidSetting(HttpSetting),
     /// Indicates that the local peer has discovered an overflow in the size of one of the
     /// connection flow control window, which is a connection error.
     WindowSizeOverflow,
@@ -126,12 +203,47 @@ pub enum Error {
     UnableToConnect,
     MalformedResponse,
     ConnectionTimeout,
+    /// Response was not received in time, stream was reset with `CANCEL`
+    RequestTimeout,
+    /// Body exceeds limit of this many bytes.
+    /// Stream was reset with `CANCEL` when limit is configured for connection,
+    /// or it is returned by `HttpPartStream::collect_body`
+    BodyTooLarge(u64),
+    /// Received header list exceeds advertised SETTINGS_MAX_HEADER_LIST_SIZE of this many bytes,
+    /// stream was reset with `CANCEL`
+    HeaderListTooLarge(u32),
+    /// Request was canceled with `RequestHandle::cancel`, stream was reset with this code
+    Canceled(ErrorCode),
     /// Shutdown of local client or server
     Shutdown,
     HandlerPanicked(String),
     Other(&'static str),
 }
 
+impl Error {
+    /// Error of failed connection which can be passed to each of its streams:
+    /// errors with HTTP/2 error code become `ConnectionError`,
+    /// other errors are wrapped in `ConnectionDied`
+    pub(crate) fn into_conn_error(self) -> Error {
+        match self {
+            Error::CodeError(error_code) => Error::ConnectionError(error_code, Bytes::new()),
+            e @ Error::ConnectionError(..) => e,
+            e @ Error::ConnectionDied(..) => e,
+            e => Error::ConnectionDied(Arc::new(e)),
+        }
+    }
+
+    /// Copy of error returned by `into_conn_error`
+    pub(crate) fn clone_conn_error(&self) -> Error {
+        match *self {
+            Error::CodeError(error_code) => Error::ConnectionError(error_code, Bytes::new()),
+            Error::ConnectionError(error_code, ref debug_data) => Error::ConnectionError(error_code, debug_data.clone()),
+            Error::ConnectionDied(ref e) => Error::ConnectionDied(e.clone()),
+            _ => Error::ConnectionDied(Arc::new(Error::Other("connection died"))),
+        }
+    }
+}
+
 fn _assert_error_sync_send() {
     assert_send::<Error>();
     assert_sync::<Error>();
@@ -168,14 +280,27 @@ impl StdError for Error {
         match *self {
             Error::IoError(_) => "Encountered an IO error",
             Error::TlsError(_) => "Encountered TLS error",
+            Error::AlpnNotH2(_) => "TLS ALPN did not negotiate h2",
             Error::CodeError(_) => "Encountered HTTP named error",
+            Error::StreamReset(..) => "Stream was reset",
+            Error::ConnectionError(..) => "Connection failed",
+            Error::ConnectionDied(..) => "Connection died",
             Error::InvalidFrame(..) => "Encountered an invalid or unexpected HTTP/2 frame",
+            Error::TlsConnectionToPlaintextPort => "TLS connection to plaintext port",
+            Error::IncompleteFrame { .. } => "Connection closed in the middle of a frame",
             Error::CompressionError(_) => "Encountered an error with HPACK compression",
+            Error::DecompressionError(_) => "Failed to decode response body",
+            Error::InvalidHeaders(_) => "Outgoing headers are invalid",
+            Error::InvalidSetting(_) => "Configured setting value is invalid",
             Error::WindowSizeOverflow => "The connection flow control window overflowed",
             Error::UnknownStreamId => "Attempted an operation with an unknown HTTP/2 stream ID",
             Error::UnableToConnect => "An error attempting to establish an HTTP/2 connection",
             Error::MalformedResponse => "The received response was malformed",
             Error::ConnectionTimeout => "Connection time out",
+            Error::RequestTimeout => "Request time out",
+            Error::BodyTooLarge(_) => "Body is too large",
+            Error::HeaderListTooLarge(_) => "Header list is too large",
+            Error::Canceled(_) => "Request canceled",
             Error::Shutdown => "Local shutdown",
             Error::HandlerPanicked(_) => "Handler panicked",
             Error::Other(_) => "An unknown error",
@@ -186,6 +311,8 @@ impl StdError for Error {
         match *self {
             Error::IoError(ref e) => Some(e),
             Error::TlsError(ref e) => Some(e),
+            Error::DecompressionError(ref e) => Some(e),
+            Error::ConnectionDied(ref e) => Some(&**e),
             _ => None,
         }
     }
@@ -0,0 +1,211 @@
+//! Hook to inspect frames sent and received on connection
+
+use std::cmp;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::fmt;
+use std::sync::Arc;
+
+use futures::Poll;
+
+use tokio_io::AsyncRead;
+use tokio_io::AsyncWrite;
+
+use solicit::frame::RawFrame;
+use solicit::frame::FRAME_HEADER_LEN;
+use solicit::frame::FrameHeaderBuffer;
+use solicit::frame::unpack_header;
+
+use solicit_async::PREFACE;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Observer is called for each frame written to or read from the connection,
+/// before frame is parsed.
+pub trait FrameObserver : Send + Sync + 'static {
+    fn frame(&self, frame: &RawFrame, direction: Direction);
+}
+
+impl fmt::Debug for FrameObserver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FrameObserver")
+    }
+}
+
+impl<F : Fn(&RawFrame, Direction) + Send + Sync + 'static> FrameObserver for F {
+    fn frame(&self, frame: &RawFrame, direction: Direction) {
+        self(frame, direction)
+    }
+}
+
+
+/// Split byte stream into frames
+pub(crate) struct FrameSplitter {
+    // PREFACE bytes which are not frames, or payload when only headers are needed
+    skip: usize,
+    // current frame
+    buf: Vec<u8>,
+    // length of current frame including header, known after header is read
+    frame_len: Option<usize>,
+    headers_only: bool,
+}
+
+impl FrameSplitter {
+    /// Splitter passing whole frames
+    pub fn frames(skip: usize) -> FrameSplitter {
+        FrameSplitter::new(skip, false)
+    }
+
+    /// Splitter passing only frame headers, payload is skipped without copying
+    pub fn headers(skip: usize) -> FrameSplitter {
+        FrameSplitter::new(skip, true)
+    }
+
+    fn new(skip: usize, headers_only: bool) -> FrameSplitter {
+        FrameSplitter {
+            skip: skip,
+            buf: Vec::with_capacity(FRAME_HEADER_LEN),
+            frame_len: None,
+            headers_only: headers_only,
+        }
+    }
+
+    /// `frame` is called with each complete frame (or frame header)
+    pub fn feed<F : FnMut(&[u8])>(&mut self, mut data: &[u8], mut frame: F) {
+        loop {
+            if self.skip > 0 {
+                let skip = cmp::min(self.skip, data.len());
+                self.skip -= skip;
+                data = &data[skip..];
+            }
+
+            let want = self.frame_len.unwrap_or(FRAME_HEADER_LEN);
+            let take = cmp::min(want - self.buf.len(), data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+
+            if self.buf.len() < want {
+                return;
+            }
+
+            let payload_len = {
+                let mut header: FrameHeaderBuffer = [0; FRAME_HEADER_LEN];
+                header.copy_from_slice(&self.buf[..FRAME_HEADER_LEN]);
+                unpack_header(&header).length as usize
+            };
+
+            if self.frame_len.is_none() && !self.headers_only {
+                self.frame_len = Some(FRAME_HEADER_LEN + payload_len);
+                continue;
+            }
+
+            frame(&self.buf);
+            if self.headers_only {
+                self.skip = payload_len;
+            }
+            self.buf.clear();
+            self.frame_len = None;
+        }
+    }
+}
+
+/// Socket wrapper which passes all frames to the observer
+pub struct ObservedIo<I> {
+    io: I,
+    observer: Option<Arc<FrameObserver>>,
+    read: FrameSplitter,
+    write: FrameSplitter,
+}
+
+impl<I> ObservedIo<I> {
+    /// Client side of connection, client sends PREFACE
+    pub fn client(io: I, observer: Option<Arc<FrameObserver>>) -> ObservedIo<I> {
+        ObservedIo {
+            io: io,
+            observer: observer,
+            read: FrameSplitter::frames(0),
+            write: FrameSplitter::frames(PREFACE.len()),
+        }
+    }
+
+    /// Server side of connection, server receives PREFACE
+    pub fn server(io: I, observer: Option<Arc<FrameObserver>>) -> ObservedIo<I> {
+        ObservedIo {
+            io: io,
+            observer: observer,
+            read: FrameSplitter::frames(PREFACE.len()),
+            write: FrameSplitter::frames(0),
+        }
+    }
+}
+
+impl<I : Read> Read for ObservedIo<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.io.read(buf)?;
+        if let Some(ref observer) = self.observer {
+            self.read.feed(&buf[..n], |frame| observer.frame(&RawFrame::from(frame), Direction::Received));
+        }
+        Ok(n)
+    }
+}
+
+impl<I : Write> Write for ObservedIo<I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.io.write(buf)?;
+        if let Some(ref observer) = self.observer {
+            self.write.feed(&buf[..n], |frame| observer.frame(&RawFrame::from(frame), Direction::Sent));
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<I : AsyncRead> AsyncRead for ObservedIo<I> {
+}
+
+impl<I : AsyncWrite> AsyncWrite for ObservedIo<I> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use solicit::frame::FrameIR;
+    use solicit::frame::data::DataFrame;
+    use solicit::frame::settings::SettingsFrame;
+
+    #[test]
+    fn frame_splitter_split_input() {
+        let mut buf = PREFACE.to_vec();
+        let settings_ack = SettingsFrame::new_ack().serialize_into_vec();
+        let data = DataFrame::with_data(1, vec![17; 100]).serialize_into_vec();
+        buf.extend(&settings_ack);
+        buf.extend(&data);
+
+        let mut frames = Vec::new();
+        let mut splitter = FrameSplitter::frames(PREFACE.len());
+        for chunk in buf.chunks(7) {
+            splitter.feed(chunk, |frame| frames.push(frame.to_vec()));
+        }
+        assert_eq!(vec![settings_ack.clone(), data.clone()], frames);
+
+        let mut headers = Vec::new();
+        let mut splitter = FrameSplitter::headers(PREFACE.len());
+        for chunk in buf.chunks(7) {
+            splitter.feed(chunk, |header| headers.push(header.to_vec()));
+        }
+        assert_eq!(vec![settings_ack[..FRAME_HEADER_LEN].to_vec(), data[..FRAME_HEADER_LEN].to_vec()], headers);
+    }
+}
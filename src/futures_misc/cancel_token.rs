@@ -0,0 +1,91 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use futures::task;
+use futures::task::Task;
+
+use futures::Async;
+use futures::Poll;
+use futures::future::Future;
+
+use error::Error;
+
+
+struct Shared {
+    canceled: bool,
+    tasks: Vec<Task>,
+}
+
+/// Cancels all clones of `CancelToken`, kept by connection
+pub struct CancelController {
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// Future resolved when stream is canceled, never fails.
+///
+/// It is not resolved when `CancelController` is dropped without `cancel`.
+#[derive(Clone)]
+pub struct CancelToken {
+    shared: Arc<Mutex<Shared>>,
+}
+
+pub fn cancel_token() -> (CancelController, CancelToken) {
+    let shared = Arc::new(Mutex::new(Shared {
+        canceled: false,
+        tasks: Vec::new(),
+    }));
+    (CancelController { shared: shared.clone() }, CancelToken { shared: shared })
+}
+
+impl CancelController {
+    pub fn cancel(&self) {
+        let mut shared = self.shared.lock().expect("lock");
+        shared.canceled = true;
+        for task in shared.tasks.drain(..) {
+            task.notify();
+        }
+    }
+}
+
+impl CancelToken {
+    /// Token which is never canceled
+    pub fn never() -> CancelToken {
+        cancel_token().1
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.shared.lock().expect("lock").canceled
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> CancelToken {
+        CancelToken::never()
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("canceled", &self.is_canceled())
+            .finish()
+    }
+}
+
+impl Future for CancelToken {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        let mut shared = self.shared.lock().expect("lock");
+        if shared.canceled {
+            return Ok(Async::Ready(()));
+        }
+        let current = task::current();
+        if !shared.tasks.iter().any(|t| t.will_notify_current()) {
+            shared.tasks.push(current);
+        }
+        Ok(Async::NotReady)
+    }
+}
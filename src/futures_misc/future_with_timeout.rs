@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use futures::future;
+use futures::future::Either;
+use futures::future::Future;
+
+use tokio_core::reactor;
+
+use error::Error;
+
+
+/// Future which fails with `Error::ConnectionTimeout`
+/// if given future is not resolved within `timeout`.
+pub fn future_with_timeout<'a, F>(f: F, timeout: Duration, lh: &reactor::Handle)
+    -> Box<Future<Item=F::Item, Error=Error> + 'a>
+    where F : Future<Error=Error> + 'a
+{
+    let timeout = match reactor::Timeout::new(timeout, lh) {
+        Ok(timeout) => timeout,
+        Err(e) => return Box::new(future::err(Error::from(e))),
+    };
+
+    Box::new(f.select2(timeout).then(|r| {
+        match r {
+            Ok(Either::A((item, _))) => Ok(item),
+            Ok(Either::B(((), _))) => Err(Error::ConnectionTimeout),
+            Err(Either::A((e, _))) => Err(e),
+            Err(Either::B((e, _))) => Err(Error::from(e)),
+        }
+    }))
+}
@@ -4,9 +4,13 @@ mod stream_single;
 mod stream_merge2;
 mod stream_with_eof;
 mod stream_with_eof_and_error;
+mod stream_with_guard;
+mod stream_with_flush;
 mod shutdown_signal;
 mod signal;
 mod latch;
+mod future_with_timeout;
+mod cancel_token;
 
 mod sender_with_last;
 pub use self::sender_with_last::*;
@@ -21,6 +25,8 @@ pub use self::stream_merge2::Merged2Item;
 
 pub use self::stream_with_eof::*;
 pub use self::stream_with_eof_and_error::*;
+pub use self::stream_with_guard::*;
+pub use self::stream_with_flush::*;
 
 pub use self::stream_deferred::stream_deferred;
 
@@ -28,3 +34,5 @@ pub use self::shutdown_signal::*;
 
 pub use self::signal::*;
 pub use self::latch::*;
+pub use self::future_with_timeout::*;
+pub use self::cancel_token::*;
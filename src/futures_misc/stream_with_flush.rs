@@ -0,0 +1,44 @@
+use futures::stream::Stream;
+use futures::Poll;
+use futures::Async;
+
+
+pub enum StreamWithFlushMessage<T> {
+    Item(T),
+    /// Underlying stream has no more items ready
+    Flush,
+}
+
+/// Stream which yields `Flush` after a batch of items,
+/// when underlying stream is not ready or finished
+pub fn stream_with_flush<S>(s: S) -> StreamWithFlush<S> {
+    StreamWithFlush {
+        stream: s,
+        need_flush: false,
+    }
+}
+
+pub struct StreamWithFlush<S> {
+    stream: S,
+    need_flush: bool,
+}
+
+impl<S : Stream> Stream for StreamWithFlush<S> {
+    type Item = StreamWithFlushMessage<S::Item>;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.stream.poll()? {
+            Async::Ready(Some(item)) => {
+                self.need_flush = true;
+                Ok(Async::Ready(Some(StreamWithFlushMessage::Item(item))))
+            }
+            Async::Ready(None) | Async::NotReady if self.need_flush => {
+                self.need_flush = false;
+                Ok(Async::Ready(Some(StreamWithFlushMessage::Flush)))
+            }
+            Async::Ready(None) => Ok(Async::Ready(None)),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
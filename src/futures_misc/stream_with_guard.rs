@@ -0,0 +1,28 @@
+use futures::stream::Stream;
+use futures::Poll;
+
+
+/// Stream which holds given value until stream is dropped.
+///
+/// Useful to notify another party that stream consumer is gone,
+/// e. g. when guard is a sender half of a channel.
+pub fn stream_with_guard<S, G>(s: S, guard: G) -> StreamWithGuard<S, G> {
+    StreamWithGuard {
+        stream: s,
+        _guard: guard,
+    }
+}
+
+pub struct StreamWithGuard<S, G> {
+    stream: S,
+    _guard: G,
+}
+
+impl<S : Stream, G> Stream for StreamWithGuard<S, G> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        self.stream.poll()
+    }
+}
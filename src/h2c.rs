@@ -0,0 +1,443 @@
+//! HTTP/1.1 `Upgrade: h2c` (RFC 7540, section 3.2)
+
+use std::str;
+
+use futures::future;
+use futures::future::Future;
+use futures::future::Loop;
+use futures::future::loop_fn;
+
+use tokio_io::io::read_exact;
+use tokio_io::io::write_all;
+use tokio_io::AsyncRead;
+use tokio_io::AsyncWrite;
+
+use error::Error;
+use result::Result;
+
+use solicit::header::*;
+use solicit::frame::settings::SettingsFrame;
+use solicit::frame::settings::HttpSetting;
+
+use solicit_async::*;
+
+use server_tls::ReplayIo;
+
+use misc::BsDebug;
+
+
+/// Max size of HTTP/1.1 request or response head
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// HTTP/1.1 headers which are specific to connection and must not be forwarded to HTTP/2
+const CONNECTION_SPECIFIC_HEADERS: &'static [&'static str] = &[
+    "connection",
+    "upgrade",
+    "http2-settings",
+    "host",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+];
+
+static SWITCHING_PROTOCOLS: &'static [u8] =
+    b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+static BAD_REQUEST: &'static [u8] =
+    b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+
+/// HTTP/1.1 request which requested upgrade to HTTP/2
+#[derive(Debug)]
+pub struct H2cUpgrade {
+    /// Request translated to HTTP/2 headers, served as stream 1
+    pub headers: Headers,
+    /// Client settings from `HTTP2-Settings` header
+    pub settings: Vec<HttpSetting>,
+}
+
+/// Decode base64url without padding (RFC 4648, section 5).
+///
+/// `HTTP2-Settings` must have trailing `=` omitted (RFC 7540, section 3.2.1),
+/// so padding is rejected as well as incomplete or non-canonical trailing characters.
+fn base64url_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut r = Vec::new();
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let v = if c >= b'A' && c <= b'Z' {
+            c - b'A'
+        } else if c >= b'a' && c <= b'z' {
+            c - b'a' + 26
+        } else if c >= b'0' && c <= b'9' {
+            c - b'0' + 52
+        } else if c == b'-' {
+            62
+        } else if c == b'_' {
+            63
+        } else {
+            return None;
+        };
+
+        acc = (acc << 6) | (v as u32);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            r.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    if acc != 0 {
+        return None;
+    }
+    Some(r)
+}
+
+/// Encode base64url without padding (RFC 4648, section 5)
+fn base64url_encode(data: &[u8]) -> String {
+    const ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    let mut r = String::new();
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for &b in data {
+        acc = (acc << 8) | (b as u32);
+        bits += 8;
+        while bits >= 6 {
+            bits -= 6;
+            r.push(ALPHABET[((acc >> bits) & 0x3f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        r.push(ALPHABET[((acc << (6 - bits)) & 0x3f) as usize] as char);
+    }
+    r
+}
+
+/// `HTTP2-Settings` header value: base64url encoded SETTINGS frame payload
+fn http2_settings_header(settings: &[HttpSetting]) -> String {
+    let mut payload = Vec::new();
+    for setting in settings {
+        payload.push((setting.get_id() >> 8) as u8);
+        payload.push(setting.get_id() as u8);
+        let val = setting.get_val();
+        payload.extend_from_slice(&[(val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8]);
+    }
+    base64url_encode(&payload)
+}
+
+fn has_token(value: &str, token: &str) -> bool {
+    value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// Parse HTTP/1.1 request head (including terminating empty line)
+pub fn parse_upgrade_request(head: &[u8]) -> Result<H2cUpgrade> {
+    let head = str::from_utf8(head)
+        .map_err(|_| Error::Other("HTTP/1.1 request is not UTF-8"))?;
+
+    let mut lines = head.split("\r\n");
+
+    let request_line = lines.next().unwrap_or("");
+    let mut parts = request_line.split(' ');
+    let (method, path, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version), None) => (method, path, version),
+        _ => return Err(Error::Other("malformed HTTP/1.1 request line")),
+    };
+    if version != "HTTP/1.1" {
+        return Err(Error::Other("expecting HTTP/1.1 request"));
+    }
+
+    let mut host = None;
+    let mut upgrade = false;
+    let mut connection = None;
+    let mut settings = None;
+    let mut regular = Vec::new();
+
+    for line in lines {
+        if line.is_empty() {
+            break;
+        }
+
+        let colon = line.find(':')
+            .ok_or(Error::Other("malformed HTTP/1.1 header"))?;
+        let name = line[..colon].trim().to_ascii_lowercase();
+        let value = line[colon + 1..].trim();
+
+        match &name[..] {
+            "host" => host = Some(value),
+            "upgrade" => upgrade = has_token(value, "h2c"),
+            "connection" => connection = Some(value),
+            "http2-settings" => {
+                if settings.is_some() {
+                    return Err(Error::Other("duplicate HTTP2-Settings header"));
+                }
+                settings = Some(value);
+            }
+            "content-length" => {
+                if value != "0" {
+                    return Err(Error::Other("h2c upgrade request with body is not supported"));
+                }
+            }
+            _ => {}
+        }
+
+        if !CONNECTION_SPECIFIC_HEADERS.contains(&&name[..]) {
+            regular.push(Header::new(name.into_bytes(), value.as_bytes().to_vec()));
+        }
+    }
+
+    if !upgrade {
+        return Err(Error::Other("HTTP/1.1 request without Upgrade: h2c"));
+    }
+
+    // 3.2.1
+    // A client sending the HTTP2-Settings header field MUST also send
+    // HTTP2-Settings as a connection option in the Connection header field
+    match connection {
+        Some(c) if has_token(c, "upgrade") && has_token(c, "http2-settings") => {}
+        _ => return Err(Error::Other("h2c upgrade request without Connection: Upgrade, HTTP2-Settings")),
+    }
+
+    let settings = settings.ok_or(Error::Other("h2c upgrade request without HTTP2-Settings"))?;
+    let payload = base64url_decode(settings)
+        .ok_or(Error::Other("HTTP2-Settings is not base64url"))?;
+    SettingsFrame::check_payload(&payload).map_err(Error::CodeError)?;
+    let settings = SettingsFrame::parse_payload(&payload)
+        .ok_or(Error::Other("malformed HTTP2-Settings"))?;
+
+    let mut headers = Headers::new();
+    headers.add(":method", method);
+    headers.add(":scheme", "http");
+    if let Some(host) = host {
+        headers.add(":authority", host);
+    }
+    headers.add(":path", path);
+    headers.extend(Headers(regular));
+
+    Ok(H2cUpgrade {
+        headers: headers,
+        settings: settings,
+    })
+}
+
+/// Read bytes until the end of HTTP/1.1 message head, `head` contains bytes already read
+fn recv_http1_head<I : AsyncRead + Send + 'static>(conn: I, head: Vec<u8>) -> HttpFuture<(I, Vec<u8>)> {
+    Box::new(loop_fn((conn, head), |(conn, mut head)| {
+        if head.ends_with(b"\r\n\r\n") {
+            return future::Either::A(future::ok(Loop::Break((conn, head))));
+        }
+        if head.len() >= MAX_HEAD_SIZE {
+            return future::Either::A(future::err(Error::Other("HTTP/1.1 message head is too large")));
+        }
+
+        // read byte by byte, because anything after the head belongs to HTTP/2
+        future::Either::B(read_exact(conn, [0u8; 1])
+            .map_err(Error::from)
+            .map(move |(conn, buf)| {
+                head.push(buf[0]);
+                Loop::Continue((conn, head))
+            }))
+    }))
+}
+
+/// Receive HTTP/1.1 request with `Upgrade: h2c` unless client starts with PREFACE.
+///
+/// On upgrade, `101 Switching Protocols` is sent, if request is not a valid
+/// upgrade request, `400 Bad Request` is sent and connection fails. Otherwise connection
+/// continues with `server_handshake`: bytes read while looking for upgrade request
+/// are returned by connection again. Resolves to connection and upgrade request if any.
+pub fn server_upgrade<I>(conn: I)
+    -> HttpFuture<(ReplayIo<I>, Option<H2cUpgrade>)>
+    where I : AsyncRead + AsyncWrite + Send + 'static
+{
+    let mut preface_buf = Vec::with_capacity(PREFACE.len());
+    preface_buf.resize(PREFACE.len(), 0);
+    let recv_start = read_exact(conn, preface_buf)
+        .map_err(Error::from);
+
+    Box::new(recv_start.and_then(move |(conn, buf)| -> HttpFuture<(ReplayIo<I>, Option<H2cUpgrade>)> {
+        // TLS ClientHello is reported by `server_handshake`
+        if buf == PREFACE || buf[0] == 0x16 {
+            return Box::new(future::ok((ReplayIo::new(buf, conn), None)));
+        }
+
+        debug!("not a preface, expecting HTTP/1.1 upgrade request");
+
+        Box::new(recv_http1_head(conn, buf)
+            .and_then(|(conn, head)| -> HttpFuture<(I, H2cUpgrade)> {
+                match parse_upgrade_request(&head) {
+                    Ok(upgrade) => Box::new(future::ok((conn, upgrade))),
+                    Err(e) => {
+                        debug!("rejecting HTTP/1.1 request: {:?}", e);
+                        Box::new(write_all(conn, BAD_REQUEST)
+                            .map_err(Error::from)
+                            .and_then(move |_| Err(e)))
+                    }
+                }
+            })
+            .and_then(|(conn, upgrade)| {
+                debug!("upgrading to h2c: {:?}", upgrade);
+                write_all(conn, SWITCHING_PROTOCOLS)
+                    .map_err(Error::from)
+                    .map(move |(conn, _)| (conn, upgrade))
+            })
+            .map(|(conn, upgrade)| (ReplayIo::new(Vec::new(), conn), Some(upgrade))))
+    }))
+}
+
+
+/// Send HTTP/1.1 `OPTIONS *` request with `Upgrade: h2c` and wait for `101 Switching Protocols`,
+/// after that client must proceed with `client_handshake`.
+///
+/// Resolves to `None` if server did not upgrade (or failed to respond with HTTP/1.1),
+/// connection is closed then. Response to upgrade request is sent by server in stream 1.
+pub fn client_upgrade<I>(conn: I, authority: &str, settings: &[HttpSetting])
+    -> HttpFuture<Option<I>>
+    where I : AsyncRead + AsyncWrite + Send + 'static
+{
+    let request = format!(
+        "OPTIONS * HTTP/1.1\r\n\
+        Host: {}\r\n\
+        Connection: Upgrade, HTTP2-Settings\r\n\
+        Upgrade: h2c\r\n\
+        HTTP2-Settings: {}\r\n\
+        \r\n",
+        authority, http2_settings_header(settings));
+
+    Box::new(write_all(conn, request.into_bytes())
+        .map_err(Error::from)
+        .and_then(|(conn, _)| recv_http1_head(conn, Vec::new()))
+        .then(|r| -> Result<Option<I>> {
+            match r {
+                Ok((conn, head)) => {
+                    if head.starts_with(b"HTTP/1.1 101 ") {
+                        Ok(Some(conn))
+                    } else {
+                        debug!("server did not upgrade to h2c: {:?}", BsDebug(&head));
+                        Ok(None)
+                    }
+                }
+                Err(e) => {
+                    debug!("h2c upgrade failed: {:?}", e);
+                    Ok(None)
+                }
+            }
+        }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use mock_io::MockIo;
+
+    use super::*;
+
+    #[test]
+    fn server_upgrade_tls_client_hello() {
+        let (mut client, server) = MockIo::pair();
+        client.write_all(&[0x16; 24]).expect("write");
+        let (server, upgrade) = server_upgrade(server).wait().expect("server_upgrade");
+        assert!(upgrade.is_none());
+        match server_handshake(server, Vec::new()).wait() {
+            Err(Error::TlsConnectionToPlaintextPort) => {}
+            r => panic!("expecting TlsConnectionToPlaintextPort, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn server_upgrade_preface() {
+        let (mut client, server) = MockIo::pair();
+        client.write_all(PREFACE).expect("write");
+        let (server, upgrade) = server_upgrade(server).wait().expect("server_upgrade");
+        assert!(upgrade.is_none());
+        server_handshake(server, Vec::new()).wait().expect("server_handshake");
+    }
+
+    #[test]
+    fn test_base64url_decode() {
+        assert_eq!(Some(Vec::new()), base64url_decode(""));
+        assert_eq!(Some(b"f".to_vec()), base64url_decode("Zg"));
+        assert_eq!(Some(b"fo".to_vec()), base64url_decode("Zm8"));
+        assert_eq!(Some(b"foo".to_vec()), base64url_decode("Zm9v"));
+        assert_eq!(Some(b"foob".to_vec()), base64url_decode("Zm9vYg"));
+        assert_eq!(Some(vec![0xfb, 0xff]), base64url_decode("-_8"));
+        assert_eq!(None, base64url_decode("Zm+v"));
+        // padding
+        assert_eq!(None, base64url_decode("Zm9vYg=="));
+        // incomplete
+        assert_eq!(None, base64url_decode("Zm9vY"));
+        // non-zero trailing bits
+        assert_eq!(None, base64url_decode("Zh"));
+    }
+
+    #[test]
+    fn test_base64url_encode() {
+        for s in &["", "f", "fo", "foo", "foob", "fooba", "foobar"] {
+            let encoded = base64url_encode(s.as_bytes());
+            assert!(!encoded.contains('='));
+            assert_eq!(Some(s.as_bytes().to_vec()), base64url_decode(&encoded));
+        }
+        assert_eq!("-_8", base64url_encode(&[0xfb, 0xff]));
+    }
+
+    #[test]
+    fn test_http2_settings_header() {
+        let settings = vec![HttpSetting::MaxConcurrentStreams(100), HttpSetting::InitialWindowSize(0xffff)];
+        assert_eq!("AAMAAABkAAQAAP__", http2_settings_header(&settings));
+    }
+
+    #[test]
+    fn test_parse_upgrade_request() {
+        let head = b"GET /index.html HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Connection: Upgrade, HTTP2-Settings\r\n\
+            Upgrade: h2c\r\n\
+            HTTP2-Settings: AAMAAABkAAQAAP__\r\n\
+            User-Agent: test\r\n\
+            \r\n";
+
+        let upgrade = parse_upgrade_request(head).expect("parse");
+        assert_eq!(
+            vec![HttpSetting::MaxConcurrentStreams(100), HttpSetting::InitialWindowSize(0xffff)],
+            upgrade.settings);
+        assert_eq!("GET", upgrade.headers.get(":method"));
+        assert_eq!("http", upgrade.headers.get(":scheme"));
+        assert_eq!("example.com", upgrade.headers.get(":authority"));
+        assert_eq!("/index.html", upgrade.headers.get(":path"));
+        assert_eq!("test", upgrade.headers.get("user-agent"));
+        assert_eq!(None, upgrade.headers.get_opt("connection"));
+        assert_eq!(None, upgrade.headers.get_opt("http2-settings"));
+    }
+
+    #[test]
+    fn test_parse_upgrade_request_without_connection_options() {
+        let head = b"GET / HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Connection: Upgrade\r\n\
+            Upgrade: h2c\r\n\
+            HTTP2-Settings: AAMAAABkAAQAAP__\r\n\
+            \r\n";
+        assert!(parse_upgrade_request(head).is_err());
+    }
+
+    #[test]
+    fn test_parse_upgrade_request_invalid_settings() {
+        let head = b"GET / HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Connection: Upgrade, HTTP2-Settings\r\n\
+            Upgrade: h2c\r\n\
+            HTTP2-Settings: AAMAAABkAAQAAP__=\r\n\
+            \r\n";
+        assert!(parse_upgrade_request(head).is_err());
+    }
+
+    #[test]
+    fn test_parse_upgrade_request_without_upgrade() {
+        let head = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        assert!(parse_upgrade_request(head).is_err());
+    }
+}
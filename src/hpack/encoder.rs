@@ -170,6 +170,9 @@ pub fn encode_integer(value: usize, prefix_size: u8) -> Vec<u8> {
 pub struct Encoder<'a> {
     /// The header table represents the encoder's context
     header_table: HeaderTable<'a>,
+    /// Dynamic table size update to be emitted at the beginning
+    /// of the next header block
+    pending_size_update: Option<usize>,
 }
 
 impl<'a> Encoder<'a> {
@@ -178,9 +181,20 @@ impl<'a> Encoder<'a> {
     pub fn new() -> Encoder<'a> {
         Encoder {
             header_table: HeaderTable::with_static_table(STATIC_TABLE),
+            pending_size_update: None,
         }
     }
 
+    /// Sets a new maximum dynamic table size for the encoder.
+    ///
+    /// Headers are evicted from the table immediately, and a dynamic table
+    /// size update is emitted at the beginning of the next encoded header block,
+    /// as required by the HPACK spec (section 4.2).
+    pub fn set_max_table_size(&mut self, new_max_size: usize) {
+        self.header_table.dynamic_table.set_max_table_size(new_max_size);
+        self.pending_size_update = Some(new_max_size);
+    }
+
     /// Encodes the given headers using the HPACK rules and returns a newly
     /// allocated `Vec` containing the bytes representing the encoded header
     /// set.
@@ -206,6 +220,9 @@ impl<'a> Encoder<'a> {
     pub fn encode_into<'b, I, W>(&mut self, headers: I, writer: &mut W) -> io::Result<()>
             where I: IntoIterator<Item=(&'b [u8], &'b [u8])>,
                   W: io::Write {
+        if let Some(new_max_size) = self.pending_size_update.take() {
+            encode_integer_into(new_max_size, 5, 0x20, writer)?;
+        }
         for header in headers {
             self.encode_header_into(header, writer)?;
         }
@@ -327,6 +344,31 @@ mod tests {
 
     use super::super::Decoder;
 
+    #[test]
+    fn test_set_max_table_size() {
+        let mut encoder: Encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+
+        let headers = vec![
+            (b"custom-key".to_vec(), b"custom-value".to_vec()),
+        ];
+
+        let result = encoder.encode(headers.iter().map(|h| (&h.0[..], &h.1[..])));
+        assert_eq!(headers, decoder.decode(&result).unwrap());
+
+        encoder.set_max_table_size(0);
+
+        // Size update is emitted first, and header is not indexed anymore
+        let result = encoder.encode(headers.iter().map(|h| (&h.0[..], &h.1[..])));
+        assert_eq!(0x20, result[0]);
+        assert_eq!(headers, decoder.decode(&result).unwrap());
+
+        // Size update is emitted only once
+        let result = encoder.encode(headers.iter().map(|h| (&h.0[..], &h.1[..])));
+        assert_eq!(0x40, result[0]);
+        assert_eq!(headers, decoder.decode(&result).unwrap());
+    }
+
     #[test]
     fn test_encode_integer() {
         assert_eq!(encode_integer(10, 5), [10]);
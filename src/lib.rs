@@ -10,6 +10,17 @@ extern crate tokio_timer;
 
 extern crate net2;
 extern crate bytes;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(all(any(feature = "openssl-v102", feature = "openssl-v110"),
+    not(any(target_os = "windows", target_os = "macos", target_os = "ios"))))]
+extern crate openssl;
+#[cfg(feature = "tls-rustls")]
+extern crate rustls;
+#[cfg(feature = "tls-rustls")]
+extern crate tokio_rustls;
+#[cfg(feature = "tls-rustls")]
+extern crate webpki;
 
 pub mod solicit;
 
@@ -26,9 +37,17 @@ pub mod server_conf;
 pub mod server_conn;
 mod server_tls;
 pub mod server;
+pub mod alpn;
+pub mod client_auth;
+#[cfg(feature = "tls-rustls")]
+pub mod rustls_tls;
+mod h2c;
 
 mod common;
 
+pub mod frame_observer;
+mod metrics;
+
 pub mod stream_part;
 pub mod message;
 
@@ -45,22 +64,42 @@ pub mod misc;
 mod rc_mut;
 
 mod resp;
+#[cfg(feature = "gzip")]
+mod content_coding;
+
+mod mock_io;
 
 pub use solicit::HttpScheme;
 pub use solicit::header::Header;
 pub use solicit::header::Headers;
+pub use solicit::header::StatusCode;
 
 pub use service::Service;
+pub use service::ServiceContext;
+pub use futures_misc::CancelToken;
 
 pub use client::Client;
+pub use client::TunnelWriter;
+pub use client::RequestWriter;
 pub use client_conf::ClientConf;
+pub use common::StreamPriority;
+pub use client_conn::AltSvc;
 pub use client_tls::ClientTlsOption;
 
 pub use server::Server;
 pub use server_conf::ServerConf;
+pub use server_conf::ConnectionErrorHandler;
 pub use server_tls::ServerTlsOption;
+pub use server_tls::SniResolver;
+
+pub use common::DEFAULT_WRITE_BUFFER_SIZE;
+pub use common::HeadersHook;
+pub use common::PeerSettings;
+
+pub use metrics::Metrics;
 
 pub use resp::Response;
+pub use resp::RequestHandle;
 pub use stream_part::HttpPartStream;
 
 pub use error::Error;
@@ -69,5 +108,18 @@ pub use result::Result;
 
 pub mod for_test {
     pub use common::ConnectionStateSnapshot;
+    pub use common::StreamStateSnapshot;
+    pub use client_conn::ClientConnection;
     pub use server_conn::ServerConnection;
+    pub use solicit::frame::RawFrame;
+    pub use mock_io::MockIo;
+
+    use client::Client;
+    use solicit::StreamId;
+    use solicit_async::HttpFutureSend;
+
+    /// Skip stream ids of current client connection so that `next_id` is allocated next
+    pub fn fast_forward_stream_ids(client: &Client, next_id: StreamId) -> HttpFutureSend<()> {
+        client.fast_forward_stream_ids(next_id)
+    }
 }
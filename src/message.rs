@@ -8,6 +8,8 @@ use stream_part::*;
 pub struct SimpleHttpMessage {
     pub headers: Headers,
     pub body: Bytes,
+    /// HEADERS frame received after body
+    pub trailers: Option<Headers>,
 }
 
 impl SimpleHttpMessage {
@@ -35,6 +37,7 @@ impl SimpleHttpMessage {
         SimpleHttpMessage {
             headers: Headers::not_found_404(),
             body: Bytes::from(message),
+            trailers: None,
         }
     }
 
@@ -42,13 +45,21 @@ impl SimpleHttpMessage {
         SimpleHttpMessage {
             headers: Headers::ok_200(),
             body: Bytes::from(body),
+            trailers: None,
         }
     }
 
     pub fn add(&mut self, part: HttpStreamPartContent) {
         match part {
             HttpStreamPartContent::Headers(headers) => {
-                self.headers.extend(headers);
+                if self.body.is_empty() {
+                    self.headers.extend(headers);
+                } else {
+                    match self.trailers {
+                        Some(ref mut trailers) => trailers.extend(headers),
+                        None => self.trailers = Some(headers),
+                    }
+                }
             }
             HttpStreamPartContent::Data(data) => {
                 self.body.extend_from_slice(&data);
@@ -0,0 +1,254 @@
+//! Counters of frames, bytes and streams of connections
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use futures::Poll;
+
+use tokio_io::AsyncRead;
+use tokio_io::AsyncWrite;
+
+use solicit::frame::FRAME_HEADER_LEN;
+use solicit::frame::FrameHeaderBuffer;
+use solicit::frame::unpack_header;
+use solicit::frame::rst_stream::RST_STREAM_FRAME_TYPE;
+
+use frame_observer::Direction;
+use frame_observer::FrameSplitter;
+
+use solicit_async::PREFACE;
+
+
+/// Snapshot of connection counters, returned by `metrics` of
+/// `ClientConnection`, `Client` and `Server`.
+///
+/// For `Client` and `Server` counters are summed over all connections,
+/// including closed ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Number of frames sent by frame type
+    pub frames_sent_by_type: BTreeMap<u8, u64>,
+    /// Number of frames received by frame type
+    pub frames_received_by_type: BTreeMap<u8, u64>,
+    /// Bytes written to socket, including connection preface
+    pub bytes_sent: u64,
+    /// Bytes read from socket, including connection preface
+    pub bytes_received: u64,
+    /// Streams opened by either side
+    pub streams_opened: u64,
+    /// Streams completed, reset or failed with connection
+    pub streams_closed: u64,
+}
+
+impl Metrics {
+    pub fn frames_sent(&self, frame_type: u8) -> u64 {
+        self.frames_sent_by_type.get(&frame_type).cloned().unwrap_or(0)
+    }
+
+    pub fn frames_received(&self, frame_type: u8) -> u64 {
+        self.frames_received_by_type.get(&frame_type).cloned().unwrap_or(0)
+    }
+
+    pub fn rst_stream_sent(&self) -> u64 {
+        self.frames_sent(RST_STREAM_FRAME_TYPE)
+    }
+
+    pub fn rst_stream_received(&self) -> u64 {
+        self.frames_received(RST_STREAM_FRAME_TYPE)
+    }
+
+    /// Streams currently open
+    pub fn open_streams(&self) -> u64 {
+        self.streams_opened - self.streams_closed
+    }
+
+    fn frame(&mut self, direction: Direction, frame_type: u8) {
+        let by_type = match direction {
+            Direction::Sent => &mut self.frames_sent_by_type,
+            Direction::Received => &mut self.frames_received_by_type,
+        };
+        *by_type.entry(frame_type).or_insert(0) += 1;
+    }
+
+    pub fn record_stream_opened(&mut self) {
+        self.streams_opened += 1;
+    }
+
+    pub fn record_streams_closed(&mut self, count: usize) {
+        self.streams_closed += count as u64;
+    }
+
+    fn add(&mut self, other: &Metrics) {
+        for (&frame_type, &count) in &other.frames_sent_by_type {
+            *self.frames_sent_by_type.entry(frame_type).or_insert(0) += count;
+        }
+        for (&frame_type, &count) in &other.frames_received_by_type {
+            *self.frames_received_by_type.entry(frame_type).or_insert(0) += count;
+        }
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.streams_opened += other.streams_opened;
+        self.streams_closed += other.streams_closed;
+    }
+}
+
+/// Metrics shared by connection and its handle
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+/// Metrics of all connections of `Client` or `Server`
+#[derive(Default)]
+pub struct MetricsSet {
+    /// Sum of connections which are gone
+    closed: Metrics,
+    conns: Vec<SharedMetrics>,
+}
+
+impl MetricsSet {
+    pub fn add_conn(&mut self, metrics: SharedMetrics) {
+        self.remove_closed();
+        self.conns.push(metrics);
+    }
+
+    /// Connection is gone when nobody else holds its metrics,
+    /// streams still open at that point are counted as closed
+    fn remove_closed(&mut self) {
+        let closed = &mut self.closed;
+        self.conns.retain(|metrics| {
+            if Arc::strong_count(metrics) > 1 {
+                return true;
+            }
+            let mut metrics = metrics.lock().unwrap().clone();
+            metrics.streams_closed = metrics.streams_opened;
+            closed.add(&metrics);
+            false
+        });
+    }
+
+    pub fn total(&mut self) -> Metrics {
+        self.remove_closed();
+        let mut total = self.closed.clone();
+        for metrics in &self.conns {
+            total.add(&metrics.lock().unwrap());
+        }
+        total
+    }
+}
+
+
+/// Count frames by reading only frame headers
+struct FrameCounter {
+    direction: Direction,
+    splitter: FrameSplitter,
+}
+
+impl FrameCounter {
+    fn new(direction: Direction, skip: usize) -> FrameCounter {
+        FrameCounter {
+            direction: direction,
+            splitter: FrameSplitter::headers(skip),
+        }
+    }
+
+    fn feed(&mut self, data: &[u8], metrics: &mut Metrics) {
+        let direction = self.direction;
+        self.splitter.feed(data, |header| {
+            let mut raw_header: FrameHeaderBuffer = [0; FRAME_HEADER_LEN];
+            raw_header.copy_from_slice(header);
+            metrics.frame(direction, unpack_header(&raw_header).frame_type);
+        });
+    }
+}
+
+/// Socket wrapper which updates metrics with bytes and frames read and written
+pub struct MeteredIo<I> {
+    io: I,
+    metrics: SharedMetrics,
+    read: FrameCounter,
+    write: FrameCounter,
+}
+
+impl<I> MeteredIo<I> {
+    /// Client side of connection, client sends PREFACE
+    pub fn client(io: I, metrics: SharedMetrics) -> MeteredIo<I> {
+        MeteredIo {
+            io: io,
+            metrics: metrics,
+            read: FrameCounter::new(Direction::Received, 0),
+            write: FrameCounter::new(Direction::Sent, PREFACE.len()),
+        }
+    }
+
+    /// Server side of connection, server receives PREFACE
+    pub fn server(io: I, metrics: SharedMetrics) -> MeteredIo<I> {
+        MeteredIo {
+            io: io,
+            metrics: metrics,
+            read: FrameCounter::new(Direction::Received, PREFACE.len()),
+            write: FrameCounter::new(Direction::Sent, 0),
+        }
+    }
+}
+
+impl<I : Read> Read for MeteredIo<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.io.read(buf)?;
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.bytes_received += n as u64;
+        self.read.feed(&buf[..n], &mut metrics);
+        Ok(n)
+    }
+}
+
+impl<I : Write> Write for MeteredIo<I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.io.write(buf)?;
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.bytes_sent += n as u64;
+        self.write.feed(&buf[..n], &mut metrics);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<I : AsyncRead> AsyncRead for MeteredIo<I> {
+}
+
+impl<I : AsyncWrite> AsyncWrite for MeteredIo<I> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use solicit::frame::FrameIR;
+    use solicit::frame::data::DataFrame;
+    use solicit::frame::data::DATA_FRAME_TYPE;
+    use solicit::frame::ping::PingFrame;
+    use solicit::frame::ping::PING_FRAME_TYPE;
+
+    #[test]
+    fn frame_counter_split_input() {
+        let mut buf = DataFrame::with_data(1, vec![17; 100]).serialize_into_vec();
+        buf.extend(PingFrame::with_data(23).serialize_into_vec());
+
+        let mut metrics = Metrics::default();
+        let mut counter = FrameCounter::new(Direction::Received, 0);
+        for chunk in buf.chunks(5) {
+            counter.feed(chunk, &mut metrics);
+        }
+
+        assert_eq!(1, metrics.frames_received(DATA_FRAME_TYPE));
+        assert_eq!(1, metrics.frames_received(PING_FRAME_TYPE));
+        assert_eq!(0, metrics.frames_sent(DATA_FRAME_TYPE));
+    }
+}
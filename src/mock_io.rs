@@ -0,0 +1,193 @@
+//! In-memory duplex connection for tests
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use futures::Async;
+use futures::Poll;
+use futures::task;
+use futures::task::Task;
+
+use tokio_io::AsyncRead;
+use tokio_io::AsyncWrite;
+
+
+// Bytes written by one end and not yet read by another
+#[derive(Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    // writer is dropped or shut down
+    closed: bool,
+    // reader waiting for data
+    read_task: Option<Task>,
+}
+
+impl Pipe {
+    fn notify(&mut self) {
+        if let Some(task) = self.read_task.take() {
+            task.notify();
+        }
+    }
+}
+
+/// One end of in-memory connection implementing `AsyncRead + AsyncWrite`.
+///
+/// Bytes written to one end are read from the other,
+/// dropping an end is seen as EOF by the peer.
+pub struct MockIo {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+    max_read: Option<usize>,
+}
+
+impl MockIo {
+    /// Create connected pair
+    pub fn pair() -> (MockIo, MockIo) {
+        let a_to_b = Arc::new(Mutex::new(Pipe::default()));
+        let b_to_a = Arc::new(Mutex::new(Pipe::default()));
+        let a = MockIo {
+            read: b_to_a.clone(),
+            write: a_to_b.clone(),
+            max_read: None,
+        };
+        let b = MockIo {
+            read: a_to_b,
+            write: b_to_a,
+            max_read: None,
+        };
+        (a, b)
+    }
+
+    /// Return at most `max_read` bytes from single `read` call,
+    /// to test handling of partial reads
+    pub fn set_max_read(&mut self, max_read: Option<usize>) {
+        self.max_read = max_read;
+    }
+
+    fn close_write(&self) {
+        let mut pipe = self.write.lock().unwrap();
+        pipe.closed = true;
+        pipe.notify();
+    }
+}
+
+impl Read for MockIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.read.lock().unwrap();
+
+        if pipe.buf.is_empty() {
+            if pipe.closed || buf.is_empty() {
+                return Ok(0);
+            }
+            pipe.read_task = Some(task::current());
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "no data"));
+        }
+
+        let len = cmp::min(buf.len(), pipe.buf.len());
+        let len = cmp::min(len, self.max_read.unwrap_or(len));
+
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..len)) {
+            *dst = src;
+        }
+
+        Ok(len)
+    }
+}
+
+impl Write for MockIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.write.lock().unwrap();
+
+        if pipe.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"));
+        }
+
+        pipe.buf.extend(buf);
+        pipe.notify();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for MockIo {}
+
+impl AsyncWrite for MockIo {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.close_write();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for MockIo {
+    fn drop(&mut self) {
+        self.close_write();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use futures::Future;
+
+    use solicit::connection::HttpFrame;
+    use solicit::frame::headers::HeadersFrame;
+    use solicit::frame::headers::HeadersFlag;
+    use solicit::frame::FrameIR;
+    use solicit_async::recv_http_frame;
+    use solicit_async::send_frame;
+
+    use super::*;
+
+    #[test]
+    fn headers_frame() {
+        let (a, mut b) = MockIo::pair();
+        // every read returns single byte
+        b.set_max_read(Some(1));
+
+        let mut frame = HeadersFrame::new(&b"fragment"[..], 3);
+        frame.set_flag(HeadersFlag::EndHeaders);
+        let a = send_frame(a, frame).wait().expect("send");
+
+        let (mut b, frame) = recv_http_frame(b, 16384).wait().expect("recv");
+        match frame {
+            HttpFrame::Headers(frame) => {
+                assert_eq!(3, frame.stream_id);
+                assert_eq!(&b"fragment"[..], &frame.header_fragment()[..]);
+                assert!(frame.is_headers_end());
+            }
+            f => panic!("wrong frame: {:?}", f),
+        }
+
+        drop(a);
+        let mut buf = [0; 1];
+        assert_eq!(0, b.read(&mut buf).expect("read"));
+    }
+
+    #[test]
+    fn read_waits_for_write() {
+        let (mut a, b) = MockIo::pair();
+
+        let mut frame = HeadersFrame::new(&b"x"[..], 1);
+        frame.set_flag(HeadersFlag::EndHeaders);
+        let raw = frame.serialize_into_vec();
+        a.write_all(&raw[..4]).expect("write");
+
+        let recv = thread::spawn(move || recv_http_frame(b, 16384).wait().map(|(_, f)| f));
+        a.write_all(&raw[4..]).expect("write");
+
+        match recv.join().expect("join").expect("recv") {
+            HttpFrame::Headers(frame) => assert_eq!(1, frame.stream_id),
+            f => panic!("wrong frame: {:?}", f),
+        }
+    }
+}
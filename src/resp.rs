@@ -1,21 +1,113 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
 use futures::future;
 use futures::future::Future;
 use futures::stream;
 use futures::stream::Stream;
+use futures::sync::oneshot;
 
 use bytes::Bytes;
 
 use solicit_async::*;
 use solicit::header::Headers;
+use solicit::StreamId;
 use message::SimpleHttpMessage;
 
 use error::Error;
+use error::ErrorCode;
+use error::InvalidFrameInfo;
 
 use stream_part::*;
 
+#[cfg(feature = "gzip")]
+use content_coding;
+
+
+/// Stream id of client request, set by connection when request stream is opened
+#[derive(Clone, Default)]
+pub struct StreamIdSlot(Arc<AtomicUsize>);
+
+impl StreamIdSlot {
+    pub fn set(&self, stream_id: StreamId) {
+        self.0.store(stream_id as usize, Ordering::SeqCst);
+    }
+
+    pub fn get(&self) -> Option<StreamId> {
+        // zero is never an id of request stream
+        match self.0.load(Ordering::SeqCst) {
+            0 => None,
+            stream_id => Some(stream_id as StreamId),
+        }
+    }
+}
+
+/// Handle of client request which can be used while response is still awaited,
+/// obtained with `Response::request_handle`.
+#[derive(Clone, Default)]
+pub struct RequestHandle {
+    stream_id: StreamIdSlot,
+    cancel_tx: Arc<Mutex<Option<oneshot::Sender<ErrorCode>>>>,
+}
+
+/// Resets the request stream with `CANCEL` when response is dropped
+pub struct CancelOnDrop(Arc<Mutex<Option<oneshot::Sender<ErrorCode>>>>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        // dropped sender completes receiver with error,
+        // connection handles it like `cancel(ErrorCode::Cancel)` without reporting an error
+        self.0.lock().unwrap().take();
+    }
+}
+
+impl RequestHandle {
+    pub(crate) fn stream_id_slot(&self) -> StreamIdSlot {
+        self.stream_id.clone()
+    }
+
+    /// Channel used by connection to learn the request is canceled.
+    /// Replaces previous channel if request is replayed on a new connection.
+    pub(crate) fn cancel_channel(&self) -> (CancelOnDrop, oneshot::Receiver<ErrorCode>) {
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        *self.cancel_tx.lock().unwrap() = Some(cancel_tx);
+        (CancelOnDrop(self.cancel_tx.clone()), cancel_rx)
+    }
+
+    /// Id of client request stream, see `Response::stream_id`.
+    pub fn stream_id(&self) -> Option<StreamId> {
+        self.stream_id.get()
+    }
+
+    /// Reset request stream with RST_STREAM with given error code.
+    ///
+    /// Response resolves with `Error::Canceled`. Does nothing if response
+    /// is already complete or dropped, or if request was already canceled.
+    pub fn cancel(&self, error_code: ErrorCode) {
+        if let Some(cancel_tx) = self.cancel_tx.lock().unwrap().take() {
+            // connection may be already dead
+            cancel_tx.send(error_code).ok();
+        }
+    }
+}
 
 /// Convenient wrapper around async HTTP response future/stream
-pub struct Response(pub HttpFutureSend<(Headers, HttpPartStream)>);
+///
+/// Response future is public field `0`, but response also carries server pushes
+/// and client request handle in a private field, so `Response` cannot be
+/// constructed or destructured as a tuple: use `Response::new` or other constructors.
+pub struct Response(pub HttpFutureSend<(Headers, HttpPartStream)>, ResponseExtras);
+
+/// Parts of response not exposed as fields, see `with_push` and `request_handle`
+#[derive(Default)]
+struct ResponseExtras {
+    /// Promised request headers and corresponding responses
+    pushes: Vec<(Headers, Response)>,
+    /// Handle of client request
+    request_handle: RequestHandle,
+}
 
 impl Response {
     // constructors
@@ -23,7 +115,7 @@ impl Response {
     pub fn new<F>(future: F) -> Response
         where F : Future<Item=(Headers, HttpPartStream), Error=Error> + Send + 'static
     {
-        Response(Box::new(future))
+        Response(Box::new(future), ResponseExtras::default())
     }
 
     pub fn headers_and_stream(headers: Headers, stream: HttpPartStream) -> Response
@@ -31,6 +123,9 @@ impl Response {
         Response::new(future::ok((headers, stream)))
     }
 
+    /// Response with body generated lazily: each item is sent as DATA
+    /// (split by peer max frame size, subject to flow control) as soon as it is available,
+    /// END_STREAM is sent when stream completes.
     pub fn headers_and_bytes_stream<S>(headers: Headers, content: S) -> Response
         where S : Stream<Item=Bytes, Error=Error> + Send + 'static
     {
@@ -45,6 +140,23 @@ impl Response {
         Response::headers_and_bytes(message.headers, message.body)
     }
 
+    /// Response with trailers: body is followed by HEADERS frame with END_STREAM flag
+    pub fn headers_and_bytes_with_trailers(headers: Headers, content: Bytes, trailers: Headers)
+        -> Response
+    {
+        let parts = vec![
+            HttpStreamPart::intermediate_data(content),
+            HttpStreamPart::last_headers(trailers),
+        ];
+        Response::headers_and_stream(headers, HttpPartStream::new(stream::iter_ok(parts)))
+    }
+
+    pub fn message_with_trailers(message: SimpleHttpMessage, trailers: Headers) -> Response {
+        Response::headers_and_bytes_with_trailers(message.headers, message.body, trailers)
+    }
+
+    /// Response from stream of parts, first part must be HEADERS.
+    /// To respond with headers and lazily generated body use `headers_and_bytes_stream`.
     pub fn from_stream<S>(stream: S) -> Response
         where S : Stream<Item=HttpStreamPart, Error=Error> + Send + 'static
     {
@@ -56,12 +168,12 @@ impl Response {
                             Ok((headers, HttpPartStream::new(rem)))
                         },
                         HttpStreamPartContent::Data(..) => {
-                            Err(Error::InvalidFrame("data before headers".to_owned()))
+                            Err(Error::InvalidFrame(InvalidFrameInfo::new("data before headers")))
                         }
                     }
                 }
                 None => {
-                    Err(Error::InvalidFrame("empty response, expecting headers".to_owned()))
+                    Err(Error::InvalidFrame(InvalidFrameInfo::new("empty response, expecting headers")))
                 }
             }
         }))
@@ -71,8 +183,44 @@ impl Response {
         Response::new(future::err(err))
     }
 
+    /// Server push: PUSH_PROMISE frames for given requests are sent on the client's stream
+    /// before response headers, then promised responses are sent in new streams.
+    ///
+    /// Pushes are silently dropped if client disabled push with `SETTINGS_ENABLE_PUSH`
+    /// or server disabled push with `ServerConf::enable_push`. Ignored by client.
+    pub fn with_push(mut self, promises: Vec<(Headers, Response)>) -> Response {
+        self.1.pushes.extend(promises);
+        self
+    }
+
+    pub(crate) fn with_request_handle(mut self, handle: RequestHandle) -> Response {
+        self.1.request_handle = handle;
+        self
+    }
+
     // getters
 
+    /// Id of client request stream, `None` until the stream is opened:
+    /// request may be queued waiting for connection or for
+    /// server `SETTINGS_MAX_CONCURRENT_STREAMS` limit.
+    /// Always `None` for responses not created by `Client`.
+    pub fn stream_id(&self) -> Option<StreamId> {
+        self.1.request_handle.stream_id()
+    }
+
+    /// Handle to cancel client request without dropping the response.
+    /// Canceling does nothing for responses not created by `Client`.
+    pub fn request_handle(&self) -> RequestHandle {
+        self.1.request_handle.clone()
+    }
+
+    /// Response future and attached server pushes
+    pub(crate) fn into_future_and_pushes(self)
+        -> (HttpFutureSend<(Headers, HttpPartStream)>, Vec<(Headers, Response)>)
+    {
+        (self.0, self.1.pushes)
+    }
+
     pub fn into_stream_flag(self) -> HttpFutureStreamSend<HttpStreamPart> {
         Box::new(self.0.map(|(headers, rem)| {
             // NOTE: flag may be wrong for first item
@@ -88,6 +236,33 @@ impl Response {
         HttpPartStream::new(self.into_stream_flag())
     }
 
+    /// Resolves as soon as response headers are received,
+    /// body DATA frames are then streamed as they arrive.
+    /// Trailing headers, if any, are skipped.
+    pub fn into_headers_and_body(self) -> HttpFutureSend<(Headers, HttpFutureStreamSend<Bytes>)> {
+        Box::new(self.0.map(|(headers, rem)| (headers, rem.filter_data())))
+    }
+
+    /// Decode body if response has `content-encoding` `gzip` or `deflate`,
+    /// other responses are returned unchanged.
+    ///
+    /// `content-encoding` and `content-length` headers are removed from decoded response,
+    /// invalid encoded body is reported as `Error::DecompressionError`.
+    #[cfg(feature = "gzip")]
+    pub fn decompress(self) -> Response {
+        self.decompress_limited(None)
+    }
+
+    /// Like `decompress`, decoded body fails with `Error::BodyTooLarge`
+    /// if it exceeds `max_size`
+    #[cfg(feature = "gzip")]
+    pub(crate) fn decompress_limited(self, max_size: Option<u64>) -> Response {
+        let Response(future, extras) = self;
+        Response(
+            Box::new(future.map(move |(headers, body)| content_coding::decompress(headers, body, max_size))),
+            extras)
+    }
+
     pub fn collect(self) -> HttpFutureSend<SimpleHttpMessage> {
         Box::new(self.into_stream().fold(SimpleHttpMessage::new(), |mut c, p| {
             c.add(p);
@@ -0,0 +1,80 @@
+//! TLS with rustls, enabled with `tls-rustls` feature.
+//!
+//! Unlike native-tls, rustls supports ALPN on all platforms,
+//! configs must offer `h2`, e. g. with `config.set_protocols(&h2_protocols())`.
+
+use std::sync::Arc;
+
+use futures::future;
+use futures::future::Future;
+
+use tokio_io::AsyncRead;
+use tokio_io::AsyncWrite;
+
+use rustls::ClientConfig;
+use rustls::ClientSession;
+use rustls::ServerConfig;
+use rustls::ServerSession;
+use rustls::Session;
+
+use tokio_rustls::ClientConfigExt;
+use tokio_rustls::ServerConfigExt;
+use tokio_rustls::TlsStream;
+
+use webpki::DNSNameRef;
+
+use error::Error;
+
+use alpn;
+use service::ServiceContext;
+use solicit_async::*;
+
+
+/// Protocols to pass to `set_protocols` of `ServerConfig` or `ClientConfig`
+pub fn h2_protocols() -> Vec<String> {
+    vec![String::from_utf8_lossy(alpn::H2).into_owned()]
+}
+
+/// TLS handshake, connection is rejected if client negotiated protocol other than `h2`
+/// with ALPN, or negotiated nothing and `require_alpn_h2` is set.
+///
+/// Client certificates are verified by config (e. g. with `AllowAnyAuthenticatedClient`),
+/// `require_client_cert` only checks client sent one, `client_cert` is not filled.
+pub fn accept_h2<I>(
+    config: &Arc<ServerConfig>, socket: I, require_client_cert: bool, require_alpn_h2: bool,
+    mut context: ServiceContext)
+    -> HttpFutureSend<(TlsStream<I, ServerSession>, ServiceContext)>
+    where I : AsyncRead + AsyncWrite + Send + 'static
+{
+    Box::new(config.accept_async(socket)
+        .map_err(Error::from)
+        .and_then(move |socket| {
+            context.alpn_protocol = socket.get_ref().1.get_alpn_protocol().map(|p| p.to_owned());
+            info!("TLS ALPN negotiated protocol: {:?}", context.alpn_protocol);
+            alpn::check_negotiated_h2(
+                socket.get_ref().1.get_alpn_protocol().map(|p| p.as_bytes()), require_alpn_h2)?;
+            if require_client_cert && socket.get_ref().1.get_peer_certificates().is_none() {
+                return Err(Error::Other("no TLS client certificate"));
+            }
+            Ok((socket, context))
+        }))
+}
+
+/// TLS handshake with server, `domain` is sent in SNI and certificate is verified against it
+pub fn connect_h2<I>(config: &Arc<ClientConfig>, domain: &str, socket: I)
+    -> HttpFutureSend<TlsStream<I, ClientSession>>
+    where I : AsyncRead + AsyncWrite + Send + 'static
+{
+    let domain = match DNSNameRef::try_from_ascii_str(domain) {
+        Ok(domain) => domain,
+        Err(()) => return Box::new(future::err(Error::Other("invalid TLS domain name"))),
+    };
+
+    Box::new(config.connect_async(domain, socket)
+        .map_err(Error::from)
+        .and_then(|socket| {
+            alpn::check_negotiated_h2(
+                socket.get_ref().1.get_alpn_protocol().map(|p| p.as_bytes()), require_alpn_h2)?;
+            Ok(socket)
+        }))
+}
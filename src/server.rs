@@ -1,4 +1,5 @@
 use std::thread;
+use std::net;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
 use std::collections::HashMap;
@@ -6,6 +7,9 @@ use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::io;
+use std::time::Duration;
+
+use bytes::Bytes;
 
 use tokio_core::reactor;
 use tokio_core::net::TcpListener;
@@ -15,8 +19,11 @@ use futures::stream;
 use futures::Stream;
 use futures::Future;
 use futures::future::join_all;
+use futures::future::Either;
+use futures::sync::oneshot;
 
 use error::Error;
+use error::ErrorCode;
 
 use solicit_async::*;
 
@@ -31,12 +38,16 @@ use service::Service;
 
 use server_conf::*;
 
+use metrics::Metrics;
+use metrics::MetricsSet;
+
 pub use server_tls::ServerTlsOption;
 
 
 struct LoopToServer {
     shutdown: ShutdownSignal,
     local_addr: SocketAddr,
+    loop_handle: reactor::Remote,
 }
 
 
@@ -46,15 +57,41 @@ pub struct Server {
     loop_to_server: LoopToServer,
     alive_rx: mpsc::Receiver<()>,
     thread_join_handle: Option<thread::JoinHandle<()>>,
+    // stops accept loop, sender passed with the message receives
+    // graceful shutdown future of connections accepted before listener is closed
+    stop_accept_tx: Mutex<Option<oneshot::Sender<oneshot::Sender<HttpFutureSend<()>>>>>,
 }
 
 #[derive(Default)]
 struct ServerState {
     last_conn_id: u64,
     conns: HashMap<u64, ServerConnection>,
+    /// Set when graceful shutdown started
+    goaway: Option<(ErrorCode, Bytes)>,
+    /// Counters of all accepted connections
+    metrics: MetricsSet,
+    /// Set when accept loop is running
+    ready: bool,
+    /// Set when event loop exited
+    stopped: bool,
+    ready_waiters: Vec<oneshot::Sender<()>>,
 }
 
 impl ServerState {
+    fn set_ready(&mut self) {
+        self.ready = true;
+        for tx in self.ready_waiters.drain(..) {
+            // ignore error, waiter may be dropped
+            tx.send(()).ok();
+        }
+    }
+
+    fn set_stopped(&mut self) {
+        self.stopped = true;
+        // waiters are notified by canceled oneshot
+        self.ready_waiters.clear();
+    }
+
     fn snapshot(&self) -> HttpFutureSend<ServerStateSnapshot> {
         let futures: Vec<_> = self.conns.iter()
             .map(|(&id, conn)| conn.dump_state().map(move |state| (id, state)))
@@ -65,6 +102,16 @@ impl ServerState {
                 conns: states.into_iter().collect(),
             }))
     }
+
+    fn shutdown_gracefully(&mut self, error_code: ErrorCode, debug_data: Bytes) -> HttpFutureSend<()> {
+        self.goaway = Some((error_code, debug_data.clone()));
+
+        let futures: Vec<_> = self.conns.values()
+            .map(|conn| conn.shutdown_gracefully(error_code, debug_data.clone()))
+            .collect();
+
+        Box::new(join_all(futures).map(|_| ()))
+    }
 }
 
 pub struct ServerStateSnapshot {
@@ -87,45 +134,85 @@ fn configure_tcp(_tcp: &net2::TcpBuilder, conf: &ServerConf) -> io::Result<()> {
 
 fn listener(
     addr: &SocketAddr,
-    handle: &reactor::Handle,
     conf: &ServerConf)
-        -> io::Result<TcpListener>
+        -> io::Result<net::TcpListener>
 {
     let listener = match *addr {
         SocketAddr::V4(_) => net2::TcpBuilder::new_v4()?,
         SocketAddr::V6(_) => net2::TcpBuilder::new_v6()?,
     };
     configure_tcp(&listener, conf)?;
-    listener.reuse_address(true)?;
+    if let (&SocketAddr::V6(_), Some(only_v6)) = (addr, conf.only_v6) {
+        listener.only_v6(only_v6)?;
+    }
+    listener.reuse_address(conf.reuse_address.unwrap_or(true))?;
     listener.bind(addr)?;
     let backlog = conf.backlog.unwrap_or(1024);
-    let listener = listener.listen(backlog)?;
-    TcpListener::from_listener(listener, addr, handle)
+    listener.listen(backlog)
+}
+
+/// Connection ended the way connections normally end, not worth reporting
+fn is_ordinary_disconnect(e: &Error) -> bool {
+    match *e {
+        Error::ConnectionDied(ref e) => is_ordinary_disconnect(e),
+        // peer closed connection at frame boundary
+        Error::IoError(ref e) => e.kind() == io::ErrorKind::UnexpectedEof,
+        // read loop ends this way after GOAWAY was sent or received
+        Error::Other("GOAWAY") => true,
+        Error::ConnectionError(ErrorCode::NoError, _) => true,
+        Error::Shutdown => true,
+        _ => false,
+    }
+}
+
+/// Marks server state stopped when event loop exits, including exit by panic
+struct StoppedOnDrop(Arc<Mutex<ServerState>>);
+
+impl Drop for StoppedOnDrop {
+    fn drop(&mut self) {
+        // state may be poisoned by panic in event loop
+        let mut g = match self.0.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner(),
+        };
+        g.set_stopped();
+    }
 }
 
 fn run_server_event_loop<S>(
-    listen_addr: SocketAddr,
+    listener: net::TcpListener,
     state: Arc<Mutex<ServerState>>,
     tls: ServerTlsOption,
     conf: ServerConf,
     service: S,
     send_to_back: mpsc::Sender<LoopToServer>,
+    stop_accept_rx: oneshot::Receiver<oneshot::Sender<HttpFutureSend<()>>>,
     _alive_tx: mpsc::Sender<()>)
         where S : Service,
 {
+    let _stopped_on_drop = StoppedOnDrop(state.clone());
+
     let service = Arc::new(service);
 
     let mut lp = reactor::Core::new().expect("http2server");
 
     let (shutdown_signal, shutdown_future) = shutdown_signal();
 
-    let listen = listener(&listen_addr, &lp.handle(), &conf).unwrap();
+    let listen_addr = listener.local_addr().unwrap();
+    let listen = TcpListener::from_listener(listener, &listen_addr, &lp.handle()).unwrap();
+
+    let state_for_stop = state.clone();
+    let state_for_ready = state.clone();
 
     let stuff = stream::repeat((lp.handle(), service, state, tls, conf));
 
     let local_addr = listen.local_addr().unwrap();
     send_to_back
-        .send(LoopToServer { shutdown: shutdown_signal, local_addr: local_addr })
+        .send(LoopToServer {
+            shutdown: shutdown_signal,
+            local_addr: local_addr,
+            loop_handle: lp.remote(),
+        })
         .expect("send back");
 
     let loop_run = listen.incoming().map_err(Error::from).zip(stuff)
@@ -135,12 +222,19 @@ fn run_server_event_loop<S>(
             let no_delay = conf.no_delay.unwrap_or(true);
             socket.set_nodelay(no_delay).expect("failed to set TCP_NODELAY");
 
+            let on_connection_error = conf.on_connection_error.clone();
+
             let (conn, future) = ServerConnection::new(&loop_handle, socket, tls, conf, service);
 
             let conn_id = {
                 let mut g = state.lock().expect("lock");
                 g.last_conn_id += 1;
                 let conn_id = g.last_conn_id;
+                if let Some((error_code, ref debug_data)) = g.goaway {
+                    // shutdown future is already returned, so it does not wait for this connection
+                    drop(conn.shutdown_gracefully(error_code, debug_data.clone()));
+                }
+                g.metrics.add_conn(conn.shared_metrics());
                 let prev = g.conns.insert(conn_id, conn);
                 assert!(prev.is_none());
                 conn_id
@@ -151,12 +245,47 @@ fn run_server_event_loop<S>(
                     let mut g = state.lock().expect("lock");
                     let removed = g.conns.remove(&conn_id);
                     assert!(removed.is_some());
-                    r
+                    r.map_err(|e| (e, g.goaway.is_some()))
                 })
-                .map_err(|e| { warn!("connection end: {:?}", e); () }));
+                .map_err(move |(e, shutting_down)| {
+                    if shutting_down || is_ordinary_disconnect(&e) {
+                        debug!("connection end: {:?}", e);
+                        return;
+                    }
+                    warn!("connection end: {:?}", e);
+                    if let Some(on_connection_error) = on_connection_error {
+                        on_connection_error.connection_error(peer_addr, &e);
+                    }
+                }));
             Ok(())
         });
 
+    // Listener is registered in event loop on first poll of accept loop
+    let loop_run = futures::lazy(move || {
+        state_for_ready.lock().expect("lock").set_ready();
+        loop_run
+    });
+
+    // Listener is closed when accept loop is dropped,
+    // accepted connections continue to run until shutdown
+    let loop_run = loop_run.select2(stop_accept_rx).then(move |r| {
+        match r {
+            Ok(Either::A(((), _))) => Ok(()),
+            Err(Either::A((e, _))) => Err(e),
+            Ok(Either::B((stopped_tx, loop_run))) => {
+                drop(loop_run);
+                info!("stopped accepting connections");
+                let graceful = state_for_stop.lock().expect("lock")
+                    .shutdown_gracefully(ErrorCode::NoError, Bytes::new());
+                // ignore error, shutdown future may be dropped
+                drop(stopped_tx.send(graceful));
+                Ok(())
+            }
+            // `Server` is dropped, loop is stopped by shutdown signal
+            Err(Either::B((_canceled, _))) => Ok(()),
+        }
+    });
+
     let shutdown_future = shutdown_future
         .then(move |_| {
             // Must complete with error,
@@ -173,13 +302,34 @@ fn run_server_event_loop<S>(
 }
 
 impl Server {
+    /// Bind listener and start server event loop.
+    ///
+    /// Listener is bound and listening when this function returns,
+    /// so connections are queued by OS until accepted,
+    /// `wait_ready` can be used to wait for accept loop to start.
     pub fn new<A: ToSocketAddrs, S>(addr: A, tls: ServerTlsOption, conf: ServerConf, service: S) -> Server
         where S : Service
     {
         let listen_addr = addr.to_socket_addrs().unwrap().next().unwrap();
+        let listener = listener(&listen_addr, &conf).unwrap();
+        Server::from_listener(listener, tls, conf, service)
+    }
+
+    /// Start server on already bound listener,
+    /// e. g. passed by systemd socket activation.
+    ///
+    /// Listener options like `SO_REUSEADDR` and backlog from `conf` are not applied.
+    pub fn from_listener<S>(listener: net::TcpListener, tls: ServerTlsOption, conf: ServerConf, service: S)
+        -> Server
+            where S : Service
+    {
+        if let ServerTlsOption::Plain = tls {
+            assert!(!conf.require_client_cert.unwrap_or(false), "require_client_cert is set, but TLS is not configured");
+        }
 
         let (get_from_loop_tx, get_from_loop_rx) = mpsc::channel();
         let (alive_tx, alive_rx) = mpsc::channel();
+        let (stop_accept_tx, stop_accept_rx) = oneshot::channel();
 
         let state: Arc<Mutex<ServerState>> = Default::default();
 
@@ -189,11 +339,12 @@ impl Server {
             .name(conf.thread_name.clone().unwrap_or_else(|| "http2-server-loop".to_owned()).to_string())
             .spawn(move || {
                 run_server_event_loop(
-                    listen_addr,
+                    listener,
                     state_copy,
                     tls,
                     conf, service,
                     get_from_loop_tx,
+                    stop_accept_rx,
                     alive_tx);
             })
             .expect("spawn");
@@ -205,22 +356,98 @@ impl Server {
             loop_to_server: loop_to_server,
             thread_join_handle: Some(join_handle),
             alive_rx: alive_rx,
+            stop_accept_tx: Mutex::new(Some(stop_accept_tx)),
         }
     }
 
-    pub fn local_addr(&self) -> &SocketAddr {
-        &self.loop_to_server.local_addr
+    /// Address server is listening on, as reported by listening socket,
+    /// so it contains OS-assigned port when server was bound to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.loop_to_server.local_addr
+    }
+
+    /// Future resolved once server event loop is accepting connections.
+    ///
+    /// Fails if event loop stopped before accepting connections.
+    pub fn wait_ready(&self) -> HttpFutureSend<()> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut g = self.state.lock().expect("lock");
+            if g.ready {
+                // ignore error, receiver is alive
+                tx.send(()).ok();
+            } else if !g.stopped {
+                g.ready_waiters.push(tx);
+            }
+        }
+        Box::new(rx.map_err(|_| Error::Other("server event loop stopped")))
     }
 
     pub fn is_alive(&self) -> bool {
         self.alive_rx.try_recv() != Err(mpsc::TryRecvError::Disconnected)
     }
 
+    /// Send GOAWAY with given error code and debug data to all connections
+    /// and stop accepting new streams.
+    ///
+    /// Returned future is resolved when all active streams are completed.
+    /// Can be called again to send another GOAWAY.
+    pub fn shutdown_gracefully(&self, error_code: ErrorCode, debug_data: Bytes) -> HttpFutureSend<()> {
+        self.state.lock().expect("lock").shutdown_gracefully(error_code, debug_data)
+    }
+
+    /// Stop accepting connections and gracefully shutdown active connections
+    /// with GOAWAY `NO_ERROR`.
+    ///
+    /// Returned future is resolved when listening socket is closed
+    /// and all active streams are completed, or when `deadline` expires.
+    /// Connections still active after deadline are closed when `Server` is dropped.
+    pub fn shutdown(&self, deadline: Option<Duration>) -> HttpFutureSend<()> {
+        // GOAWAY is sent by event loop after listener is closed,
+        // so no accepted connection is missed
+        let graceful: HttpFutureSend<()> = match self.stop_accept_tx.lock().expect("lock").take() {
+            Some(stop_accept_tx) => {
+                let (stopped_tx, stopped_rx) = oneshot::channel();
+                // ignore error, accept loop may be already dead
+                drop(stop_accept_tx.send(stopped_tx));
+                // canceled means event loop is dead
+                Box::new(stopped_rx.then(|r| r.unwrap_or_else(|_| Box::new(futures::finished(())))))
+            }
+            None => self.shutdown_gracefully(ErrorCode::NoError, Bytes::new()),
+        };
+
+        match deadline {
+            None => Box::new(graceful),
+            Some(deadline) => {
+                let (expired_tx, expired_rx) = oneshot::channel();
+                self.loop_to_server.loop_handle.spawn(move |handle| {
+                    futures::done(reactor::Timeout::new(deadline, handle))
+                        .flatten()
+                        .then(move |_| {
+                            // ignore error, shutdown may be already completed
+                            drop(expired_tx.send(()));
+                            Ok(())
+                        })
+                });
+                let expired = expired_rx.then(|_| {
+                    info!("shutdown deadline expired");
+                    Ok(())
+                });
+                Box::new(graceful.select(expired).map(|_| ()).map_err(|(e, _)| e))
+            }
+        }
+    }
+
     // for tests
     pub fn dump_state(&self) -> HttpFutureSend<ServerStateSnapshot> {
         let g = self.state.lock().expect("lock");
         g.snapshot()
     }
+
+    /// Frame, byte and stream counters summed over all accepted connections
+    pub fn metrics(&self) -> Metrics {
+        self.state.lock().expect("lock").metrics.total()
+    }
 }
 
 // We shutdown the server in the destructor.
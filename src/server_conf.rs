@@ -1,13 +1,81 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
 use common::CommonConf;
+use error::Error;
+
+/// Called when connection accepted by server is closed because of error
+pub trait ConnectionErrorHandler : Send + Sync + 'static {
+    fn connection_error(&self, peer_addr: SocketAddr, error: &Error);
+}
+
+impl fmt::Debug for ConnectionErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ConnectionErrorHandler")
+    }
+}
+
+impl<F : Fn(SocketAddr, &Error) + Send + Sync + 'static> ConnectionErrorHandler for F {
+    fn connection_error(&self, peer_addr: SocketAddr, error: &Error) {
+        self(peer_addr, error)
+    }
+}
+
+/// Default of `ServerConf::rapid_reset_limit`
+pub const DEFAULT_RAPID_RESET_LIMIT: u32 = 100;
+/// Default of `ServerConf::rapid_reset_window`, in seconds
+pub const DEFAULT_RAPID_RESET_WINDOW_SECS: u64 = 30;
 
 #[derive(Default, Debug, Clone)]
 pub struct ServerConf {
-    /// TCP_NODELAY
+    /// TCP_NODELAY, default is true
     pub no_delay: Option<bool>,
     pub thread_name: Option<String>,
-    /// Ignored on Windows
+    /// SO_REUSEADDR, default is true
+    pub reuse_address: Option<bool>,
+    /// SO_REUSEPORT, ignored on Windows
     pub reuse_port: Option<bool>,
+    /// IPV6_V6ONLY for IPv6 listen address: when false, IPv4 clients are accepted too.
+    /// Default is system-specific
+    pub only_v6: Option<bool>,
+    /// Listen backlog of listener socket, i. e. maximum number of connections
+    /// queued by OS before they are accepted, default is 1024
     pub backlog: Option<i32>,
+    /// Send pushes attached to responses with `Response::with_push`, default is true.
+    /// Pushes are never sent if client disabled them.
+    pub enable_push: Option<bool>,
+    /// Called when connection fails, e. g. because of invalid preface,
+    /// protocol error or I/O error.
+    /// Not called when client closes connection between frames, after GOAWAY,
+    /// or when server is shutting down
+    pub on_connection_error: Option<Arc<ConnectionErrorHandler>>,
+    /// Close connection if client does not complete handshake
+    /// (TLS handshake and preface) within this time
+    pub client_handshake_timeout: Option<Duration>,
+    /// Close connection if it has no open streams and no frames are
+    /// sent or received within this time
+    pub read_timeout: Option<Duration>,
+    /// Connection is closed with ENHANCE_YOUR_CALM if client resets more than this many
+    /// streams before they are completed within `rapid_reset_window`
+    /// (Rapid Reset attack, CVE-2023-44487). Default is `DEFAULT_RAPID_RESET_LIMIT`
+    pub rapid_reset_limit: Option<u32>,
+    /// Default is `DEFAULT_RAPID_RESET_WINDOW_SECS`
+    pub rapid_reset_window: Option<Duration>,
+    /// Close TLS connections of clients which did not send verified certificate.
+    /// Acceptor must request certificates, see `client_auth::acceptor_require_client_cert`.
+    /// Server cannot be created with `ServerTlsOption::Plain` if set. Default is false
+    pub require_client_cert: Option<bool>,
+    /// Close TLS connections which did not negotiate `h2` with ALPN.
+    /// By default connections which negotiated no protocol are accepted,
+    /// and connections which negotiated other protocol (e. g. `http/1.1`) are closed.
+    /// Ignored if ALPN is not supported, see `alpn::SUPPORTED`. Default is false
+    pub require_alpn_h2: Option<bool>,
+    /// Accept HTTP/1.1 requests with `Upgrade: h2c` on cleartext connections
+    /// (RFC 7540, section 3.2). When not set, clients must start with connection preface.
+    /// Default is false
+    pub h2c_upgrade: Option<bool>,
 
     pub common: CommonConf,
 }
@@ -1,13 +1,28 @@
 use std::io;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicUsize;
 use std::panic;
 
 use error;
+use error::ErrorCode;
+use error::GoAwayInfo;
 use result;
 
 use solicit::StreamId;
 use solicit::header::*;
 use solicit::connection::EndStream;
+use solicit::session::StreamState;
+use solicit::frame::settings::HttpSetting;
+use solicit::frame::settings::MIN_MAX_FRAME_SIZE;
+use solicit::frame::settings::MAX_MAX_FRAME_SIZE;
+use solicit::frame::rst_stream::RstStreamFrame;
+use solicit::frame::push_promise::PushPromiseFrame;
+use solicit::frame::push_promise::PushPromiseFlag;
+use solicit::frame::RawFrame;
+use solicit::frame::AltSvcFrame;
 
 use bytes::Bytes;
 
@@ -15,22 +30,36 @@ use futures;
 use futures::Future;
 use futures::stream;
 use futures::stream::Stream;
+use futures::sync::mpsc::UnboundedSender;
 
 use tokio_io::AsyncRead;
 use tokio_io::AsyncWrite;
 use tokio_core::net::TcpStream;
 use tokio_core::reactor;
 use tokio_tls::TlsAcceptorExt;
+use tokio_tls::TlsStream;
+
+use native_tls::TlsAcceptor;
 
 use futures_misc::*;
 
 use solicit_async::*;
 use service::Service;
+use service::ServiceContext;
 use stream_part::*;
 use common::*;
 
 use server_tls::*;
+use alpn;
+use client_auth;
+#[cfg(feature = "tls-rustls")]
+use rustls_tls;
+use h2c::*;
 use server_conf::*;
+use frame_observer::ObservedIo;
+use metrics::Metrics;
+use metrics::MeteredIo;
+use metrics::SharedMetrics;
 
 use misc::any_to_string;
 use rc_mut::*;
@@ -77,6 +106,12 @@ impl HttpStream for ServerStream {
 
 struct ServerConnData {
     factory: Arc<Service>,
+    context: ServiceContext,
+    enable_push: bool,
+    /// Times client reset streams, within `rapid_reset_window`
+    peer_resets: VecDeque<Instant>,
+    rapid_reset_limit: u32,
+    rapid_reset_window: Duration,
 }
 
 impl ConnDataSpecific for ServerConnData {
@@ -86,9 +121,8 @@ impl ConnDataSpecific for ServerConnData {
 type ServerInner = ConnData<ServerTypes>;
 
 impl ServerInner {
-    fn new_stream(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, headers: Headers)
-        -> result::Result<HttpStreamRef<ServerTypes>>
-    {
+    /// Check id of new stream initiated by client and remember it as the last one
+    fn new_peer_stream_id(&mut self, stream_id: StreamId) -> result::Result<()> {
         if ServerTypes::is_init_locally(stream_id) {
             return Err(error::Error::Other("initiated stream with server id from client"));
         }
@@ -101,13 +135,33 @@ impl ServerInner {
 
         debug!("new stream: {}", stream_id);
 
+        Ok(())
+    }
+
+    fn new_stream(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, headers: Headers)
+        -> result::Result<HttpStreamRef<ServerTypes>>
+    {
+        self.new_peer_stream_id(stream_id)?;
+
         let (req_tx, req_rx) = futures::sync::mpsc::unbounded();
 
         let req_rx = req_rx.map_err(|()| error::Error::from(io::Error::new(io::ErrorKind::Other, "req")));
         let req_rx = stream_with_eof_and_error(req_rx, || error::Error::from(io::Error::new(io::ErrorKind::Other, "unexpected eof")));
 
+        // Release window only when service takes the data, so slow service throttles client
+        let unconsumed = Arc::new(AtomicUsize::new(0));
+        let req_rx = DataConsumedStream::new(req_rx, stream_id, self.to_write_tx.clone(), unconsumed.clone());
+
+        // context is cloned to pass cancel token of this stream
+        let (cancel_ctr, cancel) = cancel_token();
+        let context = ServiceContext {
+            cancel: cancel.clone(),
+            ..self.specific.context.clone()
+        };
+
         let response = panic::catch_unwind(panic::AssertUnwindSafe(|| {
-            self.specific.factory.start_request(headers, HttpPartStream::new(req_rx))
+            self.specific.factory.start_request_with_context(
+                &context, headers, HttpPartStream::new(req_rx))
         }));
 
         let response = response.unwrap_or_else(|e| {
@@ -121,6 +175,36 @@ impl ServerInner {
             ]))
         });
 
+        self.start_stream(self_rc, stream_id, req_tx, Some(unconsumed), (cancel_ctr, cancel), response)
+    }
+
+    /// Respond to new stream with empty response of given status without calling service,
+    /// request body is discarded
+    fn respond_without_service(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, status: u32)
+        -> result::Result<HttpStreamRef<ServerTypes>>
+    {
+        self.new_peer_stream_id(stream_id)?;
+
+        let (req_tx, _) = futures::sync::mpsc::unbounded();
+        let response = Response::headers_and_bytes(Headers::from_status(status), Bytes::new());
+        self.start_stream(self_rc, stream_id, req_tx, None, cancel_token(), response)
+    }
+
+    /// Insert stream initiated by the client and start sending the response
+    fn start_stream(
+        &mut self,
+        self_rc: RcMut<Self>,
+        stream_id: StreamId,
+        req_tx: UnboundedSender<ResultOrEof<HttpStreamPart, error::Error>>,
+        unconsumed: Option<Arc<AtomicUsize>>,
+        (cancel_ctr, cancel): (CancelController, CancelToken),
+        response: Response)
+            -> result::Result<HttpStreamRef<ServerTypes>>
+    {
+        let (response, pushes) = response.into_future_and_pushes();
+
+        self.push_promises(self_rc.clone(), stream_id, pushes)?;
+
         let (latch_ctr, latch) = latch();
 
         // TODO
@@ -128,19 +212,90 @@ impl ServerInner {
 
         {
             // New stream initiated by the client
-            let stream = HttpStreamCommon::new(
+            let mut stream = HttpStreamCommon::new(
+                self.conn.our_settings.initial_window_size,
                 self.conn.peer_settings.initial_window_size,
                 req_tx,
                 latch_ctr,
                 ServerStreamData {});
+            stream.in_window_by_consumer = unconsumed;
+            stream.cancel = Some(cancel_ctr);
             self.streams.insert(stream_id, stream);
         }
 
-        self.pump_stream_to_write_loop(self_rc, stream_id, response.into_part_stream(), latch);
+        let response = Response::new(response);
+        self.pump_stream_to_write_loop(self_rc, stream_id, response.into_part_stream(), latch, cancel);
 
         Ok(self.streams.get_mut(stream_id).expect("get stream"))
     }
 
+    /// Send PUSH_PROMISE frames in client stream and start promised streams
+    fn push_promises(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, pushes: Vec<(Headers, Response)>)
+        -> result::Result<()>
+    {
+        if pushes.is_empty() {
+            return Ok(());
+        }
+
+        // 8.2: server must not push if client disabled push
+        if !self.specific.enable_push || !self.conn.peer_settings.enable_push {
+            debug!("push disabled, dropping {} promises in stream {}", pushes.len(), stream_id);
+            return Ok(());
+        }
+
+        for (headers, response) in pushes {
+            let promised_stream_id = match self.local_stream_ids.allocate() {
+                Some(id) => id,
+                None => {
+                    debug!("stream ids exhausted, dropping promise in stream {}", stream_id);
+                    continue;
+                }
+            };
+
+            debug!("promising stream {} in stream {}", promised_stream_id, stream_id);
+
+            // header block is encoded in write loop to keep HPACK state in sync with frame order
+            self.to_write_tx.send(ServerToWriteMessage::PushPromise(stream_id, promised_stream_id, headers))
+                .map_err(|_| error::Error::Other("write loop died"))?;
+
+            // pushes of pushed response are ignored, client cannot push
+            let response = response.0;
+
+            let (latch_ctr, latch) = latch();
+            latch_ctr.open();
+
+            // nothing is received in promised stream
+            let (req_tx, _req_rx) = futures::sync::mpsc::unbounded();
+
+            let mut stream = HttpStreamCommon::new(
+                self.conn.our_settings.initial_window_size,
+                self.conn.peer_settings.initial_window_size,
+                req_tx,
+                latch_ctr,
+                ServerStreamData {});
+            stream.peer_tx = None;
+            // reserved (local)
+            stream.state = StreamState::HalfClosedRemote;
+            let (cancel_ctr, cancel) = cancel_token();
+            stream.cancel = Some(cancel_ctr);
+            self.streams.insert(promised_stream_id, stream);
+
+            let response = Response::new(response);
+            self.pump_stream_to_write_loop(self_rc.clone(), promised_stream_id, response.into_part_stream(), latch, cancel);
+        }
+
+        Ok(())
+    }
+
+    /// HTTP/1.1 request upgraded to HTTP/2 is served as half-closed stream 1 (RFC 7540, 3.2)
+    fn process_h2c_upgrade(&mut self, self_rc: RcMut<Self>, upgrade: H2cUpgrade) -> result::Result<()> {
+        self.apply_peer_settings(upgrade.settings)?;
+
+        let mut stream = self.new_stream(self_rc, 1, upgrade.headers)?;
+        stream.stream().close_remote();
+        Ok(())
+    }
+
     fn get_or_create_stream(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, headers: Headers, last: bool)
         -> result::Result<HttpStreamRef<ServerTypes>>
     {
@@ -161,27 +316,160 @@ impl ConnInner for ServerInner {
     fn process_headers(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, end_stream: EndStream, headers: Headers)
         -> result::Result<Option<HttpStreamRef<ServerTypes>>>
     {
-        let stream = self.get_or_create_stream(
+        let mut content_length = None;
+
+        if let Some(mut stream) = self.streams.get_mut(stream_id) {
+            // 8.1.2.1: trailers must not contain pseudo-headers
+            if headers.contains_pseudo_headers() {
+                warn!("pseudo-headers in trailers of stream {}", stream_id);
+                if let Some(peer_tx) = stream.stream().peer_tx.take() {
+                    // it is OK to ignore error: handler may be already dead
+                    drop(peer_tx.send(ResultOrEof::Error(error::Error::CodeError(ErrorCode::ProtocolError))));
+                }
+                stream.rst_remove(ErrorCode::ProtocolError);
+                self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::ProtocolError))?;
+                return Ok(None);
+            }
+
+            // 8.1.2.6: trailers end the stream, body must match declared length
+            if end_stream == EndStream::Yes && stream.stream().content_length_mismatch(true) {
+                let received = stream.stream().data_received;
+                warn!("body of stream {} does not match content-length {:?}, received {}",
+                    stream_id, stream.stream().content_length, received);
+                stream.rst_remove(ErrorCode::ProtocolError);
+                self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::ProtocolError))?;
+                return Ok(None);
+            }
+        } else {
+            // 5.1.1: streams initiated by client must use odd-numbered identifiers
+            if ServerTypes::is_init_locally(stream_id) {
+                warn!("client initiated stream with even id {}", stream_id);
+                return Err(error::Error::CodeError(ErrorCode::ProtocolError));
+            }
+
+            // 8.1.2.1: pseudo-headers must be known, unique and precede regular headers.
+            // Malformed request is a stream error, other streams of connection continue.
+            if let Err(e) = headers.validate(RequestOrResponse::Request) {
+                warn!("malformed request headers in stream {}: {:?}", stream_id, e);
+                self.new_peer_stream_id(stream_id)?;
+                self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::ProtocolError))?;
+                return Ok(None);
+            }
+
+            // 8.1.2.6: request without body cannot declare non-zero length
+            content_length = match headers.content_length() {
+                Ok(content_length) => content_length,
+                Err(_) => {
+                    warn!("invalid content-length in request in stream {}", stream_id);
+                    self.new_peer_stream_id(stream_id)?;
+                    self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::ProtocolError))?;
+                    return Ok(None);
+                }
+            };
+            if end_stream == EndStream::Yes && content_length.unwrap_or(0) != 0 {
+                warn!("request without body in stream {} declares content-length {:?}",
+                    stream_id, content_length);
+                self.new_peer_stream_id(stream_id)?;
+                self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::ProtocolError))?;
+                return Ok(None);
+            }
+
+            // 5.1.2: streams above advertised limit are refused
+            if self.streams.peer_count() >= self.conn.our_settings.max_concurrent_streams as usize {
+                debug!("refusing stream {}: too many concurrent streams", stream_id);
+                if stream_id > self.last_peer_stream_id {
+                    self.last_peer_stream_id = stream_id;
+                }
+                self.send_frame(RstStreamFrame::new(stream_id, ErrorCode::RefusedStream))?;
+                return Ok(None);
+            }
+
+            // 8.1.2.3: well-formed request with target server cannot serve,
+            // `:path` must be in origin form or `*`
+            let origin_form = headers.path().map_or(true, |path| path.starts_with('/') || path == "*");
+            if !origin_form {
+                warn!("invalid :path in stream {}: {:?}", stream_id, headers.path());
+                return self.respond_without_service(self_rc, stream_id, 400).map(Some);
+            }
+        }
+
+        let mut stream = self.get_or_create_stream(
             self_rc,
             stream_id,
             headers,
             end_stream == EndStream::Yes)?;
 
+        if content_length.is_some() {
+            stream.stream().content_length = content_length;
+        }
+
         Ok(Some(stream))
     }
 
-    fn goaway_received(&mut self, _stream_id: StreamId, _raw_error_code: u32) {
+    fn process_headers_too_large(&mut self, self_rc: RcMut<Self>, stream_id: StreamId, _end_stream: EndStream)
+        -> result::Result<Option<HttpStreamRef<ServerTypes>>>
+    {
+        // 10.5.1: server can respond with 431 to header block it is not willing to handle
+        self.respond_without_service(self_rc, stream_id, 431).map(Some)
+    }
+
+    fn process_push_promise(&mut self, _self_rc: RcMut<Self>, _stream_id: StreamId, _promised_stream_id: StreamId, _headers: Headers)
+        -> result::Result<()>
+    {
+        // 8.2: A client cannot push
+        Err(error::Error::CodeError(ErrorCode::ProtocolError))
+    }
+
+    fn goaway_received(&mut self, _info: GoAwayInfo) {
         // ignore
     }
+
+    fn origin_received(&mut self, _origins: Vec<String>) {
+        // RFC 8336, section 2.1: server must ignore ORIGIN frames
+    }
+
+    fn alt_svc_received(&mut self, _frame: AltSvcFrame) {
+        // RFC 7838, section 4: server must ignore ALTSVC frames
+    }
+
+    fn streams_updated(&mut self) {
+    }
+
+    fn stream_reset_by_peer(&mut self, stream_id: StreamId) -> result::Result<()> {
+        if ServerTypes::is_init_locally(stream_id) {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let window = self.specific.rapid_reset_window;
+        while self.specific.peer_resets.front().map_or(false, |t| now.duration_since(*t) >= window) {
+            self.specific.peer_resets.pop_front();
+        }
+        self.specific.peer_resets.push_back(now);
+
+        // CVE-2023-44487: streams opened and reset at once
+        // make server do work without being limited by max concurrent streams
+        if self.specific.peer_resets.len() > self.specific.rapid_reset_limit as usize {
+            warn!("client reset more than {} streams within {:?}, closing connection",
+                self.specific.rapid_reset_limit, window);
+            return Err(error::Error::CodeError(ErrorCode::EnhanceYourCalm));
+        }
+
+        Ok(())
+    }
 }
 
 type ServerReadLoop<I> = ReadLoopData<I, ServerTypes>;
 type ServerWriteLoop<I> = WriteLoopData<I, ServerTypes>;
 type ServerCommandLoop = CommandLoopData<ServerTypes>;
 
+type ServerIdleTimeoutLoop = IdleTimeoutLoopData<ServerTypes>;
+
 
 enum ServerToWriteMessage {
     Common(CommonToWriteMessage),
+    /// Stream id, promised stream id, promised request headers
+    PushPromise(StreamId, StreamId, Headers),
 }
 
 impl From<CommonToWriteMessage> for ServerToWriteMessage {
@@ -192,6 +480,8 @@ impl From<CommonToWriteMessage> for ServerToWriteMessage {
 
 enum ServerCommandMessage {
     DumpState(futures::sync::oneshot::Sender<ConnectionStateSnapshot>),
+    Goaway(ErrorCode, Bytes, futures::sync::oneshot::Sender<()>),
+    SendRawFrame(RawFrame, futures::sync::oneshot::Sender<()>),
 }
 
 
@@ -205,16 +495,28 @@ impl<I : AsyncWrite + Send> ServerWriteLoop<I> {
             ServerToWriteMessage::Common(common) => {
                 self.process_common(common)
             },
+            ServerToWriteMessage::PushPromise(stream_id, promised_stream_id, headers) => {
+                self.process_push_promise(stream_id, promised_stream_id, headers)
+            },
         }
     }
 
+    fn process_push_promise(self, stream_id: StreamId, promised_stream_id: StreamId, headers: Headers)
+        -> HttpFuture<Self>
+    {
+        let fragment = self.inner.with(|inner| {
+            inner.conn.encoder.encode(headers.0.iter().map(|h| (h.name(), h.value())))
+        });
+
+        let mut frame = PushPromiseFrame::new(fragment, stream_id, promised_stream_id);
+        frame.set_flag(PushPromiseFlag::EndHeaders);
+
+        self.write_frame(frame.into())
+    }
+
     fn run(self, requests: HttpFutureStream<ServerToWriteMessage>) -> HttpFuture<()> {
         let requests = requests.map_err(error::Error::from);
-        Box::new(requests
-            .fold(self, move |wl, message: ServerToWriteMessage| {
-                wl.process_message(message)
-            })
-            .map(|_| ()))
+        self.run_messages(requests, Self::process_message)
     }
 }
 
@@ -225,9 +527,26 @@ impl ServerCommandLoop {
         Box::new(futures::finished(self))
     }
 
+    fn process_goaway(self, error_code: ErrorCode, debug_data: Bytes, tx: futures::sync::oneshot::Sender<()>)
+        -> HttpFuture<Self>
+    {
+        let r = self.inner.with(move |inner| inner.send_goaway(error_code, debug_data, tx));
+        Box::new(futures::done(r.map(|()| self)))
+    }
+
+    fn process_send_raw_frame(self, frame: RawFrame, tx: futures::sync::oneshot::Sender<()>)
+        -> HttpFuture<Self>
+    {
+        let r = self.inner.with(move |inner| inner.send_raw_frame(frame, tx));
+        Box::new(futures::done(r.map(|()| self)))
+    }
+
     fn process_message(self, message: ServerCommandMessage) -> HttpFuture<Self> {
         match message {
             ServerCommandMessage::DumpState(sender) => self.process_dump_state(sender),
+            ServerCommandMessage::Goaway(error_code, debug_data, tx) =>
+                self.process_goaway(error_code, debug_data, tx),
+            ServerCommandMessage::SendRawFrame(frame, tx) => self.process_send_raw_frame(frame, tx),
         }
     }
 
@@ -244,10 +563,69 @@ impl ServerCommandLoop {
 
 pub struct ServerConnection {
     command_tx: futures::sync::mpsc::UnboundedSender<ServerCommandMessage>,
+    metrics: SharedMetrics,
+}
+
+/// Settings sent to client in addition to default
+pub(crate) fn settings(conf: &ServerConf) -> result::Result<Vec<HttpSetting>> {
+    let mut settings = Vec::new();
+    if let Some(header_table_size) = conf.common.header_table_size {
+        settings.push(HttpSetting::HeaderTableSize(header_table_size));
+    }
+    if let Some(max_concurrent_streams) = conf.common.max_concurrent_streams {
+        settings.push(HttpSetting::MaxConcurrentStreams(max_concurrent_streams));
+    }
+    if let Some(max_header_list_size) = conf.common.max_header_list_size {
+        settings.push(HttpSetting::MaxHeaderListSize(max_header_list_size));
+    }
+    if let Some(max_frame_size) = conf.common.max_frame_size {
+        // 6.5.2: values outside of this range are not allowed
+        if max_frame_size < MIN_MAX_FRAME_SIZE || max_frame_size > MAX_MAX_FRAME_SIZE {
+            return Err(error::Error::InvalidSetting(HttpSetting::MaxFrameSize(max_frame_size)));
+        }
+        settings.push(HttpSetting::MaxFrameSize(max_frame_size));
+    }
+    conf.common.add_extra_settings(&mut settings)?;
+    Ok(settings)
+}
+
+/// TLS handshake, connection is rejected unless client negotiated `h2` with ALPN
+fn accept_h2<I>(acceptor: &TlsAcceptor, socket: I, require_client_cert: bool, require_alpn_h2: bool, mut context: ServiceContext)
+    -> HttpFutureSend<(TlsStream<I>, ServiceContext)>
+    where I : AsyncRead + AsyncWrite + Send + 'static
+{
+    Box::new(acceptor.accept_async(socket)
+        .map_err(error::Error::from)
+        .and_then(move |socket| {
+            context.alpn_protocol = alpn::negotiated_protocol(socket.get_ref())
+                .map(|p| String::from_utf8_lossy(&p).into_owned());
+            info!("TLS ALPN negotiated protocol: {:?}", context.alpn_protocol);
+            alpn::check_h2(socket.get_ref(), require_alpn_h2)?;
+            context.client_cert = match require_client_cert {
+                true => Some(client_auth::check_client_cert(socket.get_ref())?),
+                false => client_auth::verified_peer_certificate(socket.get_ref()),
+            };
+            Ok((socket, context))
+        }))
+}
+
+/// TLS handshake with acceptor selected by SNI server name
+fn accept_h2_sni(resolver: Arc<SniResolver>, socket: TcpStream, require_client_cert: bool, require_alpn_h2: bool, context: ServiceContext)
+    -> HttpFutureSend<(TlsStream<ReplayIo<TcpStream>>, ServiceContext)>
+{
+    Box::new(read_client_hello(socket).and_then(move |(server_name, socket)| {
+        match resolver.resolve(server_name.as_ref().map(|s| &s[..])) {
+            Some(acceptor) => accept_h2(&acceptor, socket, require_client_cert, require_alpn_h2, context),
+            None => {
+                warn!("no TLS acceptor for SNI server name {:?}", server_name);
+                Box::new(futures::failed(error::Error::Other("no TLS acceptor for SNI server name")))
+            }
+        }
+    }))
 }
 
 impl ServerConnection {
-    fn connected<F, I>(lh: &reactor::Handle, socket: HttpFutureSend<I>, conf: ServerConf, service: Arc<F>)
+    fn connected<F, I>(lh: &reactor::Handle, socket: HttpFutureSend<(I, ServiceContext)>, h2c_upgrade: bool, conf: ServerConf, service: Arc<F>)
                        -> (ServerConnection, HttpFuture<()>)
         where
             F : Service,
@@ -261,30 +639,90 @@ impl ServerConnection {
         let to_write_rx = to_write_rx.map_err(|()| error::Error::IoError(io::Error::new(io::ErrorKind::Other, "to_write")));
         let command_rx = Box::new(command_rx.map_err(|()| error::Error::IoError(io::Error::new(io::ErrorKind::Other, "command"))));
 
-        let handshake = socket.and_then(server_handshake);
-
-        let run = handshake.and_then(move |socket| {
+        let enable_push = conf.enable_push.unwrap_or(true);
+        let rapid_reset_limit = conf.rapid_reset_limit.unwrap_or(DEFAULT_RAPID_RESET_LIMIT);
+        let rapid_reset_window = conf.rapid_reset_window.unwrap_or(Duration::from_secs(DEFAULT_RAPID_RESET_WINDOW_SECS));
+
+        let metrics = SharedMetrics::default();
+
+        let read_timeout = conf.read_timeout;
+
+        let frame_observer = conf.common.frame_observer.clone();
+        let handshake = {
+            let metrics = metrics.clone();
+            futures::done(settings(&conf))
+                .and_then(move |settings| socket.map(move |(socket, context)| (socket, context, settings)))
+                .and_then(move |(socket, context, settings)| -> HttpFuture<_> {
+                    if h2c_upgrade {
+                        Box::new(server_upgrade(socket)
+                            .map(|(socket, upgrade)| (socket, upgrade, context, settings)))
+                    } else {
+                        Box::new(futures::finished((ReplayIo::new(Vec::new(), socket), None, context, settings)))
+                    }
+                })
+                .and_then(move |(socket, upgrade, context, settings)| {
+                    // HTTP/1.1 upgrade request and response are not counted and not observed
+                    let socket = ObservedIo::server(MeteredIo::server(socket, metrics), frame_observer);
+                    server_handshake(socket, settings.clone()).map(|socket| (socket, upgrade, context, settings))
+                })
+        };
+
+        let handshake: HttpFuture<_> = match conf.client_handshake_timeout {
+            Some(timeout) => future_with_timeout(handshake, timeout, &lh),
+            None => Box::new(handshake),
+        };
+
+        let metrics_for_conn = metrics.clone();
+        let run = handshake.and_then(move |(socket, upgrade, context, settings)| {
             let (read, write) = socket.split();
 
             let inner = RcMut::new(ConnData::new(
                 lh,
                 ServerConnData {
                     factory: service,
+                    context: context,
+                    enable_push: enable_push,
+                    peer_resets: VecDeque::new(),
+                    rapid_reset_limit: rapid_reset_limit,
+                    rapid_reset_window: rapid_reset_window,
                 },
                 conf.common,
+                &settings,
+                metrics_for_conn,
                 to_write_tx.clone()));
 
-            let run_write = ServerWriteLoop { write: write, inner: inner.clone() }.run(Box::new(to_write_rx));
-            let run_read = ServerReadLoop { read: read, inner: inner.clone() }.run();
+            if let Some(upgrade) = upgrade {
+                let inner_rc = inner.clone();
+                if let Err(e) = inner.with(move |inner| inner.process_h2c_upgrade(inner_rc, upgrade)) {
+                    return Box::new(futures::failed(e)) as HttpFuture<()>;
+                }
+            }
+
+            let inner_rc = inner.clone();
+            inner.with(move |inner| inner.start_settings_ack_timer(inner_rc));
+
+            let run_write = ServerWriteLoop::new(write, inner.clone()).run(Box::new(to_write_rx));
+            let run_read = ServerReadLoop {
+                read: PooledRead::new(read),
+                inner: inner.clone(),
+            }.run();
             let run_command = ServerCommandLoop { inner: inner.clone() }.run(command_rx);
 
-            run_write.join(run_read).join(run_command).map(|_| ())
+            let run_idle_timeout: HttpFuture<()> = match read_timeout {
+                Some(timeout) => ServerIdleTimeoutLoop { inner: inner.clone() }.run(timeout),
+                None => Box::new(futures::empty()),
+            };
+
+            // idle timeout loop never completes successfully
+            let run = run_write.join(run_read).join(run_command).map(|_| ());
+            Box::new(run.select(run_idle_timeout).map(|_| ()).map_err(|(e, _)| e))
         });
 
         let future = Box::new(run.then(|x| { info!("connection end: {:?}", x); x }));
 
         (ServerConnection {
             command_tx: command_tx,
+            metrics: metrics,
         }, future)
     }
 
@@ -293,13 +731,36 @@ impl ServerConnection {
         where
             S : Service,
     {
+        let context = ServiceContext {
+            peer_addr: socket.peer_addr().ok(),
+            alpn_protocol: None,
+            client_cert: None,
+            cancel: CancelToken::never(),
+        };
+        let require_client_cert = conf.require_client_cert.unwrap_or(false);
+        let require_alpn_h2 = conf.require_alpn_h2.unwrap_or(false);
+
         match tls {
-            ServerTlsOption::Plain =>
+            ServerTlsOption::Plain if require_client_cert =>
                 ServerConnection::connected(
-                    lh, Box::new(futures::finished(socket)), conf, service),
+                    lh, Box::new(futures::failed::<(TcpStream, ServiceContext), _>(
+                        error::Error::Other("client certificate required on plain connection"))),
+                    false, conf, service),
+            ServerTlsOption::Plain => {
+                let h2c_upgrade = conf.h2c_upgrade.unwrap_or(false);
+                ServerConnection::connected(
+                    lh, Box::new(futures::finished((socket, context))), h2c_upgrade, conf, service)
+            }
             ServerTlsOption::Tls(acceptor) =>
                 ServerConnection::connected(
-                    lh, Box::new(acceptor.accept_async(socket).map_err(error::Error::from)), conf, service),
+                    lh, Box::new(accept_h2(&acceptor, socket, require_client_cert, require_alpn_h2, context)), false, conf, service),
+            ServerTlsOption::Sni(resolver) =>
+                ServerConnection::connected(
+                    lh, accept_h2_sni(resolver, socket, require_client_cert, require_alpn_h2, context), false, conf, service),
+            #[cfg(feature = "tls-rustls")]
+            ServerTlsOption::Rustls(config) =>
+                ServerConnection::connected(
+                    lh, rustls_tls::accept_h2(&config, socket, require_client_cert, require_alpn_h2, context), false, conf, service),
         }
     }
 
@@ -341,4 +802,255 @@ impl ServerConnection {
         Box::new(rx)
     }
 
+    /// Frame, byte and stream counters of this connection
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    #[doc(hidden)]
+    pub fn shared_metrics(&self) -> SharedMetrics {
+        self.metrics.clone()
+    }
+
+    /// Write arbitrary frame to this connection, for tests and experiments.
+    ///
+    /// Frame is written as is, after frames already queued.
+    /// Returned future is resolved when frame is written.
+    pub fn send_raw_frame(&self, frame: RawFrame) -> HttpFutureSend<()> {
+        let (tx, rx) = futures::oneshot();
+
+        // error is reported by canceled oneshot
+        drop(self.command_tx.send(ServerCommandMessage::SendRawFrame(frame, tx)));
+
+        let rx = rx.map_err(|_| error::Error::Other("connection closed"));
+
+        Box::new(rx)
+    }
+
+    /// Send GOAWAY and stop accepting new streams.
+    ///
+    /// Returned future is resolved when all active streams are completed
+    /// or connection is closed.
+    pub fn shutdown_gracefully(&self, error_code: ErrorCode, debug_data: Bytes) -> HttpFutureSend<()> {
+        let (tx, rx) = futures::oneshot();
+
+        // ignore error, connection may be already closed
+        drop(self.command_tx.send(ServerCommandMessage::Goaway(error_code, debug_data, tx)));
+
+        // canceled means connection is closed
+        Box::new(rx.then(|_| Ok(())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::sync::mpsc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+
+    use futures;
+    use futures::Future;
+    use futures::Poll;
+    use futures::future::join_all;
+    use futures::stream::Stream;
+
+    use bytes::Bytes;
+
+    use tokio_core::net::TcpStream;
+    use tokio_core::reactor;
+    use tokio_io::AsyncRead;
+    use tokio_io::AsyncWrite;
+
+    use solicit::header::Headers;
+    use solicit::frame::FrameHeader;
+    use solicit::frame::RawFrame;
+    use solicit::frame::pack_header;
+    use solicit::frame::FrameIR;
+    use solicit::frame::FRAME_HEADER_LEN;
+    use solicit::frame::data::DataFrame;
+    use solicit::frame::data::DataFlag;
+    use solicit::frame::data::DATA_FRAME_TYPE;
+    use solicit::frame::headers::HeadersFrame;
+    use solicit::frame::headers::HeadersFlag;
+    use solicit::frame::settings::SettingsFrame;
+    use solicit_async::PREFACE;
+    use stream_part::HttpPartStream;
+    use stream_part::HttpStreamPartContent;
+    use hpack;
+    use mock_io::MockIo;
+    use service::Service;
+    use resp::Response;
+
+    use super::ServerConnection;
+
+    /// Socket counting `write` calls
+    struct CountingIo {
+        io: TcpStream,
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl Read for CountingIo {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.io.read(buf)
+        }
+    }
+
+    impl Write for CountingIo {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            self.io.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.io.flush()
+        }
+    }
+
+    impl AsyncRead for CountingIo {}
+
+    impl AsyncWrite for CountingIo {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            AsyncWrite::shutdown(&mut self.io)
+        }
+    }
+
+    struct NoRequests;
+
+    impl Service for NoRequests {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            panic!("no requests expected");
+        }
+    }
+
+    #[test]
+    fn small_frames_are_coalesced() {
+        const FRAMES: usize = 1000;
+        let payload = b"abcd";
+
+        let listener = net::TcpListener::bind("[::1]:0").expect("bind");
+        let port = listener.local_addr().expect("local_addr").port();
+
+        let client = thread::spawn(move || {
+            let mut conn = net::TcpStream::connect(("::1", port)).expect("connect");
+            conn.write_all(PREFACE).expect("preface");
+            // empty SETTINGS
+            conn.write_all(&pack_header(&FrameHeader {
+                length: 0,
+                frame_type: 0x4,
+                flags: 0,
+                stream_id: 0,
+            })).expect("settings");
+
+            let mut received = Vec::new();
+            conn.read_to_end(&mut received).ok();
+            received.len()
+        });
+
+        let (socket, _) = listener.accept().expect("accept");
+
+        let mut lp = reactor::Core::new().expect("core");
+        let socket = TcpStream::from_stream(socket, &lp.handle()).expect("from_stream");
+        let writes = Arc::new(AtomicUsize::new(0));
+        let io = CountingIo { io: socket, writes: writes.clone() };
+
+        let (conn, future) = ServerConnection::connected(
+            &lp.handle(), Box::new(futures::finished((io, Default::default()))), false, Default::default(), Arc::new(NoRequests));
+        lp.handle().spawn(future.map_err(|_| ()));
+
+        let sent: Vec<_> = (0..FRAMES).map(|_| {
+            let mut frame = pack_header(&FrameHeader {
+                length: payload.len() as u32,
+                frame_type: DATA_FRAME_TYPE,
+                flags: 0,
+                stream_id: 1,
+            }).to_vec();
+            frame.extend_from_slice(payload);
+            conn.send_raw_frame(RawFrame::from(frame))
+        }).collect();
+        lp.run(join_all(sent)).expect("send");
+
+        let writes = writes.load(Ordering::SeqCst);
+
+        // close the connection
+        drop(conn);
+        drop(lp);
+
+        let received = client.join().expect("join");
+        assert!(received >= FRAMES * (9 + payload.len()), "received {} bytes", received);
+        assert!(writes < FRAMES / 10, "{} writes for {} frames", writes, FRAMES);
+    }
+
+    /// Sends request body stream to the test
+    struct BodyToTest {
+        tx: Mutex<mpsc::Sender<HttpPartStream>>,
+    }
+
+    impl Service for BodyToTest {
+        fn start_request(&self, _headers: Headers, req: HttpPartStream) -> Response {
+            self.tx.lock().unwrap().send(req).unwrap();
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::new())
+        }
+    }
+
+    #[test]
+    fn request_body_is_not_copied() {
+        let (mut client, server) = MockIo::pair();
+
+        let header_block = hpack::Encoder::new().encode(vec![
+            (&b":method"[..], &b"POST"[..]),
+            (&b":path"[..], &b"/"[..]),
+            (&b":scheme"[..], &b"http"[..]),
+            (&b":authority"[..], &b"localhost"[..]),
+        ]);
+        let mut headers = HeadersFrame::new(header_block, 1);
+        headers.set_flag(HeadersFlag::EndHeaders);
+        // larger than `Bytes` inline capacity
+        let mut last = DataFrame::with_data(1, vec![b'b'; 100]);
+        last.set_flag(DataFlag::EndStream);
+
+        // whole request is read from socket at once
+        client.write_all(PREFACE).unwrap();
+        client.write_all(&SettingsFrame::new().serialize_into_vec()).unwrap();
+        client.write_all(&headers.serialize_into_vec()).unwrap();
+        client.write_all(&DataFrame::with_data(1, vec![b'a'; 100]).serialize_into_vec()).unwrap();
+        client.write_all(&last.serialize_into_vec()).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+
+        let mut lp = reactor::Core::new().expect("core");
+        let (_conn, future) = ServerConnection::connected(
+            &lp.handle(), Box::new(futures::finished((server, Default::default()))), false, Default::default(),
+            Arc::new(BodyToTest { tx: Mutex::new(tx) }));
+        lp.handle().spawn(future.map_err(|_| ()));
+
+        let body = loop {
+            lp.turn(Some(Duration::from_millis(100)));
+            match rx.try_recv() {
+                Ok(body) => break body,
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(mpsc::TryRecvError::Disconnected) => panic!("service dropped"),
+            }
+        };
+        let parts = lp.run(body.collect()).expect("body");
+
+        let data: Vec<Bytes> = parts.into_iter().filter_map(|part| match part.content {
+            HttpStreamPartContent::Data(data) => Some(data),
+            HttpStreamPartContent::Headers(..) => None,
+        }).collect();
+        assert_eq!(vec![Bytes::from(vec![b'a'; 100]), Bytes::from(vec![b'b'; 100])], data);
+
+        // both payloads are slices of the same read buffer,
+        // separated only by header of the second frame
+        assert_eq!(
+            data[0].as_ptr() as usize + data[0].len() + FRAME_HEADER_LEN,
+            data[1].as_ptr() as usize);
+    }
 }
@@ -1,11 +1,279 @@
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::str;
 use std::sync::Arc;
 
+use futures::Poll;
+use futures::future;
+use futures::future::Either;
+use futures::future::Future;
+
+use tokio_io::io::read_exact;
+use tokio_io::AsyncRead;
+use tokio_io::AsyncWrite;
+
 use native_tls::TlsAcceptor;
 
+#[cfg(feature = "tls-rustls")]
+use rustls::ServerConfig;
+
+use error::Error;
+
+use solicit_async::*;
+
+
+/// Selects acceptor by server name sent by client in TLS SNI extension
+pub trait SniResolver : Send + Sync + 'static {
+    /// `server_name` is `None` if client did not send SNI.
+    /// Connection is closed if `None` is returned.
+    fn resolve(&self, server_name: Option<&str>) -> Option<Arc<TlsAcceptor>>;
+}
+
+impl fmt::Debug for SniResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SniResolver")
+    }
+}
+
+impl<F : Fn(Option<&str>) -> Option<Arc<TlsAcceptor>> + Send + Sync + 'static> SniResolver for F {
+    fn resolve(&self, server_name: Option<&str>) -> Option<Arc<TlsAcceptor>> {
+        self(server_name)
+    }
+}
 
 #[derive(Clone)]
 pub enum ServerTlsOption {
     Plain,
+    /// Acceptor should negotiate `h2` protocol, see `alpn::acceptor_set_protocols`
     Tls(Arc<TlsAcceptor>),
+    /// Acceptor is selected per connection by SNI server name,
+    /// acceptors must negotiate `h2` protocol like with `Tls`
+    Sni(Arc<SniResolver>),
+    /// TLS with rustls, config must offer `h2`, see `rustls_tls::h2_protocols`
+    #[cfg(feature = "tls-rustls")]
+    Rustls(Arc<ServerConfig>),
+}
+
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 1;
+const EXTENSION_SERVER_NAME: u16 = 0;
+const SERVER_NAME_TYPE_HOST_NAME: u8 = 0;
+
+/// TLS record header length: content type, version and length
+const RECORD_HEADER_LEN: usize = 5;
+
+/// Read two-byte length-prefixed vector and advance `data` past it
+fn take_vec16<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if data.len() < 2 {
+        return None;
+    }
+    let len = (data[0] as usize) << 8 | data[1] as usize;
+    take(data, 2 + len).map(|v| &v[2..])
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if data.len() < len {
+        return None;
+    }
+    let (r, rem) = data.split_at(len);
+    *data = rem;
+    Some(r)
+}
+
+/// Host name from SNI extension (RFC 6066, section 3) of ClientHello
+/// contained in given TLS record, `None` if record is not ClientHello
+/// or it has no SNI
+fn client_hello_server_name(record: &[u8]) -> Option<String> {
+    let mut data = record;
+    let header = take(&mut data, RECORD_HEADER_LEN)?;
+    if header[0] != CONTENT_TYPE_HANDSHAKE {
+        return None;
+    }
+
+    // handshake type and length, ClientHello may be truncated if it spans multiple records
+    let handshake_header = take(&mut data, 4)?;
+    if handshake_header[0] != HANDSHAKE_TYPE_CLIENT_HELLO {
+        return None;
+    }
+
+    // version and random
+    take(&mut data, 2 + 32)?;
+    // session id
+    let session_id_len = *take(&mut data, 1)?.first()? as usize;
+    take(&mut data, session_id_len)?;
+    // cipher suites
+    take_vec16(&mut data)?;
+    // compression methods
+    let compression_methods_len = *take(&mut data, 1)?.first()? as usize;
+    take(&mut data, compression_methods_len)?;
+
+    let mut extensions = take_vec16(&mut data)?;
+    while !extensions.is_empty() {
+        let extension_type = take(&mut extensions, 2)?;
+        let extension_type = (extension_type[0] as u16) << 8 | extension_type[1] as u16;
+        let mut extension_data = take_vec16(&mut extensions)?;
+        if extension_type != EXTENSION_SERVER_NAME {
+            continue;
+        }
+
+        let mut server_names = take_vec16(&mut extension_data)?;
+        while !server_names.is_empty() {
+            let name_type = take(&mut server_names, 1)?[0];
+            let name = take_vec16(&mut server_names)?;
+            if name_type == SERVER_NAME_TYPE_HOST_NAME {
+                return str::from_utf8(name).ok().map(|name| name.to_owned());
+            }
+        }
+        return None;
+    }
+
+    None
 }
 
+/// Read first TLS record sent by client and find SNI host name in it.
+///
+/// Returned socket replays the record, so it can be passed to TLS acceptor.
+pub fn read_client_hello<I>(socket: I) -> HttpFutureSend<(Option<String>, ReplayIo<I>)>
+    where I : AsyncRead + Send + 'static
+{
+    Box::new(read_exact(socket, [0; RECORD_HEADER_LEN])
+        .and_then(|(socket, header)| {
+            if header[0] != CONTENT_TYPE_HANDSHAKE {
+                // not TLS, acceptor fails the handshake
+                return Either::A(future::ok((socket, header.to_vec())));
+            }
+            let len = (header[3] as usize) << 8 | header[4] as usize;
+            Either::B(read_exact(socket, vec![0; len]).map(move |(socket, body)| {
+                let mut record = header.to_vec();
+                record.extend(body);
+                (socket, record)
+            }))
+        })
+        .map(|(socket, record)| {
+            let server_name = client_hello_server_name(&record);
+            debug!("TLS SNI server name: {:?}", server_name);
+            (server_name, ReplayIo::new(record, socket))
+        })
+        .map_err(Error::from))
+}
+
+
+/// Socket wrapper which returns given bytes before data read from socket
+pub struct ReplayIo<I> {
+    replay: Vec<u8>,
+    pos: usize,
+    io: I,
+}
+
+impl<I> ReplayIo<I> {
+    pub fn new(replay: Vec<u8>, io: I) -> ReplayIo<I> {
+        ReplayIo {
+            replay: replay,
+            pos: 0,
+            io: io,
+        }
+    }
+}
+
+impl<I : Read> Read for ReplayIo<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.replay.len() {
+            let n = cmp::min(buf.len(), self.replay.len() - self.pos);
+            buf[..n].copy_from_slice(&self.replay[self.pos..self.pos + n]);
+            self.pos += n;
+            return Ok(n);
+        }
+        self.io.read(buf)
+    }
+}
+
+impl<I : Write> Write for ReplayIo<I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<I : AsyncRead> AsyncRead for ReplayIo<I> {
+}
+
+impl<I : AsyncWrite> AsyncWrite for ReplayIo<I> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// ClientHello record with SNI extension if `server_name` is specified
+    fn client_hello(server_name: Option<&str>) -> Vec<u8> {
+        fn vec16(data: &[u8]) -> Vec<u8> {
+            let mut r = vec![(data.len() >> 8) as u8, data.len() as u8];
+            r.extend_from_slice(data);
+            r
+        }
+
+        let mut extensions = Vec::new();
+        // unrelated extension first: supported_versions
+        extensions.extend_from_slice(&[0, 43]);
+        extensions.extend(vec16(&[2, 3, 4]));
+        if let Some(server_name) = server_name {
+            let mut name = vec![SERVER_NAME_TYPE_HOST_NAME];
+            name.extend(vec16(server_name.as_bytes()));
+            extensions.extend_from_slice(&[0, 0]);
+            extensions.extend(vec16(&vec16(&name)));
+        }
+
+        let mut body = vec![3, 3];
+        body.extend_from_slice(&[7; 32]);
+        // session id
+        body.extend_from_slice(&[2, 1, 2]);
+        // cipher suites
+        body.extend(vec16(&[0x13, 0x01]));
+        // compression methods
+        body.extend_from_slice(&[1, 0]);
+        body.extend(vec16(&extensions));
+
+        let mut handshake = vec![HANDSHAKE_TYPE_CLIENT_HELLO, 0];
+        handshake.extend(vec16(&body));
+
+        let mut record = vec![CONTENT_TYPE_HANDSHAKE, 3, 1];
+        record.extend(vec16(&handshake));
+        record
+    }
+
+    #[test]
+    fn server_name() {
+        assert_eq!(Some("example.com".to_owned()), client_hello_server_name(&client_hello(Some("example.com"))));
+        assert_eq!(None, client_hello_server_name(&client_hello(None)));
+    }
+
+    #[test]
+    fn server_name_malformed() {
+        let record = client_hello(Some("example.com"));
+        for len in 0..record.len() {
+            assert_eq!(None, client_hello_server_name(&record[..len]));
+        }
+        assert_eq!(None, client_hello_server_name(b"GET / HTTP/1.1\r\n\r\n"));
+    }
+
+    #[test]
+    fn replay_io() {
+        let mut io = ReplayIo::new(b"abc".to_vec(), &b"de"[..]);
+        let mut buf = [0; 2];
+        assert_eq!(2, io.read(&mut buf).unwrap());
+        assert_eq!(b"ab", &buf);
+        let mut rem = Vec::new();
+        io.read_to_end(&mut rem).unwrap();
+        assert_eq!(b"cde", &rem[..]);
+    }
+}
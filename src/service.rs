@@ -1,11 +1,40 @@
+use std::net::SocketAddr;
+
 use solicit::header::Headers;
 use stream_part::HttpPartStream;
 use resp::Response;
+use futures_misc::CancelToken;
+use client_auth::PeerCertificate;
+
 
+/// Information about connection request is received on
+#[derive(Debug, Clone, Default)]
+pub struct ServiceContext {
+    /// Address of the client
+    pub peer_addr: Option<SocketAddr>,
+    /// Protocol negotiated with TLS ALPN, `None` for plain connections
+    pub alpn_protocol: Option<String>,
+    /// Client certificate verified during TLS handshake,
+    /// `None` if client sent no certificate, see `client_auth`
+    pub client_cert: Option<PeerCertificate>,
+    /// Resolved when client reset the request stream or connection failed.
+    ///
+    /// Response stream is dropped by server when it is canceled.
+    pub cancel: CancelToken,
+}
 
 /// HTTP/2 service interface
 ///
 /// Implemented by `Client` and it is callback provided by user.
 pub trait Service : Send + 'static {
     fn start_request(&self, headers: Headers, req: HttpPartStream) -> Response;
+
+    /// Called by server instead of `start_request` with information about connection.
+    ///
+    /// Default implementation ignores context.
+    fn start_request_with_context(&self, _context: &ServiceContext, headers: Headers, req: HttpPartStream)
+        -> Response
+    {
+        self.start_request(headers, req)
+    }
 }
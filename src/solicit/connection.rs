@@ -24,6 +24,7 @@ use bytes::Bytes;
 
 use error::Error;
 use error::ErrorCode;
+use error::InvalidFrameInfo;
 use result::Result;
 use solicit::{StreamId, WindowSize};
 use solicit::DEFAULT_SETTINGS;
@@ -46,6 +47,8 @@ pub enum HttpFrameType {
     Goaway,
     WindowUpdate,
     Continuation,
+    Origin,
+    AltSvc,
     Unknown(u8),
 }
 
@@ -67,22 +70,49 @@ pub enum HttpFrame {
     Goaway(GoawayFrame),
     WindowUpdate(WindowUpdateFrame),
     Continuation(ContinuationFrame),
+    Origin(OriginFrame),
+    AltSvc(AltSvcFrame),
     Unknown(RawFrame),
 }
 
 impl HttpFrame{
     pub fn from_raw(raw_frame: &RawFrame) -> Result<HttpFrame> {
-        let frame = match raw_frame.header().frame_type {
-            frame::data::DATA_FRAME_TYPE =>
-                HttpFrame::Data(HttpFrame::parse_frame(&raw_frame)?),
+        let header = raw_frame.header();
+
+        // Frames which are always associated with a stream
+        // must not use stream 0 (6.1, 6.2, 6.3, 6.4, 6.6, 6.10)
+        let stream_frame = match header.frame_type {
+            frame::data::DATA_FRAME_TYPE |
+            frame::headers::HEADERS_FRAME_TYPE |
+            frame::priority::PRIORITY_FRAME_TYPE |
+            frame::rst_stream::RST_STREAM_FRAME_TYPE |
+            frame::push_promise::PUSH_PROMISE_FRAME_TYPE |
+            frame::continuation::CONTINUATION_FRAME_TYPE => true,
+            _ => false,
+        };
+        if stream_frame && header.stream_id == 0 {
+            return Err(Error::CodeError(ErrorCode::ProtocolError));
+        }
+
+        let frame = match header.frame_type {
+            frame::data::DATA_FRAME_TYPE => {
+                // 6.1: DATA frame is malformed only if padding length
+                // is not less than payload length
+                let frame = HttpFrame::parse_frame(&raw_frame)
+                    .map_err(|_| Error::CodeError(ErrorCode::ProtocolError))?;
+                HttpFrame::Data(frame)
+            }
             frame::headers::HEADERS_FRAME_TYPE =>
                 HttpFrame::Headers(HttpFrame::parse_frame(&raw_frame)?),
             frame::priority::PRIORITY_FRAME_TYPE =>
                 HttpFrame::Priority(HttpFrame::parse_frame(&raw_frame)?),
             frame::rst_stream::RST_STREAM_FRAME_TYPE =>
                 HttpFrame::RstStream(HttpFrame::parse_frame(&raw_frame)?),
-            frame::settings::SETTINGS_FRAME_TYPE =>
-                HttpFrame::Settings(HttpFrame::parse_frame(&raw_frame)?),
+            frame::settings::SETTINGS_FRAME_TYPE => {
+                frame::settings::SettingsFrame::check_payload(&raw_frame.payload())
+                    .map_err(Error::CodeError)?;
+                HttpFrame::Settings(HttpFrame::parse_frame(&raw_frame)?)
+            }
             frame::push_promise::PUSH_PROMISE_FRAME_TYPE =>
                 HttpFrame::PushPromise(HttpFrame::parse_frame(&raw_frame)?),
             frame::ping::PING_FRAME_TYPE =>
@@ -93,6 +123,16 @@ impl HttpFrame{
                 HttpFrame::WindowUpdate(HttpFrame::parse_frame(&raw_frame)?),
             frame::continuation::CONTINUATION_FRAME_TYPE =>
                 HttpFrame::Continuation(HttpFrame::parse_frame(&raw_frame)?),
+            // RFC 8336: ORIGIN frame on non-zero stream or malformed is ignored
+            frame::origin::ORIGIN_FRAME_TYPE => match OriginFrame::from_raw(&raw_frame) {
+                Some(f) => HttpFrame::Origin(f),
+                None => HttpFrame::Unknown(raw_frame.as_ref().into()),
+            },
+            // RFC 7838, section 4: invalid ALTSVC frame is ignored
+            frame::altsvc::ALTSVC_FRAME_TYPE => match AltSvcFrame::from_raw(&raw_frame) {
+                Some(f) => HttpFrame::AltSvc(f),
+                None => HttpFrame::Unknown(raw_frame.as_ref().into()),
+            },
             _ =>
                 HttpFrame::Unknown(raw_frame.as_ref().into()),
         };
@@ -114,8 +154,9 @@ impl HttpFrame{
         //       taken (e.g. responding with a PROTOCOL_ERROR).
         match Frame::from_raw(&raw_frame) {
             Some(f) => Ok(f),
-            None => Err(Error::InvalidFrame(
-                format!("failed to parse frame {:?}", raw_frame.header()))),
+            None => Err(Error::InvalidFrame(InvalidFrameInfo::new(
+                format!("failed to parse frame {:?}", raw_frame.header()))
+                    .with_header(raw_frame.header()))),
         }
     }
 
@@ -132,10 +173,31 @@ impl HttpFrame{
             &HttpFrame::Goaway(ref f) => f.get_stream_id(),
             &HttpFrame::WindowUpdate(ref f) => f.get_stream_id(),
             &HttpFrame::Continuation(ref f) => f.get_stream_id(),
+            &HttpFrame::Origin(ref f) => f.get_stream_id(),
+            &HttpFrame::AltSvc(ref f) => f.get_stream_id(),
             &HttpFrame::Unknown(ref f) => f.get_stream_id(),
         }
     }
 
+    /// Header of the frame, length of joined HEADERS is total length of header block
+    pub fn get_header(&self) -> FrameHeader {
+        match self {
+            &HttpFrame::Data(ref f) => f.get_header(),
+            &HttpFrame::Headers(ref f) => f.get_header(),
+            &HttpFrame::Priority(ref f) => f.get_header(),
+            &HttpFrame::RstStream(ref f) => f.get_header(),
+            &HttpFrame::Settings(ref f) => f.get_header(),
+            &HttpFrame::PushPromise(ref f) => f.get_header(),
+            &HttpFrame::Ping(ref f) => f.get_header(),
+            &HttpFrame::Goaway(ref f) => f.get_header(),
+            &HttpFrame::WindowUpdate(ref f) => f.get_header(),
+            &HttpFrame::Continuation(ref f) => f.get_header(),
+            &HttpFrame::Origin(ref f) => f.get_header(),
+            &HttpFrame::AltSvc(ref f) => f.get_header(),
+            &HttpFrame::Unknown(ref f) => f.header(),
+        }
+    }
+
     pub fn frame_type(&self) -> HttpFrameType {
         match self {
             &HttpFrame::Data(..) => HttpFrameType::Data,
@@ -148,6 +210,8 @@ impl HttpFrame{
             &HttpFrame::Goaway(..) => HttpFrameType::Goaway,
             &HttpFrame::WindowUpdate(..) => HttpFrameType::WindowUpdate,
             &HttpFrame::Continuation(..) => HttpFrameType::Continuation,
+            &HttpFrame::Origin(..) => HttpFrameType::Origin,
+            &HttpFrame::AltSvc(..) => HttpFrameType::AltSvc,
             &HttpFrame::Unknown(ref f) => HttpFrameType::Unknown(f.frame_type()),
         }
     }
@@ -166,6 +230,8 @@ impl FrameIR for HttpFrame {
             HttpFrame::Goaway(f)       => f.serialize_into(builder),
             HttpFrame::WindowUpdate(f) => f.serialize_into(builder),
             HttpFrame::Continuation(f) => f.serialize_into(builder),
+            HttpFrame::Origin(f)       => f.serialize_into(builder),
+            HttpFrame::AltSvc(f)       => f.serialize_into(builder),
             HttpFrame::Unknown(f)      => f.serialize_into(builder),
         }
     }
@@ -231,6 +297,53 @@ impl From<ContinuationFrame> for HttpFrame {
     }
 }
 
+impl From<OriginFrame> for HttpFrame {
+    fn from(frame: OriginFrame) -> Self {
+        HttpFrame::Origin(frame)
+    }
+}
+
+impl From<AltSvcFrame> for HttpFrame {
+    fn from(frame: AltSvcFrame) -> Self {
+        HttpFrame::AltSvc(frame)
+    }
+}
+
+/// Parse single complete frame from buffer, without any I/O.
+///
+/// Buffer must contain exactly one frame: header and payload of the length declared in header.
+/// Frames larger than `max_frame_size` are rejected with `FRAME_SIZE_ERROR`,
+/// frames of unknown type are rejected too.
+/// Never panics, so can be used as fuzzing target.
+pub fn parse_frame(bytes: &[u8], max_frame_size: u32) -> Result<HttpFrame> {
+    if bytes.len() < FRAME_HEADER_LEN {
+        return Err(Error::InvalidFrame(InvalidFrameInfo::new(
+            format!("frame header is truncated: {} bytes", bytes.len()))));
+    }
+
+    let mut raw_header = [0; FRAME_HEADER_LEN];
+    raw_header.copy_from_slice(&bytes[..FRAME_HEADER_LEN]);
+    let header = unpack_header(&raw_header);
+
+    if header.length > max_frame_size {
+        return Err(Error::CodeError(ErrorCode::FrameSizeError));
+    }
+
+    let payload_len = bytes.len() - FRAME_HEADER_LEN;
+    if header.length as usize != payload_len {
+        return Err(Error::InvalidFrame(InvalidFrameInfo::new(
+            format!("frame length is {}, but payload is {} bytes", header.length, payload_len))
+                .with_header(header)));
+    }
+
+    match HttpFrame::from_raw(&RawFrame::from(bytes))? {
+        HttpFrame::Unknown(_) => Err(Error::InvalidFrame(InvalidFrameInfo::new(
+            format!("unknown frame type: {}", header.frame_type))
+                .with_header(header))),
+        frame => Ok(frame),
+    }
+}
+
 /// The struct implements the HTTP/2 connection level logic.
 ///
 /// This means that the struct is a bridge between the low level raw frame reads/writes (i.e. what
@@ -253,6 +366,8 @@ pub struct HttpConnection {
     pub in_window_size: WindowSize,
     /// Last known peer settings
     pub peer_settings: HttpSettings,
+    /// Settings sent to peer
+    pub our_settings: HttpSettings,
 }
 
 /// A trait that should be implemented by types that can provide the functionality
@@ -458,6 +573,7 @@ impl HttpConnection {
             decoder: hpack::Decoder::new(),
             encoder: hpack::Encoder::new(),
             peer_settings: DEFAULT_SETTINGS,
+            our_settings: DEFAULT_SETTINGS,
             in_window_size: WindowSize::new(DEFAULT_SETTINGS.initial_window_size as i32),
             out_window_size: WindowSize::new(DEFAULT_SETTINGS.initial_window_size as i32),
         }
@@ -497,3 +613,108 @@ impl HttpConnection {
             .map_err(|_| Error::WindowSizeOverflow)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame_bytes(frame_type: u8, flags: u8, stream_id: StreamId, length: u32, payload: &[u8]) -> Vec<u8> {
+        let mut buf = pack_header(&FrameHeader {
+            length: length,
+            frame_type: frame_type,
+            flags: flags,
+            stream_id: stream_id,
+        }).to_vec();
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn parse_frame_valid() {
+        let buf = frame_bytes(frame::ping::PING_FRAME_TYPE, 0, 0, 8, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        match parse_frame(&buf, 16384) {
+            Ok(HttpFrame::Ping(ping)) => assert_eq!(0x0102030405060708, ping.opaque_data()),
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_frame_data_padded() {
+        let buf = frame_bytes(frame::data::DATA_FRAME_TYPE, 0x8, 1, 6, &[2, b'a', b'b', b'c', 0, 0]);
+        match parse_frame(&buf, 16384) {
+            Ok(HttpFrame::Data(data)) => {
+                assert_eq!(&b"abc"[..], &data.data[..]);
+                // padding counts against flow control
+                assert_eq!(6, data.payload_len());
+            }
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        for &(length, ref payload) in &[(3, vec![3, 0, 0]), (2, vec![200, 1]), (0, vec![])] {
+            let buf = frame_bytes(frame::data::DATA_FRAME_TYPE, 0x8, 1, length, payload);
+            match parse_frame(&buf, 16384) {
+                Err(Error::CodeError(ErrorCode::ProtocolError)) => {}
+                r => panic!("expecting PROTOCOL_ERROR for {:?}, got {:?}", payload, r),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_frame_malformed() {
+        let cases = vec![
+            // empty buffer
+            Vec::new(),
+            // truncated header
+            vec![0, 0, 8, 6, 0],
+            // payload shorter than declared length
+            frame_bytes(frame::ping::PING_FRAME_TYPE, 0, 0, 8, &[1, 2]),
+            // trailing bytes after payload
+            frame_bytes(frame::ping::PING_FRAME_TYPE, 0, 0, 8, &[0; 10]),
+            // larger than max frame size
+            frame_bytes(frame::data::DATA_FRAME_TYPE, 0, 1, 0xffffff, &[]),
+            // unknown frame type
+            frame_bytes(0xfa, 0, 0, 3, &[1, 2, 3]),
+            // DATA on stream 0
+            frame_bytes(frame::data::DATA_FRAME_TYPE, 0, 0, 1, &[1]),
+            // padding longer than payload
+            frame_bytes(frame::data::DATA_FRAME_TYPE, 0x8, 1, 2, &[200, 1]),
+            // padded flag without pad length
+            frame_bytes(frame::headers::HEADERS_FRAME_TYPE, 0x8, 1, 0, &[]),
+            // priority flag with truncated priority
+            frame_bytes(frame::headers::HEADERS_FRAME_TYPE, 0x20, 1, 2, &[0, 0]),
+            // SETTINGS length not multiple of 6
+            frame_bytes(frame::settings::SETTINGS_FRAME_TYPE, 0, 0, 5, &[0; 5]),
+            // PING of wrong length
+            frame_bytes(frame::ping::PING_FRAME_TYPE, 0, 0, 3, &[0; 3]),
+            // WINDOW_UPDATE of wrong length
+            frame_bytes(frame::window_update::WINDOW_UPDATE_FRAME_TYPE, 0, 0, 2, &[0; 2]),
+            // RST_STREAM of wrong length
+            frame_bytes(frame::rst_stream::RST_STREAM_FRAME_TYPE, 0, 1, 5, &[0; 5]),
+            // GOAWAY shorter than fixed part
+            frame_bytes(frame::goaway::GOAWAY_FRAME_TYPE, 0, 0, 4, &[0; 4]),
+            // PUSH_PROMISE without promised stream id
+            frame_bytes(frame::push_promise::PUSH_PROMISE_FRAME_TYPE, 0, 1, 2, &[0; 2]),
+        ];
+
+        for buf in cases {
+            assert!(parse_frame(&buf, 16384).is_err(), "expecting error for {:?}", buf);
+        }
+    }
+
+    #[test]
+    fn parse_frame_does_not_panic() {
+        for frame_type in 0..12 {
+            for flags in &[0, 0x1, 0x4, 0x8, 0x20, 0x2d, 0xff] {
+                for len in 0..20 {
+                    for fill in &[0, 1, 7, 0xff] {
+                        let payload = vec![*fill; len];
+                        let buf = frame_bytes(frame_type, *flags, 1, len as u32, &payload);
+                        let _ = parse_frame(&buf, 16384);
+                        let _ = parse_frame(&buf, 5);
+                        let _ = parse_frame(&buf[..buf.len() / 2], 16384);
+                    }
+                }
+            }
+        }
+    }
+}
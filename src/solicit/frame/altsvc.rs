@@ -0,0 +1,173 @@
+//! Implements the `ALTSVC` HTTP/2 frame (RFC 7838, section 4).
+
+use std::io;
+
+use bytes::Bytes;
+
+use solicit::StreamId;
+use solicit::frame::{Frame, FrameIR, FrameBuilder, FrameHeader, RawFrame};
+use solicit::frame::flags::*;
+
+/// The minimum size for the `ALTSVC` frame payload: origin length field.
+pub const ALTSVC_MIN_FRAME_LEN: u32 = 2;
+/// The frame type of the `ALTSVC` frame.
+pub const ALTSVC_FRAME_TYPE: u8 = 0xa;
+
+/// The struct represents the `ALTSVC` frame.
+///
+/// On stream 0 the frame contains the origin alternative service applies to,
+/// on other streams origin is empty and the origin of the stream is implied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AltSvcFrame {
+    pub stream_id: StreamId,
+    pub origin: Bytes,
+    /// Same format as `Alt-Svc` header field value
+    pub field_value: Bytes,
+    flags: Flags<NoFlag>,
+}
+
+impl AltSvcFrame {
+    /// Create a new `ALTSVC` frame with the given parts.
+    pub fn new(stream_id: StreamId, origin: Bytes, field_value: Bytes) -> AltSvcFrame {
+        AltSvcFrame {
+            stream_id: stream_id,
+            origin: origin,
+            field_value: field_value,
+            flags: Flags::default(),
+        }
+    }
+
+    /// Returns the total length of the frame's payload.
+    pub fn payload_len(&self) -> u32 {
+        ALTSVC_MIN_FRAME_LEN + self.origin.len() as u32 + self.field_value.len() as u32
+    }
+}
+
+impl Frame for AltSvcFrame {
+    type FlagType = NoFlag;
+
+    fn from_raw(raw_frame: &RawFrame) -> Option<Self> {
+        let FrameHeader { length, frame_type, flags, stream_id } = raw_frame.header();
+        if length < ALTSVC_MIN_FRAME_LEN {
+            return None;
+        }
+        if frame_type != ALTSVC_FRAME_TYPE {
+            return None;
+        }
+
+        let payload = raw_frame.payload();
+        if length as usize != payload.len() {
+            return None;
+        }
+
+        let origin_len = ((payload[0] as usize) << 8) | payload[1] as usize;
+        let origin_end = ALTSVC_MIN_FRAME_LEN as usize + origin_len;
+        if payload.len() < origin_end {
+            return None;
+        }
+
+        // Frame on stream 0 without origin or on a stream with origin must be ignored
+        if (stream_id == 0) != (origin_len != 0) {
+            return None;
+        }
+
+        Some(AltSvcFrame {
+            stream_id: stream_id,
+            origin: payload.slice(ALTSVC_MIN_FRAME_LEN as usize, origin_end),
+            field_value: payload.slice_from(origin_end),
+            flags: Flags::new(flags),
+        })
+    }
+
+    fn flags(&self) -> Flags<NoFlag> {
+        self.flags
+    }
+
+    fn get_stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    fn get_header(&self) -> FrameHeader {
+        FrameHeader {
+            length: self.payload_len(),
+            frame_type: ALTSVC_FRAME_TYPE,
+            flags: self.flags.0,
+            stream_id: self.stream_id,
+        }
+    }
+}
+
+impl FrameIR for AltSvcFrame {
+    fn serialize_into<B: FrameBuilder>(self, builder: &mut B) -> io::Result<()> {
+        builder.write_header(self.get_header())?;
+        let origin_len = self.origin.len() as u16;
+        builder.write_all(&[(origin_len >> 8) as u8, origin_len as u8])?;
+        builder.write_all(&self.origin)?;
+        builder.write_all(&self.field_value)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AltSvcFrame;
+
+    use solicit::tests::common::{serialize_frame, raw_frame_from_parts};
+    use solicit::frame::Frame;
+    use solicit::frame::FrameHeader;
+
+    use bytes::Bytes;
+
+    #[test]
+    fn test_parse_stream_0() {
+        let mut payload = vec![0, 19];
+        payload.extend(b"https://example.com");
+        payload.extend(b"h3=\":443\"");
+        let raw = raw_frame_from_parts(FrameHeader::new(30, 0xa, 0, 0), payload);
+        let frame = AltSvcFrame::from_raw(&raw).expect("Expected successful parse");
+        assert_eq!(0, frame.stream_id);
+        assert_eq!(Bytes::from("https://example.com"), frame.origin);
+        assert_eq!(Bytes::from("h3=\":443\""), frame.field_value);
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let mut payload = vec![0, 0];
+        payload.extend(b"h2=\":8000\"");
+        let raw = raw_frame_from_parts(FrameHeader::new(12, 0xa, 0, 3), payload);
+        let frame = AltSvcFrame::from_raw(&raw).expect("Expected successful parse");
+        assert_eq!(3, frame.stream_id);
+        assert_eq!(Bytes::new(), frame.origin);
+        assert_eq!(Bytes::from("h2=\":8000\""), frame.field_value);
+    }
+
+    #[test]
+    fn test_parse_invalid_origin() {
+        // no origin on stream 0
+        let raw = raw_frame_from_parts(FrameHeader::new(3, 0xa, 0, 0), vec![0, 0, b'x']);
+        assert!(AltSvcFrame::from_raw(&raw).is_none());
+
+        // origin on stream
+        let raw = raw_frame_from_parts(FrameHeader::new(3, 0xa, 0, 1), vec![0, 1, b'x']);
+        assert!(AltSvcFrame::from_raw(&raw).is_none());
+
+        // origin length exceeds payload
+        let raw = raw_frame_from_parts(FrameHeader::new(3, 0xa, 0, 0), vec![0, 5, b'x']);
+        assert!(AltSvcFrame::from_raw(&raw).is_none());
+
+        // too short
+        let raw = raw_frame_from_parts(FrameHeader::new(1, 0xa, 0, 0), vec![0]);
+        assert!(AltSvcFrame::from_raw(&raw).is_none());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let frame = AltSvcFrame::new(0, Bytes::from("https://a.com"), Bytes::from("clear"));
+        let mut payload = vec![0, 13];
+        payload.extend(b"https://a.com");
+        payload.extend(b"clear");
+        let expected: Vec<u8> = raw_frame_from_parts(FrameHeader::new(20, 0xa, 0, 0), payload)
+            .as_ref().to_owned();
+        assert_eq!(expected, serialize_frame(&frame));
+    }
+}
@@ -261,6 +261,9 @@ impl Frame for HeadersFrame {
         // From the actual payload we extract the stream dependency info, if
         // the appropriate flag is set.
         let priority = flags.is_set(HeadersFlag::Priority);
+        if priority && actual.len() < 5 {
+            return None;
+        }
         let (data, stream_dep) = if priority {
             (actual.slice_from(5), Some(StreamDependency::parse(&actual[..5])))
         } else {
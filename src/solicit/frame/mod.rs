@@ -52,6 +52,8 @@ pub mod goaway;
 pub mod ping;
 pub mod window_update;
 pub mod push_promise;
+pub mod origin;
+pub mod altsvc;
 mod flags;
 
 pub use self::builder::FrameBuilder;
@@ -66,6 +68,8 @@ pub use self::ping::PingFrame;
 pub use self::window_update::WindowUpdateFrame;
 pub use self::continuation::ContinuationFrame;
 pub use self::push_promise::PushPromiseFrame;
+pub use self::origin::OriginFrame;
+pub use self::altsvc::AltSvcFrame;
 
 /// An alias for the 9-byte buffer that each HTTP/2 frame header must be stored
 /// in.
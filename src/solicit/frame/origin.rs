@@ -0,0 +1,155 @@
+//! Implements the `ORIGIN` HTTP/2 frame (RFC 8336).
+
+use std::io;
+
+use solicit::StreamId;
+use solicit::frame::{Frame, FrameIR, FrameBuilder, FrameHeader, RawFrame};
+use solicit::frame::flags::*;
+
+/// The frame type of the `ORIGIN` frame.
+pub const ORIGIN_FRAME_TYPE: u8 = 0xc;
+
+/// The struct represents the `ORIGIN` frame.
+///
+/// Frame is sent by server on stream 0 and lists origins
+/// for which the connection is authoritative.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OriginFrame {
+    pub origins: Vec<String>,
+    flags: Flags<NoFlag>,
+}
+
+impl OriginFrame {
+    /// Create a new `ORIGIN` frame with the given origins.
+    pub fn new(origins: Vec<String>) -> OriginFrame {
+        OriginFrame {
+            origins: origins,
+            flags: Flags::default(),
+        }
+    }
+
+    /// Returns the total length of the frame's payload.
+    pub fn payload_len(&self) -> u32 {
+        self.origins.iter().map(|o| 2 + o.len() as u32).sum()
+    }
+}
+
+impl Frame for OriginFrame {
+    type FlagType = NoFlag;
+
+    fn from_raw(raw_frame: &RawFrame) -> Option<Self> {
+        let FrameHeader { length, frame_type, flags, stream_id } = raw_frame.header();
+        if frame_type != ORIGIN_FRAME_TYPE {
+            return None;
+        }
+        if stream_id != 0x0 {
+            return None;
+        }
+
+        let payload = raw_frame.payload();
+        if length as usize != payload.len() {
+            return None;
+        }
+
+        // sequence of Origin-Entry: 16-bit Origin-Len followed by ASCII-Origin
+        let mut origins = Vec::new();
+        let mut rem = &payload[..];
+        while !rem.is_empty() {
+            if rem.len() < 2 {
+                return None;
+            }
+            let len = ((rem[0] as usize) << 8) | rem[1] as usize;
+            rem = &rem[2..];
+            if rem.len() < len || !rem[..len].is_ascii() {
+                return None;
+            }
+            origins.push(String::from_utf8_lossy(&rem[..len]).into_owned());
+            rem = &rem[len..];
+        }
+
+        Some(OriginFrame {
+            origins: origins,
+            flags: Flags::new(flags),
+        })
+    }
+
+    fn flags(&self) -> Flags<NoFlag> {
+        self.flags
+    }
+
+    fn get_stream_id(&self) -> StreamId {
+        0
+    }
+
+    fn get_header(&self) -> FrameHeader {
+        FrameHeader {
+            length: self.payload_len(),
+            frame_type: ORIGIN_FRAME_TYPE,
+            flags: self.flags.0,
+            stream_id: 0,
+        }
+    }
+}
+
+impl FrameIR for OriginFrame {
+    fn serialize_into<B: FrameBuilder>(self, builder: &mut B) -> io::Result<()> {
+        builder.write_header(self.get_header())?;
+        for origin in &self.origins {
+            let len = origin.len() as u16;
+            builder.write_all(&[(len >> 8) as u8, len as u8])?;
+            builder.write_all(origin.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OriginFrame;
+
+    use solicit::tests::common::{serialize_frame, raw_frame_from_parts};
+    use solicit::frame::Frame;
+    use solicit::frame::FrameHeader;
+
+    #[test]
+    fn test_parse_valid() {
+        let mut payload = vec![0, 19];
+        payload.extend(b"https://example.com");
+        payload.extend(&[0, 0]);
+        let raw = raw_frame_from_parts(FrameHeader::new(23, 0xc, 0, 0), payload);
+        let frame = OriginFrame::from_raw(&raw).expect("Expected successful parse");
+        assert_eq!(vec!["https://example.com".to_owned(), "".to_owned()], frame.origins);
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let raw = raw_frame_from_parts(FrameHeader::new(0, 0xc, 0, 0), vec![]);
+        let frame = OriginFrame::from_raw(&raw).expect("Expected successful parse");
+        assert!(frame.origins.is_empty());
+    }
+
+    #[test]
+    fn test_parse_invalid_stream_id() {
+        let raw = raw_frame_from_parts(FrameHeader::new(0, 0xc, 0, 1), vec![]);
+        assert!(OriginFrame::from_raw(&raw).is_none(), "expected invalid stream id");
+    }
+
+    #[test]
+    fn test_parse_truncated_entry() {
+        let raw = raw_frame_from_parts(FrameHeader::new(4, 0xc, 0, 0), vec![0, 5, b'a', b'b']);
+        assert!(OriginFrame::from_raw(&raw).is_none(), "expected truncated entry");
+
+        let raw = raw_frame_from_parts(FrameHeader::new(1, 0xc, 0, 0), vec![0]);
+        assert!(OriginFrame::from_raw(&raw).is_none(), "expected truncated length");
+    }
+
+    #[test]
+    fn test_serialize() {
+        let frame = OriginFrame::new(vec!["https://a.com".to_owned()]);
+        let mut payload = vec![0, 13];
+        payload.extend(b"https://a.com");
+        let expected: Vec<u8> = raw_frame_from_parts(FrameHeader::new(15, 0xc, 0, 0), payload)
+            .as_ref().to_owned();
+        assert_eq!(expected, serialize_frame(&frame));
+    }
+}
@@ -55,6 +55,30 @@ impl Flag for PushPromiseFlag {
 }
 
 impl PushPromiseFrame {
+    /// Creates a new `PushPromiseFrame` with the given header fragment,
+    /// stream ID and promised stream ID. No padding and no flags are set.
+    pub fn new<B : Into<Bytes>>(fragment: B, stream_id: StreamId, promised_stream_id: StreamId)
+        -> PushPromiseFrame
+    {
+        PushPromiseFrame {
+            flags: Flags::default(),
+            stream_id: stream_id,
+            promised_stream_id: promised_stream_id,
+            header_fragment: fragment.into(),
+            padding_len: 0,
+        }
+    }
+
+    /// Sets the given flag for the frame.
+    pub fn set_flag(&mut self, flag: PushPromiseFlag) {
+        self.flags.set(flag);
+    }
+
+    /// Returns whether this frame ends the header block
+    pub fn is_headers_end(&self) -> bool {
+        self.flags.is_set(PushPromiseFlag::EndHeaders)
+    }
+
     /// Returns the length of the payload of the current frame, including any
     /// possible padding in the number of bytes.
     fn payload_len(&self) -> u32 {
@@ -107,12 +131,17 @@ impl Frame for PushPromiseFrame {
             Some(t) => t,
         };
 
+        if payload.len() < 4 {
+            return None;
+        }
+
         let mut buf = (&payload).into_buf();
 
-        let promised_stream_id = buf.get_u32::<BigEndian>();
+        // Reserved bit is ignored
+        let promised_stream_id = buf.get_u32::<BigEndian>() & !0x80000000;
 
         let header_fragment = payload.slice(
-            (length as usize) - buf.remaining(),
+            payload.len() - buf.remaining(),
             payload.len());
 
         Some(PushPromiseFrame {
@@ -149,6 +178,7 @@ impl FrameIR for PushPromiseFrame {
         if padded {
             b.write_all(&[self.padding_len])?;
         }
+        b.write_u32(self.promised_stream_id)?;
         // Now the actual headers fragment
         b.write_all(&self.header_fragment)?;
         // Finally, add the trailing padding, if required
@@ -160,3 +190,37 @@ impl FrameIR for PushPromiseFrame {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::PushPromiseFrame;
+    use super::PushPromiseFlag;
+
+    use solicit::frame::Frame;
+    use solicit::frame::FrameIR;
+    use solicit::frame::RawFrame;
+
+    #[test]
+    fn test_serialize_parse() {
+        let mut frame = PushPromiseFrame::new(&b"abcd"[..], 1, 2);
+        frame.set_flag(PushPromiseFlag::EndHeaders);
+
+        let raw = RawFrame::parse(frame.clone().serialize_into_vec()).expect("raw");
+        let parsed = PushPromiseFrame::from_raw(&raw).expect("parse");
+        assert_eq!(frame, parsed);
+        assert_eq!(2, parsed.promised_stream_id);
+        assert!(parsed.is_headers_end());
+    }
+
+    #[test]
+    fn test_parse_padded() {
+        let mut buf = vec![0, 0, 10, 0x5, 0x8 | 0x4, 0, 0, 0, 3];
+        // padding length, promised stream id, fragment, padding
+        buf.extend(&[1, 0, 0, 0, 4, b'a', b'b', b'c', b'd', 0]);
+
+        let raw = RawFrame::parse(buf).expect("raw");
+        let frame = PushPromiseFrame::from_raw(&raw).expect("parse");
+        assert_eq!(3, frame.stream_id);
+        assert_eq!(4, frame.promised_stream_id);
+        assert_eq!(&b"abcd"[..], &frame.header_fragment[..]);
+    }
+}
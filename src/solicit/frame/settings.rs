@@ -1,12 +1,18 @@
 //! The module contains the implementation of the `SETTINGS` frame and associated flags.
 
 use std::io;
+use error::ErrorCode;
 use solicit::StreamId;
+use solicit::MAX_WINDOW_SIZE;
 use solicit::frame::{FrameBuilder, FrameIR, Frame, FrameHeader, RawFrame};
 use solicit::frame::flags::*;
 
 pub const SETTINGS_FRAME_TYPE: u8 = 0x4;
 
+/// Allowed range of `SETTINGS_MAX_FRAME_SIZE` (6.5.2)
+pub const MIN_MAX_FRAME_SIZE: u32 = 16_384;
+pub const MAX_MAX_FRAME_SIZE: u32 = 16_777_215;
+
 /// An enum that lists all valid settings that can be sent in a SETTINGS
 /// frame.
 ///
@@ -22,6 +28,9 @@ pub enum HttpSetting {
     InitialWindowSize(u32),
     MaxFrameSize(u32),
     MaxHeaderListSize(u32),
+    /// Setting with an ID not defined in section 6.5.2, e. g. an extension
+    /// setting. Only sent, unknown settings are ignored when received.
+    Unknown(u16, u32),
 }
 
 impl HttpSetting {
@@ -57,6 +66,18 @@ impl HttpSetting {
         HttpSetting::from_id(id, val)
     }
 
+    /// Checks that the value is allowed for the setting with given ID,
+    /// returning the error code the connection must be terminated with
+    /// otherwise (section 6.5.2).
+    pub fn check_value(id: u16, val: u32) -> Result<(), ErrorCode> {
+        match id {
+            2 if val > 1 => Err(ErrorCode::ProtocolError),
+            4 if val > MAX_WINDOW_SIZE => Err(ErrorCode::FlowControlError),
+            5 if val < MIN_MAX_FRAME_SIZE || val > MAX_MAX_FRAME_SIZE => Err(ErrorCode::ProtocolError),
+            _ => Ok(()),
+        }
+    }
+
     /// Returns the setting ID as an unsigned 16 bit integer, as defined in
     /// section 6.5.2.
     pub fn get_id(&self) -> u16 {
@@ -67,6 +88,7 @@ impl HttpSetting {
             HttpSetting::InitialWindowSize(_) => 4,
             HttpSetting::MaxFrameSize(_) => 5,
             HttpSetting::MaxHeaderListSize(_) => 6,
+            HttpSetting::Unknown(id, _) => id,
         }
     }
 
@@ -77,7 +99,8 @@ impl HttpSetting {
             HttpSetting::MaxConcurrentStreams(val) |
             HttpSetting::InitialWindowSize(val)    |
             HttpSetting::MaxFrameSize(val)         |
-            HttpSetting::MaxHeaderListSize(val)    => val,
+            HttpSetting::MaxHeaderListSize(val)    |
+            HttpSetting::Unknown(_, val)           => val,
             HttpSetting::EnablePush(true)  => 1,
             HttpSetting::EnablePush(false) => 0,
         }
@@ -96,7 +119,7 @@ impl HttpSetting {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct HttpSettings {
     pub header_table_size: u32,
     pub enable_push: bool,
@@ -115,6 +138,7 @@ impl HttpSettings {
             HttpSetting::InitialWindowSize(s) => self.initial_window_size = s,
             HttpSetting::MaxFrameSize(s) => self.max_frame_size = s,
             HttpSetting::MaxHeaderListSize(s) => self.max_header_list_size = s,
+            HttpSetting::Unknown(..) => {}
         }
     }
 }
@@ -226,7 +250,7 @@ impl SettingsFrame {
     ///
     /// If the frame is invalid (i.e. the length of the payload is not a
     /// multiple of 6) it returns `None`.
-    fn parse_payload(payload: &[u8]) -> Option<Vec<HttpSetting>> {
+    pub fn parse_payload(payload: &[u8]) -> Option<Vec<HttpSetting>> {
         if payload.len() % 6 != 0 {
             return None;
         }
@@ -238,6 +262,22 @@ impl SettingsFrame {
                     .collect())
     }
 
+    /// Checks values of all settings in the raw frame payload.
+    /// Malformed payload is not checked here, it is rejected by the parser.
+    pub fn check_payload(payload: &[u8]) -> Result<(), ErrorCode> {
+        if payload.len() % 6 != 0 {
+            return Ok(());
+        }
+
+        for chunk in payload.chunks(6) {
+            let id: u16 = ((chunk[0] as u16) << 8) | (chunk[1] as u16);
+            let val: u32 = unpack_octets_4!(chunk, 2, u32);
+            HttpSetting::check_value(id, val)?;
+        }
+
+        Ok(())
+    }
+
     /// Sets the given flag for the frame.
     pub fn set_flag(&mut self, flag: SettingsFlag) {
         self.flags.set(flag);
@@ -338,6 +378,7 @@ impl FrameIR for SettingsFrame {
 #[cfg(test)]
 mod tests {
     use super::{HttpSetting, SettingsFrame};
+    use error::ErrorCode;
     use solicit::tests::common::{raw_frame_from_parts, serialize_frame};
     use solicit::frame::{pack_header, Frame, FrameHeader};
 
@@ -692,4 +733,23 @@ mod tests {
             assert_eq!(buf, setting.serialize());
         }
     }
+
+    #[test]
+    fn test_settings_check_payload() {
+        // ENABLE_PUSH
+        assert_eq!(Ok(()), SettingsFrame::check_payload(&[0, 2, 0, 0, 0, 1]));
+        assert_eq!(Err(ErrorCode::ProtocolError), SettingsFrame::check_payload(&[0, 2, 0, 0, 0, 2]));
+        // INITIAL_WINDOW_SIZE
+        assert_eq!(Ok(()), SettingsFrame::check_payload(&[0, 4, 0x7f, 0xff, 0xff, 0xff]));
+        assert_eq!(Err(ErrorCode::FlowControlError), SettingsFrame::check_payload(&[0, 4, 0x80, 0, 0, 0]));
+        // MAX_FRAME_SIZE
+        assert_eq!(Ok(()), SettingsFrame::check_payload(&[0, 5, 0, 0, 0x40, 0]));
+        assert_eq!(Ok(()), SettingsFrame::check_payload(&[0, 5, 0, 0xff, 0xff, 0xff]));
+        assert_eq!(Err(ErrorCode::ProtocolError), SettingsFrame::check_payload(&[0, 5, 0, 0, 0x3f, 0xff]));
+        assert_eq!(Err(ErrorCode::ProtocolError), SettingsFrame::check_payload(&[0, 5, 1, 0, 0, 0]));
+        // invalid setting after valid
+        assert_eq!(
+            Err(ErrorCode::ProtocolError),
+            SettingsFrame::check_payload(&[0, 1, 0, 0, 0, 1, 0, 2, 0, 0, 0, 3]));
+    }
 }
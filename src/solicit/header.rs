@@ -7,6 +7,8 @@ use assert_types::*;
 
 use bytes::Bytes;
 
+use solicit::HttpScheme;
+
 /// A convenience struct representing a part of a header (either the name or the value).
 pub struct HeaderPart(Bytes);
 
@@ -135,6 +137,22 @@ impl Header {
     pub fn value(&self) -> &[u8] {
         &self.value
     }
+
+    /// Pseudo-header is a header which name starts with colon
+    pub fn is_pseudo(&self) -> bool {
+        self.name.starts_with(b":")
+    }
+
+    /// Header is forbidden in HTTP/2 as connection-specific (8.1.2.2);
+    /// `te` is only allowed with value `trailers`
+    pub fn is_connection_specific(&self) -> bool {
+        match self.name() {
+            b"connection" | b"keep-alive" | b"proxy-connection" |
+            b"transfer-encoding" | b"upgrade" => true,
+            b"te" => self.value() != b"trailers",
+            _ => false,
+        }
+    }
 }
 
 impl<N: Into<HeaderPart>, V: Into<HeaderPart>> From<(N, V)> for Header {
@@ -143,14 +161,188 @@ impl<N: Into<HeaderPart>, V: Into<HeaderPart>> From<(N, V)> for Header {
     }
 }
 
-#[derive(Default,Debug)]
+/// Whether headers are request or response headers,
+/// used to check which pseudo-headers are allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOrResponse {
+    Request,
+    Response,
+}
+
+impl RequestOrResponse {
+    fn pseudo_headers(&self) -> &'static [&'static [u8]] {
+        match *self {
+            RequestOrResponse::Request => &[b":method", b":scheme", b":authority", b":path"],
+            RequestOrResponse::Response => &[b":status"],
+        }
+    }
+}
+
+/// Reason why header list is malformed (HTTP/2 spec 8.1.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    UnknownPseudoHeader(Vec<u8>),
+    PseudoHeaderAfterRegularHeader(Vec<u8>),
+    DuplicatePseudoHeader(Vec<u8>),
+    /// `:path` is empty in non-`CONNECT` request
+    EmptyPath,
+    /// `:path` is specified for `CONNECT` request
+    PathInConnect,
+    /// `:status` is not a three-digit integer
+    InvalidStatus(Vec<u8>),
+    /// Response without `:status`
+    MissingStatus,
+    /// Header field name contains uppercase characters (8.1.2)
+    UppercaseHeaderName(Vec<u8>),
+    /// Connection-specific header field, or `te` other than `trailers` (8.1.2.2)
+    ConnectionSpecificHeader(Vec<u8>),
+    /// `content-length` is not a decimal integer, or repeated with different values
+    InvalidContentLength(Vec<u8>),
+    /// Mandatory pseudo-header is absent: `:method`, or `:path` in non-`CONNECT` request (8.1.2.3)
+    MissingPseudoHeader(Vec<u8>),
+}
+
+/// Response status code, three-digit integer from `:status` pseudo-header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StatusCode(u16);
+
+impl StatusCode {
+    /// Create status code, `None` if code is not in range `100..1000`
+    pub fn new(code: u16) -> Option<StatusCode> {
+        if code >= 100 && code < 1000 {
+            Some(StatusCode(code))
+        } else {
+            None
+        }
+    }
+
+    /// Parse `:status` pseudo-header value, which must be exactly three digits
+    pub fn parse(value: &[u8]) -> Result<StatusCode, HeaderError> {
+        if value.len() != 3 || !value.iter().all(|b| b.is_ascii_digit()) {
+            return Err(HeaderError::InvalidStatus(value.to_vec()));
+        }
+        let code = value.iter().fold(0, |code, b| code * 10 + (b - b'0') as u16);
+        StatusCode::new(code).ok_or_else(|| HeaderError::InvalidStatus(value.to_vec()))
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// 1xx, interim response
+    pub fn is_informational(&self) -> bool {
+        self.0 < 200
+    }
+
+    /// 2xx
+    pub fn is_success(&self) -> bool {
+        self.0 >= 200 && self.0 < 300
+    }
+
+    /// 3xx
+    pub fn is_redirect(&self) -> bool {
+        self.0 >= 300 && self.0 < 400
+    }
+
+    /// 4xx
+    pub fn is_client_error(&self) -> bool {
+        self.0 >= 400 && self.0 < 500
+    }
+
+    /// 5xx
+    pub fn is_server_error(&self) -> bool {
+        self.0 >= 500 && self.0 < 600
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Request method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+        }
+    }
+}
+
+#[derive(Default,Debug,Clone)]
 pub struct Headers(pub Vec<Header>);
 
+/// Regular header names are matched case-insensitively, pseudo-header names exactly
+fn header_name_matches(header_name: &[u8], name: &str) -> bool {
+    if name.starts_with(":") {
+        header_name == name.as_bytes()
+    } else {
+        header_name.eq_ignore_ascii_case(name.as_bytes())
+    }
+}
+
 impl Headers {
     pub fn new() -> Headers {
         Default::default()
     }
 
+    /// Request headers with pseudo-headers in correct order.
+    ///
+    /// `:path` must not be empty, except for `CONNECT` requests,
+    /// which contain only `:method` and `:authority` (8.3),
+    /// so `scheme` is ignored and `path` must be empty for them.
+    pub fn request(method: Method, path: &str, scheme: HttpScheme, authority: &str)
+        -> Result<Headers, HeaderError>
+    {
+        if method == Method::Connect {
+            if !path.is_empty() {
+                return Err(HeaderError::PathInConnect);
+            }
+            return Ok(Headers(vec![
+                Header::new(":method", method.as_str()),
+                Header::new(":authority", authority.to_owned()),
+            ]));
+        }
+
+        if path.is_empty() {
+            return Err(HeaderError::EmptyPath);
+        }
+
+        Ok(Headers(vec![
+            Header::new(":method", method.as_str()),
+            Header::new(":scheme", scheme.as_bytes()),
+            Header::new(":authority", authority.to_owned()),
+            Header::new(":path", path.to_owned()),
+        ]))
+    }
+
+    /// Response headers with `:status` pseudo-header
+    pub fn response(status: u16) -> Headers {
+        Headers::from_status(status as u32)
+    }
+
     pub fn new_get(path: &str) -> Headers {
         Headers(vec![
             Header::new(":method", "GET"),
@@ -183,10 +375,21 @@ impl Headers {
         Headers::from_status(500)
     }
 
+    /// First value of header with given name, see `get_all` for name matching
     pub fn get_opt<'a>(&'a self, name: &str) -> Option<&'a str> {
         self.0.iter()
-            .find(|h| h.name() == name.as_bytes())
-            .and_then(|h| str::from_utf8(h.value()).ok())
+            .filter(|h| header_name_matches(h.name(), name))
+            .filter_map(|h| str::from_utf8(h.value()).ok())
+            .next()
+    }
+
+    /// All values of header with given name in order, e. g. for repeated `set-cookie`.
+    /// Name is matched case-insensitively, except pseudo-header names which must match exactly.
+    pub fn get_all<'a>(&'a self, name: &str) -> Vec<&'a str> {
+        self.0.iter()
+            .filter(|h| header_name_matches(h.name(), name))
+            .filter_map(|h| str::from_utf8(h.value()).ok())
+            .collect()
     }
 
     pub fn get<'a>(&'a self, name: &str) -> &'a str {
@@ -198,16 +401,53 @@ impl Headers {
             .and_then(|h| h.parse().ok())
     }
 
-    pub fn status(&self) -> u32 {
-        self.get_opt_parse(":status").unwrap()
+    /// `:status` of response headers, `None` if absent or not a number
+    pub fn status(&self) -> Option<u32> {
+        self.get_opt_parse(":status")
     }
 
-    pub fn path(&self) -> &str {
-        self.get(":path")
+    /// Typed `:status` of response headers
+    pub fn status_code(&self) -> Result<StatusCode, HeaderError> {
+        match self.0.iter().find(|h| h.name() == b":status") {
+            Some(h) => StatusCode::parse(h.value()),
+            None => Err(HeaderError::MissingStatus),
+        }
     }
 
-    pub fn method(&self) -> &str {
-        self.get(":method")
+    /// Body length declared by `content-length` header, `None` if header is absent
+    pub fn content_length(&self) -> Result<Option<u64>, HeaderError> {
+        let mut content_length = None;
+        for h in self.0.iter().filter(|h| h.name() == b"content-length") {
+            let value = str::from_utf8(h.value()).ok()
+                .filter(|v| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|v| v.parse::<u64>().ok());
+            match (value, content_length) {
+                (Some(value), None) => content_length = Some(value),
+                (Some(value), Some(prev)) if value == prev => {}
+                _ => return Err(HeaderError::InvalidContentLength(h.value().to_vec())),
+            }
+        }
+        Ok(content_length)
+    }
+
+    /// `:method` of request headers
+    pub fn method(&self) -> Option<&str> {
+        self.get_opt(":method")
+    }
+
+    /// `:scheme` of request headers, absent in `CONNECT` request
+    pub fn scheme(&self) -> Option<&str> {
+        self.get_opt(":scheme")
+    }
+
+    /// `:authority` of request headers
+    pub fn authority(&self) -> Option<&str> {
+        self.get_opt(":authority")
+    }
+
+    /// `:path` of request headers, absent in `CONNECT` request
+    pub fn path(&self) -> Option<&str> {
+        self.get_opt(":path")
     }
 
     pub fn add(&mut self, name: &str, value: &str) {
@@ -218,11 +458,90 @@ impl Headers {
         self.0.extend(headers.0);
     }
 
+    /// Size of header list as defined for SETTINGS_MAX_HEADER_LIST_SIZE (6.5.2):
+    /// uncompressed name and value lengths plus 32 bytes per field
+    pub fn list_size(&self) -> usize {
+        self.0.iter().map(|h| h.name().len() + h.value().len() + 32).sum()
+    }
+
+    /// `:method` is present, `:path` is present and non-empty unless method is `CONNECT`.
+    ///
+    /// `:scheme` is not required for compatibility with clients which omit it.
+    fn check_request_pseudo_headers(&self) -> Result<(), HeaderError> {
+        let connect = match self.method() {
+            Some(method) => method == Method::Connect.as_str(),
+            None => return Err(HeaderError::MissingPseudoHeader(b":method".to_vec())),
+        };
+        match self.path() {
+            Some(_) if connect => Err(HeaderError::PathInConnect),
+            Some("") => Err(HeaderError::EmptyPath),
+            None if !connect => Err(HeaderError::MissingPseudoHeader(b":path".to_vec())),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn contains_pseudo_headers(&self) -> bool {
+        self.0.iter().any(|h| h.is_pseudo())
+    }
+
+    /// Check that no connection-specific headers are present (8.1.2.2)
+    pub fn check_connection_specific(&self) -> Result<(), HeaderError> {
+        match self.0.iter().find(|h| h.is_connection_specific()) {
+            Some(header) => Err(HeaderError::ConnectionSpecificHeader(header.name().to_vec())),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that header names are lowercase, pseudo-headers are known, not duplicated
+    /// and precede regular headers, mandatory request pseudo-headers are present,
+    /// no connection-specific headers are present and `content-length` is valid
+    pub fn validate(&self, req_or_resp: RequestOrResponse) -> Result<(), HeaderError> {
+        let mut saw_regular_header = false;
+        let mut pseudo_headers: Vec<&[u8]> = Vec::new();
+        for header in &self.0 {
+            if header.name().iter().any(|b| b.is_ascii_uppercase()) {
+                return Err(HeaderError::UppercaseHeaderName(header.name().to_vec()));
+            }
+
+            if !header.is_pseudo() {
+                saw_regular_header = true;
+                continue;
+            }
+
+            let name = header.name();
+            if !req_or_resp.pseudo_headers().contains(&name) {
+                return Err(HeaderError::UnknownPseudoHeader(name.to_vec()));
+            }
+            if saw_regular_header {
+                return Err(HeaderError::PseudoHeaderAfterRegularHeader(name.to_vec()));
+            }
+            if pseudo_headers.contains(&name) {
+                return Err(HeaderError::DuplicatePseudoHeader(name.to_vec()));
+            }
+            if name == b":status" {
+                StatusCode::parse(header.value())?;
+            }
+            pseudo_headers.push(name);
+        }
+        if req_or_resp == RequestOrResponse::Request {
+            self.check_request_pseudo_headers()?;
+        }
+        self.check_connection_specific()?;
+        self.content_length()?;
+        Ok(())
+    }
+
 }
 
 #[cfg(test)]
 mod test {
     use solicit::header::Header;
+    use solicit::header::Headers;
+    use solicit::header::HeaderError;
+    use solicit::header::RequestOrResponse;
+    use solicit::header::Method;
+    use solicit::header::StatusCode;
+    use solicit::HttpScheme;
 
     #[test]
     fn test_partial_eq_of_headers() {
@@ -244,4 +563,244 @@ mod test {
             "Header { name: b\":method\", value: b\"\\xcd\" }",
             format!("{:?}", Header::new(b":method", b"\xcd")));
     }
+
+    #[test]
+    fn test_validate() {
+        let req = Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/"),
+            Header::new("accept", "*/*"),
+        ]);
+        assert_eq!(Ok(()), req.validate(RequestOrResponse::Request));
+        assert_eq!(
+            Err(HeaderError::UnknownPseudoHeader(b":method".to_vec())),
+            req.validate(RequestOrResponse::Response));
+
+        let regular_first = Headers(vec![
+            Header::new("accept", "*/*"),
+            Header::new(":method", "GET"),
+        ]);
+        assert_eq!(
+            Err(HeaderError::PseudoHeaderAfterRegularHeader(b":method".to_vec())),
+            regular_first.validate(RequestOrResponse::Request));
+
+        let duplicate = Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/a"),
+            Header::new(":path", "/b"),
+        ]);
+        assert_eq!(
+            Err(HeaderError::DuplicatePseudoHeader(b":path".to_vec())),
+            duplicate.validate(RequestOrResponse::Request));
+
+        let unknown = Headers(vec![
+            Header::new(":status", "200"),
+            Header::new(":foo", "bar"),
+        ]);
+        assert_eq!(
+            Err(HeaderError::UnknownPseudoHeader(b":foo".to_vec())),
+            unknown.validate(RequestOrResponse::Response));
+    }
+
+    #[test]
+    fn test_validate_request_pseudo_headers() {
+        let no_method = Headers(vec![
+            Header::new(":path", "/"),
+        ]);
+        assert_eq!(
+            Err(HeaderError::MissingPseudoHeader(b":method".to_vec())),
+            no_method.validate(RequestOrResponse::Request));
+
+        let no_path = Headers(vec![
+            Header::new(":method", "GET"),
+        ]);
+        assert_eq!(
+            Err(HeaderError::MissingPseudoHeader(b":path".to_vec())),
+            no_path.validate(RequestOrResponse::Request));
+
+        let empty_path = Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", ""),
+        ]);
+        assert_eq!(Err(HeaderError::EmptyPath), empty_path.validate(RequestOrResponse::Request));
+
+        let connect = Headers::request(Method::Connect, "", HttpScheme::Https, "example.com:443")
+            .expect("request");
+        assert_eq!(Ok(()), connect.validate(RequestOrResponse::Request));
+
+        let mut connect_with_path = connect.clone();
+        connect_with_path.add(":path", "/");
+        assert_eq!(Err(HeaderError::PathInConnect), connect_with_path.validate(RequestOrResponse::Request));
+    }
+
+    #[test]
+    fn test_request() {
+        let headers = Headers::request(Method::Get, "/index", HttpScheme::Https, "example.com")
+            .expect("request");
+        let names: Vec<&[u8]> = headers.0.iter().map(|h| h.name()).collect();
+        assert_eq!(vec![&b":method"[..], b":scheme", b":authority", b":path"], names);
+        assert_eq!(Some("GET"), headers.method());
+        assert_eq!(Some("https"), headers.scheme());
+        assert_eq!(Some("example.com"), headers.authority());
+        assert_eq!(Some("/index"), headers.path());
+        assert_eq!(None, headers.status());
+        assert_eq!(Ok(()), headers.validate(RequestOrResponse::Request));
+
+        let mut headers = headers;
+        headers.add("accept", "*/*");
+        assert_eq!(Ok(()), headers.validate(RequestOrResponse::Request));
+    }
+
+    #[test]
+    fn test_request_empty_path() {
+        assert_eq!(
+            HeaderError::EmptyPath,
+            Headers::request(Method::Post, "", HttpScheme::Http, "example.com").unwrap_err());
+    }
+
+    #[test]
+    fn test_request_connect() {
+        let headers = Headers::request(Method::Connect, "", HttpScheme::Https, "example.com:443")
+            .expect("request");
+        let names: Vec<&[u8]> = headers.0.iter().map(|h| h.name()).collect();
+        assert_eq!(vec![&b":method"[..], b":authority"], names);
+        assert_eq!(Some("CONNECT"), headers.method());
+        assert_eq!(Some("example.com:443"), headers.authority());
+        assert_eq!(None, headers.scheme());
+        assert_eq!(None, headers.path());
+
+        assert_eq!(
+            HeaderError::PathInConnect,
+            Headers::request(Method::Connect, "/", HttpScheme::Https, "example.com:443").unwrap_err());
+    }
+
+    #[test]
+    fn test_response() {
+        let headers = Headers::response(204);
+        assert_eq!(Some(204), headers.status());
+        assert_eq!(None, headers.method());
+        assert_eq!(None, headers.path());
+        assert_eq!(Ok(()), headers.validate(RequestOrResponse::Response));
+    }
+
+    #[test]
+    fn test_status_code_parse() {
+        let ok = StatusCode::parse(b"200").expect("200");
+        assert_eq!(200, ok.as_u16());
+        assert!(ok.is_success());
+        assert!(!ok.is_redirect());
+
+        assert!(StatusCode::parse(b"301").unwrap().is_redirect());
+        assert!(StatusCode::parse(b"404").unwrap().is_client_error());
+        assert!(StatusCode::parse(b"503").unwrap().is_server_error());
+        assert_eq!(Ok(StatusCode::new(100).unwrap()), StatusCode::parse(b"100"));
+
+        for invalid in &[&b""[..], b"20", b"2000", b"2x0", b"+20", b" 200", b"099", b"-10"] {
+            assert_eq!(
+                Err(HeaderError::InvalidStatus(invalid.to_vec())),
+                StatusCode::parse(invalid));
+        }
+    }
+
+    #[test]
+    fn test_status_code_from_headers() {
+        assert_eq!(Ok(StatusCode::new(204).unwrap()), Headers::response(204).status_code());
+        assert_eq!(Err(HeaderError::MissingStatus), Headers::new().status_code());
+
+        let invalid = Headers(vec![Header::new(":status", "20")]);
+        assert_eq!(Err(HeaderError::InvalidStatus(b"20".to_vec())), invalid.status_code());
+        assert_eq!(
+            Err(HeaderError::InvalidStatus(b"20".to_vec())),
+            invalid.validate(RequestOrResponse::Response));
+    }
+
+    #[test]
+    fn test_get_all() {
+        let headers = Headers(vec![
+            Header::new(":status", "200"),
+            Header::new("set-cookie", "a=1"),
+            Header::new("content-type", "text/plain"),
+            Header::new("set-cookie", "b=2"),
+        ]);
+        assert_eq!(vec!["a=1", "b=2"], headers.get_all("set-cookie"));
+        assert_eq!(vec!["a=1", "b=2"], headers.get_all("Set-Cookie"));
+        assert_eq!(Some("a=1"), headers.get_opt("SET-COOKIE"));
+        assert_eq!("text/plain", headers.get("Content-Type"));
+        assert!(headers.get_all("x-missing").is_empty());
+        assert_eq!(Some("200"), headers.get_opt(":status"));
+        assert_eq!(None, headers.get_opt(":STATUS"));
+    }
+
+    #[test]
+    fn test_validate_uppercase() {
+        let headers = Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/"),
+            Header::new("X-Foo", "bar"),
+        ]);
+        assert_eq!(
+            Err(HeaderError::UppercaseHeaderName(b"X-Foo".to_vec())),
+            headers.validate(RequestOrResponse::Request));
+
+        let headers = Headers(vec![Header::new(":Status", "200")]);
+        assert_eq!(
+            Err(HeaderError::UppercaseHeaderName(b":Status".to_vec())),
+            headers.validate(RequestOrResponse::Response));
+    }
+
+    #[test]
+    fn test_validate_connection_specific() {
+        for name in &["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"] {
+            let headers = Headers(vec![
+                Header::new(":method", "GET"),
+                Header::new(":path", "/"),
+                Header::new(*name, "keep-alive"),
+            ]);
+            assert_eq!(
+                Err(HeaderError::ConnectionSpecificHeader(name.as_bytes().to_vec())),
+                headers.validate(RequestOrResponse::Request));
+        }
+
+        let headers = Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/"),
+            Header::new("te", "gzip"),
+        ]);
+        assert_eq!(
+            Err(HeaderError::ConnectionSpecificHeader(b"te".to_vec())),
+            headers.validate(RequestOrResponse::Request));
+
+        let headers = Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/"),
+            Header::new("te", "trailers"),
+        ]);
+        assert_eq!(Ok(()), headers.validate(RequestOrResponse::Request));
+    }
+
+    #[test]
+    fn test_content_length() {
+        assert_eq!(Ok(None), Headers::ok_200().content_length());
+
+        let mut headers = Headers::ok_200();
+        headers.add("content-length", "10");
+        assert_eq!(Ok(Some(10)), headers.content_length());
+        headers.add("content-length", "10");
+        assert_eq!(Ok(Some(10)), headers.content_length());
+        headers.add("content-length", "11");
+        assert_eq!(
+            Err(HeaderError::InvalidContentLength(b"11".to_vec())),
+            headers.content_length());
+
+        for invalid in &["", "-1", "+1", "1.0", "ten", "99999999999999999999999"] {
+            let mut headers = Headers::ok_200();
+            headers.add("content-length", invalid);
+            assert_eq!(
+                Err(HeaderError::InvalidContentLength(invalid.as_bytes().to_vec())),
+                headers.content_length());
+            assert_eq!(
+                Err(HeaderError::InvalidContentLength(invalid.as_bytes().to_vec())),
+                headers.validate(RequestOrResponse::Response));
+        }
+    }
 }
@@ -1,19 +1,27 @@
 use std::io;
 use std::io::Read;
+use std::cmp;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use bytes::Bytes;
+use bytes::BytesMut;
+use bytes::BufMut;
 
+use futures::Async;
+use futures::Poll;
 use futures::future;
 use futures::future::done;
 use futures::future::Loop;
 use futures::future::loop_fn;
 use futures::future::Future;
 use futures::future::BoxFuture;
+use futures::stream;
 use futures::stream::Stream;
 use futures::stream::BoxStream;
 
 use tokio_io::io::read_exact;
+use tokio_io::io as tokio_io;
 use tokio_io::io::write_all;
 use tokio_core::net::TcpStream;
 use tokio_core::reactor;
@@ -23,18 +31,27 @@ use tokio_io::AsyncRead;
 use error;
 use error::Error;
 use error::ErrorCode;
+use error::InvalidFrameInfo;
 use result::Result;
 
 use solicit::StreamId;
+use solicit::DEFAULT_SETTINGS;
 use solicit::frame::FRAME_HEADER_LEN;
 use solicit::frame::RawFrame;
+use solicit::frame::FrameHeader;
 use solicit::frame::RawFrameRef;
 use solicit::frame::FrameIR;
+use solicit::frame::Frame;
 use solicit::frame::headers::HeadersFlag;
 use solicit::frame::headers::HeadersFrame;
 use solicit::frame::push_promise::PushPromiseFrame;
 use solicit::frame::push_promise::PushPromiseFlag;
 use solicit::frame::unpack_header;
+use solicit::frame::data::DATA_FRAME_TYPE;
+use solicit::frame::data::DataFlag;
+use solicit::frame::headers::HEADERS_FRAME_TYPE;
+use solicit::frame::push_promise::PUSH_PROMISE_FRAME_TYPE;
+use solicit::frame::continuation::CONTINUATION_FRAME_TYPE;
 use solicit::frame::settings::SettingsFrame;
 use solicit::frame::settings::HttpSetting;
 use solicit::connection::HttpFrame;
@@ -62,35 +79,462 @@ impl<T> AsMut<[T]> for VecWithPos<T> {
     }
 }
 
-pub fn recv_raw_frame<'r, R : AsyncRead + 'r>(read: R, max_frame_size: u32)
+/// Limits applied to frames received from the peer.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameSizeLimits {
+    /// Frames with larger payload are rejected with `FRAME_SIZE_ERROR`
+    pub max_frame_size: u32,
+    /// Upper bound for buffer reserved for HEADERS, PUSH_PROMISE and CONTINUATION payload
+    pub max_header_list_size: u32,
+}
+
+impl FrameSizeLimits {
+    pub fn new(max_frame_size: u32) -> FrameSizeLimits {
+        FrameSizeLimits {
+            max_frame_size: max_frame_size,
+            max_header_list_size: DEFAULT_SETTINGS.max_header_list_size,
+        }
+    }
+
+    /// How many bytes of payload can be reserved before payload actually arrives
+    fn reserve_limit(&self, header: &FrameHeader) -> usize {
+        let limit = match header.frame_type {
+            HEADERS_FRAME_TYPE | PUSH_PROMISE_FRAME_TYPE | CONTINUATION_FRAME_TYPE =>
+                cmp::min(self.max_frame_size, self.max_header_list_size),
+            _ => self.max_frame_size,
+        };
+        cmp::min(cmp::min(header.length, limit) as usize, RECV_CHUNK_SIZE)
+    }
+}
+
+/// Payload is read from the socket by chunks of this size,
+/// so buffer grows only when peer actually sends data
+const RECV_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Result of `recv_raw_frame_with_limits`
+pub enum RecvRawFrame {
+    Frame(RawFrame),
+    /// Frame exceeded `max_frame_size`, payload was read and discarded,
+    /// so caller may reset the stream and continue reading from the connection
+    Discarded(FrameHeader),
+}
+
+/// Read into `buf` until it is full or EOF is reached, EOF flag is returned
+fn read_full<'r, R : AsyncRead + 'r>(read: R, buf: VecWithPos<u8>)
+    -> Box<Future<Item=(R, VecWithPos<u8>, bool), Error=error::Error> + 'r>
+{
+    Box::new(loop_fn((read, buf), |(read, buf)| -> Box<Future<Item=_, Error=_> + 'r> {
+        if buf.pos == buf.vec.len() {
+            return Box::new(future::ok(Loop::Break((read, buf, false))));
+        }
+
+        Box::new(tokio_io::read(read, buf)
+            .map(|(read, mut buf, n)| {
+                if n == 0 {
+                    Loop::Break((read, buf, true))
+                } else {
+                    buf.pos += n;
+                    Loop::Continue((read, buf))
+                }
+            })
+            .map_err(error::Error::from))
+    }))
+}
+
+fn recv_header<'r, R : AsyncRead + 'r>(read: R)
+    -> Box<Future<Item=(R, [u8; FRAME_HEADER_LEN]), Error=error::Error> + 'r>
+{
+    let buf = VecWithPos {
+        vec: vec![0; FRAME_HEADER_LEN],
+        pos: 0,
+    };
+
+    Box::new(read_full(read, buf).and_then(|(read, buf, eof)| {
+        if eof {
+            if buf.pos == 0 {
+                // clean EOF at frame boundary
+                return Err(error::Error::from(io::Error::new(io::ErrorKind::UnexpectedEof, "EOF")));
+            }
+            return Err(error::Error::IncompleteFrame {
+                expected: FRAME_HEADER_LEN as u32,
+                got: buf.pos as u32,
+            });
+        }
+
+        let mut raw_header = [0; FRAME_HEADER_LEN];
+        raw_header.copy_from_slice(&buf.vec);
+        Ok((read, raw_header))
+    }))
+}
+
+fn recv_payload<'r, R : AsyncRead + 'r>(read: R, raw_header: [u8; FRAME_HEADER_LEN], limits: FrameSizeLimits)
     -> Box<Future<Item=(R, RawFrame), Error=error::Error> + 'r>
 {
-    let header = read_exact(read, [0; FRAME_HEADER_LEN]).map_err(error::Error::from);
-    let frame_buf = header.and_then(move |(read, raw_header)| -> Box<Future<Item=_, Error=_> + 'r> {
+    let header = unpack_header(&raw_header);
+    let length = header.length;
+    let total_len = FRAME_HEADER_LEN + length as usize;
+
+    let mut full_frame = VecWithPos {
+        vec: Vec::with_capacity(FRAME_HEADER_LEN + limits.reserve_limit(&header)),
+        pos: FRAME_HEADER_LEN,
+    };
+    full_frame.vec.extend(&raw_header);
+
+    Box::new(loop_fn((read, full_frame), move |(read, mut full_frame)| -> Box<Future<Item=_, Error=_> + 'r> {
+        if full_frame.vec.len() == total_len {
+            return Box::new(future::ok(Loop::Break((read, RawFrame::from(full_frame.vec)))));
+        }
+
+        let chunk_end = cmp::min(total_len, full_frame.pos + RECV_CHUNK_SIZE);
+        full_frame.vec.resize(chunk_end, 0);
+
+        Box::new(read_full(read, full_frame)
+            .and_then(move |(read, full_frame, eof)| {
+                if eof {
+                    return Err(error::Error::IncompleteFrame {
+                        expected: length,
+                        got: (full_frame.pos - FRAME_HEADER_LEN) as u32,
+                    });
+                }
+                Ok(Loop::Continue((read, full_frame)))
+            }))
+    }))
+}
+
+fn discard_payload<'r, R : AsyncRead + 'r>(read: R, length: u32)
+    -> Box<Future<Item=R, Error=error::Error> + 'r>
+{
+    let buf = vec![0; cmp::min(length as usize, RECV_CHUNK_SIZE)];
+    Box::new(loop_fn((read, buf, length as usize), |(read, mut buf, rem)| -> Box<Future<Item=_, Error=_> + 'r> {
+        if rem == 0 {
+            return Box::new(future::ok(Loop::Break(read)));
+        }
+
+        let chunk = cmp::min(rem, buf.len());
+        buf.truncate(chunk);
+        Box::new(read_exact(read, buf)
+            .map(move |(read, buf)| Loop::Continue((read, buf, rem - chunk)))
+            .map_err(error::Error::from))
+    }))
+}
+
+/// Receive raw frame from reader.
+///
+/// Oversized DATA frame is not a connection error, so its payload
+/// is skipped and `RecvRawFrame::Discarded` is returned.
+pub fn recv_raw_frame_with_limits<'r, R : AsyncRead + 'r>(read: R, limits: FrameSizeLimits)
+    -> Box<Future<Item=(R, RecvRawFrame), Error=error::Error> + 'r>
+{
+    Box::new(recv_header(read).and_then(move |(read, raw_header)| -> Box<Future<Item=_, Error=_> + 'r> {
+        if raw_header.starts_with(b"HTTP/1.") {
+            warn!("closing conn because peer responded with HTTP/1");
+            return Box::new(future::err(error::Error::ConnectionError(
+                ErrorCode::ProtocolError, Bytes::from_static(b"peer responded with HTTP/1"))));
+        }
+
         let header = unpack_header(&raw_header);
 
-        if header.length > max_frame_size {
+        if header.length > limits.max_frame_size {
+            if header.frame_type == DATA_FRAME_TYPE && header.stream_id != 0 {
+                warn!("discarding DATA frame with size: {}, max_frame_size: {}",
+                    header.length, limits.max_frame_size);
+                return Box::new(discard_payload(read, header.length)
+                    .map(move |read| (read, RecvRawFrame::Discarded(header))));
+            }
+
             warn!("closing conn because peer sent frame with size: {}, max_frame_size: {}",
-                header.length, max_frame_size);
+                header.length, limits.max_frame_size);
             return Box::new(future::err(error::Error::CodeError(ErrorCode::FrameSizeError)));
         }
 
-        let total_len = FRAME_HEADER_LEN + header.length as usize;
-        let mut full_frame = VecWithPos {
-            vec: Vec::with_capacity(total_len),
-            pos: 0,
+        Box::new(recv_payload(read, raw_header, limits)
+            .map(|(read, frame)| (read, RecvRawFrame::Frame(frame))))
+    }))
+}
+
+pub fn recv_raw_frame<'r, R : AsyncRead + 'r>(read: R, max_frame_size: u32)
+    -> Box<Future<Item=(R, RawFrame), Error=error::Error> + 'r>
+{
+    Box::new(recv_raw_frame_with_limits(read, FrameSizeLimits::new(max_frame_size))
+        .and_then(|(read, frame)| {
+            match frame {
+                RecvRawFrame::Frame(frame) => Ok((read, frame)),
+                RecvRawFrame::Discarded(_) => Err(error::Error::CodeError(ErrorCode::FrameSizeError)),
+            }
+        }))
+}
+
+/// Reader with buffer reused between frames.
+///
+/// Socket is read by chunks into the buffer and received frames
+/// are split off the buffer as `Bytes` slices, so no memory is allocated
+/// per frame. Once frames referencing the buffer are dropped,
+/// its memory is reclaimed for subsequent reads.
+pub struct PooledRead<R> {
+    read: R,
+    buf: BytesMut,
+}
+
+impl<R> PooledRead<R> {
+    pub fn new(read: R) -> PooledRead<R> {
+        PooledRead::with_buf(read, BytesMut::with_capacity(RECV_CHUNK_SIZE))
+    }
+
+    /// Read into caller-provided buffer, which may contain data already read
+    pub fn with_buf(read: R, buf: BytesMut) -> PooledRead<R> {
+        PooledRead {
+            read: read,
+            buf: buf,
+        }
+    }
+
+    /// Reader and data read but not yet returned as frames
+    pub fn into_parts(self) -> (R, BytesMut) {
+        (self.read, self.buf)
+    }
+}
+
+/// Future returned by `fill`
+struct Fill<R> {
+    read: Option<PooledRead<R>>,
+    need: usize,
+}
+
+impl<R : AsyncRead> Future for Fill<R> {
+    /// Reader and EOF flag
+    type Item = (PooledRead<R>, bool);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(PooledRead<R>, bool), Error> {
+        let eof = {
+            let read = self.read.as_mut().expect("poll after completion");
+            loop {
+                if read.buf.len() >= self.need {
+                    break false;
+                }
+
+                let want = cmp::min(self.need - read.buf.len(), RECV_CHUNK_SIZE);
+                if read.buf.remaining_mut() < want {
+                    // memory is reused if no frames reference it
+                    // and it fits a chunk together with the data already read
+                    read.buf.reserve(cmp::max(want, RECV_CHUNK_SIZE.saturating_sub(read.buf.len())));
+                }
+
+                match AsyncRead::read_buf(&mut read.read, &mut read.buf)? {
+                    Async::Ready(0) => break true,
+                    Async::Ready(_) => {}
+                    Async::NotReady => return Ok(Async::NotReady),
+                }
+            }
         };
+        Ok(Async::Ready((self.read.take().unwrap(), eof)))
+    }
+}
+
+/// Read until buffer contains at least `need` bytes or EOF is reached
+fn fill<R : AsyncRead>(read: PooledRead<R>, need: usize) -> Fill<R> {
+    Fill {
+        read: Some(read),
+        need: need,
+    }
+}
+
+fn discard_payload_pooled<'r, R : AsyncRead + 'r>(read: PooledRead<R>, length: u32)
+    -> Box<Future<Item=PooledRead<R>, Error=error::Error> + 'r>
+{
+    Box::new(loop_fn((read, length as usize), move |(mut read, rem)| -> Box<Future<Item=_, Error=_> + 'r> {
+        if rem == 0 {
+            return Box::new(future::ok(Loop::Break(read)));
+        }
+
+        if !read.buf.is_empty() {
+            let skip = cmp::min(rem, read.buf.len());
+            drop(read.buf.split_to(skip));
+            return Box::new(future::ok(Loop::Continue((read, rem - skip))));
+        }
+
+        Box::new(fill(read, 1).and_then(move |(read, eof)| {
+            if eof {
+                return Err(error::Error::IncompleteFrame {
+                    expected: length,
+                    got: length - rem as u32,
+                });
+            }
+            Ok(Loop::Continue((read, rem)))
+        }))
+    }))
+}
+
+/// Receive raw frame like `recv_raw_frame_with_limits`,
+/// frame references memory of reader buffer.
+pub fn recv_raw_frame_pooled<'r, R : AsyncRead + 'r>(read: PooledRead<R>, limits: FrameSizeLimits)
+    -> Box<Future<Item=(PooledRead<R>, RecvRawFrame), Error=error::Error> + 'r>
+{
+    Box::new(fill(read, FRAME_HEADER_LEN).and_then(move |(read, eof)| -> Box<Future<Item=_, Error=_> + 'r> {
+        if eof {
+            if read.buf.is_empty() {
+                // clean EOF at frame boundary
+                return Box::new(future::err(error::Error::from(io::Error::new(io::ErrorKind::UnexpectedEof, "EOF"))));
+            }
+            return Box::new(future::err(error::Error::IncompleteFrame {
+                expected: FRAME_HEADER_LEN as u32,
+                got: read.buf.len() as u32,
+            }));
+        }
+
+        if read.buf.starts_with(b"HTTP/1.") {
+            warn!("closing conn because peer responded with HTTP/1");
+            return Box::new(future::err(error::Error::ConnectionError(
+                ErrorCode::ProtocolError, Bytes::from_static(b"peer responded with HTTP/1"))));
+        }
+
+        let mut raw_header = [0; FRAME_HEADER_LEN];
+        raw_header.copy_from_slice(&read.buf[..FRAME_HEADER_LEN]);
+        let header = unpack_header(&raw_header);
+
+        if header.length > limits.max_frame_size {
+            if header.frame_type == DATA_FRAME_TYPE && header.stream_id != 0 {
+                warn!("discarding DATA frame with size: {}, max_frame_size: {}",
+                    header.length, limits.max_frame_size);
+                let mut read = read;
+                drop(read.buf.split_to(FRAME_HEADER_LEN));
+                return Box::new(discard_payload_pooled(read, header.length)
+                    .map(move |read| (read, RecvRawFrame::Discarded(header))));
+            }
+
+            warn!("closing conn because peer sent frame with size: {}, max_frame_size: {}",
+                header.length, limits.max_frame_size);
+            return Box::new(future::err(error::Error::CodeError(ErrorCode::FrameSizeError)));
+        }
+
+        let length = header.length;
+        let total_len = FRAME_HEADER_LEN + length as usize;
+
+        Box::new(fill(read, total_len).and_then(move |(mut read, eof)| {
+            if eof {
+                return Err(error::Error::IncompleteFrame {
+                    expected: length,
+                    got: (read.buf.len() - FRAME_HEADER_LEN) as u32,
+                });
+            }
+            let frame = RawFrame { raw_content: read.buf.split_to(total_len).freeze() };
+            Ok((read, RecvRawFrame::Frame(frame)))
+        }))
+    }))
+}
+
+/// Receive HTTP frame from reader with reusable buffer
+pub fn recv_http_frame_pooled<'r, R : AsyncRead + 'r>(read: PooledRead<R>, max_frame_size: u32)
+    -> Box<Future<Item=(PooledRead<R>, HttpFrame), Error=Error> + 'r>
+{
+    Box::new(recv_raw_frame_pooled(read, FrameSizeLimits::new(max_frame_size))
+        .and_then(|(read, frame)| {
+            match frame {
+                RecvRawFrame::Frame(raw_frame) => Ok((read, HttpFrame::from_raw(&raw_frame)?)),
+                RecvRawFrame::Discarded(_) => Err(error::Error::CodeError(ErrorCode::FrameSizeError)),
+            }
+        }))
+}
+
+/// Item of stream returned by `recv_raw_frame_chunked`
+pub enum RawFrameChunk {
+    /// Part of DATA frame payload (without padding).
+    /// Flag is set on the last part of the frame with END_STREAM flag.
+    Data(StreamId, Bytes, bool),
+    /// Any frame other than DATA
+    Frame(RawFrame),
+}
+
+enum RecvChunkedState<R> {
+    Header(R),
+    Data {
+        read: R,
+        stream_id: StreamId,
+        rem: usize,
+        pad: usize,
+        end_stream: bool,
+    },
+}
 
-        full_frame.vec.extend(&raw_header);
-        full_frame.vec.resize(total_len, 0);
-        full_frame.pos = FRAME_HEADER_LEN;
+/// Receive frames from reader, yielding DATA frame payload by parts of at most `chunk_size`
+/// bytes as soon as they are read. Other frames are buffered and returned whole.
+pub fn recv_raw_frame_chunked<'r, R : AsyncRead + 'r>(read: R, max_frame_size: u32, chunk_size: usize)
+    -> Box<Stream<Item=RawFrameChunk, Error=Error> + 'r>
+{
+    assert!(chunk_size > 0);
+
+    fn recv_data_chunk<'r, R : AsyncRead + 'r>(
+        read: R, stream_id: StreamId, rem: usize, pad: usize, end_stream: bool, chunk_size: usize)
+            -> Box<Future<Item=(RawFrameChunk, RecvChunkedState<R>), Error=Error> + 'r>
+    {
+        let chunk = vec![0; cmp::min(rem, chunk_size)];
+        Box::new(read_exact(read, chunk).map_err(Error::from).and_then(move |(read, chunk)| {
+            let rem = rem - chunk.len();
+            let chunk = Bytes::from(chunk);
+            if rem != 0 {
+                let state = RecvChunkedState::Data {
+                    read: read,
+                    stream_id: stream_id,
+                    rem: rem,
+                    pad: pad,
+                    end_stream: end_stream,
+                };
+                Box::new(future::ok((RawFrameChunk::Data(stream_id, chunk, false), state)))
+                    as Box<Future<Item=_, Error=_> + 'r>
+            } else {
+                Box::new(discard_payload(read, pad as u32).map(move |read| {
+                    (RawFrameChunk::Data(stream_id, chunk, end_stream), RecvChunkedState::Header(read))
+                }))
+            }
+        }))
+    }
+
+    Box::new(stream::unfold(RecvChunkedState::Header(read), move |state| {
+        let r: Box<Future<Item=_, Error=_> + 'r> = match state {
+            RecvChunkedState::Data { read, stream_id, rem, pad, end_stream } => {
+                recv_data_chunk(read, stream_id, rem, pad, end_stream, chunk_size)
+            }
+            RecvChunkedState::Header(read) => {
+                let header = read_exact(read, [0; FRAME_HEADER_LEN]).map_err(Error::from);
+                Box::new(header.and_then(move |(read, raw_header)| -> Box<Future<Item=_, Error=_> + 'r> {
+                    let header = unpack_header(&raw_header);
+
+                    if header.length > max_frame_size {
+                        warn!("peer sent frame with size: {}, max_frame_size: {}",
+                            header.length, max_frame_size);
+                        return Box::new(future::err(Error::CodeError(ErrorCode::FrameSizeError)));
+                    }
+
+                    if header.frame_type != DATA_FRAME_TYPE {
+                        return Box::new(recv_payload(read, raw_header, FrameSizeLimits::new(max_frame_size))
+                            .map(|(read, frame)| (RawFrameChunk::Frame(frame), RecvChunkedState::Header(read))));
+                    }
 
-        Box::new(read_exact(read, full_frame).map_err(error::Error::from))
-    });
-    let frame = frame_buf.map(|(read, frame_buf)| {
-        (read, RawFrame::from(frame_buf.vec))
-    });
-    Box::new(frame)
+                    let stream_id = header.stream_id;
+                    let end_stream = header.flags & (DataFlag::EndStream as u8) != 0;
+                    let length = header.length as usize;
+
+                    if header.flags & (DataFlag::Padded as u8) == 0 {
+                        return recv_data_chunk(read, stream_id, length, 0, end_stream, chunk_size);
+                    }
+
+                    if length == 0 {
+                        return Box::new(future::err(Error::CodeError(ErrorCode::ProtocolError)));
+                    }
+
+                    Box::new(read_exact(read, [0; 1]).map_err(Error::from).and_then(move |(read, pad)| {
+                        let pad = pad[0] as usize;
+                        if pad > length - 1 {
+                            return Box::new(future::err(Error::CodeError(ErrorCode::ProtocolError)))
+                                as Box<Future<Item=_, Error=_> + 'r>;
+                        }
+                        recv_data_chunk(read, stream_id, length - 1 - pad, pad, end_stream, chunk_size)
+                    }))
+                }))
+            }
+        };
+        Some(r)
+    }))
 }
 
 struct SyncRead<'r, R : Read + ?Sized + 'r>(&'r mut R);
@@ -118,9 +562,37 @@ pub fn recv_http_frame<'r, R : AsyncRead + 'r>(read: R, max_frame_size: u32)
     }))
 }
 
+/// Default limit of CONTINUATION frames following single HEADERS or PUSH_PROMISE frame
+pub const DEFAULT_MAX_CONTINUATION_FRAMES: u32 = 100;
+
 /// Recieve HTTP frame, joining CONTINUATION frame with preceding HEADER frames.
-pub fn recv_http_frame_join_cont<'r, R : AsyncRead + 'r>(read: R, max_frame_size: u32)
-    -> Box<Future<Item=(R, HttpFrame), Error=Error> + 'r>
+///
+/// Frames of unknown type are discarded (RFC 7540, section 4.1),
+/// unless they are received inside header block.
+///
+/// Any frame other than CONTINUATION of the same stream inside header block,
+/// or CONTINUATION outside of header block is `PROTOCOL_ERROR` (RFC 7540, section 6.10).
+///
+/// More than `max_continuation_frames` CONTINUATION frames in a single header block
+/// is treated as flood and rejected with `ENHANCE_YOUR_CALM`.
+pub fn recv_http_frame_join_cont<'r, R : AsyncRead + 'r>(
+    read: R, max_frame_size: u32, max_continuation_frames: u32)
+        -> Box<Future<Item=(R, HttpFrame), Error=Error> + 'r>
+{
+    join_cont(read, max_continuation_frames, move |read| recv_http_frame(read, max_frame_size))
+}
+
+/// Receive HTTP frame like `recv_http_frame_join_cont`, from reader with reusable buffer
+pub fn recv_http_frame_join_cont_pooled<'r, R : AsyncRead + 'r>(
+    read: PooledRead<R>, max_frame_size: u32, max_continuation_frames: u32)
+        -> Box<Future<Item=(PooledRead<R>, HttpFrame), Error=Error> + 'r>
+{
+    join_cont(read, max_continuation_frames, move |read| recv_http_frame_pooled(read, max_frame_size))
+}
+
+fn join_cont<'r, S : 'r, F>(read: S, max_continuation_frames: u32, recv: F)
+    -> Box<Future<Item=(S, HttpFrame), Error=Error> + 'r>
+    where F : Fn(S) -> Box<Future<Item=(S, HttpFrame), Error=Error> + 'r> + 'r
 {
     enum ContinuableFrame {
         Headers(HeadersFrame),
@@ -160,35 +632,41 @@ pub fn recv_http_frame_join_cont<'r, R : AsyncRead + 'r>(read: R, max_frame_size
         }
     }
 
-    Box::new(loop_fn::<(R, Option<ContinuableFrame>), _, _, _>((read, None), move |(read, header_opt)| {
-        recv_http_frame(read, max_frame_size).and_then(move |(read, frame)| {
+    Box::new(loop_fn::<(S, Option<ContinuableFrame>, u32), _, _, _>((read, None, 0), move |(read, header_opt, continuations)| {
+        recv(read).and_then(move |(read, frame)| {
             match frame {
                 HttpFrame::Headers(h) => {
                     if let Some(_) = header_opt {
-                        Err(Error::Other("expecting CONTINUATION frame, got HEADERS"))
+                        warn!("expecting CONTINUATION frame, got HEADERS");
+                        Err(Error::CodeError(ErrorCode::ProtocolError))
                     } else {
                         if h.flags.is_set(HeadersFlag::EndHeaders) {
                             Ok(Loop::Break((read, HttpFrame::Headers(h))))
                         } else {
-                            Ok(Loop::Continue((read, Some(ContinuableFrame::Headers(h)))))
+                            Ok(Loop::Continue((read, Some(ContinuableFrame::Headers(h)), 0)))
                         }
                     }
                 }
                 HttpFrame::PushPromise(p) => {
                     if let Some(_) = header_opt {
-                        Err(Error::Other("expecting CONTINUATION frame, got PUSH_PROMISE"))
+                        warn!("expecting CONTINUATION frame, got PUSH_PROMISE");
+                        Err(Error::CodeError(ErrorCode::ProtocolError))
                     } else {
                         if p.flags.is_set(PushPromiseFlag::EndHeaders) {
                             Ok(Loop::Break((read, HttpFrame::PushPromise(p))))
                         } else {
-                            Ok(Loop::Continue((read, Some(ContinuableFrame::PushPromise(p)))))
+                            Ok(Loop::Continue((read, Some(ContinuableFrame::PushPromise(p)), 0)))
                         }
                     }
                 }
                 HttpFrame::Continuation(c) => {
                     if let Some(mut h) = header_opt {
                         if h.get_stream_id() != c.stream_id {
-                            Err(Error::Other("CONTINUATION frame with different stream id"))
+                            warn!("CONTINUATION frame with different stream id");
+                            Err(Error::CodeError(ErrorCode::ProtocolError))
+                        } else if continuations >= max_continuation_frames {
+                            warn!("too many CONTINUATION frames in header block");
+                            Err(Error::CodeError(ErrorCode::EnhanceYourCalm))
                         } else {
                             let header_end = c.is_headers_end();
                             h.extend_header_fragment(c.header_fragment);
@@ -196,16 +674,27 @@ pub fn recv_http_frame_join_cont<'r, R : AsyncRead + 'r>(read: R, max_frame_size
                                 h.set_end_headers();
                                 Ok(Loop::Break((read, h.into_frame())))
                             } else {
-                                Ok(Loop::Continue((read, Some(h))))
+                                Ok(Loop::Continue((read, Some(h), continuations + 1)))
                             }
                         }
                     } else {
-                        Err(Error::Other("CONTINUATION frame without headers"))
+                        warn!("CONTINUATION frame without headers");
+                        Err(Error::CodeError(ErrorCode::ProtocolError))
+                    }
+                }
+                HttpFrame::Unknown(f) => {
+                    if let Some(_) = header_opt {
+                        warn!("expecting CONTINUATION frame, got unknown frame");
+                        Err(Error::CodeError(ErrorCode::ProtocolError))
+                    } else {
+                        debug!("ignoring frame of unknown type {}", f.frame_type());
+                        Ok(Loop::Continue((read, None, 0)))
                     }
                 }
                 f => {
                     if let Some(_) = header_opt {
-                        Err(Error::Other("expecting CONTINUATION frame"))
+                        warn!("expecting CONTINUATION frame");
+                        Err(Error::CodeError(ErrorCode::ProtocolError))
                     } else {
                         Ok(Loop::Break((read, f)))
                     }
@@ -225,7 +714,9 @@ pub fn recv_settings_frame<'r, R : AsyncRead + 'r>(read: R, max_frame_size: u32)
                     Ok((read, f))
                 }
                 f => {
-                    Err(Error::InvalidFrame(format!("unexpected frame, expected SETTINGS, got {:?}", f.frame_type())))
+                    Err(Error::InvalidFrame(InvalidFrameInfo::new(
+                        format!("unexpected frame, expected SETTINGS, got {:?}", f.frame_type()))
+                            .with_header(f.get_header())))
                 }
             }
         }))
@@ -238,7 +729,8 @@ pub fn recv_settings_frame_ack<R : AsyncRead + Send + 'static>(read: R, max_fram
         if frame.is_ack() {
             Ok((read, frame))
         } else {
-            Err(Error::InvalidFrame("expecting SETTINGS with ack, got without ack".to_owned()))
+            Err(Error::InvalidFrame(InvalidFrameInfo::new("expecting SETTINGS with ack, got without ack")
+                .with_header(frame.get_header())))
         }
     }))
 }
@@ -250,7 +742,8 @@ pub fn recv_settings_frame_set<R : AsyncRead + Send + 'static>(read: R, max_fram
         if !frame.is_ack() {
             Ok((read, frame))
         } else {
-            Err(Error::InvalidFrame("expecting SETTINGS without ack, got with ack".to_owned()))
+            Err(Error::InvalidFrame(InvalidFrameInfo::new("expecting SETTINGS without ack, got with ack")
+                .with_header(frame.get_header())))
         }
     }))
 }
@@ -271,30 +764,46 @@ pub fn send_frame<W : AsyncWrite + Send + 'static, F : FrameIR>(write: W, frame:
         .map_err(|e| e.into()))
 }
 
-static PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+pub static PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
 
-fn send_settings<W : AsyncWrite + Send + 'static>(conn: W) -> HttpFuture<W> {
+/// Send SETTINGS frame with given settings, push is disabled unless specified
+pub fn send_settings<W : AsyncWrite + Send + 'static>(conn: W, settings: Vec<HttpSetting>) -> HttpFuture<W> {
     let settings = {
         let mut frame = SettingsFrame::new();
-        frame.add_setting(HttpSetting::EnablePush(false));
+        let push_specified = settings.iter().any(|s| match *s {
+            HttpSetting::EnablePush(..) => true,
+            _ => false,
+        });
+        if !push_specified {
+            frame.add_setting(HttpSetting::EnablePush(false));
+        }
+        for setting in settings {
+            frame.add_setting(setting);
+        }
         frame
     };
 
     Box::new(send_frame(conn, settings))
 }
 
-pub fn client_handshake<I : AsyncWrite + AsyncRead + Send + 'static>(conn: I) -> HttpFuture<I> {
+/// Send PREFACE and SETTINGS frame with given settings
+pub fn client_handshake<I : AsyncWrite + AsyncRead + Send + 'static>(conn: I, settings: Vec<HttpSetting>)
+    -> HttpFuture<I>
+{
     debug!("send PREFACE");
     let send_preface = write_all(conn, PREFACE)
         .map(|(conn, _)| conn)
         .map_err(|e| e.into());
 
-    let send_settings = send_preface.and_then(send_settings);
+    let send_settings = send_preface.and_then(move |conn| send_settings(conn, settings));
 
     Box::new(send_settings)
 }
 
-pub fn server_handshake<I : AsyncRead + AsyncWrite + Send + 'static>(conn: I) -> HttpFuture<I> {
+/// Receive PREFACE and send SETTINGS frame with given settings
+pub fn server_handshake<I : AsyncRead + AsyncWrite + Send + 'static>(conn: I, settings: Vec<HttpSetting>)
+    -> HttpFuture<I>
+{
     let mut preface_buf = Vec::with_capacity(PREFACE.len());
     preface_buf.resize(PREFACE.len(), 0);
     let recv_preface = read_exact(conn, preface_buf)
@@ -304,14 +813,16 @@ pub fn server_handshake<I : AsyncRead + AsyncWrite + Send + 'static>(conn: I) ->
                 Ok((conn))
             } else {
                 if preface_buf[0] == 0x16 {
-                    Err(Error::InvalidFrame(format!("wrong preface, likely TLS: {:?}", BsDebug(&preface_buf))))
+                    warn!("wrong preface, likely TLS: {:?}", BsDebug(&preface_buf));
+                    Err(Error::TlsConnectionToPlaintextPort)
                 } else {
-                    Err(Error::InvalidFrame(format!("wrong preface: {:?}", BsDebug(&preface_buf))))
+                    Err(Error::InvalidFrame(InvalidFrameInfo::new(
+                        format!("wrong preface: {:?}", BsDebug(&preface_buf)))))
                 }
             })
         });
 
-    let send_settings = recv_preface.and_then(send_settings);
+    let send_settings = recv_preface.and_then(move |conn| send_settings(conn, settings));
 
     Box::new(send_settings)
 }
@@ -320,7 +831,452 @@ pub fn connect_and_handshake(lh: &reactor::Handle, addr: &SocketAddr) -> HttpFut
     let connect = TcpStream::connect(&addr, lh)
         .map_err(|e| e.into());
 
-    let handshake = connect.and_then(client_handshake);
+    let handshake = connect.and_then(|conn| client_handshake(conn, Vec::new()));
 
     Box::new(handshake)
 }
+
+/// Connect, send PREFACE and SETTINGS, then wait for server SETTINGS and acknowledge them.
+///
+/// Fails with `ConnectionTimeout` if all of that is not completed in given time.
+/// Resolves to the connection and SETTINGS frame received from server.
+pub fn connect_and_handshake_with_timeout(lh: &reactor::Handle, addr: &SocketAddr, timeout: Option<Duration>)
+    -> HttpFuture<(TcpStream, SettingsFrame)>
+{
+    let handshake = connect_and_handshake(lh, addr)
+        .and_then(|conn| recv_settings_frame_set(conn, DEFAULT_SETTINGS.max_frame_size))
+        .and_then(|(conn, settings)| {
+            send_frame(conn, SettingsFrame::new_ack()).map(move |conn| (conn, settings))
+        });
+    let handshake: HttpFuture<_> = Box::new(handshake);
+
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return handshake,
+    };
+
+    let timeout = match reactor::Timeout::new(timeout, lh) {
+        Ok(timeout) => timeout,
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+
+    let timeout = timeout
+        .map_err(Error::from)
+        .and_then(|()| future::err::<(TcpStream, SettingsFrame), _>(Error::ConnectionTimeout));
+
+    Box::new(handshake.select(timeout)
+        .map(|(r, _)| r)
+        .map_err(|(e, _)| e))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::io::Write;
+    use std::net;
+    use std::thread;
+    use std::time::Instant;
+
+    use futures::Future;
+
+    use solicit::frame::FrameIR;
+    use solicit::frame::pack_header;
+    use solicit::frame::data::DataFrame;
+    use solicit::frame::data::DataFlag;
+    use solicit::frame::ping::PingFrame;
+    use solicit::frame::ping::PING_FRAME_TYPE;
+    use solicit::frame::continuation::ContinuationFrame;
+    use solicit::frame::continuation::ContinuationFlag;
+    use solicit::frame::settings::SETTINGS_FRAME_TYPE;
+
+    use mock_io::MockIo;
+
+    use super::*;
+
+    #[test]
+    fn recv_raw_frame_truncated_payload() {
+        let mut buf = pack_header(&FrameHeader {
+            length: 100,
+            frame_type: PING_FRAME_TYPE,
+            flags: 0,
+            stream_id: 0,
+        }).to_vec();
+        buf.extend(&[1; 10]);
+        let mut read = io::Cursor::new(buf);
+
+        match recv_raw_frame(SyncRead(&mut read), 16384).wait() {
+            Err(Error::IncompleteFrame { expected: 100, got: 10 }) => {}
+            Err(e) => panic!("wrong error: {:?}", e),
+            Ok(..) => panic!("expecting error"),
+        }
+    }
+
+    #[test]
+    fn recv_raw_frame_truncated_header() {
+        let mut read = io::Cursor::new(vec![0, 0, 8, PING_FRAME_TYPE]);
+
+        match recv_raw_frame(SyncRead(&mut read), 16384).wait() {
+            Err(Error::IncompleteFrame { expected: 9, got: 4 }) => {}
+            Err(e) => panic!("wrong error: {:?}", e),
+            Ok(..) => panic!("expecting error"),
+        }
+    }
+
+    #[test]
+    fn recv_raw_frame_eof_at_frame_boundary() {
+        let mut read = io::Cursor::new(PingFrame::with_data(23).serialize_into_vec());
+
+        recv_raw_frame(SyncRead(&mut read), 16384).wait().expect("frame");
+        match recv_raw_frame(SyncRead(&mut read), 16384).wait() {
+            Err(Error::IoError(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            Err(e) => panic!("wrong error: {:?}", e),
+            Ok(..) => panic!("expecting error"),
+        }
+    }
+
+    #[test]
+    fn recv_raw_frame_with_limits_discards_oversized_data() {
+        let mut buf = Vec::new();
+        buf.extend(DataFrame::with_data(1, vec![17; 40000]).serialize_into_vec());
+        buf.extend(PingFrame::with_data(23).serialize_into_vec());
+        let mut read = io::Cursor::new(buf);
+
+        let limits = FrameSizeLimits::new(16384);
+
+        let frame = recv_raw_frame_with_limits(SyncRead(&mut read), limits).wait().unwrap().1;
+        match frame {
+            RecvRawFrame::Discarded(header) => {
+                assert_eq!(1, header.stream_id);
+                assert_eq!(40000, header.length);
+            }
+            RecvRawFrame::Frame(..) => panic!("expecting discarded frame"),
+        }
+
+        let frame = recv_raw_frame_with_limits(SyncRead(&mut read), limits).wait().unwrap().1;
+        match frame {
+            RecvRawFrame::Frame(frame) => assert_eq!(PING_FRAME_TYPE, frame.frame_type()),
+            RecvRawFrame::Discarded(..) => panic!("expecting PING frame"),
+        }
+    }
+
+    #[test]
+    fn recv_raw_frame_oversized_data_is_error() {
+        let buf = DataFrame::with_data(1, vec![17; 40000]).serialize_into_vec();
+        match recv_raw_frame_sync(&mut io::Cursor::new(buf), 16384) {
+            Err(Error::CodeError(ErrorCode::FrameSizeError)) => {}
+            r => panic!("expecting FRAME_SIZE_ERROR, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn recv_raw_frame_oversized_headers_is_error() {
+        let mut buf = DataFrame::with_data(1, vec![17; 40000]).serialize_into_vec();
+        buf[3] = HEADERS_FRAME_TYPE;
+        let limits = FrameSizeLimits::new(16384);
+        match recv_raw_frame_with_limits(SyncRead(&mut io::Cursor::new(buf)), limits).wait() {
+            Err(Error::CodeError(ErrorCode::FrameSizeError)) => {}
+            _ => panic!("expecting FRAME_SIZE_ERROR"),
+        }
+    }
+
+    #[test]
+    fn recv_raw_frame_pooled_reuses_buffer() {
+        let mut buf = Vec::new();
+        for i in 0..10000 {
+            buf.extend(PingFrame::with_data(i).serialize_into_vec());
+        }
+        let mut read = io::Cursor::new(buf);
+        let mut read = PooledRead::new(SyncRead(&mut read));
+
+        let mut first_ptr = None;
+        for i in 0..10000 {
+            let (r, frame) = recv_raw_frame_pooled(read, FrameSizeLimits::new(16384)).wait().unwrap();
+            read = r;
+            let frame = match frame {
+                RecvRawFrame::Frame(frame) => frame,
+                RecvRawFrame::Discarded(..) => panic!("expecting PING frame"),
+            };
+            assert_eq!(PING_FRAME_TYPE, frame.frame_type());
+
+            // frames dropped before next read, so all frames are slices
+            // of the same allocation
+            let ptr = frame.raw_content.as_ptr() as usize;
+            let first_ptr = *first_ptr.get_or_insert(ptr);
+            assert!(ptr >= first_ptr && ptr < first_ptr + RECV_CHUNK_SIZE, "frame {} is reallocated", i);
+        }
+    }
+
+    #[test]
+    fn recv_raw_frame_pooled_discards_oversized_data() {
+        let mut buf = Vec::new();
+        buf.extend(DataFrame::with_data(1, vec![17; 40000]).serialize_into_vec());
+        buf.extend(PingFrame::with_data(23).serialize_into_vec());
+        let mut read = io::Cursor::new(buf);
+        let read = PooledRead::new(SyncRead(&mut read));
+
+        let limits = FrameSizeLimits::new(16384);
+
+        let (read, frame) = recv_raw_frame_pooled(read, limits).wait().unwrap();
+        match frame {
+            RecvRawFrame::Discarded(header) => assert_eq!(40000, header.length),
+            RecvRawFrame::Frame(..) => panic!("expecting discarded frame"),
+        }
+
+        let (read, frame) = recv_raw_frame_pooled(read, limits).wait().unwrap();
+        match frame {
+            RecvRawFrame::Frame(frame) => assert_eq!(PING_FRAME_TYPE, frame.frame_type()),
+            RecvRawFrame::Discarded(..) => panic!("expecting PING frame"),
+        }
+
+        match recv_raw_frame_pooled(read, limits).wait() {
+            Err(Error::IoError(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            _ => panic!("expecting EOF"),
+        }
+    }
+
+    #[test]
+    fn recv_http_frame_join_cont_too_many_continuations() {
+        let mut buf = HeadersFrame::new(vec![0x82], 1).serialize_into_vec();
+        // flood is larger than read chunk
+        for _ in 0..10000 {
+            buf.extend(ContinuationFrame::new(vec![0x82], 1).serialize_into_vec());
+        }
+        let mut read = io::Cursor::new(buf);
+        match recv_http_frame_join_cont_pooled(PooledRead::new(SyncRead(&mut read)), 16384, DEFAULT_MAX_CONTINUATION_FRAMES).wait() {
+            Err(Error::CodeError(ErrorCode::EnhanceYourCalm)) => {}
+            r => panic!("expecting ENHANCE_YOUR_CALM, got {:?}", r.map(|_| ())),
+        }
+        // rejected without reading the whole flood
+        assert!(read.position() < read.get_ref().len() as u64);
+    }
+
+    #[test]
+    fn recv_http_frame_join_cont_skips_unknown() {
+        let unknown = RawFrame::from(&[0, 0, 1, 0xfa, 0, 0, 0, 0, 0, 17][..]);
+
+        let mut buf = unknown.serialize().to_vec();
+        buf.extend(PingFrame::with_data(23).serialize_into_vec());
+        let mut read = io::Cursor::new(buf);
+        match recv_http_frame_join_cont_pooled(PooledRead::new(SyncRead(&mut read)), 16384, DEFAULT_MAX_CONTINUATION_FRAMES).wait() {
+            Ok((_, HttpFrame::Ping(ping))) => assert_eq!(23, ping.opaque_data()),
+            r => panic!("expecting PING, got {:?}", r.map(|(_, f)| f)),
+        }
+
+        // unknown frame inside header block is an error
+        let mut buf = HeadersFrame::new(vec![0x82], 1).serialize_into_vec();
+        buf.extend(unknown.serialize().iter());
+        let mut read = io::Cursor::new(buf);
+        match recv_http_frame_join_cont_pooled(PooledRead::new(SyncRead(&mut read)), 16384, DEFAULT_MAX_CONTINUATION_FRAMES).wait() {
+            Err(Error::CodeError(ErrorCode::ProtocolError)) => {}
+            r => panic!("expecting PROTOCOL_ERROR, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn recv_http_frame_join_cont_unpooled() {
+        let mut buf = HeadersFrame::new(vec![0x82], 1).serialize_into_vec();
+        let mut continuation = ContinuationFrame::new(vec![0x84], 1);
+        continuation.set_flag(ContinuationFlag::EndHeaders);
+        buf.extend(continuation.serialize_into_vec());
+        let frames_len = buf.len() as u64;
+        buf.extend(PingFrame::with_data(23).serialize_into_vec());
+
+        let mut read = io::Cursor::new(buf);
+        match recv_http_frame_join_cont(SyncRead(&mut read), 16384, DEFAULT_MAX_CONTINUATION_FRAMES).wait() {
+            Ok((_, HttpFrame::Headers(h))) => assert_eq!(&[0x82, 0x84][..], &h.header_fragment[..]),
+            r => panic!("expecting HEADERS, got {:?}", r.map(|(_, f)| f)),
+        }
+        // nothing is read past the header block
+        assert_eq!(frames_len, read.position());
+    }
+
+    fn join_cont_protocol_error(frames: Vec<Vec<u8>>) {
+        let mut read = io::Cursor::new(frames.concat());
+        match recv_http_frame_join_cont_pooled(PooledRead::new(SyncRead(&mut read)), 16384, DEFAULT_MAX_CONTINUATION_FRAMES).wait() {
+            Err(Error::CodeError(ErrorCode::ProtocolError)) => {}
+            r => panic!("expecting PROTOCOL_ERROR, got {:?}", r.map(|(_, f)| f)),
+        }
+    }
+
+    #[test]
+    fn recv_http_frame_join_cont_headers_while_pending() {
+        join_cont_protocol_error(vec![
+            HeadersFrame::new(vec![0x82], 1).serialize_into_vec(),
+            HeadersFrame::new(vec![0x82], 3).serialize_into_vec(),
+        ]);
+    }
+
+    #[test]
+    fn recv_http_frame_join_cont_push_promise_while_pending() {
+        join_cont_protocol_error(vec![
+            HeadersFrame::new(vec![0x82], 1).serialize_into_vec(),
+            PushPromiseFrame::new(vec![0x82], 1, 2).serialize_into_vec(),
+        ]);
+    }
+
+    #[test]
+    fn recv_http_frame_join_cont_continuation_other_stream() {
+        join_cont_protocol_error(vec![
+            HeadersFrame::new(vec![0x82], 1).serialize_into_vec(),
+            ContinuationFrame::new(vec![0x82], 3).serialize_into_vec(),
+        ]);
+    }
+
+    #[test]
+    fn recv_http_frame_join_cont_continuation_without_headers() {
+        join_cont_protocol_error(vec![
+            ContinuationFrame::new(vec![0x82], 1).serialize_into_vec(),
+        ]);
+    }
+
+    #[test]
+    fn recv_http_frame_join_cont_other_frame_while_pending() {
+        join_cont_protocol_error(vec![
+            HeadersFrame::new(vec![0x82], 1).serialize_into_vec(),
+            PingFrame::with_data(1).serialize_into_vec(),
+        ]);
+    }
+
+    #[test]
+    fn recv_http_frame_join_cont_data_while_pending() {
+        join_cont_protocol_error(vec![
+            HeadersFrame::new(vec![0x82], 1).serialize_into_vec(),
+            DataFrame::with_data(1, &b"abcd"[..]).serialize_into_vec(),
+            ContinuationFrame::new(vec![0x82], 1).serialize_into_vec(),
+        ]);
+    }
+
+    #[test]
+    fn server_handshake_tls_client_hello() {
+        let (mut client, server) = MockIo::pair();
+        // TLS handshake record header followed by ClientHello
+        let mut client_hello = vec![0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00, 0x01, 0xfc, 0x03, 0x03];
+        client_hello.resize(PREFACE.len(), 0);
+        client.write_all(&client_hello).expect("write");
+        match server_handshake(server, Vec::new()).wait() {
+            Err(Error::TlsConnectionToPlaintextPort) => {}
+            r => panic!("expecting TlsConnectionToPlaintextPort, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn server_handshake_wrong_preface() {
+        let (mut client, server) = MockIo::pair();
+        client.write_all(b"PRI * HTTP/2.0\r\n\r\nXX\r\n\r\n").expect("write");
+        match server_handshake(server, Vec::new()).wait() {
+            Err(Error::InvalidFrame(..)) => {}
+            r => panic!("expecting InvalidFrame, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn connect_and_handshake_timeout() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // accept, but never send SETTINGS
+        let accept = thread::spawn(move || listener.accept().unwrap());
+
+        let mut lp = reactor::Core::new().unwrap();
+        let handshake = connect_and_handshake_with_timeout(
+            &lp.handle(), &addr, Some(Duration::from_millis(200)));
+
+        let start = Instant::now();
+        match lp.run(handshake) {
+            Err(Error::ConnectionTimeout) => {}
+            r => panic!("expecting timeout, got {:?}", r.map(|_| ())),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        accept.join().unwrap();
+    }
+
+    #[test]
+    fn recv_settings_frame_unexpected_frame() {
+        let mut read = io::Cursor::new(PingFrame::with_data(23).serialize_into_vec());
+        match recv_settings_frame(SyncRead(&mut read), 16384).wait() {
+            Err(Error::InvalidFrame(info)) => {
+                assert_eq!(Some(PING_FRAME_TYPE), info.frame_type);
+                assert_eq!(Some(0), info.stream_id);
+                assert_eq!(Some(8), info.length);
+                assert_eq!("unexpected frame, expected SETTINGS, got Ping", info.to_string());
+            }
+            r => panic!("expecting InvalidFrame, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn recv_settings_frame_invalid_value() {
+        fn recv(setting: &[u8]) -> Result<SettingsFrame> {
+            let mut buf = Vec::new();
+            buf.extend(&[0, 0, setting.len() as u8, SETTINGS_FRAME_TYPE, 0, 0, 0, 0, 0]);
+            buf.extend(setting);
+            recv_settings_frame(SyncRead(&mut io::Cursor::new(buf)), 16384).wait().map(|(_, f)| f)
+        }
+
+        assert!(recv(&[0, 2, 0, 0, 0, 0]).is_ok());
+
+        let cases: &[(&[u8], ErrorCode)] = &[
+            (&[0, 2, 0, 0, 0, 2], ErrorCode::ProtocolError),
+            (&[0, 4, 0x80, 0, 0, 0], ErrorCode::FlowControlError),
+            (&[0, 5, 0, 0, 0x3f, 0xff], ErrorCode::ProtocolError),
+            (&[0, 5, 1, 0, 0, 0], ErrorCode::ProtocolError),
+        ];
+        for &(setting, expected) in cases {
+            match recv(setting) {
+                Err(Error::CodeError(code)) => assert_eq!(expected, code),
+                r => panic!("expecting {:?}, got {:?}", expected, r.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn recv_raw_frame_chunked_large_data() {
+        let payload: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+        let mut buf = Vec::new();
+        let mut frame = DataFrame::with_data(1, payload.clone());
+        frame.set_flag(DataFlag::EndStream);
+        buf.extend(frame.serialize_into_vec());
+        buf.extend(PingFrame::with_data(23).serialize_into_vec());
+        let mut read = io::Cursor::new(buf);
+
+        let chunks = recv_raw_frame_chunked(SyncRead(&mut read), 0xffffff, 16 * 1024)
+            .take(65).collect().wait().unwrap();
+
+        let mut data = Vec::new();
+        for (i, chunk) in chunks[..64].iter().enumerate() {
+            match chunk {
+                &RawFrameChunk::Data(stream_id, ref bytes, end) => {
+                    assert_eq!(1, stream_id);
+                    assert_eq!(16 * 1024, bytes.len());
+                    assert_eq!(i == 63, end);
+                    data.extend_from_slice(&bytes);
+                }
+                &RawFrameChunk::Frame(..) => panic!("expecting DATA chunk"),
+            }
+        }
+        assert!(data == payload);
+
+        match chunks[64] {
+            RawFrameChunk::Frame(ref frame) => assert_eq!(PING_FRAME_TYPE, frame.frame_type()),
+            RawFrameChunk::Data(..) => panic!("expecting PING frame"),
+        }
+    }
+
+    #[test]
+    fn recv_raw_frame_chunked_padded_data() {
+        let mut frame = DataFrame::with_data(3, &b"abcdefghij"[..]);
+        frame.set_padding(5);
+        let mut read = io::Cursor::new(frame.serialize_into_vec());
+
+        let chunks = recv_raw_frame_chunked(SyncRead(&mut read), 16384, 4)
+            .take(3).collect().wait().unwrap();
+
+        let data: Vec<u8> = chunks.iter().flat_map(|c| match c {
+            &RawFrameChunk::Data(3, ref bytes, false) => bytes.to_vec(),
+            _ => panic!("expecting DATA chunk"),
+        }).collect();
+        assert_eq!(&b"abcdefghij"[..], &data[..]);
+    }
+}
@@ -244,6 +244,10 @@ pub enum HttpFrameConn {
     Ping(PingFrame),
     Goaway(GoawayFrame),
     WindowUpdate(WindowUpdateFrame),
+    Origin(OriginFrame),
+    /// ALTSVC frame does not affect stream state, so it is processed
+    /// by connection even if associated with a stream
+    AltSvc(AltSvcFrame),
 }
 
 impl HttpFrameConn {
@@ -254,6 +258,8 @@ impl HttpFrameConn {
             HttpFrameConn::Ping(f) => HttpFrame::Ping(f),
             HttpFrameConn::Goaway(f) => HttpFrame::Goaway(f),
             HttpFrameConn::WindowUpdate(f) => HttpFrame::WindowUpdate(f),
+            HttpFrameConn::Origin(f) => HttpFrame::Origin(f),
+            HttpFrameConn::AltSvc(f) => HttpFrame::AltSvc(f),
         }
     }
 }
@@ -284,6 +290,8 @@ impl HttpFrameClassified {
                 }
             },
             HttpFrame::Continuation(f) => HttpFrameClassified::Stream(HttpFrameStream::Continuation(f)),
+            HttpFrame::Origin(f) => HttpFrameClassified::Conn(HttpFrameConn::Origin(f)),
+            HttpFrame::AltSvc(f) => HttpFrameClassified::Conn(HttpFrameConn::AltSvc(f)),
             HttpFrame::Unknown(f) => HttpFrameClassified::Unknown(f),
         }
     }
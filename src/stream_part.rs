@@ -2,10 +2,12 @@ use std::io;
 use std::panic;
 
 use futures::Poll;
+use futures::Future;
 use futures::stream;
 use futures::stream::Stream;
 
 use bytes::Bytes;
+use bytes::BytesMut;
 
 use error;
 
@@ -26,6 +28,7 @@ pub enum HttpStreamPartContent {
 }
 
 /// Stream frame content with END_STREAM flag
+#[derive(Debug)]
 pub struct HttpStreamPart {
     pub content: HttpStreamPartContent,
     /// END_STREAM
@@ -129,6 +132,21 @@ impl HttpPartStream {
         }))
     }
 
+    /// Concatenate `DATA` frames of the stream, trailers are ignored.
+    ///
+    /// Fails with `Error::BodyTooLarge` when body exceeds `max_size` bytes.
+    pub fn collect_body(self, max_size: u64) -> HttpFutureSend<Bytes> {
+        Box::new(self.filter_data()
+            .fold(BytesMut::new(), move |mut body, data| {
+                if (body.len() + data.len()) as u64 > max_size {
+                    return Err(error::Error::BodyTooLarge(max_size));
+                }
+                body.extend_from_slice(&data);
+                Ok(body)
+            })
+            .map(BytesMut::freeze))
+    }
+
     /// Wrap a stream with `catch_unwind` combinator.
     /// Transform panic into `error::Error`
     pub fn catch_unwind(self) -> HttpPartStream {
@@ -1,8 +1,17 @@
 //! Tests for client.
 
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net;
 use std::str;
 use std::thread;
 use std::time::Duration;
+use std::time::Instant;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 
 extern crate bytes;
 extern crate httpbis;
@@ -13,16 +22,45 @@ extern crate tokio_tls;
 #[macro_use]
 extern crate log;
 extern crate env_logger;
+#[cfg(feature = "gzip")]
+extern crate flate2;
 
 use bytes::Bytes;
 
 use futures::Future;
+use futures::stream::Stream;
 
 mod test_misc;
 
 use httpbis::solicit::header::*;
+use httpbis::solicit::session::StreamState;
+use httpbis::solicit::DEFAULT_SETTINGS;
+use httpbis::solicit::connection::HttpFrame;
+use httpbis::solicit::frame::Frame;
+use httpbis::solicit::frame::RawFrame;
+use httpbis::solicit::frame::settings::SETTINGS_FRAME_TYPE;
+use httpbis::solicit::frame::headers::HEADERS_FRAME_TYPE;
+use httpbis::solicit::frame::data::DATA_FRAME_TYPE;
+use httpbis::solicit::frame::data::DataFrame;
+use httpbis::solicit::frame::data::DataFlag;
+use httpbis::solicit::frame::FrameHeader;
+use httpbis::solicit::frame::pack_header;
+use httpbis::frame_observer::Direction;
+use httpbis::stream_part::HttpStreamPart;
+use httpbis::stream_part::HttpStreamPartContent;
+use httpbis::solicit::frame::ping::PingFrame;
+use httpbis::solicit::frame::settings::SettingsFrame;
+use httpbis::solicit::frame::settings::HttpSetting;
+use httpbis::solicit::frame::window_update::WindowUpdateFrame;
+use httpbis::solicit::frame::goaway::GoawayFrame;
+use httpbis::solicit::frame::origin::OriginFrame;
+use httpbis::solicit::frame::altsvc::AltSvcFrame;
+use httpbis::solicit::frame::headers::HeadersFlag;
+use httpbis::solicit::frame::headers::StreamDependency;
+use httpbis::solicit::StreamId;
 use httpbis::error::Error;
 use httpbis::error::ErrorCode;
+use httpbis::error::GoAwayInfo;
 use httpbis::*;
 use httpbis::for_test::*;
 
@@ -66,6 +104,50 @@ fn stream_count() {
     assert_eq!(0, state.streams.len(), "{:?}", state);
 }
 
+#[test]
+fn metrics() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    for &stream_id in &[1, 3] {
+        let req = client.start_post("/foobar", "localhost", Bytes::from(&b"xxyy"[..])).collect();
+        server_tester.recv_frame_headers_check(stream_id, false);
+        server_tester.recv_frame_data_check(stream_id, true);
+        server_tester.send_headers(stream_id, Headers::ok_200(), false);
+        server_tester.send_data(stream_id, b"aabb", true);
+        req.wait().expect("r");
+    }
+
+    let req = client.start_get("/reset", "localhost").collect();
+    server_tester.recv_message(5);
+    server_tester.send_rst(5, ErrorCode::Cancel);
+    assert!(req.wait().is_err());
+
+    // wait for event loop to process everything
+    assert_eq!(0, client.dump_state().wait().expect("state").streams.len());
+
+    let metrics: Metrics = client.metrics();
+    assert_eq!(3, metrics.frames_sent(HEADERS_FRAME_TYPE));
+    assert_eq!(3, metrics.frames_sent(DATA_FRAME_TYPE));
+    assert_eq!(2, metrics.frames_received(HEADERS_FRAME_TYPE));
+    assert_eq!(2, metrics.frames_received(DATA_FRAME_TYPE));
+    assert_eq!(1, metrics.rst_stream_received());
+    assert_eq!(0, metrics.rst_stream_sent());
+    assert_eq!(3, metrics.streams_opened);
+    assert_eq!(3, metrics.streams_closed);
+    assert_eq!(0, metrics.open_streams());
+    assert!(metrics.bytes_sent > 0);
+    assert!(metrics.bytes_received > 0);
+}
+
 #[test]
 fn rst_is_error() {
     env_logger::init().ok();
@@ -82,14 +164,14 @@ fn rst_is_error() {
     let req = client.start_get("/fgfg", "localhost").collect();
 
     let get = server_tester.recv_message(1);
-    assert_eq!("GET", get.headers.method());
+    assert_eq!(Some("GET"), get.headers.method());
 
     server_tester.send_headers(1, Headers::ok_200(), false);
     server_tester.send_rst(1, ErrorCode::InadequateSecurity);
 
     match req.wait() {
         Ok(..) => panic!("expected error"),
-        Err(Error::CodeError(ErrorCode::InadequateSecurity)) => {},
+        Err(Error::StreamReset(1, ErrorCode::InadequateSecurity)) => {},
         Err(e) => panic!("wrong error: {:?}", e),
     }
 
@@ -97,6 +179,91 @@ fn rst_is_error() {
     assert_eq!(0, state.streams.len(), "{:?}", state);
 }
 
+#[test]
+fn rst_does_not_affect_other_streams() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req1 = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+
+    let req3 = client.start_get("/3", "localhost").collect();
+    server_tester.recv_message(3);
+
+    server_tester.send_rst(1, ErrorCode::Cancel);
+
+    match req1.wait() {
+        Err(Error::StreamReset(1, ErrorCode::Cancel)) => {},
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+
+    server_tester.send_headers(3, Headers::ok_200(), false);
+    server_tester.send_data(3, b"ok", true);
+
+    let resp = req3.wait().expect("stream 3");
+    assert_eq!(&b"ok"[..], &resp.body[..]);
+}
+
+#[test]
+fn goaway_is_connection_error() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+
+    server_tester.send_frame(GoawayFrame::with_debug_data(
+        0, ErrorCode::EnhanceYourCalm, Bytes::from("go away")));
+
+    match req.wait() {
+        Err(Error::ConnectionError(ErrorCode::EnhanceYourCalm, ref debug))
+            if debug == &Bytes::from("go away") => {},
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn flow_control_error_is_connection_error() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+
+    let mut settings = SettingsFrame::new();
+    settings.add_setting(HttpSetting::InitialWindowSize(0x80000000));
+    server_tester.send_frame(settings);
+
+    match req.wait() {
+        Err(Error::ConnectionError(ErrorCode::FlowControlError, _)) => {},
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+}
+
 #[test]
 fn client_call_dropped() {
     env_logger::init().ok();
@@ -117,6 +284,9 @@ fn client_call_dropped() {
 
         drop(req);
 
+        server_tester.recv_rst_frame_check(1, ErrorCode::Cancel);
+
+        // response sent before server processed RST_STREAM is ignored
         server_tester.send_headers(1, Headers::ok_200(), true);
     }
 
@@ -125,7 +295,7 @@ fn client_call_dropped() {
         server_tester.recv_message(3);
         server_tester.send_headers(3, Headers::ok_200(), true);
         let resp = req.wait().expect("OK");
-        assert_eq!(200, resp.headers.status());
+        assert_eq!(Some(200), resp.headers.status());
     }
 
     let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
@@ -150,7 +320,7 @@ fn reconnect_on_disconnect() {
         server_tester.recv_message(1);
         server_tester.send_headers(1, Headers::ok_200(), true);
         let resp = req.wait().expect("OK");
-        assert_eq!(200, resp.headers.status());
+        assert_eq!(Some(200), resp.headers.status());
     }
 
     // drop server connection
@@ -171,7 +341,73 @@ fn reconnect_on_disconnect() {
         server_tester.recv_message(1);
         server_tester.send_headers(1, Headers::ok_200(), true);
         let resp = req.wait().expect("OK");
-        assert_eq!(200, resp.headers.status());
+        assert_eq!(Some(200), resp.headers.status());
+    }
+}
+
+#[test]
+fn retry_idempotent_on_disconnect() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.retry_idempotent = Some(true);
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let req = {
+        let mut server_tester = server.accept();
+        server_tester.recv_preface();
+        server_tester.settings_xchg();
+
+        let req = client.start_get("/111", "localhost").collect();
+        server_tester.recv_message(1);
+
+        // connection is dropped before any response is sent
+        req
+    };
+
+    // retry is performed when response is polled
+    let resp = thread::spawn(move || req.wait());
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg_but_ack();
+
+    let get = server_tester.recv_message(1);
+    assert_eq!(Some("/111"), get.headers.path());
+    server_tester.send_headers(1, Headers::ok_200(), true);
+
+    let resp = resp.join().expect("join").expect("OK");
+    assert_eq!(Some(200), resp.headers.status());
+}
+
+#[test]
+fn post_is_not_retried() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.retry_idempotent = Some(true);
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_post("/111", "localhost", Bytes::from("abc")).collect();
+    server_tester.recv_message(1);
+
+    drop(server_tester);
+
+    match req.wait() {
+        Err(Error::ConnectionDied(..)) => {},
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
     }
 }
 
@@ -193,7 +429,7 @@ fn reconnect_on_goaway() {
         server_tester.recv_message(1);
         server_tester.send_headers(1, Headers::ok_200(), true);
         let resp = req.wait().expect("OK");
-        assert_eq!(200, resp.headers.status());
+        assert_eq!(Some(200), resp.headers.status());
 
         server_tester.send_goaway(1);
 
@@ -214,6 +450,2526 @@ fn reconnect_on_goaway() {
         server_tester.recv_message(1);
         server_tester.send_headers(1, Headers::ok_200(), true);
         let resp = req.wait().expect("OK");
-        assert_eq!(200, resp.headers.status());
+        assert_eq!(Some(200), resp.headers.status());
+    }
+}
+
+#[test]
+fn reconnect_on_stream_ids_exhausted() {
+    env_logger::init().ok();
+
+    const MAX_STREAM_ID: StreamId = 0x7fffffff;
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    fast_forward_stream_ids(&client, MAX_STREAM_ID - 2).wait().expect("fast_forward");
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(MAX_STREAM_ID - 2);
+    server_tester.send_headers(MAX_STREAM_ID - 2, Headers::ok_200(), true);
+    assert_eq!(Some(200), req.wait().expect("r1").headers.status());
+
+    // last stream id
+    let req = client.start_get("/2", "localhost").collect();
+    server_tester.recv_frame_headers_check(MAX_STREAM_ID, false);
+
+    let goaway = server_tester.recv_frame_goaway();
+    assert_eq!(ErrorCode::NoError, goaway.error_code());
+
+    server_tester.recv_frame_data_check_empty_end(MAX_STREAM_ID);
+
+    // started on new connection
+    let req3 = client.start_get("/3", "localhost").collect();
+
+    // old connection is closed after remaining stream completes
+    server_tester.send_headers(MAX_STREAM_ID, Headers::ok_200(), true);
+    assert_eq!(Some(200), req.wait().expect("r2").headers.status());
+    server_tester.recv_eof();
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg_but_ack();
+
+    let get = server_tester.recv_message(1);
+    assert_eq!(Some("/3"), get.headers.path());
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(Some(200), req3.wait().expect("r3").headers.status());
+}
+
+#[test]
+fn settings_ack_timeout() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.common.settings_ack_timeout = Some(Duration::from_millis(200));
+
+    let start = Instant::now();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    // client SETTINGS are received, but never acknowledged
+    server_tester.recv_frame_settings_set();
+    server_tester.send_frame(SettingsFrame::new());
+    server_tester.recv_frame_settings_ack();
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+
+    server_tester.recv_goaway_eof(ErrorCode::SettingsTimeout);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    assert!(req.wait().is_err());
+}
+
+#[test]
+fn settings_ack_sent_before_other_frames() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    let mut settings = SettingsFrame::new();
+    settings.settings.push(HttpSetting::InitialWindowSize(0));
+    server_tester.send_frame(settings);
+    server_tester.recv_frame_settings_set();
+    server_tester.send_frame(SettingsFrame::new_ack());
+    server_tester.recv_frame_settings_ack();
+
+    // body is blocked by zero window
+    let req = client.start_post("/1", "localhost", Bytes::from("abcd")).collect();
+    server_tester.recv_frame_headers_check(1, false);
+
+    let mut settings = SettingsFrame::new();
+    settings.settings.push(HttpSetting::InitialWindowSize(100));
+    server_tester.send_frame(settings);
+    server_tester.send_frame(PingFrame::with_data(11));
+
+    // ACK precedes DATA unblocked by SETTINGS and response to subsequent PING
+    assert!(server_tester.recv_frame_settings().is_ack());
+    let mut data = Vec::new();
+    let mut ping_ack = false;
+    while !ping_ack || data.is_empty() {
+        match server_tester.recv_frame() {
+            HttpFrame::Ping(ping) => {
+                assert!(ping.is_ack());
+                ping_ack = true;
+            }
+            HttpFrame::Data(frame) => data.extend_from_slice(&frame.data),
+            f => panic!("unexpected frame: {:?}", f),
+        }
+    }
+    assert_eq!(b"abcd", &data[..]);
+
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(Some(200), req.wait().expect("r").headers.status());
+}
+
+#[test]
+fn request_headers_hook() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.common.headers_hook = Some(Arc::new(|headers: &mut Headers| headers.add("x-trace-id", "17")));
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/1", "localhost").collect();
+    let get = server_tester.recv_message(1);
+    assert_eq!("17", get.headers.get("x-trace-id"));
+    assert_eq!(Some("/1"), get.headers.path());
+
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(Some(200), req.wait().expect("r").headers.status());
+}
+
+#[test]
+fn data_padding_received() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), false);
+
+    let mut frame = DataFrame::with_data(1, &b"abc"[..]);
+    frame.set_padding(7);
+    frame.set_flag(DataFlag::EndStream);
+    server_tester.send_frame(frame);
+
+    assert_eq!(&b"abc"[..], &req.wait().expect("r").body[..]);
+
+    let req = client.start_get("/2", "localhost").collect();
+    server_tester.recv_message(3);
+    server_tester.send_headers(3, Headers::ok_200(), false);
+
+    // pad length is not less than payload length
+    let mut frame = pack_header(&FrameHeader {
+        length: 3,
+        frame_type: DATA_FRAME_TYPE,
+        // PADDED
+        flags: 0x8,
+        stream_id: 3,
+    }).to_vec();
+    frame.extend_from_slice(&[3, 0, 0]);
+    server_tester.send_raw(&frame);
+
+    server_tester.recv_goaway_eof(ErrorCode::ProtocolError);
+    assert!(req.wait().is_err());
+}
+
+#[test]
+fn data_padding_sent() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.common.data_padding = Some(10);
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let _req = client.start_post("/1", "localhost", Bytes::from("abcd"));
+    server_tester.recv_frame_headers_check(1, false);
+
+    let frame = server_tester.recv_frame_data();
+    assert!(frame.is_padded());
+    assert_eq!(&b"abcd"[..], &frame.data[..]);
+    assert_eq!(4 + 1 + 10, frame.payload_len());
+
+    // padding counts against flow control
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(DEFAULT_SETTINGS.initial_window_size as i32 - 15, state.streams[&1].out_window_size);
+    assert_eq!(4, state.streams[&1].data_sent);
+}
+
+#[test]
+fn peer_settings() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    let mut settings = SettingsFrame::new();
+    settings.settings.push(HttpSetting::MaxConcurrentStreams(7));
+    server_tester.send_frame(settings);
+    server_tester.recv_frame_settings_set();
+    server_tester.send_frame(SettingsFrame::new_ack());
+    server_tester.recv_frame_settings_ack();
+
+    let settings: PeerSettings = client.peer_settings().wait().expect("settings");
+    assert_eq!(7, settings.max_concurrent_streams);
+    assert_eq!(DEFAULT_SETTINGS.max_frame_size, settings.max_frame_size);
+
+    // updated by subsequent SETTINGS
+    let mut settings = SettingsFrame::new();
+    settings.settings.push(HttpSetting::MaxFrameSize(20000));
+    server_tester.send_frame(settings);
+    assert!(server_tester.recv_frame_settings().is_ack());
+
+    let settings = client.peer_settings().wait().expect("settings");
+    assert_eq!(7, settings.max_concurrent_streams);
+    assert_eq!(20000, settings.max_frame_size);
+}
+
+#[test]
+fn max_response_body_size() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.max_response_body_size = Some(15);
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"0123456789", false);
+    server_tester.send_data(1, b"0123456789", false);
+
+    server_tester.recv_rst_frame_check(1, ErrorCode::Cancel);
+
+    match req.wait() {
+        Err(Error::BodyTooLarge(15)) => {}
+        r => panic!("expecting BodyTooLarge, got {:?}", r.map(|_| ())),
+    }
+
+    // body within limit
+    let req = client.start_get("/2", "localhost").collect();
+    server_tester.recv_message(3);
+    server_tester.send_headers(3, Headers::ok_200(), false);
+    server_tester.send_data(3, b"012345678901234", true);
+    assert_eq!(15, req.wait().expect("r").body.len());
+}
+
+#[test]
+fn ping() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let ping = client.ping();
+
+    let frame = server_tester.recv_frame_ping();
+    assert!(!frame.is_ack());
+
+    // unmatched ACK is ignored
+    server_tester.send_frame(PingFrame::new_ack(frame.opaque_data().wrapping_add(100)));
+    server_tester.send_frame(PingFrame::new_ack(frame.opaque_data()));
+
+    ping.wait().expect("ping");
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams.len(), "{:?}", state);
+}
+
+#[test]
+fn keepalive_timeout() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.keepalive_interval = Some(Duration::from_millis(50));
+    conf.keepalive_timeout = Some(Duration::from_millis(50));
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    // never send ACK
+    let frame = server_tester.recv_frame_ping();
+    assert!(!frame.is_ack());
+
+    server_tester.recv_eof();
+
+    // waiting for client connection to die
+    while let Ok(_) = client.dump_state().wait() {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn goaway_drains_streams() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    {
+        let mut server_tester = server.accept();
+        server_tester.recv_preface();
+        server_tester.settings_xchg();
+
+        let req1 = client.start_get("/1", "localhost").collect();
+        server_tester.recv_message(1);
+        let req3 = client.start_get("/3", "localhost").collect();
+        server_tester.recv_message(3);
+        let req5 = client.start_get("/5", "localhost").collect();
+        server_tester.recv_message(5);
+
+        server_tester.send_headers(1, Headers::ok_200(), false);
+        server_tester.send_headers(3, Headers::ok_200(), false);
+
+        server_tester.send_goaway(3);
+
+        server_tester.send_data(1, b"one", true);
+        server_tester.send_data(3, b"three", true);
+
+        let resp1 = req1.wait().expect("1");
+        assert_eq!(Some(200), resp1.headers.status());
+        assert_eq!(&b"one"[..], &resp1.body[..]);
+
+        let resp3 = req3.wait().expect("3");
+        assert_eq!(Some(200), resp3.headers.status());
+        assert_eq!(&b"three"[..], &resp3.body[..]);
+
+        assert!(req5.wait().is_err());
+
+        server_tester.recv_eof();
+    }
+
+    {
+        let req = client.start_get("/111", "localhost").collect();
+
+        let mut server_tester = server.accept();
+        server_tester.recv_preface();
+        server_tester.settings_xchg_but_ack();
+
+        server_tester.recv_message(1);
+        server_tester.send_headers(1, Headers::ok_200(), true);
+        let resp = req.wait().expect("OK");
+        assert_eq!(Some(200), resp.headers.status());
+    }
+}
+
+#[test]
+fn initial_window_size() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.initial_window_size = Some(1_000_000);
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.send_frame(SettingsFrame::new());
+    let settings = server_tester.recv_frame_settings_set();
+    assert!(settings.settings.contains(&HttpSetting::InitialWindowSize(1_000_000)));
+    server_tester.send_frame(SettingsFrame::new_ack());
+    server_tester.recv_frame_settings_ack();
+
+    let req = client.start_get("/large", "localhost").collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), false);
+
+    // more than default window, without waiting for WINDOW_UPDATE
+    let chunk = [17; 16384];
+    for _ in 0..6 {
+        server_tester.send_data(1, &chunk, false);
+    }
+    server_tester.send_data(1, b"", true);
+
+    let resp = req.wait().expect("OK");
+    assert_eq!(6 * 16384, resp.body.len());
+}
+
+#[test]
+fn initial_window_size_too_large() {
+    env_logger::init().ok();
+
+    let mut conf = ClientConf::new();
+    conf.initial_window_size = Some(0x80000000);
+
+    match Client::new("::1", 1, false, conf) {
+        Err(Error::InvalidSetting(HttpSetting::InitialWindowSize(0x80000000))) => {}
+        Err(e) => panic!("wrong error: {:?}", e),
+        Ok(_) => panic!("expecting error"),
+    }
+}
+
+#[test]
+fn extra_settings_invalid_value() {
+    env_logger::init().ok();
+
+    let mut conf = ClientConf::new();
+    conf.common.extra_settings = vec![HttpSetting::Unknown(5, 100)];
+
+    match Client::new("::1", 1, false, conf) {
+        Err(Error::InvalidSetting(HttpSetting::Unknown(5, 100))) => {}
+        Err(e) => panic!("wrong error: {:?}", e),
+        Ok(_) => panic!("expecting error"),
+    }
+}
+
+#[test]
+fn empty_data_with_end_stream() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let mut parts = client.start_get("/empty", "localhost").into_stream_flag().wait();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"abcd", false);
+
+    match parts.next().expect("headers").expect("headers") {
+        HttpStreamPart { content: HttpStreamPartContent::Headers(h), last: false } =>
+            assert_eq!(Some(200), h.status()),
+        p => panic!("unexpected: {:?}", p),
+    }
+    match parts.next().expect("data").expect("data") {
+        HttpStreamPart { content: HttpStreamPartContent::Data(d), last: false } =>
+            assert_eq!(&b"abcd"[..], &d[..]),
+        p => panic!("unexpected: {:?}", p),
+    }
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    let stream = state.streams.get(&1).expect("stream 1");
+    assert_eq!(StreamState::HalfClosedLocal, stream.state);
+    assert_eq!(4, stream.data_received);
+    assert_eq!(DEFAULT_SETTINGS.initial_window_size as i32 - 4, stream.in_window_size);
+
+    // costs nothing in flow control, but closes the stream
+    server_tester.send_data(1, b"", true);
+
+    match parts.next().expect("end").expect("end") {
+        HttpStreamPart { content: HttpStreamPartContent::Data(d), last: true } =>
+            assert!(d.is_empty()),
+        p => panic!("unexpected: {:?}", p),
+    }
+    assert!(parts.next().is_none());
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams.len(), "{:?}", state);
+
+    // connection is still usable
+    let req = client.start_get("/next", "localhost").collect();
+    server_tester.recv_message(3);
+    server_tester.send_headers(3, Headers::ok_200(), false);
+    server_tester.send_data(3, b"", true);
+    let resp = req.wait().expect("OK");
+    assert_eq!(Some(200), resp.headers.status());
+    assert!(resp.body.is_empty());
+}
+
+#[test]
+fn data_frames_split_by_max_frame_size() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let mut settings = SettingsFrame::new();
+    settings.add_setting(HttpSetting::InitialWindowSize(200_000));
+    server_tester.send_recv_settings(settings);
+    server_tester.send_frame(WindowUpdateFrame::for_connection(200_000));
+
+    let body = vec![17; 100_000];
+    let req = client.start_post("/upload", "localhost", Bytes::from(body)).collect();
+
+    server_tester.recv_frame_headers_check(1, false);
+
+    let mut frame_sizes = Vec::new();
+    loop {
+        let data = server_tester.recv_frame_data();
+        assert_eq!(1, data.stream_id);
+        frame_sizes.push(data.data.len());
+        if data.is_end_of_stream() {
+            break;
+        }
+    }
+
+    assert_eq!(100_000, frame_sizes.iter().sum::<usize>());
+    assert!(frame_sizes.len() > 1);
+    assert!(frame_sizes.iter().all(|&s| s <= 16384), "{:?}", frame_sizes);
+    assert_eq!(16384, frame_sizes[0]);
+
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    req.wait().expect("OK");
+}
+
+#[test]
+fn conn_window_update() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.common.conn_window_update_threshold = Some(16384);
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/large", "localhost").collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), false);
+
+    // more than default window
+    let chunk = [17; 16384];
+    for _ in 0..7 {
+        server_tester.send_data(1, &chunk, false);
+    }
+    server_tester.send_data(1, b"", true);
+
+    let resp = req.wait().expect("OK");
+    assert_eq!(7 * 16384, resp.body.len());
+
+    let mut conn_increments = Vec::new();
+    while conn_increments.len() < 7 {
+        if let HttpFrame::WindowUpdate(f) = server_tester.recv_frame() {
+            if f.get_stream_id() == 0 {
+                conn_increments.push(f.increment());
+            }
+        }
+    }
+    assert_eq!(vec![16384; 7], conn_increments);
+}
+
+#[test]
+fn frame_observer() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let observed = Arc::new(Mutex::new(Vec::new()));
+
+    let mut conf = ClientConf::new();
+    conf.common.frame_observer = Some({
+        let observed = observed.clone();
+        Arc::new(move |frame: &RawFrame, direction: Direction| {
+            observed.lock().unwrap().push((frame.frame_type(), direction));
+        })
+    });
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_post("/foobar", "localhost", Bytes::from(&b"xxyy"[..])).collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"aabb", true);
+    req.wait().expect("OK");
+
+    let observed = observed.lock().unwrap().clone();
+
+    let sent: Vec<u8> = observed.iter()
+        .filter(|&&(_, d)| d == Direction::Sent)
+        .map(|&(t, _)| t)
+        .collect();
+    assert_eq!(SETTINGS_FRAME_TYPE, sent[0]);
+    let pos_headers = sent.iter().position(|&t| t == HEADERS_FRAME_TYPE).expect("HEADERS");
+    let pos_data = sent.iter().position(|&t| t == DATA_FRAME_TYPE).expect("DATA");
+    assert!(pos_headers < pos_data);
+
+    let received: Vec<u8> = observed.iter()
+        .filter(|&&(_, d)| d == Direction::Received)
+        .map(|&(t, _)| t)
+        .collect();
+    assert_eq!(SETTINGS_FRAME_TYPE, received[0]);
+    let pos_headers = received.iter().position(|&t| t == HEADERS_FRAME_TYPE).expect("HEADERS");
+    let pos_data = received.iter().position(|&t| t == DATA_FRAME_TYPE).expect("DATA");
+    assert!(pos_headers < pos_data);
+}
+
+#[test]
+fn last_goaway() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    assert_eq!(None, client.last_goaway().wait().expect("last_goaway"));
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+
+    server_tester.send_frame(GoawayFrame::with_debug_data(
+        0, ErrorCode::EnhanceYourCalm, Bytes::from("rate limited")));
+
+    assert!(req.wait().is_err());
+
+    let expected = GoAwayInfo {
+        last_stream_id: 0,
+        error_code: ErrorCode::EnhanceYourCalm,
+        debug_data: Bytes::from("rate limited"),
+    };
+    assert_eq!(Some(expected), client.last_goaway().wait().expect("last_goaway"));
+}
+
+#[test]
+fn stream_priority() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get_with_priority("/1", "localhost", StreamPriority::new(0, 41, true))
+        .collect();
+
+    let headers = server_tester.recv_frame_headers();
+    assert_eq!(1, headers.stream_id);
+    assert!(headers.flags.is_set(HeadersFlag::Priority));
+    assert_eq!(Some(StreamDependency::new(0, 41, true)), headers.stream_dep);
+    server_tester.recv_frame_data_check_empty_end(1);
+
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(Some(200), req.wait().expect("r").headers.status());
+
+    // priority is not sent unless requested
+    let req = client.start_get("/3", "localhost").collect();
+    let headers = server_tester.recv_frame_headers();
+    assert_eq!(3, headers.stream_id);
+    assert!(!headers.flags.is_set(HeadersFlag::Priority));
+    assert_eq!(None, headers.stream_dep);
+    server_tester.recv_frame_data_check_empty_end(3);
+
+    server_tester.send_headers(3, Headers::ok_200(), true);
+    assert_eq!(Some(200), req.wait().expect("r").headers.status());
+}
+
+#[test]
+fn response_trailers() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"body", false);
+    let mut trailers = Headers::new();
+    trailers.add("grpc-status", "0");
+    server_tester.send_headers(1, trailers, true);
+
+    let resp = req.wait().expect("resp");
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(None, resp.headers.get_opt("grpc-status"));
+    assert_eq!(&b"body"[..], &resp.body[..]);
+    assert_eq!("0", resp.trailers.expect("trailers").get("grpc-status"));
+
+    // pseudo-headers are not allowed in trailers
+    let req = client.start_get("/3", "localhost").collect();
+    server_tester.recv_message(3);
+
+    server_tester.send_headers(3, Headers::ok_200(), false);
+    server_tester.send_data(3, b"body", false);
+    server_tester.send_headers(3, Headers::ok_200(), true);
+
+    server_tester.recv_rst_frame_check(3, ErrorCode::ProtocolError);
+    assert!(req.wait().is_err());
+}
+
+#[test]
+fn request_timeout() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let start = Instant::now();
+    let req = client.start_get_with_timeout("/1", "localhost", Duration::from_millis(200)).collect();
+    server_tester.recv_message(1);
+
+    // server never responds
+    match req.wait() {
+        Err(Error::RequestTimeout) => {}
+        r => panic!("expecting timeout, got {:?}", r.map(|_| ())),
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+
+    server_tester.recv_rst_frame_check(1, ErrorCode::Cancel);
+
+    assert_eq!(0, client.dump_state().wait().expect("dump_state").streams.len());
+}
+
+#[test]
+fn request_handle_cancel() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let resp = client.start_get("/1", "localhost");
+    let handle = resp.request_handle();
+    let req = resp.collect();
+    server_tester.recv_message(1);
+
+    handle.cancel(ErrorCode::Cancel);
+
+    server_tester.recv_rst_frame_check(1, ErrorCode::Cancel);
+
+    match req.wait() {
+        Err(Error::Canceled(ErrorCode::Cancel)) => {}
+        r => panic!("expecting cancel, got {:?}", r.map(|_| ())),
+    }
+
+    assert_eq!(0, client.dump_state().wait().expect("dump_state").streams.len());
+}
+
+#[test]
+fn header_table_size() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.common.header_table_size = Some(64);
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+
+    let client_settings = server_tester.recv_frame_settings_set();
+    assert!(client_settings.settings.contains(&HttpSetting::HeaderTableSize(64)));
+
+    let mut settings = SettingsFrame::new();
+    settings.add_setting(HttpSetting::HeaderTableSize(0));
+    server_tester.send_frame(settings);
+    server_tester.send_frame(SettingsFrame::new_ack());
+    server_tester.recv_frame_settings_ack();
+
+    for &stream_id in &[1, 3] {
+        let headers = Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/table"),
+            Header::new(":authority", "localhost"),
+            Header::new(":scheme", "http"),
+            Header::new("x-custom", "custom-value"),
+        ]);
+        let req = client.start_request_simple(headers, Bytes::new()).collect();
+
+        let headers_frame = server_tester.recv_frame_headers();
+        assert_eq!(stream_id, headers_frame.get_stream_id());
+        // dynamic table size update is sent only in the first header block
+        assert_eq!(stream_id == 1, headers_frame.header_fragment()[0] == 0x20);
+
+        let headers = server_tester.conn.decoder.decode(headers_frame.header_fragment())
+            .expect("decode");
+        let headers = Headers(headers.into_iter().map(|(n, v)| Header::new(n, v)).collect());
+        assert_eq!(Some("/table"), headers.path());
+        assert_eq!(Some("custom-value"), headers.get_opt("x-custom"));
+
+        server_tester.recv_frame_data_check_empty_end(stream_id);
+
+        server_tester.send_headers(stream_id, Headers::ok_200(), true);
+        let resp = req.wait().expect("OK");
+        assert_eq!(Some(200), resp.headers.status());
+    }
+}
+
+#[test]
+fn header_table_size_applied_after_ack() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.common.header_table_size = Some(0);
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.recv_frame_settings_set();
+    server_tester.send_frame(SettingsFrame::new());
+    server_tester.recv_frame_settings_ack();
+
+    // client SETTINGS are not acknowledged yet,
+    // so server encoder may use default dynamic table size
+    for &stream_id in &[1, 3] {
+        let req = client.start_get("/table", "localhost").collect();
+        server_tester.recv_message(stream_id);
+
+        let mut headers = Headers::ok_200();
+        headers.add("x-custom", "custom-value");
+        server_tester.send_headers(stream_id, headers, true);
+
+        let resp = req.wait().expect("OK");
+        assert_eq!(Some("custom-value"), resp.headers.get_opt("x-custom"));
+    }
+}
+
+#[test]
+fn connection_specific_request_header_rejected() {
+    env_logger::init().ok();
+
+    let server = HttpServerEcho::new();
+
+    let client: Client =
+        Client::new("::1", server.port, false, Default::default()).expect("connect");
+
+    let mut headers = Headers::new_get("/echo");
+    headers.add("connection", "keep-alive");
+    match client.start_request_simple(headers, Bytes::new()).collect().wait() {
+        Err(Error::InvalidHeaders(HeaderError::ConnectionSpecificHeader(name))) => {
+            assert_eq!(b"connection".to_vec(), name)
+        }
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+
+    let mut headers = Headers::new_post("/echo");
+    headers.add("te", "trailers");
+    let resp = client.start_request_simple(headers, Bytes::from("hi")).collect().wait().expect("wait");
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"hi"[..], &resp.body[..]);
+}
+
+#[test]
+fn empty_path_rejected() {
+    env_logger::init().ok();
+
+    let server = HttpServerEcho::new();
+
+    let client: Client =
+        Client::new("::1", server.port, false, Default::default()).expect("connect");
+
+    let priority = StreamPriority::new(0, 16, false);
+    let responses = vec![
+        client.start_get_with_priority("", "localhost", priority),
+        client.start_post_with_priority("", "localhost", Bytes::from("hi"), priority),
+        client.start_get_with_timeout("", "localhost", Duration::from_secs(10)),
+    ];
+    for resp in responses {
+        match resp.collect().wait() {
+            Err(Error::InvalidHeaders(HeaderError::EmptyPath)) => {}
+            r => panic!("wrong result: {:?}", r.map(|_| ())),
+        }
+    }
+}
+
+#[test]
+fn malformed_response_headers() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let cases = vec![
+        // regular header before pseudo-header
+        Headers(vec![
+            Header::new("x-foo", "bar"),
+            Header::new(":status", "200"),
+        ]),
+        // duplicate :status
+        Headers(vec![
+            Header::new(":status", "200"),
+            Header::new(":status", "204"),
+        ]),
+        // unknown pseudo-header
+        Headers(vec![
+            Header::new(":status", "200"),
+            Header::new(":foo", "bar"),
+        ]),
+        // request pseudo-header in response
+        Headers(vec![
+            Header::new(":status", "200"),
+            Header::new(":path", "/"),
+        ]),
+        // :status is not three digits
+        Headers(vec![
+            Header::new(":status", "20"),
+        ]),
+        Headers(vec![
+            Header::new(":status", "ok"),
+        ]),
+        // uppercase header name
+        Headers(vec![
+            Header::new(":status", "200"),
+            Header::new("Content-Type", "text/plain"),
+        ]),
+        // connection-specific header
+        Headers(vec![
+            Header::new(":status", "200"),
+            Header::new("connection", "close"),
+        ]),
+    ];
+
+    let mut stream_id = 1;
+    for headers in cases {
+        let req = client.start_get("/malformed", "localhost").collect();
+        server_tester.recv_message(stream_id);
+
+        server_tester.send_headers(stream_id, headers, true);
+        server_tester.recv_rst_frame_check(stream_id, ErrorCode::ProtocolError);
+
+        match req.wait() {
+            Err(Error::StreamReset(id, ErrorCode::ProtocolError)) => assert_eq!(stream_id, id),
+            r => panic!("wrong result: {:?}", r.map(|_| ())),
+        }
+
+        stream_id += 2;
+    }
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams.len(), "{:?}", state);
+}
+
+#[test]
+fn response_content_length_mismatch() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    fn headers_with_content_length(content_length: &str) -> Headers {
+        let mut headers = Headers::ok_200();
+        headers.add("content-length", content_length);
+        headers
+    }
+
+    // declared length is larger than body
+    let req = client.start_get("/larger", "localhost").collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, headers_with_content_length("10"), false);
+    server_tester.send_data(1, b"hello", true);
+    server_tester.recv_rst_frame_check(1, ErrorCode::ProtocolError);
+    match req.wait() {
+        Err(Error::StreamReset(1, ErrorCode::ProtocolError)) => {}
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+
+    // declared length is smaller than body, stream is reset before END_STREAM
+    let req = client.start_get("/smaller", "localhost").collect();
+    server_tester.recv_message(3);
+    server_tester.send_headers(3, headers_with_content_length("3"), false);
+    server_tester.send_data(3, b"hello", false);
+    server_tester.recv_rst_frame_check(3, ErrorCode::ProtocolError);
+    match req.wait() {
+        Err(Error::StreamReset(3, ErrorCode::ProtocolError)) => {}
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+
+    // no body at all
+    let req = client.start_get("/empty", "localhost").collect();
+    server_tester.recv_message(5);
+    server_tester.send_headers(5, headers_with_content_length("5"), true);
+    server_tester.recv_rst_frame_check(5, ErrorCode::ProtocolError);
+    match req.wait() {
+        Err(Error::StreamReset(5, ErrorCode::ProtocolError)) => {}
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+
+    // matching length
+    let req = client.start_get("/exact", "localhost").collect();
+    server_tester.recv_message(7);
+    server_tester.send_headers(7, headers_with_content_length("5"), false);
+    server_tester.send_data(7, b"hello", true);
+    let resp = req.wait().expect("exact");
+    assert_eq!(&b"hello"[..], &resp.body[..]);
+
+    // response to HEAD has no body
+    let headers = Headers(vec![
+        Header::new(":method", "HEAD"),
+        Header::new(":path", "/head"),
+        Header::new(":scheme", "http"),
+    ]);
+    let req = client.start_request_simple(headers, Bytes::new()).collect();
+    server_tester.recv_message(9);
+    server_tester.send_headers(9, headers_with_content_length("5"), true);
+    let resp = req.wait().expect("head");
+    assert_eq!(Some(200), resp.headers.status());
+    assert!(resp.body.is_empty());
+
+    // invalid value
+    let req = client.start_get("/invalid", "localhost").collect();
+    server_tester.recv_message(11);
+    server_tester.send_headers(11, headers_with_content_length("5x"), false);
+    server_tester.recv_rst_frame_check(11, ErrorCode::ProtocolError);
+    match req.wait() {
+        Err(Error::StreamReset(11, ErrorCode::ProtocolError)) => {}
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams.len(), "{:?}", state);
+}
+
+#[test]
+fn headers_before_body() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let resp = client.start_get("/download", "localhost").into_headers_and_body();
+    server_tester.recv_message(1);
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+
+    // headers are available before any DATA is sent
+    let (headers, body) = resp.wait().expect("headers");
+    assert_eq!(Some(200), headers.status());
+
+    let mut body = body.wait();
+
+    server_tester.send_data(1, b"first", false);
+    assert_eq!(&b"first"[..], &body.next().unwrap().expect("first")[..]);
+
+    thread::sleep(Duration::from_millis(50));
+
+    server_tester.send_data(1, b"second", true);
+    assert_eq!(&b"second"[..], &body.next().unwrap().expect("second")[..]);
+
+    assert!(body.next().is_none());
+}
+
+#[test]
+fn connect_tunnel() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let (writer, resp) = client.connect_tunnel("example.com:443");
+
+    let headers = server_tester.recv_frame_headers_check(1, false);
+    assert_eq!(Some("CONNECT"), headers.method());
+    assert_eq!("example.com:443", headers.get(":authority"));
+    assert_eq!(None, headers.get_opt(":path"));
+    assert_eq!(None, headers.get_opt(":scheme"));
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+
+    let (headers, body) = resp.into_headers_and_body().wait().expect("headers");
+    assert_eq!(Some(200), headers.status());
+    let mut body = body.wait();
+
+    // peer echoes bytes back
+    for chunk in &[&b"ping"[..], &b"pong"[..]] {
+        writer.write(Bytes::from(*chunk)).expect("write");
+        let data = server_tester.recv_frame_data_check(1, false);
+        assert_eq!(*chunk, &data[..]);
+        server_tester.send_data(1, &data, false);
+        assert_eq!(*chunk, &body.next().unwrap().expect("data")[..]);
+    }
+
+    // half-close from client
+    writer.close();
+    server_tester.recv_frame_data_check_empty_end(1);
+
+    // tunnel is still readable until peer half-closes
+    server_tester.send_data(1, b"bye", true);
+    assert_eq!(&b"bye"[..], &body.next().unwrap().expect("data")[..]);
+    assert!(body.next().is_none());
+}
+
+#[test]
+fn rst_on_closed_stream_ignored() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(Some(200), req.wait().expect("OK").headers.status());
+
+    // stream 1 is already closed
+    server_tester.send_rst(1, ErrorCode::Cancel);
+    server_tester.send_rst(1, ErrorCode::Cancel);
+
+    let req = client.start_get("/3", "localhost").collect();
+    server_tester.recv_message(3);
+    server_tester.send_headers(3, Headers::ok_200(), true);
+    assert_eq!(Some(200), req.wait().expect("OK").headers.status());
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams.len(), "{:?}", state);
+}
+
+#[test]
+fn rst_on_idle_stream_is_connection_error() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+
+    // stream 5 is not opened yet
+    server_tester.send_rst(5, ErrorCode::Cancel);
+
+    server_tester.recv_goaway_eof(ErrorCode::ProtocolError);
+
+    assert!(req.wait().is_err());
+}
+
+#[test]
+fn unknown_frame_type_ignored() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    // frames of unknown type must be ignored
+    let payload = b"unknown";
+    let mut frame = pack_header(&FrameHeader {
+        length: payload.len() as u32,
+        frame_type: 0xfa,
+        flags: 0,
+        stream_id: 0,
+    }).to_vec();
+    frame.extend_from_slice(payload);
+    server_tester.send_raw(&frame);
+
+    let req = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(Some(200), req.wait().expect("OK").headers.status());
+}
+
+#[test]
+fn push_promise() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.enable_push = Some(true);
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.send_frame(SettingsFrame::new());
+    let client_settings = server_tester.recv_frame_settings_set();
+    assert!(client_settings.settings.contains(&HttpSetting::EnablePush(true)));
+    server_tester.send_frame(SettingsFrame::new_ack());
+    server_tester.recv_frame_settings_ack();
+
+    let mut pushed = client.pushed_responses().wait();
+
+    let req = client.start_get("/index.html", "localhost").collect();
+    server_tester.recv_message(1);
+
+    let promised = Headers(vec![
+        Header::new(":method", "GET"),
+        Header::new(":scheme", "http"),
+        Header::new(":authority", "localhost"),
+        Header::new(":path", "/style.css"),
+    ]);
+    server_tester.send_push_promise(1, 2, promised);
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"<html>", true);
+
+    server_tester.send_headers(2, Headers::ok_200(), false);
+    server_tester.send_data(2, b"body {}", true);
+
+    let resp = req.wait().expect("response");
+    assert_eq!(&b"<html>"[..], &resp.body[..]);
+
+    let (promised, pushed_resp) = pushed.next().expect("pushed").expect("pushed");
+    assert_eq!(Some("/style.css"), promised.path());
+
+    let pushed_resp = pushed_resp.collect().wait().expect("pushed response");
+    assert_eq!(Some(200), pushed_resp.headers.status());
+    assert_eq!(&b"body {}"[..], &pushed_resp.body[..]);
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams.len(), "{:?}", state);
+}
+
+#[test]
+fn push_promise_when_disabled() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/index.html", "localhost").collect();
+    server_tester.recv_message(1);
+
+    server_tester.send_push_promise(1, 2, Headers::new_get("/style.css"));
+
+    match req.wait() {
+        Err(Error::ConnectionError(ErrorCode::ProtocolError, _)) => {},
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn max_concurrent_streams() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    let mut settings = SettingsFrame::new();
+    settings.settings.push(HttpSetting::MaxConcurrentStreams(1));
+    server_tester.send_frame(settings);
+    server_tester.recv_frame_settings_set();
+    server_tester.send_frame(SettingsFrame::new_ack());
+    server_tester.recv_frame_settings_ack();
+
+    let req1 = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+
+    let req2 = client.start_get("/2", "localhost").collect();
+
+    // PING is answered, but second request is queued until first stream is closed
+    server_tester.send_frame(PingFrame::with_data(37));
+    let ping = server_tester.recv_frame_ping();
+    assert!(ping.is_ack());
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"one", true);
+    assert_eq!(&b"one"[..], &req1.wait().expect("r1").body[..]);
+
+    let req = server_tester.recv_message(3);
+    assert_eq!("/2", req.headers.get(":path"));
+
+    server_tester.send_headers(3, Headers::ok_200(), false);
+    server_tester.send_data(3, b"two", true);
+    assert_eq!(&b"two"[..], &req2.wait().expect("r2").body[..]);
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams.len());
+}
+
+#[test]
+fn request_stream_id() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    let mut settings = SettingsFrame::new();
+    settings.settings.push(HttpSetting::MaxConcurrentStreams(1));
+    server_tester.send_frame(settings);
+    server_tester.recv_frame_settings_set();
+    server_tester.send_frame(SettingsFrame::new_ack());
+    server_tester.recv_frame_settings_ack();
+
+    let resp1 = client.start_get("/1", "localhost");
+    server_tester.recv_message(1);
+    assert_eq!(Some(1), resp1.stream_id());
+
+    // queued until first stream is closed
+    let resp2 = client.start_get("/2", "localhost");
+    server_tester.send_frame(PingFrame::with_data(37));
+    assert!(server_tester.recv_frame_ping().is_ack());
+    assert_eq!(None, resp2.stream_id());
+
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    resp1.collect().wait().expect("r1");
+
+    server_tester.recv_message(3);
+    assert_eq!(Some(3), resp2.stream_id());
+
+    server_tester.send_headers(3, Headers::ok_200(), true);
+    resp2.collect().wait().expect("r2");
+}
+
+/// Server which responds with HTTP/1.1 error when signalled and keeps connection open
+fn http1_server() -> (u16, std::sync::mpsc::Sender<()>) {
+    let listener = net::TcpListener::bind("[::1]:0").expect("bind");
+    let port = listener.local_addr().unwrap().port();
+    let (respond_tx, respond_rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let (mut tcp, _) = listener.accept().expect("accept");
+        let mut buf = [0; 100];
+        respond_rx.recv().expect("recv");
+        tcp.write_all(b"HTTP/1.1 505 HTTP Version Not Supported\r\nContent-Length: 0\r\n\r\n").expect("write");
+        // do not close connection until client does
+        while tcp.read(&mut buf).map(|n| n > 0).unwrap_or(false) {}
+    });
+    (port, respond_tx)
+}
+
+#[test]
+fn prior_knowledge_to_http1_server() {
+    env_logger::init().ok();
+
+    let (port, respond_tx) = http1_server();
+
+    let client: Client =
+        Client::new_plain("::1", port, Default::default()).expect("client");
+
+    let req = client.start_get("/", "localhost").collect();
+
+    while client.dump_state().wait().expect("state").streams.is_empty() {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    respond_tx.send(()).expect("send");
+
+    let r = req.wait();
+    match r {
+        Err(Error::ConnectionError(ErrorCode::ProtocolError, _)) => {}
+        Err(e) => panic!("expecting protocol error, got {:?}", e),
+        Ok(..) => panic!("expecting protocol error"),
+    }
+}
+
+#[test]
+fn h2c_upgrade() {
+    env_logger::init().ok();
+
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    let paths_copy = paths.clone();
+    let mut server_conf = ServerConf::new();
+    server_conf.h2c_upgrade = Some(true);
+    let server = HttpServerOneConn::new_fn_with_conf(0, server_conf, move |headers, _req| {
+        paths_copy.lock().unwrap().push(format!("{} {}", headers.get(":method"), headers.get(":path")));
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let mut conf = ClientConf::new();
+    conf.h2c_upgrade = Some(true);
+    let client: Client =
+        Client::new_plain("::1", server.port(), conf).expect("client");
+
+    let r = client.start_get("/hi", "localhost").collect().wait().expect("get");
+    assert_eq!(Some(200), r.headers.status());
+    assert_eq!(&b"hello"[..], &r.body[..]);
+
+    assert_eq!(vec!["OPTIONS *".to_owned(), "GET /hi".to_owned()], *paths.lock().unwrap());
+
+    // response to upgrade request in stream 1 is discarded
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !client.dump_state().wait().expect("state").streams.is_empty() {
+        assert!(Instant::now() < deadline, "stream 1 is not closed");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn h2c_upgrade_fallback_to_prior_knowledge() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.h2c_upgrade = Some(true);
+    let client: Client =
+        Client::new_plain("::1", server.port(), conf).expect("client");
+
+    let mut http1_conn = server.accept();
+    let head = http1_conn.recv_http1_head();
+    assert!(head.starts_with("OPTIONS * HTTP/1.1\r\n"), "{:?}", head);
+    assert!(head.contains("\r\nUpgrade: h2c\r\n"), "{:?}", head);
+    http1_conn.send_raw(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let resp = client.start_get("/hi", "localhost").collect();
+
+    let req = server_tester.recv_message(1);
+    assert_eq!("/hi", req.headers.get(":path"));
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"hello", true);
+
+    let r = resp.wait().expect("get");
+    assert_eq!(Some(200), r.headers.status());
+    assert_eq!(&b"hello"[..], &r.body[..]);
+}
+
+#[test]
+fn initial_window_size_decrease_applied_to_open_streams() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let headers = Headers(vec![
+        Header::new(":method", "POST"),
+        Header::new(":path", "/upload"),
+    ]);
+    let (body_tx, body_rx) = futures::sync::mpsc::unbounded();
+    let body = HttpPartStream::bytes(body_rx.map_err(|()| Error::Other("body")));
+    let _resp = client.start_request(headers, body);
+
+    server_tester.recv_frame_headers_check(1, false);
+
+    body_tx.unbounded_send(Bytes::from(&b"abcdefghij"[..])).expect("send");
+    assert_eq!(&b"abcdefghij"[..], &server_tester.recv_frame_data_check(1, false)[..]);
+
+    // 6.9.2: window of open stream is adjusted by the difference, and becomes negative
+    let mut settings = SettingsFrame::new();
+    settings.settings.push(HttpSetting::InitialWindowSize(5));
+    server_tester.send_frame(settings);
+    assert!(server_tester.recv_frame_settings().is_ack());
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(-5, state.streams[&1].out_window_size);
+
+    // nothing is sent until window is positive
+    body_tx.unbounded_send(Bytes::from(&b"klmnopq"[..])).expect("send");
+    server_tester.send_frame(WindowUpdateFrame::for_stream(1, 10));
+    assert_eq!(&b"klmno"[..], &server_tester.recv_frame_data_check(1, false)[..]);
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams[&1].out_window_size);
+}
+
+#[test]
+fn initial_window_size_increase_overflows_stream_window() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_post("/1", "localhost", Bytes::from("abcd")).collect();
+    server_tester.recv_message(1);
+
+    // stream window is at maximum
+    let increment = 0x7fffffff - DEFAULT_SETTINGS.initial_window_size + 4;
+    server_tester.send_frame(WindowUpdateFrame::for_stream(1, increment));
+
+    let mut settings = SettingsFrame::new();
+    settings.settings.push(HttpSetting::InitialWindowSize(DEFAULT_SETTINGS.initial_window_size + 1));
+    server_tester.send_frame(settings);
+
+    server_tester.recv_goaway_eof(ErrorCode::FlowControlError);
+
+    assert!(req.wait().is_err());
+}
+
+#[test]
+fn dump_state_stream_windows() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let headers = Headers(vec![
+        Header::new(":method", "POST"),
+        Header::new(":path", "/upload"),
+    ]);
+    let (body_tx, body_rx) = futures::sync::mpsc::unbounded();
+    let body = HttpPartStream::bytes(body_rx.map_err(|()| Error::Other("body")));
+    let _resp = client.start_request(headers, body);
+
+    server_tester.recv_frame_headers_check(1, false);
+
+    body_tx.unbounded_send(Bytes::from(&b"abcdefghij"[..])).expect("send");
+    assert_eq!(&b"abcdefghij"[..], &server_tester.recv_frame_data_check(1, false)[..]);
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    let stream = &state.streams[&1];
+    assert_eq!(StreamState::Open, stream.state);
+    assert_eq!(DEFAULT_SETTINGS.initial_window_size as i32 - 10, stream.out_window_size);
+    assert_eq!(DEFAULT_SETTINGS.initial_window_size as i32, stream.in_window_size);
+    assert_eq!(10, stream.data_sent);
+    assert_eq!(0, stream.data_received);
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"xyz", false);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+        if state.streams[&1].data_received == 3 {
+            assert_eq!(DEFAULT_SETTINGS.initial_window_size as i32 - 3, state.streams[&1].in_window_size);
+            break;
+        }
+        assert!(Instant::now() < deadline, "DATA not received");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn dump_state_conn_window() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    // stream window is large, so only connection window limits the upload
+    let mut settings = SettingsFrame::new();
+    settings.settings.push(HttpSetting::InitialWindowSize(1000000));
+    server_tester.send_recv_settings(settings);
+
+    let headers = Headers(vec![
+        Header::new(":method", "POST"),
+        Header::new(":path", "/upload"),
+    ]);
+    let resp = client.start_request_simple(headers, Bytes::from(vec![17; 100000]));
+
+    server_tester.recv_frame_headers_check(1, false);
+
+    let conn_window = DEFAULT_SETTINGS.initial_window_size as usize;
+    let mut received = 0;
+    while received < conn_window {
+        received += server_tester.recv_frame_data_check(1, false).len();
+    }
+    assert_eq!(conn_window, received);
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.out_window_size);
+    assert_eq!(DEFAULT_SETTINGS.initial_window_size as i32, state.in_window_size);
+    assert_eq!(1000000, state.peer_settings.initial_window_size);
+    assert_eq!(1000000 - conn_window as i32, state.streams[&1].out_window_size);
+
+    drop(resp);
+}
+
+#[test]
+fn data_on_stream_0_is_protocol_error() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/", "localhost").collect();
+    server_tester.recv_message(1);
+
+    server_tester.send_data(0, b"abc", false);
+
+    match req.wait() {
+        Err(Error::ConnectionError(ErrorCode::ProtocolError, _)) => {},
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+
+    server_tester.recv_goaway_eof(ErrorCode::ProtocolError);
+}
+
+#[test]
+fn headers_on_unopened_even_stream_is_protocol_error() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/", "localhost").collect();
+    server_tester.recv_message(1);
+
+    server_tester.send_headers(2, Headers::ok_200(), true);
+
+    match req.wait() {
+        Err(Error::ConnectionError(ErrorCode::ProtocolError, _)) => {},
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+
+    server_tester.recv_goaway_eof(ErrorCode::ProtocolError);
+}
+
+#[test]
+fn window_update_zero_increment_resets_stream() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let headers = Headers(vec![
+        Header::new(":method", "POST"),
+        Header::new(":path", "/upload"),
+    ]);
+    let (_body_tx, body_rx) = futures::sync::mpsc::unbounded::<Bytes>();
+    let body = HttpPartStream::bytes(body_rx.map_err(|()| Error::Other("body")));
+    let resp = client.start_request(headers, body).collect();
+
+    server_tester.recv_frame_headers_check(1, false);
+
+    server_tester.send_frame(WindowUpdateFrame::for_stream(1, 0));
+
+    server_tester.recv_rst_frame_check(1, ErrorCode::ProtocolError);
+
+    match resp.wait() {
+        Err(Error::StreamReset(1, ErrorCode::ProtocolError)) => {},
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+
+    // stream error does not affect connection
+    let req = client.start_get("/next", "localhost").collect();
+    server_tester.recv_message(3);
+    server_tester.send_headers(3, Headers::ok_200(), true);
+    assert_eq!(Some(200), req.wait().expect("r").headers.status());
+}
+
+#[test]
+fn window_update_conn_overflow_is_connection_error() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let req = client.start_get("/", "localhost").collect();
+    server_tester.recv_message(1);
+
+    server_tester.send_frame(WindowUpdateFrame::for_connection(0x7fffffff));
+
+    match req.wait() {
+        Err(Error::ConnectionError(ErrorCode::FlowControlError, _)) => {},
+        r => panic!("wrong result: {:?}", r.map(|_| ())),
+    }
+
+    server_tester.recv_goaway_eof(ErrorCode::FlowControlError);
+}
+
+#[test]
+fn post_stream() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let (body_tx, body_rx) = futures::sync::mpsc::unbounded();
+    let body = Box::new(body_rx.map_err(|()| Error::Other("body")));
+    let resp = client.start_post_stream("/upload", "localhost", body).collect();
+
+    let req_headers = server_tester.recv_frame_headers_check(1, false);
+    assert_eq!(Some("POST"), req_headers.method());
+    assert_eq!(Some("/upload"), req_headers.path());
+
+    for chunk in &["aa", "bbb", "cccc"] {
+        body_tx.unbounded_send(Bytes::from(*chunk)).expect("send");
+        assert_eq!(chunk.as_bytes(), &server_tester.recv_frame_data_check(1, false)[..]);
+    }
+
+    drop(body_tx);
+    server_tester.recv_frame_data_check_empty_end(1);
+
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(Some(200), resp.wait().expect("resp").headers.status());
+}
+
+#[test]
+fn post_stream_backpressure() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    // infinite body, counting chunks taken by client
+    let pulled = Arc::new(AtomicUsize::new(0));
+    let pulled_copy = pulled.clone();
+    let body = Box::new(futures::stream::repeat::<_, Error>(()).map(move |()| {
+        pulled_copy.fetch_add(1, Ordering::SeqCst);
+        Bytes::from(vec![1; 10000])
+    }));
+    let _resp = client.start_post_stream("/upload", "localhost", body);
+
+    server_tester.recv_frame_headers_check(1, false);
+
+    let window = DEFAULT_SETTINGS.initial_window_size as usize;
+    let mut received = 0;
+    while received < window {
+        received += server_tester.recv_frame_data_check(1, false).len();
+    }
+    assert_eq!(window, received);
+
+    // window is exhausted, so client must stop pulling the body
+    thread::sleep(Duration::from_millis(100));
+    let pulled_before = pulled.load(Ordering::SeqCst);
+    assert!(pulled_before <= window / 10000 + 2, "pulled: {}", pulled_before);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(pulled_before, pulled.load(Ordering::SeqCst));
+
+    server_tester.send_frame(WindowUpdateFrame::for_connection(20000));
+    server_tester.send_frame(WindowUpdateFrame::for_stream(1, 20000));
+
+    let mut received = 0;
+    while received < 20000 {
+        received += server_tester.recv_frame_data_check(1, false).len();
+    }
+    assert_eq!(20000, received);
+}
+
+#[test]
+fn post_writer_finish_request() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let (writer, resp) = client.start_post_writer("/rpc", "localhost");
+
+    server_tester.recv_frame_headers_check(1, false);
+
+    writer.write(Bytes::from("aa")).expect("write");
+    assert_eq!(b"aa", &server_tester.recv_frame_data_check(1, false)[..]);
+    writer.write(Bytes::from("bbb")).expect("write");
+    assert_eq!(b"bbb", &server_tester.recv_frame_data_check(1, false)[..]);
+
+    writer.finish_request().expect("finish");
+    server_tester.recv_frame_data_check_empty_end(1);
+
+    assert!(writer.write(Bytes::from("c")).is_err());
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(StreamState::HalfClosedLocal, state.streams[&1].state);
+
+    // writer is still alive, response is read
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"resp", true);
+
+    let resp = resp.collect().wait().expect("resp");
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"resp"[..], &resp.body[..]);
+}
+
+#[test]
+fn origin_frame() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    assert_eq!(Vec::<String>::new(), client.origins());
+
+    server_tester.send_frame(OriginFrame::new(vec![
+        "https://example.com".to_owned(),
+        "https://example.org:8443".to_owned(),
+    ]));
+
+    // ACK is sent after ORIGIN frame is processed
+    server_tester.send_frame(PingFrame::with_data(17));
+    assert!(server_tester.recv_frame_ping().is_ack());
+
+    assert_eq!(
+        vec!["https://example.com".to_owned(), "https://example.org:8443".to_owned()],
+        client.origins());
+}
+
+#[test]
+fn altsvc_frame() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    server_tester.send_frame(AltSvcFrame::new(
+        0, Bytes::from("https://example.com"), Bytes::from("h3=\":443\"; ma=3600")));
+
+    // ACK is sent after ALTSVC frame is processed
+    server_tester.send_frame(PingFrame::with_data(17));
+    assert!(server_tester.recv_frame_ping().is_ack());
+
+    assert_eq!(
+        vec![AltSvc {
+            stream_id: 0,
+            origin: "https://example.com".to_owned(),
+            field_value: "h3=\":443\"; ma=3600".to_owned(),
+        }],
+        client.alt_svc());
+}
+
+#[test]
+fn post_expect_continue() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let resp = client.start_post_expect_continue("/upload", "localhost", Bytes::from("body"));
+
+    let headers = server_tester.recv_frame_headers_check(1, false);
+    assert_eq!("100-continue", headers.get("expect"));
+
+    // body is not sent before `100 Continue`
+    server_tester.send_frame(PingFrame::with_data(17));
+    assert!(server_tester.recv_frame_ping().is_ack());
+
+    server_tester.send_headers(1, Headers::from_status(100), false);
+    assert_eq!(b"body", &server_tester.recv_frame_data_check(1, true)[..]);
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"done", true);
+
+    let resp = resp.collect().wait().expect("resp");
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"done"[..], &resp.body[..]);
+}
+
+#[test]
+fn post_expect_continue_timeout() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.expect_continue_timeout = Some(Duration::from_millis(100));
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let resp = client.start_post_expect_continue("/upload", "localhost", Bytes::from("body"));
+
+    server_tester.recv_frame_headers_check(1, false);
+
+    // server does not respond, body is sent after timeout
+    assert_eq!(b"body", &server_tester.recv_frame_data_check(1, true)[..]);
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"done", true);
+
+    let resp = resp.collect().wait().expect("resp");
+    assert_eq!(Some(200), resp.headers.status());
+}
+
+#[test]
+fn post_expect_continue_rejected() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let resp = client.start_post_expect_continue("/upload", "localhost", Bytes::from("body"));
+
+    server_tester.recv_frame_headers_check(1, false);
+    server_tester.send_headers(1, Headers::from_status(417), true);
+
+    // request is ended without body
+    server_tester.recv_frame_data_check_empty_end(1);
+
+    let resp = resp.collect().wait().expect("resp");
+    assert_eq!(Some(417), resp.headers.status());
+
+    let state: ConnectionStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams.len(), "{:?}", state);
+}
+
+#[test]
+fn interim_responses() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let headers = Headers(vec![
+        Header::new(":method", "GET"),
+        Header::new(":path", "/"),
+        Header::new(":authority", "localhost"),
+        Header::new(":scheme", "http"),
+    ]);
+    let (interim, resp) =
+        client.start_request_with_interim(headers, HttpPartStream::once_bytes(Bytes::new()));
+
+    server_tester.recv_message(1);
+
+    let mut early_hints = Headers::from_status(103);
+    early_hints.add("link", "</style.css>; rel=preload");
+    server_tester.send_headers(1, early_hints, false);
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"hello", true);
+
+    let resp = resp.collect().wait().expect("resp");
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"hello"[..], &resp.body[..]);
+
+    let interim: Vec<Headers> = interim.collect().wait().expect("interim");
+    assert_eq!(1, interim.len());
+    assert_eq!(Some(103), interim[0].status());
+    assert_eq!("</style.css>; rel=preload", interim[0].get("link"));
+}
+
+// Smoke test: connections work with either setting, socket option itself
+// is not observable here and is checked by `client_conn` unit test
+#[test]
+fn no_delay() {
+    env_logger::init().ok();
+
+    struct ServiceImpl;
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, req: HttpPartStream) -> Response {
+            Response::headers_and_stream(Headers::ok_200(), req)
+        }
+    }
+
+    for &no_delay in &[true, false] {
+        let mut server_conf = ServerConf::new();
+        server_conf.no_delay = Some(no_delay);
+        let server = Server::new("[::1]:0", ServerTlsOption::Plain, server_conf, ServiceImpl);
+
+        let mut client_conf = ClientConf::new();
+        client_conf.no_delay = Some(no_delay);
+        let client = Client::new("::1", server.local_addr().port(), false, client_conf).expect("client");
+
+        let resp = client.start_post("/echo", "localhost", Bytes::from("abc")).collect().wait().expect("resp");
+        assert_eq!(&b"abc"[..], &resp.body[..]);
+    }
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn decompress_gzip() {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    env_logger::init().ok();
+
+    let body = b"compressed response body ".repeat(100);
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&body).unwrap();
+    let encoded = encoder.finish().unwrap();
+
+    struct ServiceImpl {
+        encoded: Vec<u8>,
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            let mut headers = Headers::ok_200();
+            headers.add("content-encoding", "gzip");
+            headers.add("content-length", &format!("{}", self.encoded.len()));
+            // split gzip member in DATA frames at arbitrary points
+            let chunks: Vec<Bytes> = self.encoded.chunks(7).map(Bytes::from).collect();
+            Response::headers_and_bytes_stream(headers, futures::stream::iter_ok(chunks))
+        }
+    }
+
+    let server = Server::new(
+        "[::1]:0", ServerTlsOption::Plain, ServerConf::new(), ServiceImpl { encoded: encoded.clone() });
+
+    let mut client_conf = ClientConf::new();
+    client_conf.decompress = Some(true);
+    let client = Client::new("::1", server.local_addr().port(), false, client_conf).expect("client");
+
+    let resp = client.start_get("/gzip", "localhost").collect().wait().expect("resp");
+    assert_eq!(&body[..], &resp.body[..]);
+    assert_eq!(None, resp.headers.get_opt("content-encoding"));
+
+    // raw body is returned by default
+    let client = Client::new("::1", server.local_addr().port(), false, ClientConf::new()).expect("client");
+    let resp = client.start_get("/gzip", "localhost").collect().wait().expect("resp");
+    assert_eq!(&encoded[..], &resp.body[..]);
+    assert_eq!("gzip", resp.headers.get("content-encoding"));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn decompress_empty_body() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut client_conf = ClientConf::new();
+    client_conf.decompress = Some(true);
+    let client: Client = Client::new("::1", server.port(), false, client_conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let mut headers = Headers::ok_200();
+    headers.add("content-encoding", "gzip");
+
+    // response to HEAD has no body
+    let head = Headers::request(Method::Head, "/", HttpScheme::Http, "localhost").unwrap();
+    let resp = client.start_request_simple(head, Bytes::new()).collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, headers.clone(), true);
+    let resp = resp.wait().expect("resp");
+    assert_eq!(Some(200), resp.headers.status());
+    assert!(resp.body.is_empty());
+
+    // empty DATA with END_STREAM
+    let resp = client.start_get("/", "localhost").collect();
+    server_tester.recv_message(3);
+    server_tester.send_headers(3, headers, false);
+    server_tester.send_data(3, b"", true);
+    assert!(resp.wait().expect("resp").body.is_empty());
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn decompress_max_response_body_size() {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut client_conf = ClientConf::new();
+    client_conf.decompress = Some(true);
+    client_conf.max_response_body_size = Some(10_000);
+    let client: Client = Client::new("::1", server.port(), false, client_conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    // small compressed body expands past the limit
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&[0; 1_000_000]).unwrap();
+    let encoded = encoder.finish().unwrap();
+    assert!(encoded.len() < 10_000);
+
+    let mut headers = Headers::ok_200();
+    headers.add("content-encoding", "gzip");
+
+    let resp = client.start_get("/bomb", "localhost").collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, headers, false);
+    server_tester.send_data(1, &encoded, false);
+
+    match resp.wait() {
+        Err(Error::BodyTooLarge(10_000)) => {}
+        r => panic!("wrong result: {:?}", r.map(|m| m.body.len())),
+    }
+    server_tester.recv_rst_frame_check(1, ErrorCode::Cancel);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn compress_requests() {
+    use flate2::read::GzDecoder;
+
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut client_conf = ClientConf::new();
+    client_conf.compress_requests = Some(true);
+    let client: Client = Client::new("::1", server.port(), false, client_conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let body = b"uploaded request body ".repeat(50);
+    let resp = client.start_post("/upload", "localhost", Bytes::from(body.clone())).collect();
+
+    let req = server_tester.recv_message(1);
+    assert_eq!(vec!["gzip"], req.headers.get_all("content-encoding"));
+    let mut decoded = Vec::new();
+    GzDecoder::new(&req.body[..]).read_to_end(&mut decoded).expect("gunzip");
+    assert_eq!(body, decoded);
+
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(Some(200), resp.wait().expect("resp").headers.status());
+
+    // body with content-encoding set by caller is sent as is
+    let mut headers = Headers::new_post("/upload");
+    headers.add("content-encoding", "identity");
+    let resp = client.start_request_simple(headers, Bytes::from("raw")).collect();
+
+    let req = server_tester.recv_message(3);
+    assert_eq!(vec!["identity"], req.headers.get_all("content-encoding"));
+    assert_eq!(&b"raw"[..], &req.body[..]);
+
+    server_tester.send_headers(3, Headers::ok_200(), true);
+    assert_eq!(Some(200), resp.wait().expect("resp").headers.status());
+
+    // other ways to start request are encoded too
+    let (writer, resp) = client.start_post_writer("/upload", "localhost");
+    writer.write(Bytes::from("written")).expect("write");
+    writer.finish_request().expect("finish");
+    let req = server_tester.recv_message(5);
+    assert_eq!(vec!["gzip"], req.headers.get_all("content-encoding"));
+    server_tester.send_headers(5, Headers::ok_200(), true);
+    assert_eq!(Some(200), resp.collect().wait().expect("resp").headers.status());
+
+    let resp = client.start_post_with_priority(
+        "/upload", "localhost", Bytes::from("prioritized"), StreamPriority::new(0, 16, false));
+    let req = server_tester.recv_message(7);
+    assert_eq!(vec!["gzip"], req.headers.get_all("content-encoding"));
+    server_tester.send_headers(7, Headers::ok_200(), true);
+    assert_eq!(Some(200), resp.collect().wait().expect("resp").headers.status());
+
+    // requests without body are not encoded
+    let resp = client.start_get_with_priority("/download", "localhost", StreamPriority::new(0, 16, false));
+    let req = server_tester.recv_message(9);
+    assert_eq!(None, req.headers.get_opt("content-encoding"));
+    server_tester.send_headers(9, Headers::ok_200(), true);
+    assert_eq!(Some(200), resp.collect().wait().expect("resp").headers.status());
+}
+
+#[test]
+fn pool_round_robin() {
+    env_logger::init().ok();
+
+    let server1 = HttpServerTester::new();
+    let server2 = HttpServerTester::new();
+
+    let addrs = vec![
+        net::SocketAddr::new("::1".parse().unwrap(), server1.port()),
+        net::SocketAddr::new("::1".parse().unwrap(), server2.port()),
+    ];
+
+    let client: Client = Client::new_pool(addrs, ClientTlsOption::Plain, Default::default())
+        .expect("pool");
+
+    let mut tester1 = server1.accept();
+    tester1.recv_preface();
+    tester1.settings_xchg();
+
+    let mut tester2 = server2.accept();
+    tester2.recv_preface();
+    tester2.settings_xchg();
+
+    // four requests are split evenly, two streams on each backend
+    for &stream_id in &[1, 3] {
+        let req1 = client.start_get("/a", "localhost").collect();
+        let req2 = client.start_get("/b", "localhost").collect();
+
+        assert_eq!("/a", tester1.recv_message(stream_id).headers.get(":path"));
+        assert_eq!("/b", tester2.recv_message(stream_id).headers.get(":path"));
+
+        tester1.send_headers(stream_id, Headers::ok_200(), true);
+        tester2.send_headers(stream_id, Headers::ok_200(), true);
+
+        assert_eq!(Some(200), req1.wait().expect("r1").headers.status());
+        assert_eq!(Some(200), req2.wait().expect("r2").headers.status());
+    }
+}
+
+#[test]
+fn pool_skips_dead_backend() {
+    env_logger::init().ok();
+
+    let server = HttpServerEcho::new();
+
+    // nothing listens on this port
+    let dead_port = net::TcpListener::bind("[::1]:0").unwrap().local_addr().unwrap().port();
+
+    let addrs = vec![
+        net::SocketAddr::new("::1".parse().unwrap(), dead_port),
+        net::SocketAddr::new("::1".parse().unwrap(), server.port),
+    ];
+
+    let client: Client = Client::new_pool(addrs, ClientTlsOption::Plain, Default::default())
+        .expect("pool");
+
+    for i in 0..6 {
+        let body = format!("req{}", i);
+        let resp = client.start_post("/echo", "localhost", Bytes::from(body.clone()))
+            .collect().wait().expect("resp");
+        assert_eq!(Some(200), resp.headers.status());
+        assert_eq!(body.as_bytes(), &resp.body[..]);
+    }
+}
+
+#[test]
+fn pool_backend_killed() {
+    env_logger::init().ok();
+
+    let server1 = HttpServerEcho::new();
+    let server2 = HttpServerEcho::new();
+
+    let addrs = vec![
+        net::SocketAddr::new("::1".parse().unwrap(), server1.port),
+        net::SocketAddr::new("::1".parse().unwrap(), server2.port),
+    ];
+
+    let client: Client = Client::new_pool(addrs, ClientTlsOption::Plain, Default::default())
+        .expect("pool");
+
+    let post = |body: String| {
+        let resp = client.start_post("/echo", "localhost", Bytes::from(body.clone()))
+            .collect().wait().expect("resp");
+        assert_eq!(body.as_bytes(), &resp.body[..]);
+    };
+
+    post("a".to_owned());
+    post("b".to_owned());
+
+    // connection-level commands are served by the first backend
+    let disconnect = client.wait_for_disconnect();
+    drop(server1);
+    disconnect.wait().expect("disconnect");
+
+    for i in 0..6 {
+        post(format!("req{}", i));
+    }
+}
+
+#[test]
+fn wait_for_disconnect() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.settings_xchg();
+
+    let disconnect = client.wait_for_disconnect();
+
+    drop(server_tester);
+
+    // EOF is reported as is, not as fabricated error code
+    match disconnect.wait() {
+        Ok(httpbis::Error::ConnectionDied(ref e)) => {
+            match **e {
+                httpbis::Error::IoError(ref e) => assert_eq!(io::ErrorKind::UnexpectedEof, e.kind()),
+                ref e => panic!("expecting I/O error: {:?}", e),
+            }
+        }
+        r => panic!("expecting connection error: {:?}", r),
+    }
+}
+
+#[test]
+fn max_header_list_size() {
+    env_logger::init().ok();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.common.max_header_list_size = Some(1000);
+
+    let client: Client =
+        Client::new("::1", server.port(), false, conf).expect("connect");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    let settings = server_tester.recv_frame_settings_set();
+    assert!(settings.settings.contains(&HttpSetting::MaxHeaderListSize(1000)));
+    server_tester.send_frame(SettingsFrame::new());
+    server_tester.send_frame(SettingsFrame::new_ack());
+    server_tester.recv_frame_settings_ack();
+
+    let req = client.start_get("/large", "localhost").collect();
+    server_tester.recv_message(1);
+
+    let mut headers = Headers::ok_200();
+    headers.add("x-large", &"x".repeat(2000));
+    server_tester.send_headers(1, headers, false);
+    server_tester.recv_rst_frame_check(1, ErrorCode::Cancel);
+
+    match req.wait() {
+        Err(httpbis::Error::HeaderListTooLarge(1000)) => {}
+        Err(e) => panic!("wrong error: {:?}", e),
+        Ok(..) => panic!("expecting error"),
     }
 }
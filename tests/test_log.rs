@@ -0,0 +1,85 @@
+//! Test log messages identify connection and stream.
+//!
+//! Separate test binary because logger is global and other tests install `env_logger`.
+
+extern crate bytes;
+extern crate futures;
+extern crate httpbis;
+extern crate log;
+
+use bytes::Bytes;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::net::SocketAddr;
+
+use futures::future::Future;
+
+use log::Log;
+use log::Record;
+use log::Metadata;
+use log::LevelFilter;
+
+use httpbis::*;
+use httpbis::message::SimpleHttpMessage;
+
+
+struct CapturingLogger {
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl Log for CapturingLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target().starts_with("httpbis") {
+            self.messages.lock().unwrap().push(format!("{}", record.args()));
+        }
+    }
+
+    fn flush(&self) {
+    }
+}
+
+#[test]
+fn log_messages_include_conn_and_stream_id() {
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let logger = CapturingLogger { messages: messages.clone() };
+    log::set_logger(Box::leak(Box::new(logger))).expect("set_logger");
+    log::set_max_level(LevelFilter::Debug);
+
+    struct ServiceImpl {
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let server = Server::new(
+        "[::1]:0".parse::<SocketAddr>().unwrap(),
+        ServerTlsOption::Plain,
+        Default::default(),
+        ServiceImpl {});
+
+    let client: Client = Client::new_expl(&server.local_addr(), ClientTlsOption::Plain, Default::default())
+        .expect("http client");
+
+    let resp: SimpleHttpMessage = client.start_get("/hi", "localhost").collect().wait().unwrap();
+    assert_eq!(Some(200), resp.headers.status());
+
+    let messages = messages.lock().unwrap().clone();
+
+    let stream_messages: Vec<&String> = messages.iter()
+        .filter(|m| m.starts_with("conn ") && m.contains(" stream 1: "))
+        .collect();
+
+    // both client and server log stream open and received frames
+    let opened = stream_messages.iter().filter(|m| m.ends_with(" stream 1: opened")).count();
+    assert_eq!(2, opened, "{:#?}", messages);
+    assert!(stream_messages.iter().any(|m| m.contains(" stream 1: received frame: Headers")), "{:#?}", messages);
+    assert!(stream_messages.iter().any(|m| m.contains(" stream 1: sending frame")), "{:#?}", messages);
+}
@@ -7,7 +7,7 @@ use httpbis::*;
 
 
 pub struct HttpServerEcho {
-    server: Server,
+    pub server: Server,
     pub port: u16,
 }
 
@@ -23,7 +23,11 @@ impl httpbis::Service for EchoService {
 
 impl HttpServerEcho {
     pub fn new() -> HttpServerEcho {
-        let http_server = Server::new("[::1]:0", ServerTlsOption::Plain, Default::default(), EchoService {});
+        HttpServerEcho::new_conf(Default::default())
+    }
+
+    pub fn new_conf(conf: ServerConf) -> HttpServerEcho {
+        let http_server = Server::new("[::1]:0", ServerTlsOption::Plain, conf, EchoService {});
         let port = http_server.local_addr().port();
         HttpServerEcho {
             server: http_server,
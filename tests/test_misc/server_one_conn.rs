@@ -18,6 +18,7 @@ use tokio_core::reactor;
 use httpbis;
 use httpbis::*;
 use httpbis::for_test::*;
+use httpbis::solicit::frame::RawFrame;
 
 
 /// Single connection HTTP/server.
@@ -37,17 +38,23 @@ impl HttpServerOneConn {
     pub fn new_fn<S>(port: u16, service: S) -> Self
         where S : Fn(Headers, httpbis::HttpPartStream) -> Response + Send + 'static
     {
-        HttpServerOneConn::new_fn_impl(port, None, service)
+        HttpServerOneConn::new_fn_impl(port, None, Default::default(), service)
+    }
+
+    pub fn new_fn_with_conf<S>(port: u16, conf: ServerConf, service: S) -> Self
+        where S : Fn(Headers, httpbis::HttpPartStream) -> Response + Send + 'static
+    {
+        HttpServerOneConn::new_fn_impl(port, None, conf, service)
     }
 
     pub fn new_tls_fn<S>(port: u16, server_context: TlsAcceptor, service: S) -> Self
         where S : Fn(Headers, httpbis::HttpPartStream) -> Response + Send + 'static
     {
-        HttpServerOneConn::new_fn_impl(port, Some(server_context), service)
+        HttpServerOneConn::new_fn_impl(port, Some(server_context), Default::default(), service)
     }
 
     #[allow(dead_code)]
-    fn new_fn_impl<S>(port: u16, server_context: Option<TlsAcceptor>, service: S) -> Self
+    fn new_fn_impl<S>(port: u16, server_context: Option<TlsAcceptor>, conf: ServerConf, service: S) -> Self
         where S : Fn(Headers, httpbis::HttpPartStream) -> Response + Send + 'static
     {
         let (from_loop_tx, from_loop_rx) = futures::oneshot();
@@ -83,7 +90,7 @@ impl HttpServerOneConn {
                         unimplemented!()
                     } else {
                         let (conn, future) = ServerConnection::new_plain_fn(
-                            &handle, conn, Default::default(), service);
+                            &handle, conn, conf, service);
                         *conn_for_thread.lock().unwrap() = Some(conn);
                         future
                     }
@@ -115,6 +122,18 @@ impl HttpServerOneConn {
         let conn = g.as_ref().expect("conn");
         conn.dump_state().wait().expect("dump_status")
     }
+
+    pub fn metrics(&self) -> Metrics {
+        let g = self.conn.lock().expect("lock");
+        let conn = g.as_ref().expect("conn");
+        conn.metrics()
+    }
+
+    pub fn send_raw_frame(&self, frame: RawFrame) {
+        let g = self.conn.lock().expect("lock");
+        let conn = g.as_ref().expect("conn");
+        conn.send_raw_frame(frame).wait().expect("send_raw_frame")
+    }
 }
 
 impl Drop for HttpServerOneConn {
@@ -22,6 +22,10 @@ use httpbis::solicit::frame::headers::HeadersFlag;
 use httpbis::solicit::frame::data::DataFrame;
 use httpbis::solicit::frame::data::DataFlag;
 use httpbis::solicit::frame::goaway::GoawayFrame;
+use httpbis::solicit::frame::ping::PingFrame;
+use httpbis::solicit::frame::push_promise::PushPromiseFrame;
+use httpbis::solicit::frame::push_promise::PushPromiseFlag;
+use httpbis::solicit::frame::window_update::WindowUpdateFrame;
 use httpbis::solicit::frame::RawFrame;
 use httpbis::solicit::frame::rst_stream::RstStreamFrame;
 use httpbis::solicit::connection::HttpFrame;
@@ -78,6 +82,10 @@ impl HttpConnectionTester {
         }
     }
 
+    pub fn local_addr(&self) -> net::SocketAddr {
+        self.tcp.local_addr().expect("local_addr")
+    }
+
     pub fn recv_preface(&mut self) {
         let mut preface = Vec::new();
         preface.resize(PREFACE.len(), 0);
@@ -103,6 +111,21 @@ impl HttpConnectionTester {
         self.tcp.write(PREFACE).expect("send");
     }
 
+    pub fn send_raw(&mut self, data: &[u8]) {
+        self.tcp.write_all(data).expect("send");
+    }
+
+    /// Read HTTP/1.1 response head up to and including empty line
+    pub fn recv_http1_head(&mut self) -> String {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut buf = [0];
+            self.tcp.read_exact(&mut buf).expect("read");
+            head.push(buf[0]);
+        }
+        String::from_utf8(head).expect("utf-8")
+    }
+
     pub fn send_frame<F : FrameIR>(&mut self, frame: F) {
         self.tcp.write(&frame.serialize_into_vec()).expect("send_frame");
     }
@@ -121,6 +144,13 @@ impl HttpConnectionTester {
         self.send_frame(headers_frame);
     }
 
+    pub fn send_push_promise(&mut self, stream_id: StreamId, promised_stream_id: StreamId, headers: Headers) {
+        let fragment = self.conn.encoder.encode(headers.0.iter().map(|h| (h.name(), h.value())));
+        let mut frame = PushPromiseFrame::new(fragment, stream_id, promised_stream_id);
+        frame.set_flag(PushPromiseFlag::EndHeaders);
+        self.send_frame(frame);
+    }
+
     pub fn send_get(&mut self, stream_id: StreamId, path: &str) {
         let mut headers = Headers::new();
         headers.add(":method", "GET");
@@ -144,7 +174,7 @@ impl HttpConnectionTester {
     pub fn recv_raw_frame(&mut self) -> RawFrame {
         httpbis::solicit_async::recv_raw_frame_sync(
             &mut self.tcp,
-            self.conn.peer_settings.max_frame_size)
+            self.conn.our_settings.max_frame_size)
                 .expect("recv_raw_frame")
     }
 
@@ -211,6 +241,9 @@ impl HttpConnectionTester {
 
     pub fn send_recv_settings(&mut self, settings: SettingsFrame) {
         assert!(!self.waiting_settings_ack);
+        for setting in &settings.settings {
+            self.conn.our_settings.apply(*setting);
+        }
         self.send_frame(settings);
         self.waiting_settings_ack = true;
         self.recv_frame_settings_ack();
@@ -236,6 +269,34 @@ impl HttpConnectionTester {
         }
     }
 
+    pub fn recv_frame_goaway(&mut self) -> GoawayFrame {
+        match self.recv_frame() {
+            HttpFrame::Goaway(goaway) => goaway,
+            f => panic!("expecting GOAWAY, got: {:?}", f),
+        }
+    }
+
+    /// Connection error: GOAWAY with given error code followed by EOF
+    pub fn recv_goaway_eof(&mut self, error_code: ErrorCode) {
+        let goaway = self.recv_frame_goaway();
+        assert_eq!(error_code, goaway.error_code());
+        self.recv_eof();
+    }
+
+    pub fn recv_frame_ping(&mut self) -> PingFrame {
+        match self.recv_frame() {
+            HttpFrame::Ping(ping) => ping,
+            f => panic!("expecting PING, got: {:?}", f),
+        }
+    }
+
+    pub fn recv_frame_window_update(&mut self) -> WindowUpdateFrame {
+        match self.recv_frame() {
+            HttpFrame::WindowUpdate(window_update) => window_update,
+            f => panic!("expecting WINDOW_UPDATE, got: {:?}", f),
+        }
+    }
+
     pub fn recv_frame_data(&mut self) -> DataFrame {
         match self.recv_frame() {
             HttpFrame::Data(data) => data,
@@ -0,0 +1,110 @@
+//! Test client and server connected with TLS using rustls.
+
+#![cfg(feature = "tls-rustls")]
+
+extern crate bytes;
+extern crate futures;
+extern crate rustls;
+extern crate httpbis;
+
+use bytes::Bytes;
+
+use std::io::BufReader;
+use std::sync::Arc;
+use std::net::SocketAddr;
+
+use futures::future::Future;
+
+use rustls::Certificate;
+use rustls::ClientConfig;
+use rustls::NoClientAuth;
+use rustls::ServerConfig;
+use rustls::internal::pemfile;
+
+use httpbis::*;
+use httpbis::message::SimpleHttpMessage;
+use httpbis::rustls_tls;
+
+
+/// Config with certificate of `a.example.com` signed by `sni-root-ca.der`
+fn test_server_config() -> ServerConfig {
+    let certs = pemfile::certs(&mut BufReader::new(&include_bytes!("sni-a.pem")[..])).unwrap();
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(&include_bytes!("sni-a.key")[..])).unwrap();
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certs, keys.remove(0));
+    config.set_protocols(&rustls_tls::h2_protocols());
+    config
+}
+
+fn test_client_config() -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config.root_store.add(&Certificate(include_bytes!("sni-root-ca.der").to_vec())).unwrap();
+    config.set_protocols(&rustls_tls::h2_protocols());
+    config
+}
+
+
+#[test]
+fn rustls() {
+    struct ServiceImpl {
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request_with_context(&self, context: &ServiceContext, _headers: Headers, _req: HttpPartStream)
+            -> Response
+        {
+            assert_eq!(Some("h2"), context.alpn_protocol.as_ref().map(|p| &p[..]));
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            unreachable!()
+        }
+    }
+
+    let server = Server::new(
+        "[::1]:0".parse::<SocketAddr>().unwrap(),
+        ServerTlsOption::Rustls(Arc::new(test_server_config())),
+        Default::default(),
+        ServiceImpl {});
+
+    let client: Client = Client::new_expl(
+        &server.local_addr(),
+        ClientTlsOption::Rustls("a.example.com".to_owned(), Arc::new(test_client_config())),
+        Default::default())
+            .expect("http client");
+
+    let resp: SimpleHttpMessage = client.start_get("/hi", "a.example.com").collect().wait().unwrap();
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"hello"[..], &resp.body[..]);
+}
+
+#[test]
+fn rustls_without_h2_is_rejected() {
+    struct ServiceImpl {
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let server = Server::new(
+        "[::1]:0".parse::<SocketAddr>().unwrap(),
+        ServerTlsOption::Rustls(Arc::new(test_server_config())),
+        Default::default(),
+        ServiceImpl {});
+
+    let mut config = test_client_config();
+    config.set_protocols(&["http/1.1".to_owned()]);
+
+    let client: Client = Client::new_expl(
+        &server.local_addr(),
+        ClientTlsOption::Rustls("a.example.com".to_owned(), Arc::new(config)),
+        Default::default())
+            .expect("http client");
+
+    assert!(client.start_get("/hi", "a.example.com").collect().wait().is_err());
+}
@@ -18,14 +18,40 @@ use std::io::Write as _Write;
 
 use futures::Future;
 use futures::stream;
+use futures::stream::Stream;
+use futures::sync::oneshot;
+use futures::sync::mpsc;
 
 use httpbis::solicit::header::*;
+use httpbis::solicit::DEFAULT_SETTINGS;
 
 use httpbis::*;
+use httpbis::error::ErrorCode;
 use httpbis::stream_part::HttpStreamPart;
+use httpbis::stream_part::HttpStreamPartContent;
 use httpbis::solicit::frame::settings::*;
+use httpbis::solicit::frame::Frame;
+use httpbis::solicit::frame::ping::PingFrame;
+use httpbis::solicit::frame::headers::HeadersFrame;
+use httpbis::solicit::frame::headers::HeadersFlag;
+use httpbis::solicit::frame::headers::HEADERS_FRAME_TYPE;
+use httpbis::solicit::frame::data::DATA_FRAME_TYPE;
+use httpbis::solicit::frame::rst_stream::RstStreamFrame;
+use httpbis::solicit::frame::FrameIR;
+use httpbis::solicit::frame::continuation::ContinuationFrame;
+use httpbis::solicit::frame::FrameHeader;
+use httpbis::solicit::frame::RawFrame;
+use httpbis::solicit::frame::pack_header;
+use httpbis::solicit::connection::HttpFrame;
+use httpbis::solicit::StreamId;
 
 use std::iter::FromIterator;
+use std::net;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
 use test_misc::*;
 
@@ -62,7 +88,7 @@ fn panic_in_handler() {
     env_logger::init().ok();
 
     let server = HttpServerOneConn::new_fn(0, |headers, _req| {
-        if headers.path() == "/panic" {
+        if headers.path() == Some("/panic") {
             panic!("requested");
         } else {
             Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hi there"))
@@ -75,18 +101,18 @@ fn panic_in_handler() {
 
     {
         let resp = tester.get(1, "/hello");
-        assert_eq!(200, resp.headers.status());
+        assert_eq!(Some(200), resp.headers.status());
         assert_eq!(&b"hi there"[..], &resp.body[..]);
     }
 
     {
         let resp = tester.get(3, "/panic");
-        assert_eq!(500, resp.headers.status());
+        assert_eq!(Some(500), resp.headers.status());
     }
 
     {
         let resp = tester.get(5, "/world");
-        assert_eq!(200, resp.headers.status());
+        assert_eq!(Some(200), resp.headers.status());
         assert_eq!(&b"hi there"[..], &resp.body[..]);
     }
 
@@ -98,7 +124,7 @@ fn panic_in_stream() {
     env_logger::init().ok();
 
     let server = HttpServerOneConn::new_fn(0, |headers, _req| {
-        if headers.path() == "/panic" {
+        if headers.path() == Some("/panic") {
             Response::from_stream(stream::iter((0..2).map(|i| {
                 match i {
                     0 => Ok(HttpStreamPart::intermediate_headers(Headers::ok_200())),
@@ -116,7 +142,7 @@ fn panic_in_stream() {
 
     {
         let resp = tester.get(1, "/hello");
-        assert_eq!(200, resp.headers.status());
+        assert_eq!(Some(200), resp.headers.status());
         assert_eq!(&b"hi there"[..], &resp.body[..]);
     }
 
@@ -128,7 +154,7 @@ fn panic_in_stream() {
 
     {
         let resp = tester.get(5, "/world");
-        assert_eq!(200, resp.headers.status());
+        assert_eq!(Some(200), resp.headers.status());
         assert_eq!(&b"hi there"[..], &resp.body[..]);
     }
 
@@ -178,7 +204,46 @@ fn rst_stream_on_data_without_stream() {
     tester.recv_rst_frame_check(11, ErrorCode::StreamClosed);
 
     let r = tester.get(1, "/fgfg");
-    assert_eq!(200, r.headers.status());
+    assert_eq!(Some(200), r.headers.status());
+}
+
+#[test]
+fn malformed_request_does_not_close_connection() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    assert_eq!(Some(200), tester.get(1, "/a").headers.status());
+
+    // uppercase header name
+    let mut headers = Headers::new_get("/b");
+    headers.add("Accept", "*/*");
+    tester.send_headers(3, headers, true);
+    tester.recv_rst_frame_check(3, ErrorCode::ProtocolError);
+
+    // missing :method
+    let mut headers = Headers::new();
+    headers.add(":path", "/c");
+    tester.send_headers(5, headers, true);
+    tester.recv_rst_frame_check(5, ErrorCode::ProtocolError);
+
+    // :path not in origin form
+    tester.send_headers(7, Headers::new_get("example.com"), true);
+    assert_eq!(Some(400), tester.recv_message(7).headers.status());
+
+    // pseudo-header in trailers
+    tester.send_headers(9, Headers::new_post("/d"), false);
+    assert_eq!(Some(200), tester.recv_message(9).headers.status());
+    tester.send_headers(9, Headers::new_get("/e"), true);
+    tester.recv_rst_frame_check(9, ErrorCode::ProtocolError);
+
+    assert_eq!(Some(200), tester.get(11, "/f").headers.status());
 }
 
 #[test]
@@ -193,59 +258,1447 @@ fn exceed_max_frame_size() {
 
     tester.send_data(1, &[0; 17_000], false);
 
-    tester.recv_eof();
+    tester.recv_goaway_eof(ErrorCode::FrameSizeError);
 
     let mut tester = HttpConnectionTester::connect(server.port);
     tester.send_preface();
     tester.settings_xchg();
 
-    assert_eq!(200, tester.get(1, "/fgfg").headers.status());
+    assert_eq!(Some(200), tester.get(1, "/fgfg").headers.status());
 }
 
 #[test]
 fn increase_frame_size() {
     env_logger::init().ok();
 
-    let server = HttpServerEcho::new();
+    let mut conf = ServerConf::new();
+    conf.common.max_frame_size = Some(20000);
+    let server = HttpServerEcho::new_conf(conf);
 
+    // before client acknowledged server SETTINGS, default limit applies
     let mut tester = HttpConnectionTester::connect(server.port);
     tester.send_preface();
-    tester.settings_xchg();
+    tester.send_frame(SettingsFrame::new());
+    let settings = tester.recv_frame_settings_set();
+    assert!(settings.settings.contains(&HttpSetting::MaxFrameSize(20000)));
 
-    let mut frame = SettingsFrame::new();
-    frame.settings.push(HttpSetting::MaxFrameSize(20000));
-    tester.send_recv_settings(frame);
+    tester.send_data(1, &[1; 20_000], true);
+
+    tester.recv_goaway_eof(ErrorCode::FrameSizeError);
+
+    let mut tester = HttpConnectionTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
 
     tester.send_headers(1, Headers::new_post("/fgfg"), false);
     tester.send_data(1, &[1; 20_000], true);
 
     let r = tester.recv_message(1);
-    assert_eq!(200, r.headers.status());
+    assert_eq!(Some(200), r.headers.status());
     assert_eq!(&[1; 20_000][..], &r.body);
+
+    // frame size advertised by client does not affect frames server accepts
+    let mut frame = SettingsFrame::new();
+    frame.settings.push(HttpSetting::MaxFrameSize(30000));
+    tester.send_recv_settings(frame);
+
+    tester.send_data(3, &[1; 25_000], true);
+
+    tester.recv_goaway_eof(ErrorCode::FrameSizeError);
+}
+
+#[test]
+fn max_frame_size_out_of_range() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.common.max_frame_size = Some(1000);
+    let server = HttpServerEcho::new_conf(conf);
+
+    let mut tester = HttpConnectionTester::connect(server.port);
+    tester.send_preface();
+    tester.recv_eof();
 }
 
 #[test]
 fn exceed_window_size() {
     env_logger::init().ok();
 
+    let mut conf = ServerConf::new();
+    conf.common.max_frame_size = Some(DEFAULT_SETTINGS.initial_window_size + 5);
+    let server = HttpServerEcho::new_conf(conf);
+
+    let mut tester = HttpConnectionTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let data = Vec::from_iter((0..tester.conn.peer_settings.initial_window_size + 3).map(|_| 2));
+
+    tester.send_data(1, &data, false);
+    tester.recv_goaway_eof(ErrorCode::FlowControlError);
+
+    let mut tester = HttpConnectionTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    assert_eq!(Some(200), tester.get(1, "/fgfg").headers.status());
+}
+
+#[test]
+fn shutdown_gracefully() {
+    env_logger::init().ok();
+
+    let server = HttpServerEcho::new();
+
+    let mut tester = HttpConnectionTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new();
+    headers.add(":method", "GET");
+    headers.add(":path", "/aabb");
+    tester.send_headers(1, headers, false);
+    tester.recv_frame_headers_check(1, false);
+
+    let shutdown = server.server.shutdown_gracefully(ErrorCode::NoError, Bytes::from("bye"));
+
+    let goaway = tester.recv_frame_goaway();
+    assert_eq!(1, goaway.last_stream_id());
+    assert_eq!(ErrorCode::NoError, goaway.error_code());
+    assert_eq!(&b"bye"[..], &goaway.debug_data()[..]);
+
+    drop(server.server.shutdown_gracefully(ErrorCode::NoError, Bytes::from("bye again")));
+    let goaway = tester.recv_frame_goaway();
+    assert_eq!(1, goaway.last_stream_id());
+
+    tester.send_get(3, "/new");
+    tester.recv_rst_frame_check(3, ErrorCode::RefusedStream);
+
+    tester.send_data(1, b"abcd", true);
+    assert_eq!(&b"abcd"[..], &tester.recv_frame_data_check(1, true)[..]);
+
+    shutdown.wait().expect("shutdown");
+}
+
+#[test]
+fn response_trailers() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        let mut trailers = Headers::new();
+        trailers.add("grpc-status", "0");
+        Response::headers_and_bytes_with_trailers(Headers::ok_200(), Bytes::from("body"), trailers)
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/trailers");
+
+    let recv_headers = tester.recv_frame_headers_check(1, false);
+    assert_eq!("200", recv_headers.get(":status"));
+    assert_eq!(&b"body"[..], &tester.recv_frame_data_check(1, false)[..]);
+    let recv_trailers = tester.recv_frame_headers_check(1, true);
+    assert_eq!("0", recv_trailers.get("grpc-status"));
+}
+
+#[test]
+fn response_headers_hook() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.common.headers_hook = Some(Arc::new(|headers: &mut Headers| headers.add("x-trace-id", "17")));
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |_headers, _req| {
+        let mut trailers = Headers::new();
+        trailers.add("grpc-status", "0");
+        Response::headers_and_bytes_with_trailers(Headers::ok_200(), Bytes::from("body"), trailers)
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/trailers");
+
+    let recv_headers = tester.recv_frame_headers_check(1, false);
+    assert_eq!("17", recv_headers.get("x-trace-id"));
+    assert_eq!(&b"body"[..], &tester.recv_frame_data_check(1, false)[..]);
+    // hook is not called for trailers
+    let recv_trailers = tester.recv_frame_headers_check(1, true);
+    assert_eq!(None, recv_trailers.get_opt("x-trace-id"));
+}
+
+#[test]
+fn response_headers_hook_invalid() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.common.headers_hook = Some(Arc::new(|headers: &mut Headers| headers.add("connection", "close")));
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("body"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // connection-specific header added by hook is not sent
+    tester.send_get(1, "/invalid");
+    tester.recv_rst_frame_check(1, ErrorCode::InternalError);
+
+    // connection is still usable
+    tester.send_get(3, "/valid");
+    tester.recv_rst_frame_check(3, ErrorCode::InternalError);
+}
+
+#[test]
+fn response_trailers_client() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        let mut trailers = Headers::new();
+        trailers.add("grpc-status", "0");
+        Response::headers_and_bytes_with_trailers(Headers::ok_200(), Bytes::from("body"), trailers)
+    });
+
+    let client = Client::new("::1", server.port(), false, Default::default()).expect("connect");
+    let parts = client.start_get("/trailers", "localhost").into_stream().collect().wait().expect("parts");
+    assert_eq!(3, parts.len());
+    match parts[0] {
+        HttpStreamPartContent::Headers(ref headers) => assert_eq!(Some(200), headers.status()),
+        ref p => panic!("expecting headers, got {:?}", p),
+    }
+    match parts[1] {
+        HttpStreamPartContent::Data(ref data) => assert_eq!(&b"body"[..], &data[..]),
+        ref p => panic!("expecting data, got {:?}", p),
+    }
+    match parts[2] {
+        HttpStreamPartContent::Headers(ref headers) => assert_eq!("0", headers.get("grpc-status")),
+        ref p => panic!("expecting trailers, got {:?}", p),
+    }
+}
+
+#[test]
+fn request_body_backpressure() {
+    env_logger::init().ok();
+
+    let (consume_tx, consume_rx) = oneshot::channel::<()>();
+    let consume_rx = Mutex::new(Some(consume_rx));
+
+    let server = HttpServerOneConn::new_fn(0, move |_headers, req| {
+        let consume_rx = consume_rx.lock().unwrap().take().expect("single request");
+        // do not touch request body until signalled
+        let len = consume_rx
+            .map_err(|_| Error::Other("canceled"))
+            .and_then(move |()| {
+                req.fold(0, |len, part| {
+                    Ok::<_, Error>(len + match part.content {
+                        HttpStreamPartContent::Data(data) => data.len(),
+                        HttpStreamPartContent::Headers(..) => 0,
+                    })
+                })
+            });
+        Response::new(len.map(|len| {
+            (Headers::ok_200(), HttpPartStream::once_bytes(format!("{}", len)))
+        }))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new();
+    headers.add(":method", "POST");
+    headers.add(":path", "/upload");
+    tester.send_headers(1, headers, false);
+
+    for _ in 0..3 {
+        tester.send_data(1, &[17; 16384], false);
+    }
+
+    // service did not consume anything, so no WINDOW_UPDATE precedes PING ACK
+    tester.send_frame(PingFrame::with_data(37));
+    let ping = tester.recv_frame_ping();
+    assert!(ping.is_ack());
+    assert_eq!(37, ping.opaque_data);
+
+    consume_tx.send(()).unwrap();
+
+    let window_update = tester.recv_frame_window_update();
+    assert_eq!(0, window_update.get_stream_id());
+    assert_eq!(32768, window_update.increment());
+    let window_update = tester.recv_frame_window_update();
+    assert_eq!(1, window_update.get_stream_id());
+    assert_eq!(32768, window_update.increment());
+
+    tester.send_data(1, b"", true);
+
+    let recv_headers = tester.recv_frame_headers_check(1, false);
+    assert_eq!(Some(200), recv_headers.status());
+    assert_eq!(&b"49152"[..], &tester.recv_frame_data_check(1, true)[..]);
+}
+
+#[test]
+fn response_body_stream() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        let (tx, rx) = mpsc::unbounded();
+        thread::spawn(move || {
+            for chunk in &["aa", "bb", "cc"] {
+                thread::sleep(Duration::from_millis(50));
+                tx.send(Bytes::from(*chunk)).unwrap();
+            }
+        });
+        let body = rx.map_err(|()| Error::Other("channel"));
+        Response::headers_and_bytes_stream(Headers::ok_200(), body)
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/events");
+
+    let recv_headers = tester.recv_frame_headers_check(1, false);
+    assert_eq!(Some(200), recv_headers.status());
+    assert_eq!(&b"aa"[..], &tester.recv_frame_data_check(1, false)[..]);
+    assert_eq!(&b"bb"[..], &tester.recv_frame_data_check(1, false)[..]);
+
+    // END_STREAM is either set on last chunk or sent in separate empty frame
+    let data = tester.recv_frame_data();
+    assert_eq!(&b"cc"[..], &data.data[..]);
+    if !data.is_end_of_stream() {
+        tester.recv_frame_data_check_empty_end(1);
+    }
+}
+
+#[test]
+fn malformed_request_headers() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hi there"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let cases = vec![
+        // regular header before pseudo-header
+        Headers(vec![
+            Header::new("x-foo", "bar"),
+            Header::new(":method", "GET"),
+            Header::new(":path", "/"),
+        ]),
+        // duplicate :path
+        Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/a"),
+            Header::new(":path", "/b"),
+        ]),
+        // unknown pseudo-header
+        Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/"),
+            Header::new(":foo", "bar"),
+        ]),
+        // uppercase header name
+        Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/"),
+            Header::new("X-Foo", "bar"),
+        ]),
+        // connection-specific header
+        Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/"),
+            Header::new("connection", "keep-alive"),
+        ]),
+        // te other than trailers
+        Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/"),
+            Header::new("te", "gzip"),
+        ]),
+    ];
+
+    let mut stream_id = 1;
+    for headers in cases {
+        tester.send_headers(stream_id, headers, true);
+        tester.recv_rst_frame_check(stream_id, ErrorCode::ProtocolError);
+        stream_id += 2;
+    }
+
+    // connection is still usable
+    let resp = tester.get(stream_id, "/ok");
+    assert_eq!(Some(200), resp.headers.status());
+
+    assert_eq!(0, server.dump_state().streams.len());
+}
+
+#[test]
+fn request_content_length_mismatch() {
+    env_logger::init().ok();
+
     let server = HttpServerEcho::new();
 
     let mut tester = HttpConnectionTester::connect(server.port);
     tester.send_preface();
     tester.settings_xchg();
 
+    // echo server may start response before request body is complete
+    fn recv_rst_skip_response(tester: &mut HttpConnectionTester, stream_id: StreamId) {
+        loop {
+            match tester.recv_frame() {
+                HttpFrame::RstStream(rst) => {
+                    assert_eq!(stream_id, rst.get_stream_id());
+                    assert_eq!(ErrorCode::ProtocolError, rst.error_code());
+                    return;
+                }
+                HttpFrame::Headers(ref f) if f.get_stream_id() == stream_id => {}
+                HttpFrame::Data(ref f) if f.get_stream_id() == stream_id => {}
+                f => panic!("expecting RST, got: {:?}", f),
+            }
+        }
+    }
+
+    fn post_with_content_length(content_length: &str) -> Headers {
+        Headers(vec![
+            Header::new(":method", "POST"),
+            Header::new(":path", "/echo"),
+            Header::new("content-length", content_length),
+        ])
+    }
+
+    // declared length is larger than body
+    tester.send_headers(1, post_with_content_length("10"), false);
+    tester.send_data(1, b"hello", true);
+    recv_rst_skip_response(&mut tester, 1);
+
+    // declared length is smaller than body
+    tester.send_headers(3, post_with_content_length("3"), false);
+    tester.send_data(3, b"hello", false);
+    recv_rst_skip_response(&mut tester, 3);
+
+    // no body at all
+    tester.send_headers(5, post_with_content_length("5"), true);
+    tester.recv_rst_frame_check(5, ErrorCode::ProtocolError);
+
+    // matching length
+    tester.send_headers(7, post_with_content_length("5"), false);
+    tester.send_data(7, b"hello", true);
+    let resp = tester.recv_message(7);
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"hello"[..], &resp.body[..]);
+
+    // invalid value
+    tester.send_headers(9, post_with_content_length("-1"), false);
+    tester.recv_rst_frame_check(9, ErrorCode::ProtocolError);
+}
+
+#[test]
+fn te_trailers_allowed() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hi there"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_headers(1, Headers(vec![
+        Header::new(":method", "GET"),
+        Header::new(":path", "/"),
+        Header::new("te", "trailers"),
+    ]), true);
+
+    let resp = tester.recv_message(1);
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"hi there"[..], &resp.body[..]);
+}
+
+#[test]
+fn connection_specific_response_header_resets_stream() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        let mut headers = Headers::ok_200();
+        headers.add("transfer-encoding", "chunked");
+        Response::headers_and_bytes(headers, Bytes::from("hi there"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+    tester.recv_rst_frame_check(1, ErrorCode::InternalError);
+}
+
+fn server_with_push() -> HttpServerOneConn {
+    HttpServerOneConn::new_fn(0, |_headers, _req| {
+        let promised = Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":scheme", "http"),
+            Header::new(":authority", "localhost"),
+            Header::new(":path", "/style.css"),
+        ]);
+        let pushed = Response::headers_and_bytes(Headers::ok_200(), Bytes::from("body {}"));
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("<html>"))
+            .with_push(vec![(promised, pushed)])
+    })
+}
+
+#[test]
+fn push_promise() {
+    env_logger::init().ok();
+
+    let server = server_with_push();
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
     let mut frame = SettingsFrame::new();
-    frame.settings.push(HttpSetting::MaxFrameSize(tester.conn.peer_settings.initial_window_size + 5));
+    frame.settings.push(HttpSetting::EnablePush(true));
     tester.send_recv_settings(frame);
 
-    let data = Vec::from_iter((0..tester.conn.peer_settings.initial_window_size + 3).map(|_| 2));
+    tester.send_get(1, "/index.html");
+
+    let push_promise = match tester.recv_frame() {
+        HttpFrame::PushPromise(f) => f,
+        f => panic!("expecting PUSH_PROMISE, got: {:?}", f),
+    };
+    assert_eq!(1, push_promise.stream_id);
+    assert_eq!(2, push_promise.promised_stream_id);
+    assert!(push_promise.is_headers_end());
+    let promised = tester.conn.decoder.decode(&push_promise.header_fragment).expect("decode");
+    assert!(promised.contains(&(b":path".to_vec(), b"/style.css".to_vec())));
+
+    // responses in streams 1 and 2 may be interleaved
+    let mut bodies = vec![Vec::new(), Vec::new()];
+    let mut ended = 0;
+    while ended < 2 {
+        let frame = tester.recv_frame();
+        let index = match frame.get_stream_id() {
+            1 => 0,
+            2 => 1,
+            id => panic!("unexpected stream {}", id),
+        };
+        let end_of_stream = match frame {
+            HttpFrame::Headers(f) => {
+                let headers = tester.conn.decoder.decode(f.header_fragment()).expect("decode");
+                assert!(headers.contains(&(b":status".to_vec(), b"200".to_vec())));
+                f.is_end_of_stream()
+            }
+            HttpFrame::Data(f) => {
+                bodies[index].extend_from_slice(&f.data);
+                f.is_end_of_stream()
+            }
+            f => panic!("expecting HEADERS or DATA, got: {:?}", f),
+        };
+        if end_of_stream {
+            ended += 1;
+        }
+    }
 
-    tester.send_data(1, &data, false);
-    tester.recv_eof();
+    assert_eq!(&b"<html>"[..], &bodies[0][..]);
+    assert_eq!(&b"body {}"[..], &bodies[1][..]);
 
-    let mut tester = HttpConnectionTester::connect(server.port);
+    assert_eq!(0, server.dump_state().streams.len());
+}
+
+#[test]
+fn push_promise_when_client_disabled_push() {
+    env_logger::init().ok();
+
+    let server = server_with_push();
+
+    let mut tester = HttpConnectionTester::connect(server.port());
     tester.send_preface();
     tester.settings_xchg();
 
-    assert_eq!(200, tester.get(1, "/fgfg").headers.status());
+    let mut frame = SettingsFrame::new();
+    frame.settings.push(HttpSetting::EnablePush(false));
+    tester.send_recv_settings(frame);
+
+    // no PUSH_PROMISE, only response
+    let resp = tester.get(1, "/index.html");
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"<html>"[..], &resp.body[..]);
+
+    assert_eq!(Some(200), tester.get(3, "/").headers.status());
+}
+
+#[test]
+fn extra_settings() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.common.max_concurrent_streams = Some(1);
+    conf.common.extra_settings = vec![
+        HttpSetting::MaxConcurrentStreams(10),
+        HttpSetting::Unknown(0xf0, 17),
+        HttpSetting::MaxConcurrentStreams(20),
+    ];
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hi there"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.send_frame(SettingsFrame::new());
+
+    // unknown settings are dropped by the parser, so check raw payload
+    let raw = tester.recv_raw_frame();
+    assert_eq!(SETTINGS_FRAME_TYPE, raw.frame_type());
+    let settings: Vec<(u16, u32)> = raw.payload().chunks(6)
+        .map(|c| (
+            ((c[0] as u16) << 8) | c[1] as u16,
+            ((c[2] as u32) << 24) | ((c[3] as u32) << 16) | ((c[4] as u32) << 8) | c[5] as u32))
+        .collect();
+    assert_eq!(1, settings.iter().filter(|&&(id, _)| id == 3).count());
+    assert!(settings.contains(&(3, 20)));
+    assert!(settings.contains(&(0xf0, 17)));
+
+    tester.send_frame(SettingsFrame::new_ack());
+    tester.recv_frame_settings_ack();
+
+    assert_eq!(Some(200), tester.get(1, "/").headers.status());
+}
+
+#[test]
+fn extra_settings_invalid_value() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.common.extra_settings = vec![HttpSetting::Unknown(2, 2)];
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hi there"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.recv_eof();
+}
+
+#[test]
+fn max_concurrent_streams() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.common.max_concurrent_streams = Some(1);
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hi there"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.send_frame(SettingsFrame::new());
+    let settings = tester.recv_frame_settings_set();
+    assert!(settings.settings.contains(&HttpSetting::MaxConcurrentStreams(1)));
+    tester.send_frame(SettingsFrame::new_ack());
+    tester.recv_frame_settings_ack();
+
+    // request body is not finished, so stream 1 stays open
+    tester.send_headers(1, Headers::new_post("/"), false);
+    tester.recv_frame_headers_check(1, false);
+    tester.recv_frame_data_check(1, true);
+
+    tester.send_headers(3, Headers::new_get("/"), true);
+    tester.recv_rst_frame_check(3, ErrorCode::RefusedStream);
+
+    tester.send_data(1, b"", true);
+
+    assert_eq!(Some(200), tester.get(5, "/").headers.status());
+}
+
+#[test]
+fn h2c_upgrade() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.h2c_upgrade = Some(true);
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |headers, _req| {
+        let body = format!("{} {}", headers.get(":method"), headers.get(":path"));
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from(body))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_raw(
+        b"GET /upgraded HTTP/1.1\r\n\
+        Host: localhost\r\n\
+        Connection: Upgrade, HTTP2-Settings\r\n\
+        Upgrade: h2c\r\n\
+        HTTP2-Settings: AAMAAABkAAQAAP__\r\n\
+        \r\n");
+
+    let head = tester.recv_http1_head();
+    assert!(head.starts_with("HTTP/1.1 101 "), "{}", head);
+
+    tester.send_preface();
+    tester.settings_xchg_but_ack();
+
+    // original request is served as stream 1
+    let resp = tester.recv_message(1);
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"GET /upgraded"[..], &resp.body[..]);
+
+    // connection continues as HTTP/2
+    assert_eq!(Some(200), tester.get(3, "/next").headers.status());
+
+    // wait for event loop to finish writing
+    server.dump_state();
+
+    // HTTP/1.1 upgrade request and response are not counted as frames
+    let metrics = server.metrics();
+    assert_eq!(1, metrics.frames_received(HEADERS_FRAME_TYPE));
+    assert_eq!(2, metrics.frames_sent(HEADERS_FRAME_TYPE));
+    assert_eq!(2, metrics.frames_sent(DATA_FRAME_TYPE));
+    assert_eq!(vec![HEADERS_FRAME_TYPE, SETTINGS_FRAME_TYPE],
+        metrics.frames_received_by_type.keys().cloned().collect::<Vec<_>>());
+}
+
+#[test]
+fn h2c_upgrade_disabled_by_default() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_raw(
+        b"GET /upgraded HTTP/1.1\r\n\
+        Host: localhost\r\n\
+        Connection: Upgrade, HTTP2-Settings\r\n\
+        Upgrade: h2c\r\n\
+        HTTP2-Settings: AAMAAABkAAQAAP__\r\n\
+        \r\n");
+
+    // request is treated as invalid preface
+    tester.recv_eof();
+}
+
+#[test]
+fn h2c_upgrade_invalid_settings() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.h2c_upgrade = Some(true);
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_raw(
+        b"GET /upgraded HTTP/1.1\r\n\
+        Host: localhost\r\n\
+        Connection: Upgrade, HTTP2-Settings\r\n\
+        Upgrade: h2c\r\n\
+        HTTP2-Settings: AAMAAABkAAQ*\r\n\
+        \r\n");
+
+    let head = tester.recv_http1_head();
+    assert!(head.starts_with("HTTP/1.1 400 "), "{}", head);
+    tester.recv_eof();
+}
+
+#[test]
+fn send_raw_frame_unknown_type() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let client: Client =
+        Client::new("::1", server.port(), false, Default::default()).expect("client");
+
+    let r = client.start_get("/1", "localhost").collect().wait().expect("get");
+    assert_eq!(&b"hello"[..], &r.body[..]);
+
+    // frames of unknown type must be ignored by peer
+    let payload = b"unknown";
+    let mut frame = pack_header(&FrameHeader {
+        length: payload.len() as u32,
+        frame_type: 0xfa,
+        flags: 0,
+        stream_id: 0,
+    }).to_vec();
+    frame.extend_from_slice(payload);
+    server.send_raw_frame(RawFrame::from(frame));
+
+    let r = client.start_get("/2", "localhost").collect().wait().expect("get");
+    assert_eq!(&b"hello"[..], &r.body[..]);
+}
+
+#[test]
+fn unknown_frame_between_requests() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    assert_eq!(&b"hello"[..], &tester.get(1, "/1").body[..]);
+
+    let payload = b"unknown";
+    let mut frame = pack_header(&FrameHeader {
+        length: payload.len() as u32,
+        frame_type: 0xfa,
+        flags: 0,
+        stream_id: 1,
+    }).to_vec();
+    frame.extend_from_slice(payload);
+    tester.send_raw(&frame);
+
+    assert_eq!(&b"hello"[..], &tester.get(3, "/2").body[..]);
+
+    assert_eq!(0, server.dump_state().streams.len());
+}
+
+#[test]
+fn data_on_stream_0_closes_connection() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_data(0, b"abc", false);
+    tester.recv_goaway_eof(ErrorCode::ProtocolError);
+}
+
+#[test]
+fn headers_on_even_stream_closes_connection() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(2, "/");
+    tester.recv_goaway_eof(ErrorCode::ProtocolError);
+}
+
+#[test]
+fn continuation_on_other_stream_closes_connection() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_frame(HeadersFrame::new(vec![0x82], 1));
+    tester.send_frame(ContinuationFrame::new(vec![0x84], 3));
+    tester.recv_goaway_eof(ErrorCode::ProtocolError);
+}
+
+#[test]
+fn data_inside_header_block_closes_connection() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, req| {
+        Response::headers_and_stream(Headers::ok_200(), req)
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // stream 1 is open
+    let mut headers = Headers::new();
+    headers.add(":method", "POST");
+    headers.add(":path", "/echo");
+    tester.send_headers(1, headers, false);
+    tester.recv_frame_headers_check(1, false);
+
+    // 6.2: header block of stream 3 must be followed by CONTINUATION,
+    // DATA of other stream is connection error, not stream error
+    tester.send_frame(HeadersFrame::new(vec![0x82], 3));
+    tester.send_data(1, b"abcd", false);
+    tester.send_frame(ContinuationFrame::new(vec![0x84], 3));
+    tester.recv_goaway_eof(ErrorCode::ProtocolError);
+}
+
+#[test]
+fn service_context_peer_addr() {
+    env_logger::init().ok();
+
+    struct ServiceImpl {
+        peer_addr: Arc<Mutex<Option<SocketAddr>>>,
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            unreachable!()
+        }
+
+        fn start_request_with_context(&self, context: &ServiceContext, _headers: Headers, _req: HttpPartStream)
+            -> Response
+        {
+            assert_eq!(None, context.alpn_protocol);
+            *self.peer_addr.lock().unwrap() = context.peer_addr;
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let peer_addr = Arc::new(Mutex::new(None));
+
+    let server = Server::new(
+        "[::1]:0",
+        ServerTlsOption::Plain,
+        Default::default(),
+        ServiceImpl { peer_addr: peer_addr.clone() });
+
+    let mut tester = HttpConnectionTester::connect(server.local_addr().port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    assert_eq!(&b"hello"[..], &tester.get(1, "/").body[..]);
+    assert_eq!(Some(tester.local_addr()), *peer_addr.lock().unwrap());
+}
+
+#[test]
+fn rst_stream_cancels_service() {
+    env_logger::init().ok();
+
+    /// Signals when response stream is dropped
+    struct DropGuard(std::sync::mpsc::Sender<()>);
+
+    impl Drop for DropGuard {
+        fn drop(&mut self) {
+            self.0.send(()).ok();
+        }
+    }
+
+    /// Response body which is never completed
+    struct PendingBody {
+        _guard: DropGuard,
+    }
+
+    impl Stream for PendingBody {
+        type Item = HttpStreamPart;
+        type Error = Error;
+
+        fn poll(&mut self) -> futures::Poll<Option<HttpStreamPart>, Error> {
+            Ok(futures::Async::NotReady)
+        }
+    }
+
+    struct ServiceImpl {
+        dropped_tx: std::sync::mpsc::Sender<()>,
+        started_tx: std::sync::mpsc::Sender<(CancelToken, HttpPartStream)>,
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            unreachable!()
+        }
+
+        fn start_request_with_context(&self, context: &ServiceContext, _headers: Headers, req: HttpPartStream)
+            -> Response
+        {
+            assert!(!context.cancel.is_canceled());
+            self.started_tx.send((context.cancel.clone(), req)).unwrap();
+            let body = PendingBody { _guard: DropGuard(self.dropped_tx.clone()) };
+            Response::headers_and_stream(Headers::ok_200(), HttpPartStream::new(body))
+        }
+    }
+
+    let (dropped_tx, dropped_rx) = std::sync::mpsc::channel();
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+
+    let server = Server::new(
+        "[::1]:0",
+        ServerTlsOption::Plain,
+        Default::default(),
+        ServiceImpl { dropped_tx: dropped_tx, started_tx: started_tx });
+
+    let mut tester = HttpConnectionTester::connect(server.local_addr().port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new();
+    headers.add(":method", "POST");
+    headers.add(":path", "/");
+    tester.send_headers(1, headers, false);
+
+    let (cancel, req) = started_rx.recv_timeout(Duration::from_secs(5)).expect("started");
+    assert_eq!("200", tester.recv_frame_headers_check(1, false).get(":status"));
+
+    tester.send_rst(1, ErrorCode::Cancel);
+
+    dropped_rx.recv_timeout(Duration::from_secs(5)).expect("response stream dropped");
+    cancel.wait().expect("canceled");
+    assert!(req.collect().wait().is_err());
+}
+
+#[test]
+fn shutdown_stops_accept() {
+    env_logger::init().ok();
+
+    let server = HttpServerEcho::new();
+
+    let mut tester = HttpConnectionTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new();
+    headers.add(":method", "POST");
+    headers.add(":path", "/echo");
+    tester.send_headers(1, headers, false);
+    tester.recv_frame_headers_check(1, false);
+
+    let shutdown = server.server.shutdown(None);
+
+    let goaway = tester.recv_frame_goaway();
+    assert_eq!(1, goaway.last_stream_id());
+    assert_eq!(ErrorCode::NoError, goaway.error_code());
+
+    // in-flight request completes
+    tester.send_data(1, b"abcd", true);
+    assert_eq!(&b"abcd"[..], &tester.recv_frame_data_check(1, true)[..]);
+
+    shutdown.wait().expect("shutdown");
+
+    assert!(net::TcpStream::connect(("::1", server.port)).is_err());
+}
+
+#[test]
+fn shutdown_deadline() {
+    env_logger::init().ok();
+
+    let server = HttpServerEcho::new();
+
+    let mut tester = HttpConnectionTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new();
+    headers.add(":method", "POST");
+    headers.add(":path", "/echo");
+    tester.send_headers(1, headers, false);
+    tester.recv_frame_headers_check(1, false);
+
+    // request is never completed
+    server.server.shutdown(Some(Duration::from_millis(100))).wait().expect("shutdown");
+    tester.recv_frame_goaway();
+
+    assert!(net::TcpStream::connect(("::1", server.port)).is_err());
+}
+
+#[test]
+fn from_listener() {
+    env_logger::init().ok();
+
+    struct ServiceImpl;
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let listener = net::TcpListener::bind("[::1]:0").expect("bind");
+    let port = listener.local_addr().expect("local_addr").port();
+
+    let server = Server::from_listener(listener, ServerTlsOption::Plain, Default::default(), ServiceImpl);
+    assert_eq!(port, server.local_addr().port());
+
+    let mut tester = HttpConnectionTester::connect(port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    assert_eq!(&b"hello"[..], &tester.get(1, "/").body[..]);
+}
+
+#[test]
+fn local_addr_ephemeral_port() {
+    env_logger::init().ok();
+
+    struct ServiceImpl;
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, req: HttpPartStream) -> Response {
+            Response::headers_and_stream(Headers::ok_200(), req)
+        }
+    }
+
+    let server = Server::new("[::1]:0", ServerTlsOption::Plain, Default::default(), ServiceImpl);
+    let port = server.local_addr().port();
+    assert_ne!(0, port);
+
+    let client = Client::new("::1", port, false, Default::default()).expect("client");
+    let resp = client.start_post("/echo", "localhost", Bytes::from("abc")).collect().wait().expect("resp");
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"abc"[..], &resp.body[..]);
+}
+
+#[test]
+fn wait_ready() {
+    env_logger::init().ok();
+
+    struct ServiceImpl;
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    for _ in 0..10 {
+        let server = Server::new("[::1]:0", ServerTlsOption::Plain, Default::default(), ServiceImpl);
+        server.wait_ready().wait().expect("ready");
+        // resolved again once ready
+        server.wait_ready().wait().expect("ready again");
+
+        // connection is not refused
+        net::TcpStream::connect(server.local_addr()).expect("connect");
+
+        let client = Client::new_expl(&server.local_addr(), ClientTlsOption::Plain, Default::default())
+            .expect("client");
+        let resp = client.start_get("/hi", "localhost").collect().wait().expect("resp");
+        assert_eq!(Some(200), resp.headers.status());
+    }
+}
+
+#[test]
+fn collect_body() {
+    env_logger::init().ok();
+
+    struct ServiceImpl;
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, req: HttpPartStream) -> Response {
+            Response::new(req.collect_body(100).then(|r| {
+                match r {
+                    Ok(body) => Ok((Headers::ok_200(), HttpPartStream::once_bytes(format!("{}", body.len())))),
+                    Err(Error::BodyTooLarge(100)) => Ok((Headers::from_status(413), HttpPartStream::empty())),
+                    Err(e) => Err(e),
+                }
+            }))
+        }
+    }
+
+    let server = Server::new("[::1]:0", ServerTlsOption::Plain, Default::default(), ServiceImpl);
+
+    let client = Client::new_expl(&server.local_addr(), ClientTlsOption::Plain, Default::default())
+        .expect("client");
+
+    let body = stream::iter(vec![Ok(Bytes::from("abc")), Ok(Bytes::from("defg"))]);
+    let resp = client.start_post_stream("/", "localhost", Box::new(body)).collect().wait().expect("resp");
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"7"[..], &resp.body[..]);
+
+    let resp = client.start_post("/", "localhost", Bytes::from(vec![17; 101])).collect().wait().expect("resp");
+    assert_eq!(Some(413), resp.headers.status());
+}
+
+#[test]
+fn dual_stack_only_v6_false() {
+    env_logger::init().ok();
+
+    struct ServiceImpl;
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let mut conf = ServerConf::new();
+    conf.only_v6 = Some(false);
+
+    let server = Server::new("[::]:0", ServerTlsOption::Plain, conf, ServiceImpl);
+    let port = server.local_addr().port();
+
+    let client = Client::new("127.0.0.1", port, false, Default::default()).expect("client");
+    let resp = client.start_get("/", "localhost").collect().wait().expect("resp");
+    assert_eq!(&b"hello"[..], &resp.body[..]);
+}
+
+#[test]
+fn backlog() {
+    env_logger::init().ok();
+
+    struct ServiceImpl;
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let mut conf = ServerConf::new();
+    conf.backlog = Some(1);
+
+    let server = Server::new("[::1]:0", ServerTlsOption::Plain, conf, ServiceImpl);
+
+    // connections are accepted normally with small backlog
+    let clients: Vec<Client> = (0..5)
+        .map(|_| Client::new_expl(&server.local_addr(), ClientTlsOption::Plain, Default::default())
+            .expect("client"))
+        .collect();
+    for client in &clients {
+        let resp = client.start_get("/", "localhost").collect().wait().expect("resp");
+        assert_eq!(&b"hello"[..], &resp.body[..]);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn reuse_port() {
+    env_logger::init().ok();
+
+    struct ServiceImpl;
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let mut conf = ServerConf::new();
+    conf.reuse_port = Some(true);
+
+    let server1 = Server::new("[::1]:0", ServerTlsOption::Plain, conf.clone(), ServiceImpl);
+    let port = server1.local_addr().port();
+    let server2 = Server::new(("::1", port), ServerTlsOption::Plain, conf, ServiceImpl);
+    assert_eq!(port, server2.local_addr().port());
+
+    let client = Client::new("::1", port, false, Default::default()).expect("client");
+    let resp = client.start_get("/", "localhost").collect().wait().expect("resp");
+    assert_eq!(&b"hello"[..], &resp.body[..]);
+}
+
+#[test]
+fn on_connection_error() {
+    env_logger::init().ok();
+
+    struct ServiceImpl;
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            unreachable!()
+        }
+    }
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let mut conf = ServerConf::new();
+    conf.on_connection_error = Some(Arc::new(move |peer_addr: SocketAddr, error: &Error| {
+        tx.lock().unwrap().send((peer_addr, format!("{:?}", error))).unwrap();
+    }));
+
+    let server = Server::new("[::1]:0", ServerTlsOption::Plain, conf, ServiceImpl);
+
+    let mut tester = HttpConnectionTester::connect(server.local_addr().port());
+    // looks like TLS ClientHello instead of preface
+    tester.send_raw(&[0x16; 24]);
+
+    let (peer_addr, error) = rx.recv_timeout(Duration::from_secs(5)).expect("callback");
+    assert_eq!(tester.local_addr(), peer_addr);
+    assert_eq!("TlsConnectionToPlaintextPort", error);
+}
+
+#[test]
+fn on_connection_error_not_called_on_clean_disconnect() {
+    env_logger::init().ok();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let mut conf = ServerConf::new();
+    conf.on_connection_error = Some(Arc::new(move |peer_addr: SocketAddr, error: &Error| {
+        tx.lock().unwrap().send((peer_addr, format!("{:?}", error))).unwrap();
+    }));
+
+    struct ServiceImpl;
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let server = Server::new("[::1]:0", ServerTlsOption::Plain, conf, ServiceImpl);
+
+    // EOF after request is completed
+    let mut tester = HttpConnectionTester::connect(server.local_addr().port());
+    tester.send_preface();
+    tester.settings_xchg();
+    tester.send_get(1, "/file");
+    tester.recv_message(1);
+    drop(tester);
+
+    // EOF after GOAWAY
+    let mut tester = HttpConnectionTester::connect(server.local_addr().port());
+    tester.send_preface();
+    tester.settings_xchg();
+    tester.send_goaway(0);
+    drop(tester);
+
+    assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+}
+
+#[test]
+fn client_handshake_timeout() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.client_handshake_timeout = Some(Duration::from_millis(100));
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    // preface is never sent
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.recv_eof();
+}
+
+#[test]
+fn read_timeout() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.read_timeout = Some(Duration::from_millis(100));
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    assert_eq!(&b"hello"[..], &tester.get(1, "/hi").body[..]);
+
+    // connection is closed when client goes silent
+    tester.recv_eof();
+}
+
+#[test]
+fn read_timeout_not_triggered_by_slow_response() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.read_timeout = Some(Duration::from_millis(100));
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |_headers, _req| {
+        let (tx, rx) = mpsc::unbounded();
+        thread::spawn(move || {
+            for chunk in &["aa", "bb", "cc"] {
+                thread::sleep(Duration::from_millis(150));
+                tx.send(Bytes::from(*chunk)).unwrap();
+            }
+        });
+        let body = rx.map_err(|()| Error::Other("channel"));
+        Response::headers_and_bytes_stream(Headers::ok_200(), body)
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // client is silent while response is streamed
+    assert_eq!(&b"aabbcc"[..], &tester.get(1, "/events").body[..]);
+
+    tester.recv_eof();
+}
+
+#[test]
+fn rapid_reset() {
+    env_logger::init().ok();
+
+    let server = HttpServerOneConn::new_fn(0, |_headers, _req| {
+        Response::new(futures::future::empty())
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // written at once, so connection is not closed while client is still writing
+    let mut frames = Vec::new();
+    for i in 0..200 {
+        let stream_id = 1 + 2 * i;
+        let fragment = tester.conn.encoder.encode(vec![(&b":method"[..], &b"GET"[..]), (&b":path"[..], &b"/"[..])]);
+        let mut headers = HeadersFrame::new(fragment, stream_id);
+        headers.set_flag(HeadersFlag::EndHeaders);
+        headers.set_flag(HeadersFlag::EndStream);
+        frames.extend(headers.serialize_into_vec());
+        frames.extend(RstStreamFrame::new(stream_id, ErrorCode::Cancel).serialize_into_vec());
+    }
+    tester.send_raw(&frames);
+
+    tester.recv_goaway_eof(ErrorCode::EnhanceYourCalm);
+}
+
+#[test]
+fn max_header_list_size() {
+    env_logger::init().ok();
+
+    let mut conf = ServerConf::new();
+    conf.common.max_header_list_size = Some(1000);
+
+    let server = HttpServerOneConn::new_fn_with_conf(0, conf, |_headers, _req| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+    });
+
+    let mut tester = HttpConnectionTester::connect(server.port());
+    tester.send_preface();
+    tester.send_frame(SettingsFrame::new());
+    let settings = tester.recv_frame_settings_set();
+    assert!(settings.settings.contains(&HttpSetting::MaxHeaderListSize(1000)));
+    tester.recv_frame_settings_ack();
+
+    let mut headers = Headers::new();
+    headers.add(":method", "GET");
+    headers.add(":path", "/small");
+    tester.send_headers(1, headers, true);
+    assert_eq!(Some(200), tester.recv_message(1).headers.status());
+
+    let mut headers = Headers::new();
+    headers.add(":method", "GET");
+    headers.add(":path", "/large");
+    headers.add("x-large", &"x".repeat(2000));
+    tester.send_headers(3, headers, true);
+    let resp = tester.recv_message(3);
+    assert_eq!(Some(431), resp.headers.status());
+    assert_eq!(0, resp.body.len());
+
+    // connection is still usable
+    assert_eq!(Some(200), tester.get(5, "/after").headers.status());
 }
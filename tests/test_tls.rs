@@ -13,12 +13,16 @@ use bytes::Bytes;
 
 use std::sync::Arc;
 use std::net::SocketAddr;
+use std::net::TcpStream;
 
 use futures::future::Future;
 
 use httpbis::solicit::header::Headers;
 use httpbis::*;
 use httpbis::message::SimpleHttpMessage;
+use httpbis::alpn;
+use httpbis::client_auth;
+use httpbis::client_auth::TlsIdentity;
 
 use native_tls::TlsAcceptor;
 use native_tls::TlsConnector;
@@ -29,19 +33,59 @@ use native_tls::Certificate;
 fn test_tls_acceptor() -> TlsAcceptor {
     let buf = include_bytes!("identity.p12");
     let pkcs12 = Pkcs12::from_der(buf, "mypass").unwrap();
-    let builder = TlsAcceptor::builder(pkcs12).unwrap();
+    let mut builder = TlsAcceptor::builder(pkcs12).unwrap();
+    if alpn::SUPPORTED {
+        alpn::acceptor_set_protocols(&mut builder, &[alpn::H2]).expect("ALPN");
+    }
     builder.build().unwrap()
 }
 
-fn test_tls_connector() -> TlsConnector {
+fn test_tls_connector_with_protocols(protocols: &[&[u8]]) -> TlsConnector {
     let root_ca = include_bytes!("root-ca.der");
     let root_ca = Certificate::from_der(root_ca).unwrap();
 
     let mut builder = TlsConnector::builder().unwrap();
     builder.add_root_certificate(root_ca).expect("add_root_certificate");
+    if alpn::SUPPORTED && !protocols.is_empty() {
+        alpn::connector_set_protocols(&mut builder, protocols).expect("ALPN");
+    }
+    builder.build().unwrap()
+}
+
+fn test_tls_connector() -> TlsConnector {
+    test_tls_connector_with_protocols(&[alpn::H2])
+}
+
+/// Acceptor with identity of `a.example.com` or `b.example.com`
+/// signed by `sni-root-ca.der`
+fn test_sni_acceptor(identity: &[u8]) -> Arc<TlsAcceptor> {
+    let pkcs12 = Pkcs12::from_der(identity, "mypass").unwrap();
+    let mut builder = TlsAcceptor::builder(pkcs12).unwrap();
+    if alpn::SUPPORTED {
+        alpn::acceptor_set_protocols(&mut builder, &[alpn::H2]).expect("ALPN");
+    }
+    Arc::new(builder.build().unwrap())
+}
+
+fn test_sni_connector_with_identity(identity: Option<&TlsIdentity>) -> TlsConnector {
+    let root_ca = include_bytes!("sni-root-ca.der");
+    let root_ca = Certificate::from_der(root_ca).unwrap();
+
+    let mut builder = TlsConnector::builder().unwrap();
+    builder.add_root_certificate(root_ca).expect("add_root_certificate");
+    if alpn::SUPPORTED {
+        alpn::connector_set_protocols(&mut builder, &[alpn::H2]).expect("ALPN");
+    }
+    if let Some(identity) = identity {
+        client_auth::connector_set_identity(&mut builder, identity).expect("identity");
+    }
     builder.build().unwrap()
 }
 
+fn test_sni_connector() -> TlsConnector {
+    test_sni_connector_with_identity(None)
+}
+
 
 #[test]
 fn tls() {
@@ -61,12 +105,228 @@ fn tls() {
         ServiceImpl {});
 
     let client: Client = Client::new_expl(
-        server.local_addr(),
+        &server.local_addr(),
         ClientTlsOption::Tls("foobar.com".to_owned(), Arc::new(test_tls_connector())),
         Default::default())
             .expect("http client");
 
     let resp: SimpleHttpMessage = client.start_get("/hi", "localhost").collect().wait().unwrap();
-    assert_eq!(200, resp.headers.status());
+    assert_eq!(Some(200), resp.headers.status());
     assert_eq!(&b"hello"[..], &resp.body[..]);
 }
+
+
+#[test]
+fn tls_without_alpn() {
+    struct ServiceImpl {
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let server = Server::new(
+        "[::1]:0".parse::<SocketAddr>().unwrap(),
+        ServerTlsOption::Tls(Arc::new(test_tls_acceptor())),
+        Default::default(),
+        ServiceImpl {});
+
+    // client does not offer any protocols, server accepts it by default
+    let client: Client = Client::new_expl(
+        &server.local_addr(),
+        ClientTlsOption::Tls("foobar.com".to_owned(), Arc::new(test_tls_connector_with_protocols(&[]))),
+        Default::default())
+            .expect("http client");
+
+    let resp: SimpleHttpMessage = client.start_get("/hi", "localhost").collect().wait().unwrap();
+    assert_eq!(Some(200), resp.headers.status());
+}
+
+
+// ALPN is needed to tell that client did not negotiate h2
+#[test]
+#[cfg(all(any(feature = "openssl-v102", feature = "openssl-v110"),
+    not(any(target_os = "windows", target_os = "macos", target_os = "ios"))))]
+fn tls_without_h2_is_rejected() {
+    use std::io::Read;
+    use std::io::Write;
+
+    struct ServiceImpl {
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let mut conf = ServerConf::new();
+    conf.require_alpn_h2 = Some(true);
+
+    let server = Server::new(
+        "[::1]:0".parse::<SocketAddr>().unwrap(),
+        ServerTlsOption::Tls(Arc::new(test_tls_acceptor())),
+        conf,
+        ServiceImpl {});
+
+    let tcp = TcpStream::connect(server.local_addr()).expect("connect");
+    let connector = test_tls_connector_with_protocols(&[alpn::HTTP_1_1]);
+    let mut tls = connector.connect("foobar.com", tcp).expect("TLS handshake");
+    assert_eq!(None, alpn::negotiated_protocol(&tls));
+
+    // server closes connection instead of sending SETTINGS
+    tls.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").ok();
+    let mut buf = Vec::new();
+    let r = tls.read_to_end(&mut buf);
+    assert!(r.is_err() || buf.is_empty(), "expecting connection closed, got {:?}", buf);
+}
+
+
+#[test]
+fn sni() {
+    struct ServiceImpl {
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from("hello"))
+        }
+    }
+
+    let a = test_sni_acceptor(include_bytes!("sni-a.p12"));
+    let b = test_sni_acceptor(include_bytes!("sni-b.p12"));
+    let resolver = move |server_name: Option<&str>| {
+        match server_name {
+            Some("a.example.com") => Some(a.clone()),
+            Some("b.example.com") => Some(b.clone()),
+            _ => None,
+        }
+    };
+
+    let server = Server::new(
+        "[::1]:0".parse::<SocketAddr>().unwrap(),
+        ServerTlsOption::Sni(Arc::new(resolver)),
+        Default::default(),
+        ServiceImpl {});
+
+    // client verifies certificate matches the name it sent in SNI
+    for name in &["a.example.com", "b.example.com"] {
+        let client: Client = Client::new_expl(
+            &server.local_addr(),
+            ClientTlsOption::Tls(name.to_string(), Arc::new(test_sni_connector())),
+            Default::default())
+                .expect("http client");
+
+        let resp: SimpleHttpMessage = client.start_get("/hi", name).collect().wait().expect(name);
+        assert_eq!(Some(200), resp.headers.status());
+        assert_eq!(&b"hello"[..], &resp.body[..]);
+    }
+
+    // connection is closed when no certificate is found for the name
+    let tcp = TcpStream::connect(server.local_addr()).expect("connect");
+    assert!(test_sni_connector().connect("c.example.com", tcp).is_err());
+}
+
+// client certificate verification is only implemented with OpenSSL backend
+#[test]
+#[cfg(all(any(feature = "openssl-v102", feature = "openssl-v110"),
+    not(any(target_os = "windows", target_os = "macos", target_os = "ios"))))]
+fn client_cert() {
+    use std::io::Read;
+    use std::io::Write;
+
+    struct ServiceImpl {
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request_with_context(&self, context: &ServiceContext, _headers: Headers, _req: HttpPartStream)
+            -> Response
+        {
+            let cert = context.client_cert.clone().expect("client_cert");
+            let names = format!("{};{}", cert.common_name.unwrap_or_default(), cert.dns_names.join(","));
+            Response::headers_and_bytes(Headers::ok_200(), Bytes::from(names))
+        }
+
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            unreachable!()
+        }
+    }
+
+    let pkcs12 = Pkcs12::from_der(include_bytes!("sni-a.p12"), "mypass").unwrap();
+    let mut builder = TlsAcceptor::builder(pkcs12).unwrap();
+    if alpn::SUPPORTED {
+        alpn::acceptor_set_protocols(&mut builder, &[alpn::H2]).expect("ALPN");
+    }
+    client_auth::acceptor_require_client_cert(&mut builder, include_bytes!("sni-root-ca.der"))
+        .expect("acceptor_require_client_cert");
+
+    let mut conf = ServerConf::new();
+    conf.require_client_cert = Some(true);
+
+    let server = Server::new(
+        "[::1]:0".parse::<SocketAddr>().unwrap(),
+        ServerTlsOption::Tls(Arc::new(builder.build().unwrap())),
+        conf,
+        ServiceImpl {});
+
+    // `sni-client.p12` is certificate of `client.example.com` signed by `sni-root-ca.der`
+    let identity = TlsIdentity::new(include_bytes!("sni-client.p12").to_vec(), "mypass");
+
+    let client: Client = Client::new_expl(
+        &server.local_addr(),
+        ClientTlsOption::Tls("a.example.com".to_owned(), Arc::new(test_sni_connector_with_identity(Some(&identity)))),
+        Default::default())
+            .expect("http client");
+
+    let resp: SimpleHttpMessage = client.start_get("/hi", "a.example.com").collect().wait().unwrap();
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b"client.example.com;"[..], &resp.body[..]);
+
+    // `sni-san-client.p12` has no subject common name, only subject alternative name
+    let identity = TlsIdentity::new(include_bytes!("sni-san-client.p12").to_vec(), "mypass");
+
+    let client: Client = Client::new_expl(
+        &server.local_addr(),
+        ClientTlsOption::Tls("a.example.com".to_owned(), Arc::new(test_sni_connector_with_identity(Some(&identity)))),
+        Default::default())
+            .expect("http client");
+
+    let resp: SimpleHttpMessage = client.start_get("/hi", "a.example.com").collect().wait().unwrap();
+    assert_eq!(Some(200), resp.headers.status());
+    assert_eq!(&b";san-client.example.com"[..], &resp.body[..]);
+
+    // handshake fails without client certificate
+    let tcp = TcpStream::connect(server.local_addr()).expect("connect");
+    let rejected = test_sni_connector().connect("a.example.com", tcp)
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|mut tls| {
+            // TLS 1.3 server rejects certificate after client finished handshake
+            tls.write_all(b"x").map_err(|e| format!("{:?}", e))?;
+            let mut buf = [0; 1];
+            match tls.read(&mut buf) {
+                Ok(0) | Err(_) => Err("closed".to_owned()),
+                Ok(_) => Ok(()),
+            }
+        });
+    assert!(rejected.is_err());
+}
+
+#[test]
+#[should_panic(expected = "require_client_cert")]
+fn client_cert_plain() {
+    struct ServiceImpl {
+    }
+
+    impl Service for ServiceImpl {
+        fn start_request(&self, _headers: Headers, _req: HttpPartStream) -> Response {
+            unreachable!()
+        }
+    }
+
+    let mut conf = ServerConf::new();
+    conf.require_client_cert = Some(true);
+
+    Server::new("[::1]:0", ServerTlsOption::Plain, conf, ServiceImpl {});
+}